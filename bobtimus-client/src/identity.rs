@@ -0,0 +1,177 @@
+//! The long-term identity of Bobtimus and the receipts it signs with it.
+//!
+//! Every quote, swap transaction and loan response comes with a
+//! signature of Bobtimus over a statement about it, so that takers and
+//! borrowers can tell that they are talking to the maker they trusted
+//! before and keep a record of what was agreed. Signatures are Bitcoin
+//! signed messages, as produced by `signmessage` of elementsd.
+
+use elements::{
+    bitcoin::{
+        hashes::{sha256, Hash},
+        secp256k1::Secp256k1,
+        util::misc::{signed_msg_hash, MessageSignature},
+        PublicKey,
+    },
+    Txid,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Header carrying the public key of the identity of Bobtimus.
+pub const IDENTITY_HEADER: &str = "bobtimus-identity";
+
+/// Header carrying the base64-encoded signature of Bobtimus over the
+/// statement the response makes.
+pub const SIGNATURE_HEADER: &str = "bobtimus-signature";
+
+/// What Bobtimus commits to by signing a response.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Statement {
+    /// The swap transaction with this txid, signed by Bobtimus
+    Swap { txid: Txid },
+    /// The loan transaction with this txid, as offered by Bobtimus
+    Loan { txid: Txid },
+    /// A quote, identified by the SHA256 digest of the response body
+    Quote { digest: sha256::Hash },
+}
+
+impl Statement {
+    /// The statement for a quote with the given response body.
+    pub fn quote(body: &[u8]) -> Self {
+        Statement::Quote {
+            digest: sha256::Hash::hash(body),
+        }
+    }
+
+    /// The message which is signed for the statement.
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Swap { txid } => write!(f, "bobtimus swap {}", txid),
+            Statement::Loan { txid } => write!(f, "bobtimus loan {}", txid),
+            Statement::Quote { digest } => write!(f, "bobtimus quote {}", digest),
+        }
+    }
+}
+
+/// A statement signed by Bobtimus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub maker: PublicKey,
+    pub statement: Statement,
+    /// Base64-encoded Bitcoin signed message
+    pub signature: String,
+}
+
+/// The identity and signature Bobtimus sent along with a response, as
+/// read from the [`IDENTITY_HEADER`] and [`SIGNATURE_HEADER`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MakerSignature {
+    pub maker: PublicKey,
+    pub signature: String,
+}
+
+impl MakerSignature {
+    /// The receipt for the statement the signature is supposed to be
+    /// over, which still has to be verified.
+    pub fn receipt(self, statement: Statement) -> Receipt {
+        Receipt {
+            maker: self.maker,
+            statement,
+            signature: self.signature,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ReceiptError {
+    #[error("Signature is not a valid signed message")]
+    Malformed,
+    #[error("Statement was not signed by maker {0}")]
+    WrongSigner(PublicKey),
+}
+
+impl Receipt {
+    /// Ensure that the statement was signed by the maker.
+    pub fn verify(&self) -> Result<(), ReceiptError> {
+        let signature =
+            MessageSignature::from_base64(&self.signature).map_err(|_| ReceiptError::Malformed)?;
+        let msg_hash = signed_msg_hash(&self.statement.message());
+
+        let signer = signature
+            .recover_pubkey(&Secp256k1::verification_only(), msg_hash)
+            .map_err(|_| ReceiptError::Malformed)?;
+
+        if signer.key != self.maker.key {
+            return Err(ReceiptError::WrongSigner(self.maker));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::bitcoin::secp256k1::{Message, SecretKey};
+    use std::str::FromStr;
+
+    fn sign(secret_key: &SecretKey, statement: &Statement) -> String {
+        let secp = Secp256k1::new();
+        let msg_hash = signed_msg_hash(&statement.message());
+        let signature = secp.sign_recoverable(
+            &Message::from_slice(&msg_hash.into_inner()).unwrap(),
+            secret_key,
+        );
+
+        MessageSignature::new(signature, true).to_base64()
+    }
+
+    fn maker(secret_key: &SecretKey) -> PublicKey {
+        PublicKey::new(elements::bitcoin::secp256k1::PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            secret_key,
+        ))
+    }
+
+    #[test]
+    fn only_statements_signed_by_the_maker_are_accepted() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let statement = Statement::Swap {
+            txid: Txid::from_str(
+                "8f1f6c8a5f1dc1b5a2b1a6c6fb4fce4b59e3dd4c57ad8b1e0a5a1d7a8ab2ef31",
+            )
+            .unwrap(),
+        };
+
+        let receipt = Receipt {
+            maker: maker(&secret_key),
+            statement,
+            signature: sign(&secret_key, &statement),
+        };
+        assert_eq!(receipt.verify(), Ok(()));
+
+        let forged = Receipt {
+            signature: sign(&other_key, &statement),
+            ..receipt.clone()
+        };
+        assert_eq!(
+            forged.verify(),
+            Err(ReceiptError::WrongSigner(maker(&secret_key)))
+        );
+
+        let other_statement = Receipt {
+            statement: Statement::quote(b"{}"),
+            ..receipt
+        };
+        assert!(other_statement.verify().is_err());
+    }
+}
@@ -0,0 +1,535 @@
+//! Types and client for the HTTP API of Bobtimus.
+//!
+//! The request and response types are shared with Bobtimus, so that
+//! takers and borrowers cannot drift apart from the maker. The client
+//! compiles for native targets and for `wasm32`, where the rate updates
+//! are received through the browser's `EventSource`.
+
+use elements::{
+    bitcoin::Amount,
+    confidential::{AssetBlindingFactor, ValueBlindingFactor},
+    pset::PartiallySignedTransaction,
+    secp256k1_zkp::SecretKey,
+    Address, AssetId, OutPoint, Transaction, TxOutSecrets, Txid,
+};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Response, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub use baru::{input::Input, loan::LoanResponse};
+
+pub mod identity;
+mod problem;
+pub mod pset;
+#[cfg(feature = "schemars")]
+pub mod schema;
+mod sse;
+
+pub use identity::{
+    MakerSignature, Receipt, ReceiptError, Statement, IDENTITY_HEADER, SIGNATURE_HEADER,
+};
+pub use problem::{Problem, ProblemType, PROBLEM_TYPE_BASE};
+pub use pset::{PsetError, PSET_CONTENT_TYPE};
+
+/// Header identifying a swap or loan request across retries.
+///
+/// Repeating a request with the same key returns the original response
+/// instead of creating a new transaction, as long as the key has not
+/// expired. Keys are scoped to the address the request comes from.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type EventStream<T> = futures::stream::BoxStream<'static, Result<T>>;
+#[cfg(target_arch = "wasm32")]
+pub type EventStream<T> = futures::stream::LocalBoxStream<'static, Result<T>>;
+
+/// Prices at which 1 L-BTC is traded, in L-USDt.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Rate {
+    /// The price at which Bobtimus sells 1 L-BTC
+    pub ask: f64,
+    /// The price at which Bobtimus buys 1 L-BTC
+    pub bid: f64,
+}
+
+/// Represents the payload for creating a swap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CreateSwapPayload {
+    pub alice_inputs: Vec<AliceInput>,
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub address: Address,
+    /// Amount of the asset the taker sells, in satoshi
+    pub amount: u64,
+}
+
+/// An input of the taker, with what Bobtimus needs to know about the
+/// output it spends to build the swap transaction.
+///
+/// Exactly one of `blinding_key` and `secrets` has to be given.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AliceInput {
+    #[cfg_attr(feature = "schemars", schemars(with = "schema::OutPoint"))]
+    pub outpoint: OutPoint,
+    /// Key to unblind the spent output
+    ///
+    /// It unblinds every output sent to the same address, prefer
+    /// `secrets`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub blinding_key: Option<SecretKey>,
+    /// Amounts and blinding factors of the spent output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<InputSecrets>,
+}
+
+impl AliceInput {
+    pub fn with_blinding_key(outpoint: OutPoint, blinding_key: SecretKey) -> Self {
+        Self {
+            outpoint,
+            blinding_key: Some(blinding_key),
+            secrets: None,
+        }
+    }
+
+    pub fn with_secrets(outpoint: OutPoint, secrets: TxOutSecrets) -> Self {
+        Self {
+            outpoint,
+            blinding_key: None,
+            secrets: Some(secrets.into()),
+        }
+    }
+}
+
+/// The asset and value of an output and the factors they are blinded
+/// with, which open its commitments but nothing else.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct InputSecrets {
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub asset: AssetId,
+    /// Hex-encoded asset blinding factor
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub asset_blinding_factor: AssetBlindingFactor,
+    /// Value in satoshi
+    pub value: u64,
+    /// Hex-encoded value blinding factor
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub value_blinding_factor: ValueBlindingFactor,
+}
+
+impl From<TxOutSecrets> for InputSecrets {
+    fn from(secrets: TxOutSecrets) -> Self {
+        Self {
+            asset: secrets.asset,
+            asset_blinding_factor: secrets.asset_bf,
+            value: secrets.value,
+            value_blinding_factor: secrets.value_bf,
+        }
+    }
+}
+
+impl From<InputSecrets> for TxOutSecrets {
+    fn from(secrets: InputSecrets) -> Self {
+        TxOutSecrets::new(
+            secrets.asset,
+            secrets.asset_blinding_factor,
+            secrets.value,
+            secrets.value_blinding_factor,
+        )
+    }
+}
+
+/// The range of loan terms Bobtimus is willing to lend under.
+///
+/// Amounts in L-USDt are nominal, i.e. in dollars.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LoanOffer {
+    /// Identifies the offer in the loan request
+    pub id: String,
+    /// Unix timestamp after which loan requests for this offer are rejected
+    pub expires_at: u32,
+    /// The rate at which the offer is binding
+    pub rate: Rate,
+    #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+    #[cfg_attr(feature = "schemars", schemars(with = "u64"))]
+    pub fee_sats_per_vbyte: Amount,
+    pub min_principal: f64,
+    pub max_principal: f64,
+    pub max_ltv: f64,
+    pub base_interest_rate: f64,
+    pub terms: Vec<Term>,
+    pub collateralizations: Vec<Collateralization>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Term {
+    pub days: u32,
+    pub interest_mod: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Collateralization {
+    pub collateralization: f64,
+    pub interest_mod: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LoanRequest {
+    /// The offer the loan is requested under
+    pub offer_id: String,
+    /// Loan term in days
+    pub term: u32,
+    /// Amount of L-USDt to borrow, in satoshi
+    #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+    #[cfg_attr(feature = "schemars", schemars(with = "u64"))]
+    pub principal_amount: Amount,
+    pub collateralization: f64,
+    #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+    #[cfg_attr(feature = "schemars", schemars(with = "u64"))]
+    pub collateral_amount: Amount,
+    /// Inputs funding the collateral
+    ///
+    /// Unlike the inputs of a swap, these disclose the blinding key of
+    /// the spent outputs, which unblinds every output sent to the same
+    /// address, as the loan protocol of baru unblinds them itself.
+    #[cfg_attr(feature = "schemars", schemars(with = "Vec<schema::Input>"))]
+    pub collateral_inputs: Vec<Input>,
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub borrower_pk: elements::bitcoin::PublicKey,
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub borrower_address: Address,
+}
+
+/// The loan transaction signed by the borrower, for the lender to sign
+/// and broadcast it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FinalizeLoanPayload {
+    #[serde(with = "baru::loan::transaction_as_string")]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub tx_hex: Transaction,
+}
+
+/// A loan response with the transaction as base64-encoded PSET as
+/// well, returned if [`PSET_CONTENT_TYPE`] is accepted.
+///
+/// The loan response is still needed to repay the loan later on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct PsetLoanResponse {
+    #[cfg_attr(feature = "schemars", schemars(with = "schema::LoanResponse"))]
+    pub loan_response: LoanResponse,
+    pub pset: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request to Bobtimus failed")]
+    Http(#[from] reqwest::Error),
+    #[error("Bobtimus rejected the request: {0}")]
+    Problem(Problem),
+    #[error("invalid response from Bobtimus")]
+    InvalidResponse(#[from] serde_json::Error),
+    #[error("invalid transaction from Bobtimus")]
+    InvalidTransaction(#[from] elements::encode::Error),
+    #[error("invalid transaction hex from Bobtimus")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("invalid PSET from Bobtimus")]
+    InvalidPset(#[from] PsetError),
+    #[error("event stream failed: {0}")]
+    EventStream(String),
+}
+
+impl Error {
+    /// Whether repeating the request with a fresh offer or rate may
+    /// succeed.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Error::Problem(problem) => problem.is_recoverable(),
+            _ => false,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    base_url: Url,
+}
+
+impl Client {
+    pub fn new(base_url: Url) -> Self {
+        Self {
+            inner: reqwest::Client::new(),
+            base_url,
+        }
+    }
+
+    /// Every rate update from now on.
+    pub fn latest_rate(&self) -> EventStream<Rate> {
+        sse::subscribe(self.inner.clone(), self.url("api/rate/lbtc-lusdt"), "rate")
+    }
+
+    /// Create a swap transaction in which the taker buys L-BTC with
+    /// L-USDt.
+    ///
+    /// Retries of the request should reuse the idempotency key.
+    pub async fn create_buy_swap(
+        &self,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<Transaction> {
+        self.create_swap("api/swap/lbtc-lusdt/buy", payload, idempotency_key)
+            .await
+    }
+
+    /// Create a swap transaction in which the taker sells L-BTC for
+    /// L-USDt.
+    ///
+    /// Retries of the request should reuse the idempotency key.
+    pub async fn create_sell_swap(
+        &self,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<Transaction> {
+        self.create_swap("api/swap/lbtc-lusdt/sell", payload, idempotency_key)
+            .await
+    }
+
+    async fn create_swap(
+        &self,
+        path: &str,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<Transaction> {
+        let response = self
+            .post(path, idempotency_key)
+            .json(payload)
+            .send()
+            .await?;
+        let tx_hex = check(response).await?.text().await?;
+        let transaction = elements::encode::deserialize(&hex::decode(tx_hex.trim())?)?;
+
+        Ok(transaction)
+    }
+
+    /// Join the next batch of swaps in which takers buy L-BTC with
+    /// L-USDt, returning the batch transaction once it is built.
+    ///
+    /// The taker has to sign their inputs and hand the transaction back
+    /// through [`Client::sign_swap_batch`].
+    pub async fn create_batched_buy_swap(
+        &self,
+        payload: &CreateSwapPayload,
+    ) -> Result<Transaction> {
+        self.create_swap("api/swap/lbtc-lusdt/buy/batch", payload, None)
+            .await
+    }
+
+    /// Join the next batch of swaps in which takers sell L-BTC for
+    /// L-USDt, returning the batch transaction once it is built.
+    ///
+    /// The taker has to sign their inputs and hand the transaction back
+    /// through [`Client::sign_swap_batch`].
+    pub async fn create_batched_sell_swap(
+        &self,
+        payload: &CreateSwapPayload,
+    ) -> Result<Transaction> {
+        self.create_swap("api/swap/lbtc-lusdt/sell/batch", payload, None)
+            .await
+    }
+
+    /// Hand the batch transaction signed by the taker to Bobtimus, which
+    /// broadcasts it once every taker of the batch signed.
+    pub async fn sign_swap_batch(&self, transaction: Transaction) -> Result<Txid> {
+        let response = self
+            .inner
+            .post(self.url("api/swap/lbtc-lusdt/batch/sign"))
+            .json(&FinalizeLoanPayload {
+                tx_hex: transaction,
+            })
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Create a swap transaction in which the taker buys L-BTC with
+    /// L-USDt, as PSET.
+    pub async fn create_buy_swap_pset(
+        &self,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<PartiallySignedTransaction> {
+        self.create_swap_pset("api/swap/lbtc-lusdt/buy", payload, idempotency_key)
+            .await
+    }
+
+    /// Create a swap transaction in which the taker sells L-BTC for
+    /// L-USDt, as PSET.
+    pub async fn create_sell_swap_pset(
+        &self,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<PartiallySignedTransaction> {
+        self.create_swap_pset("api/swap/lbtc-lusdt/sell", payload, idempotency_key)
+            .await
+    }
+
+    async fn create_swap_pset(
+        &self,
+        path: &str,
+        payload: &CreateSwapPayload,
+        idempotency_key: Option<&str>,
+    ) -> Result<PartiallySignedTransaction> {
+        let response = self
+            .post(path, idempotency_key)
+            .header(ACCEPT, PSET_CONTENT_TYPE)
+            .json(payload)
+            .send()
+            .await?;
+        let pset = check(response).await?.text().await?;
+
+        Ok(pset::decode(&pset)?)
+    }
+
+    pub async fn loan_offer(&self) -> Result<LoanOffer> {
+        let response = self
+            .inner
+            .get(self.url("api/loan/lbtc-lusdt"))
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Request a loan under a previous offer.
+    ///
+    /// Retries of the request should reuse the idempotency key.
+    pub async fn request_loan(
+        &self,
+        request: &LoanRequest,
+        idempotency_key: Option<&str>,
+    ) -> Result<LoanResponse> {
+        let response = self
+            .post("api/loan/lbtc-lusdt", idempotency_key)
+            .json(request)
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Request a loan under a previous offer, with the loan transaction
+    /// as PSET as well.
+    pub async fn request_loan_pset(
+        &self,
+        request: &LoanRequest,
+        idempotency_key: Option<&str>,
+    ) -> Result<PsetLoanResponse> {
+        let response = self
+            .post("api/loan/lbtc-lusdt", idempotency_key)
+            .header(ACCEPT, PSET_CONTENT_TYPE)
+            .json(request)
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Hand the loan transaction signed by the borrower to Bobtimus,
+    /// who signs and broadcasts it.
+    pub async fn finalize_loan(&self, transaction: Transaction) -> Result<Txid> {
+        let response = self
+            .inner
+            .post(self.url("api/loan/lbtc-lusdt/finalize"))
+            .json(&FinalizeLoanPayload {
+                tx_hex: transaction,
+            })
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Hand the loan transaction signed by the borrower to Bobtimus as
+    /// PSET, who signs and broadcasts it.
+    pub async fn finalize_loan_pset(&self, pset: &PartiallySignedTransaction) -> Result<Txid> {
+        let response = self
+            .inner
+            .post(self.url("api/loan/lbtc-lusdt/finalize"))
+            .header(CONTENT_TYPE, PSET_CONTENT_TYPE)
+            .body(pset::encode(pset))
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    /// Fund an address with L-BTC and L-USDt, only available on
+    /// instances of Bobtimus with the faucet feature enabled.
+    pub async fn faucet(&self, address: &Address) -> Result<Vec<Txid>> {
+        let response = self
+            .inner
+            .post(self.url(&format!("api/faucet/{}", address)))
+            .send()
+            .await?;
+
+        json(response).await
+    }
+
+    fn post(&self, path: &str, idempotency_key: Option<&str>) -> reqwest::RequestBuilder {
+        let request = self.inner.post(self.url(path));
+
+        match idempotency_key {
+            Some(key) => request.header(IDEMPOTENCY_KEY_HEADER, key),
+            None => request,
+        }
+    }
+
+    fn url(&self, path: &str) -> Url {
+        self.base_url.join(path).expect("valid path")
+    }
+}
+
+async fn json<T>(response: Response) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let body = check(response).await?.text().await?;
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Turn unsuccessful responses into the problem Bobtimus reported.
+async fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await?;
+    let problem = serde_json::from_str::<Problem>(&body).unwrap_or_else(|_| Problem {
+        type_url: None,
+        title: status
+            .canonical_reason()
+            .unwrap_or("Unknown error")
+            .to_owned(),
+        status: Some(status.as_u16()),
+        detail: Some(body),
+        extensions: Default::default(),
+    });
+
+    Err(Error::Problem(problem))
+}
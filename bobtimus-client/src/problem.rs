@@ -0,0 +1,334 @@
+//! Problem details returned by Bobtimus for failed requests, as
+//! described in RFC 7807.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Every problem type URI starts with this prefix, followed by the
+/// slug of the [`ProblemType`].
+pub const PROBLEM_TYPE_BASE: &str = "https://coblox.tech/bobtimus/problems/";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Problem {
+    /// URI identifying the type of the problem, see [`ProblemType`]
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_url: Option<String>,
+    pub title: String,
+    pub status: Option<u16>,
+    pub detail: Option<String>,
+    /// Fields specific to the type of the problem
+    ///
+    /// Amounts in L-USDt are nominal, i.e. in dollars.
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// The type of the problem, `None` for problems not raised by
+    /// Bobtimus itself, e.g. by a proxy in between.
+    pub fn problem_type(&self) -> Option<ProblemType> {
+        self.type_url.as_deref().and_then(ProblemType::from_uri)
+    }
+
+    /// Whether repeating the request with a fresh offer or rate may
+    /// succeed.
+    pub fn is_recoverable(&self) -> bool {
+        self.problem_type()
+            .map(ProblemType::is_recoverable)
+            .unwrap_or(false)
+    }
+
+    /// The extension field with the given name, `None` if it is missing
+    /// or of a different type.
+    pub fn extension<T>(&self, name: &str) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = self.extensions.get(name)?;
+
+        serde_json::from_value(value.clone()).ok()
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.title, detail),
+            None => write!(f, "{}", self.title),
+        }
+    }
+}
+
+/// The stable identifiers of the problems raised by Bobtimus.
+///
+/// The extension fields of each type are listed in its documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProblemType {
+    /// `request_price`, `offer_price`
+    PriceNotAcceptable,
+    /// `offer_id`
+    UnknownOffer,
+    /// `offer_id`, `expired_at`
+    OfferExpired,
+    /// `request_principal`, `min_principal`
+    PrincipalBelowMin,
+    /// `request_principal`, `max_principal`
+    PrincipalAboveMax,
+    /// `request_ltv`, `max_ltv`
+    LtvAboveMax,
+    /// `term`, `min_term`, `max_term`
+    TermNotAllowed,
+    /// `request_collateralization`, `min_collateralization`
+    CollateralizationBelowMin,
+    /// `total_principal`, `max_total_principal`
+    TotalPrincipalAboveMax,
+    /// `borrower_principal`, `max_borrower_principal`
+    BorrowerPrincipalAboveMax,
+    /// `remaining_balance`, `usdt_reserve`
+    ReserveBelowMin,
+    /// `loan_txid`
+    UnknownLoan,
+    InvalidAssetTypes,
+    InputAmountTooSmall,
+    ChangeAmountTooSmall,
+    /// `outpoint`
+    DuplicateInput,
+    /// `outpoint`
+    InputSpent,
+    /// `outpoint`, `confirmations`, `min_confirmations`
+    InputUnconfirmed,
+    /// `outpoint`
+    InputAlreadyReserved,
+    /// `outpoint`
+    InputSecretsMissing,
+    /// `outpoint`
+    InputSecretsMismatch,
+    InvalidBody,
+    InvalidPset,
+    InvalidIdempotencyKey,
+    /// `idempotency_key`
+    IdempotencyKeyReused,
+    PayloadTooLarge,
+    /// `retry_after`, in seconds
+    RateLimited,
+    /// `max_pending_negotiations`
+    TooManyPendingNegotiations,
+    /// `retry_after`, in seconds
+    TakerThrottled,
+    SwapBatchingDisabled,
+    /// `txid`
+    UnknownSwapBatch,
+    /// `txid`, unless the batch could not be built
+    SwapBatchAbandoned,
+    ServicePaused,
+    InternalError,
+}
+
+impl ProblemType {
+    pub const ALL: [ProblemType; 34] = [
+        ProblemType::PriceNotAcceptable,
+        ProblemType::UnknownOffer,
+        ProblemType::OfferExpired,
+        ProblemType::PrincipalBelowMin,
+        ProblemType::PrincipalAboveMax,
+        ProblemType::LtvAboveMax,
+        ProblemType::TermNotAllowed,
+        ProblemType::CollateralizationBelowMin,
+        ProblemType::TotalPrincipalAboveMax,
+        ProblemType::BorrowerPrincipalAboveMax,
+        ProblemType::ReserveBelowMin,
+        ProblemType::UnknownLoan,
+        ProblemType::InvalidAssetTypes,
+        ProblemType::InputAmountTooSmall,
+        ProblemType::ChangeAmountTooSmall,
+        ProblemType::DuplicateInput,
+        ProblemType::InputSpent,
+        ProblemType::InputUnconfirmed,
+        ProblemType::InputAlreadyReserved,
+        ProblemType::InputSecretsMissing,
+        ProblemType::InputSecretsMismatch,
+        ProblemType::InvalidBody,
+        ProblemType::InvalidPset,
+        ProblemType::InvalidIdempotencyKey,
+        ProblemType::IdempotencyKeyReused,
+        ProblemType::PayloadTooLarge,
+        ProblemType::RateLimited,
+        ProblemType::TooManyPendingNegotiations,
+        ProblemType::TakerThrottled,
+        ProblemType::SwapBatchingDisabled,
+        ProblemType::UnknownSwapBatch,
+        ProblemType::SwapBatchAbandoned,
+        ProblemType::ServicePaused,
+        ProblemType::InternalError,
+    ];
+
+    pub fn slug(self) -> &'static str {
+        match self {
+            ProblemType::PriceNotAcceptable => "price-not-acceptable",
+            ProblemType::UnknownOffer => "unknown-offer",
+            ProblemType::OfferExpired => "offer-expired",
+            ProblemType::PrincipalBelowMin => "principal-below-min",
+            ProblemType::PrincipalAboveMax => "principal-above-max",
+            ProblemType::LtvAboveMax => "ltv-above-max",
+            ProblemType::TermNotAllowed => "term-not-allowed",
+            ProblemType::CollateralizationBelowMin => "collateralization-below-min",
+            ProblemType::TotalPrincipalAboveMax => "total-principal-above-max",
+            ProblemType::BorrowerPrincipalAboveMax => "borrower-principal-above-max",
+            ProblemType::ReserveBelowMin => "reserve-below-min",
+            ProblemType::UnknownLoan => "unknown-loan",
+            ProblemType::InvalidAssetTypes => "invalid-asset-types",
+            ProblemType::InputAmountTooSmall => "input-amount-too-small",
+            ProblemType::ChangeAmountTooSmall => "change-amount-too-small",
+            ProblemType::DuplicateInput => "duplicate-input",
+            ProblemType::InputSpent => "input-spent",
+            ProblemType::InputUnconfirmed => "input-unconfirmed",
+            ProblemType::InputAlreadyReserved => "input-already-reserved",
+            ProblemType::InputSecretsMissing => "input-secrets-missing",
+            ProblemType::InputSecretsMismatch => "input-secrets-mismatch",
+            ProblemType::InvalidBody => "invalid-body",
+            ProblemType::InvalidPset => "invalid-pset",
+            ProblemType::InvalidIdempotencyKey => "invalid-idempotency-key",
+            ProblemType::IdempotencyKeyReused => "idempotency-key-reused",
+            ProblemType::PayloadTooLarge => "payload-too-large",
+            ProblemType::RateLimited => "rate-limited",
+            ProblemType::TooManyPendingNegotiations => "too-many-pending-negotiations",
+            ProblemType::TakerThrottled => "taker-throttled",
+            ProblemType::SwapBatchingDisabled => "swap-batching-disabled",
+            ProblemType::UnknownSwapBatch => "unknown-swap-batch",
+            ProblemType::SwapBatchAbandoned => "swap-batch-abandoned",
+            ProblemType::ServicePaused => "service-paused",
+            ProblemType::InternalError => "internal-error",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ProblemType::PriceNotAcceptable => "Price not acceptable.",
+            ProblemType::UnknownOffer => "Unknown offer.",
+            ProblemType::OfferExpired => "Offer expired.",
+            ProblemType::PrincipalBelowMin => "Principal below minimum.",
+            ProblemType::PrincipalAboveMax => "Principal above maximum.",
+            ProblemType::LtvAboveMax => "LTV above maximum.",
+            ProblemType::TermNotAllowed => "Term not allowed.",
+            ProblemType::CollateralizationBelowMin => "Collateralization below minimum.",
+            ProblemType::TotalPrincipalAboveMax => "Total principal above maximum.",
+            ProblemType::BorrowerPrincipalAboveMax => "Borrower principal above maximum.",
+            ProblemType::ReserveBelowMin => "Reserve below minimum.",
+            ProblemType::UnknownLoan => "Unknown loan.",
+            ProblemType::InvalidAssetTypes => "Invalid asset types in inputs.",
+            ProblemType::InputAmountTooSmall => "Input amount too small.",
+            ProblemType::ChangeAmountTooSmall => "Change amount too small to cover fee.",
+            ProblemType::DuplicateInput => "Input given more than once.",
+            ProblemType::InputSpent => "Input does not exist or is spent.",
+            ProblemType::InputUnconfirmed => "Input is not confirmed.",
+            ProblemType::InputAlreadyReserved => "Input is part of a pending swap or loan.",
+            ProblemType::InputSecretsMissing => "Input cannot be unblinded.",
+            ProblemType::InputSecretsMismatch => "Input secrets do not match its commitments.",
+            ProblemType::InvalidBody => "Invalid body.",
+            ProblemType::InvalidPset => "Invalid PSET.",
+            ProblemType::InvalidIdempotencyKey => "Invalid idempotency key.",
+            ProblemType::IdempotencyKeyReused => "Idempotency key already used.",
+            ProblemType::PayloadTooLarge => "Payload too large.",
+            ProblemType::RateLimited => "Too many requests.",
+            ProblemType::TooManyPendingNegotiations => "Too many pending negotiations.",
+            ProblemType::TakerThrottled => "Too many swaps not broadcast.",
+            ProblemType::SwapBatchingDisabled => "Swap batching is disabled.",
+            ProblemType::UnknownSwapBatch => "Unknown swap batch.",
+            ProblemType::SwapBatchAbandoned => "Swap batch abandoned.",
+            ProblemType::ServicePaused => "Service paused.",
+            ProblemType::InternalError => "Internal server error.",
+        }
+    }
+
+    pub fn uri(self) -> String {
+        format!("{}{}", PROBLEM_TYPE_BASE, self.slug())
+    }
+
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        let slug = uri.strip_prefix(PROBLEM_TYPE_BASE)?;
+
+        ProblemType::ALL
+            .iter()
+            .copied()
+            .find(|problem_type| problem_type.slug() == slug)
+    }
+
+    /// Whether repeating the request with a fresh offer or rate may
+    /// succeed.
+    ///
+    /// Offers and quotes expire and are forgotten when Bobtimus restarts,
+    /// and the price they are bound to may have moved since the request
+    /// was made.
+    pub fn is_recoverable(self) -> bool {
+        matches!(
+            self,
+            ProblemType::PriceNotAcceptable | ProblemType::UnknownOffer | ProblemType::OfferExpired
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_problem_type_roundtrips_through_uri() {
+        for problem_type in ProblemType::ALL.iter().copied() {
+            assert_eq!(
+                ProblemType::from_uri(&problem_type.uri()),
+                Some(problem_type)
+            );
+        }
+    }
+
+    #[test]
+    fn extension_fields_are_parsed() {
+        let problem = serde_json::from_str::<Problem>(
+            r#"{
+                "type": "https://coblox.tech/bobtimus/problems/principal-below-min",
+                "title": "Principal below minimum.",
+                "status": 400,
+                "request_principal": 50.0,
+                "min_principal": 100.0
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(problem.problem_type(), Some(ProblemType::PrincipalBelowMin));
+        assert_eq!(problem.extension::<f64>("min_principal"), Some(100.0));
+        assert!(!problem.is_recoverable());
+    }
+
+    #[test]
+    fn problems_without_type_are_not_recoverable() {
+        let problem = serde_json::from_str::<Problem>(r#"{"title": "Bad Gateway"}"#).unwrap();
+
+        assert_eq!(problem.problem_type(), None);
+        assert!(!problem.is_recoverable());
+    }
+
+    /// The web app keeps its own copy of the problem types, which has to
+    /// list the same slugs in the same order.
+    #[test]
+    fn web_app_knows_every_problem_type() {
+        let waves = include_str!("../../waves/src/Bobtimus.tsx");
+        let enum_start = waves
+            .find("export enum ProblemType {")
+            .expect("web app declares the problem types");
+        let enum_body = &waves[enum_start..];
+        let enum_body = &enum_body[..enum_body.find('}').expect("enum is closed")];
+
+        let web_app_slugs = enum_body
+            .lines()
+            .filter_map(|line| line.split('"').nth(1))
+            .collect::<Vec<_>>();
+        let slugs = ProblemType::ALL
+            .iter()
+            .map(|problem_type| problem_type.slug())
+            .collect::<Vec<_>>();
+
+        assert_eq!(web_app_slugs, slugs);
+    }
+}
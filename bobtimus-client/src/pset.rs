@@ -0,0 +1,225 @@
+//! Elements PSETs as an alternative wire format for the transactions of
+//! swaps and loans, for wallets which do not use our own types.
+//!
+//! Every input of a PSET carries the output it spends and, where it was
+//! signed already, its signatures. The blinding factors of the output
+//! are included if they are known to the party the PSET is sent to,
+//! under proprietary keys as PSET has no standard fields for them.
+
+use elements::{
+    bitcoin::PublicKey,
+    confidential::{AssetBlindingFactor, ValueBlindingFactor},
+    encode,
+    pset::{self, raw::ProprietaryKey, PartiallySignedTransaction},
+    AssetId, Transaction, TxOut, TxOutSecrets,
+};
+
+/// Media type of a base64-encoded PSET, in the `Accept` header to
+/// receive a PSET instead of a hex-encoded transaction and in the
+/// `Content-Type` header to send one.
+pub const PSET_CONTENT_TYPE: &str = "application/pset";
+
+const PROPRIETARY_PREFIX: &[u8] = b"bobtimus";
+const ASSET: u8 = 0;
+const ASSET_BLINDING_FACTOR: u8 = 1;
+const VALUE: u8 = 2;
+const VALUE_BLINDING_FACTOR: u8 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PsetError {
+    #[error("PSET is not valid base64")]
+    Base64(#[from] base64::DecodeError),
+    #[error("PSET cannot be decoded")]
+    Encoding(#[from] encode::Error),
+    #[error("PSET is not fully signed")]
+    Incomplete(#[from] pset::Error),
+}
+
+/// The output spent by an input of a transaction, with its blinding
+/// factors if they may be disclosed.
+#[derive(Debug, Clone)]
+pub struct PsetInput {
+    pub utxo: TxOut,
+    pub secrets: Option<TxOutSecrets>,
+}
+
+/// Describe a transaction as PSET, with the given input data in the
+/// order of the inputs of the transaction.
+pub fn from_transaction(
+    transaction: &Transaction,
+    inputs: Vec<PsetInput>,
+) -> PartiallySignedTransaction {
+    let mut pset = PartiallySignedTransaction::from_tx(transaction.clone());
+
+    for ((pset_input, txin), input) in pset
+        .inputs
+        .iter_mut()
+        .zip(transaction.input.iter())
+        .zip(inputs)
+    {
+        pset_input.witness_utxo = Some(input.utxo);
+
+        let witness = &txin.witness.script_witness;
+        if !witness.is_empty() {
+            // A P2WPKH spend is the only kind we can attribute a signature to
+            if let [signature, public_key] = witness.as_slice() {
+                if let Ok(public_key) = PublicKey::from_slice(public_key) {
+                    pset_input
+                        .partial_sigs
+                        .insert(public_key, signature.clone());
+                }
+            }
+            pset_input.final_script_witness = Some(witness.clone());
+        }
+
+        if let Some(secrets) = input.secrets {
+            let fields = [
+                (ASSET, encode::serialize(&secrets.asset)),
+                (
+                    ASSET_BLINDING_FACTOR,
+                    secrets.asset_bf.into_inner().as_ref().to_vec(),
+                ),
+                (VALUE, secrets.value.to_le_bytes().to_vec()),
+                (
+                    VALUE_BLINDING_FACTOR,
+                    secrets.value_bf.into_inner().as_ref().to_vec(),
+                ),
+            ];
+            for (subtype, value) in fields.iter() {
+                pset_input
+                    .proprietary
+                    .insert(proprietary_key(*subtype), value.clone());
+            }
+        }
+    }
+
+    pset
+}
+
+/// The blinding factors of the output spent by an input, if the PSET
+/// carries them.
+pub fn input_secrets(input: &pset::Input) -> Option<TxOutSecrets> {
+    let field = |subtype| input.proprietary.get(&proprietary_key(subtype));
+
+    let asset = encode::deserialize::<AssetId>(field(ASSET)?).ok()?;
+    let asset_bf = AssetBlindingFactor::from_slice(field(ASSET_BLINDING_FACTOR)?).ok()?;
+    let mut value = [0u8; 8];
+    value.copy_from_slice(field(VALUE).filter(|value| value.len() == 8)?);
+    let value_bf = ValueBlindingFactor::from_slice(field(VALUE_BLINDING_FACTOR)?).ok()?;
+
+    Some(TxOutSecrets::new(
+        asset,
+        asset_bf,
+        u64::from_le_bytes(value),
+        value_bf,
+    ))
+}
+
+/// The transaction described by a PSET, which has to be fully signed.
+pub fn extract_transaction(pset: &PartiallySignedTransaction) -> Result<Transaction, PsetError> {
+    Ok(pset.extract_tx()?)
+}
+
+pub fn encode(pset: &PartiallySignedTransaction) -> String {
+    base64::encode(encode::serialize(pset))
+}
+
+pub fn decode(pset: &str) -> Result<PartiallySignedTransaction, PsetError> {
+    let bytes = base64::decode(pset.trim())?;
+
+    Ok(encode::deserialize(&bytes)?)
+}
+
+fn proprietary_key(subtype: u8) -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PROPRIETARY_PREFIX.to_vec(),
+        subtype,
+        key: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::{
+        confidential::{Asset, Nonce, Value},
+        OutPoint, Script, TxIn, TxInWitness, TxOutWitness,
+    };
+    use std::str::FromStr;
+
+    fn transaction(witness: Vec<Vec<u8>>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                is_pegin: false,
+                has_issuance: false,
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                asset_issuance: Default::default(),
+                witness: TxInWitness {
+                    script_witness: witness,
+                    ..Default::default()
+                },
+            }],
+            output: vec![utxo()],
+        }
+    }
+
+    fn utxo() -> TxOut {
+        TxOut {
+            asset: Asset::Explicit(asset()),
+            value: Value::Explicit(100_000),
+            nonce: Nonce::Null,
+            script_pubkey: Script::default(),
+            witness: TxOutWitness::default(),
+        }
+    }
+
+    fn asset() -> AssetId {
+        AssetId::from_str("6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d")
+            .unwrap()
+    }
+
+    #[test]
+    fn signed_transaction_survives_the_round_trip() {
+        let public_key =
+            hex::decode("02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443")
+                .unwrap();
+        let transaction = transaction(vec![vec![0x30; 71], public_key]);
+
+        let pset = from_transaction(
+            &transaction,
+            vec![PsetInput {
+                utxo: utxo(),
+                secrets: None,
+            }],
+        );
+        let pset = decode(&encode(&pset)).unwrap();
+
+        assert_eq!(pset.inputs[0].partial_sigs.len(), 1);
+        assert_eq!(pset.inputs[0].witness_utxo, Some(utxo()));
+        assert_eq!(extract_transaction(&pset).unwrap(), transaction);
+    }
+
+    #[test]
+    fn blinding_factors_are_carried_by_the_input() {
+        let secrets = TxOutSecrets::new(
+            asset(),
+            AssetBlindingFactor::zero(),
+            100_000,
+            ValueBlindingFactor::zero(),
+        );
+
+        let pset = from_transaction(
+            &transaction(Vec::new()),
+            vec![PsetInput {
+                utxo: utxo(),
+                secrets: Some(secrets),
+            }],
+        );
+
+        assert_eq!(input_secrets(&pset.inputs[0]), Some(secrets));
+    }
+}
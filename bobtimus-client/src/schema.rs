@@ -0,0 +1,49 @@
+//! JSON schemas of the types which are defined outside of this crate,
+//! as they are serialized on the wire.
+
+use schemars::JsonSchema;
+
+#[derive(JsonSchema)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: u32,
+}
+
+/// An input to be spent in a loan transaction, together with the key
+/// to unblind it
+#[derive(JsonSchema)]
+pub struct Input {
+    pub txin: OutPoint,
+    pub original_txout: TxOut,
+    /// Hex-encoded blinding secret key
+    pub blinding_key: String,
+}
+
+/// A confidential or explicit transaction output
+#[derive(JsonSchema)]
+pub struct TxOut {
+    pub asset: serde_json::Value,
+    pub value: serde_json::Value,
+    pub nonce: serde_json::Value,
+    /// Hex-encoded script
+    pub script_pubkey: String,
+    pub witness: serde_json::Value,
+}
+
+/// The loan transaction signed by the lender, together with everything
+/// the borrower needs to repay the loan
+#[derive(JsonSchema)]
+pub struct LoanResponse {
+    /// Hex-encoded transaction
+    pub transaction: String,
+    /// Hex-encoded public key of the lender
+    pub lender_pk: String,
+    pub repayment_collateral_input: Input,
+    /// Hex-encoded asset blinding factor of the collateral to be repaid
+    pub repayment_collateral_abf: String,
+    /// Hex-encoded value blinding factor of the collateral to be repaid
+    pub repayment_collateral_vbf: String,
+    /// Block height or timestamp after which the lender may liquidate
+    pub timelock: u64,
+    pub repayment_principal_output: TxOut,
+}
@@ -0,0 +1,251 @@
+//! Server-sent events as pushed by Bobtimus.
+
+use crate::{Error, EventStream};
+use futures::StreamExt;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+
+/// Subscribe to the events with the given name and deserialize their
+/// data.
+pub fn subscribe<T>(client: reqwest::Client, url: Url, name: &'static str) -> EventStream<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let events = events(client, url, name)
+        .map(|data| -> Result<T, Error> { Ok(serde_json::from_str(&data?)?) });
+
+    boxed(events)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn boxed<S, T>(stream: S) -> EventStream<T>
+where
+    S: futures::Stream<Item = Result<T, Error>> + Send + 'static,
+{
+    stream.boxed()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn boxed<S, T>(stream: S) -> EventStream<T>
+where
+    S: futures::Stream<Item = Result<T, Error>> + 'static,
+{
+    stream.boxed_local()
+}
+
+/// The data of all events with the given name.
+///
+/// The connection is kept open by Bobtimus, the stream ends if it is
+/// closed nonetheless.
+#[cfg(not(target_arch = "wasm32"))]
+fn events(
+    client: reqwest::Client,
+    url: Url,
+    name: &'static str,
+) -> impl futures::Stream<Item = Result<String, Error>> {
+    use futures::{future, stream, TryStreamExt};
+
+    let response = async move {
+        let response = client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+
+        crate::check(response).await
+    };
+
+    stream::once(response)
+        .map_ok(|response| {
+            let mut parser = Parser::default();
+
+            response
+                .bytes_stream()
+                .map_ok(move |chunk| stream::iter(parser.feed(&chunk).into_iter().map(Ok)))
+                .try_flatten()
+                .err_into::<Error>()
+        })
+        .try_flatten()
+        .try_filter(move |event| future::ready(event.name == name))
+        .map_ok(|event| event.data)
+}
+
+/// The data of all events with the given name.
+///
+/// The browser reconnects on its own if the connection is lost, every
+/// connection error is passed on as error in the stream.
+#[cfg(target_arch = "wasm32")]
+fn events(
+    _: reqwest::Client,
+    url: Url,
+    name: &'static str,
+) -> impl futures::Stream<Item = Result<String, Error>> {
+    use futures::{channel::mpsc, future, stream};
+    use wasm_bindgen::{closure::Closure, JsCast};
+    use web_sys::{EventSource, MessageEvent};
+
+    let source = match EventSource::new(url.as_str()) {
+        Ok(source) => source,
+        Err(e) => {
+            let error = Error::EventStream(format!("{:?}", e));
+            return stream::once(future::ready(Err(error))).left_stream();
+        }
+    };
+
+    let (sender, receiver) = mpsc::unbounded();
+
+    let on_event = Closure::wrap(Box::new({
+        let sender = sender.clone();
+        move |event: MessageEvent| {
+            if let Some(data) = event.data().as_string() {
+                let _ = sender.unbounded_send(Ok(data));
+            }
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    let on_error = Closure::wrap(Box::new(move |_: web_sys::Event| {
+        let error = Error::EventStream("connection to Bobtimus failed".to_owned());
+        let _ = sender.unbounded_send(Err(error));
+    }) as Box<dyn FnMut(web_sys::Event)>);
+
+    if let Err(e) = source.add_event_listener_with_callback(name, on_event.as_ref().unchecked_ref())
+    {
+        source.close();
+
+        let error = Error::EventStream(format!("{:?}", e));
+        return stream::once(future::ready(Err(error))).left_stream();
+    }
+    source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+    // The event source lives as long as the stream
+    let subscription = Subscription {
+        source,
+        _on_event: on_event,
+        _on_error: on_error,
+    };
+
+    receiver
+        .map(move |data| {
+            let _ = &subscription;
+            data
+        })
+        .right_stream()
+}
+
+#[cfg(target_arch = "wasm32")]
+struct Subscription {
+    source: web_sys::EventSource,
+    _on_event: wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _on_error: wasm_bindgen::closure::Closure<dyn FnMut(web_sys::Event)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.source.close();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    name: String,
+    data: String,
+}
+
+/// Splits a stream of bytes into events.
+#[derive(Debug, Default)]
+struct Parser {
+    buffer: String,
+}
+
+impl Parser {
+    /// Add a chunk of the stream, returning the events it completed.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Event> {
+        self.buffer
+            .push_str(&String::from_utf8_lossy(chunk).replace('\r', ""));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let block = self.buffer.drain(..end + 2).collect::<String>();
+
+            if let Some(event) = parse_event(&block) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+}
+
+/// Parse a single event, `None` if it does not carry any data, e.g. a
+/// keep-alive comment.
+fn parse_event(block: &str) -> Option<Event> {
+    let mut name = "message".to_owned();
+    let mut data = Vec::new();
+
+    for line in block.lines() {
+        let (field, value) = match line.find(':') {
+            Some(0) => continue,
+            Some(colon) => (&line[..colon], line[colon + 1..].trim_start_matches(' ')),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => name = value.to_owned(),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(Event {
+        name,
+        data: data.join("\n"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_split_across_chunks_are_parsed() {
+        let mut parser = Parser::default();
+
+        assert_eq!(parser.feed(b"id:1\nevent:rate\ndata:{\"ask\":"), vec![]);
+        assert_eq!(
+            parser.feed(b"20000.0,\"bid\":19000.0}\n\nevent:rate\n"),
+            vec![Event {
+                name: "rate".to_owned(),
+                data: "{\"ask\":20000.0,\"bid\":19000.0}".to_owned()
+            }]
+        );
+        assert_eq!(
+            parser.feed(b"data: {}\n\n"),
+            vec![Event {
+                name: "rate".to_owned(),
+                data: "{}".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn keep_alive_comments_are_skipped() {
+        let mut parser = Parser::default();
+
+        assert_eq!(parser.feed(b":\n\n"), vec![]);
+    }
+
+    #[test]
+    fn multiple_data_lines_are_joined() {
+        assert_eq!(
+            parse_event("data:a\ndata:b\n"),
+            Some(Event {
+                name: "message".to_owned(),
+                data: "a\nb".to_owned()
+            })
+        );
+    }
+}
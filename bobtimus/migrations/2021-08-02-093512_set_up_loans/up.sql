@@ -0,0 +1,12 @@
+CREATE TABLE loans
+(
+       id                TEXT NOT NULL PRIMARY KEY,
+       lender_state      TEXT NOT NULL,
+       principal_amount  BIGINT NOT NULL,
+       repayment_amount  BIGINT NOT NULL,
+       collateral_amount BIGINT NOT NULL,
+       liquidation_price BIGINT NOT NULL,
+       max_ltv           TEXT NOT NULL,
+       timelock          BIGINT NOT NULL,
+       start             BIGINT NOT NULL
+);
@@ -0,0 +1,17 @@
+CREATE TABLE loans_without_borrower
+(
+       id                TEXT NOT NULL PRIMARY KEY,
+       lender_state      TEXT NOT NULL,
+       principal_amount  BIGINT NOT NULL,
+       repayment_amount  BIGINT NOT NULL,
+       collateral_amount BIGINT NOT NULL,
+       liquidation_price BIGINT NOT NULL,
+       max_ltv           TEXT NOT NULL,
+       timelock          BIGINT NOT NULL,
+       start             BIGINT NOT NULL
+);
+INSERT INTO loans_without_borrower
+SELECT id, lender_state, principal_amount, repayment_amount, collateral_amount, liquidation_price, max_ltv, timelock, start
+FROM loans;
+DROP TABLE loans;
+ALTER TABLE loans_without_borrower RENAME TO loans;
@@ -0,0 +1,18 @@
+CREATE TABLE loans_without_collateral_spent
+(
+       id                TEXT NOT NULL PRIMARY KEY,
+       lender_state      TEXT NOT NULL,
+       principal_amount  BIGINT NOT NULL,
+       repayment_amount  BIGINT NOT NULL,
+       collateral_amount BIGINT NOT NULL,
+       liquidation_price BIGINT NOT NULL,
+       max_ltv           TEXT NOT NULL,
+       timelock          BIGINT NOT NULL,
+       start             BIGINT NOT NULL,
+       borrower_pk       TEXT
+);
+INSERT INTO loans_without_collateral_spent
+SELECT id, lender_state, principal_amount, repayment_amount, collateral_amount, liquidation_price, max_ltv, timelock, start, borrower_pk
+FROM loans;
+DROP TABLE loans;
+ALTER TABLE loans_without_collateral_spent RENAME TO loans;
@@ -0,0 +1 @@
+ALTER TABLE loans ADD COLUMN collateral_spent BOOLEAN NOT NULL DEFAULT 0;
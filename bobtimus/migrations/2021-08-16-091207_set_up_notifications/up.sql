@@ -0,0 +1,9 @@
+CREATE TABLE notifications
+(
+       id               INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+       loan_txid        TEXT NOT NULL,
+       kind             TEXT NOT NULL,
+       payload          TEXT NOT NULL,
+       created_at       BIGINT NOT NULL,
+       UNIQUE (loan_txid, kind)
+);
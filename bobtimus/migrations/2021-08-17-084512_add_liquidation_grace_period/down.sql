@@ -0,0 +1,11 @@
+CREATE TABLE liquidations_without_grace_period
+(
+       id               TEXT NOT NULL PRIMARY KEY,
+       tx_hex           TEXT NOT NULL,
+       locktime         BIGINT NOT NULL
+);
+INSERT INTO liquidations_without_grace_period
+SELECT id, tx_hex, locktime
+FROM liquidations;
+DROP TABLE liquidations;
+ALTER TABLE liquidations_without_grace_period RENAME TO liquidations;
@@ -0,0 +1 @@
+ALTER TABLE liquidations ADD COLUMN grace_period BIGINT NOT NULL DEFAULT 0;
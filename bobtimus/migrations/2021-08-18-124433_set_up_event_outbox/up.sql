@@ -0,0 +1,10 @@
+CREATE TABLE event_outbox
+(
+       id               INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+       endpoint         TEXT NOT NULL,
+       payload          TEXT NOT NULL,
+       created_at       BIGINT NOT NULL,
+       attempts         INTEGER NOT NULL DEFAULT 0,
+       next_attempt_at  BIGINT NOT NULL,
+       delivered_at     BIGINT
+);
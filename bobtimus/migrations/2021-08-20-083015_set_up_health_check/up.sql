@@ -0,0 +1,5 @@
+CREATE TABLE health_check
+(
+       id               INTEGER NOT NULL PRIMARY KEY,
+       checked_at       BIGINT NOT NULL
+);
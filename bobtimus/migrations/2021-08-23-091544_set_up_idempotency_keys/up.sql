@@ -0,0 +1,10 @@
+CREATE TABLE idempotency_keys
+(
+       client           TEXT NOT NULL,
+       key              TEXT NOT NULL,
+       endpoint         TEXT NOT NULL,
+       fingerprint      TEXT NOT NULL,
+       response         TEXT NOT NULL,
+       created_at       BIGINT NOT NULL,
+       PRIMARY KEY (client, key)
+);
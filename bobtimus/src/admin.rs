@@ -0,0 +1,176 @@
+use crate::{
+    database::queries, event::Event, loan::ExposureLimits, Bobtimus, LatestRate, LenderState,
+    LiquidBtc, LiquidUsdt, RepaymentState,
+};
+use anyhow::{Context, Result};
+use elements::{
+    bitcoin::Amount,
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    AssetId, OutPoint, Txid,
+};
+use serde::{Deserialize, Serialize};
+
+/// Which of our services are currently paused
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PauseState {
+    pub swaps: bool,
+    /// New loans. Borrowers can still repay open loans.
+    pub lending: bool,
+}
+
+/// Pause or resume services, leaving those which are not given as they are
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PauseRequest {
+    pub swaps: Option<bool>,
+    pub lending: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum ServicePaused {
+    #[error("Swaps are paused")]
+    Swaps,
+    #[error("Lending is paused")]
+    Lending,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Balance {
+    /// Name of the elementsd wallet holding the balance
+    pub wallet: String,
+    pub asset_id: AssetId,
+    #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+    pub balance: Amount,
+}
+
+/// A protocol with a borrower that was started, but not finalized yet
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Negotiation {
+    Loan {
+        loan_txid: Txid,
+        principal_amount: LiquidUsdt,
+        repayment_amount: LiquidUsdt,
+        collateral_amount: LiquidBtc,
+        timelock: u32,
+    },
+    Repayment {
+        loan_txid: Txid,
+        /// Our input refunding the interest which has not accrued yet
+        refund: Option<OutPoint>,
+        expires_at: u32,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnlockRequest {
+    /// The UTXOs to unlock, all locked UTXOs if not given
+    pub outpoints: Option<Vec<OutPoint>>,
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Our balances of the assets we trade, in each of our wallets.
+    pub async fn handle_balances_request(&self) -> Result<Vec<Balance>> {
+        let mut balances = Vec::new();
+        for (wallet, client) in self.distinct_wallets().await? {
+            for asset_id in [self.btc_asset_id, self.usdt_asset_id].iter() {
+                let balance = client.get_balance(*asset_id).await?;
+
+                balances.push(Balance {
+                    wallet: wallet.clone(),
+                    asset_id: *asset_id,
+                    balance,
+                });
+            }
+        }
+
+        Ok(balances)
+    }
+
+    /// The loans and repayments which are waiting to be finalized by
+    /// the borrower.
+    pub fn handle_negotiations_request(&self) -> Vec<Negotiation> {
+        let loans = self
+            .lender_states
+            .iter()
+            .map(|(loan_txid, LenderState { terms, .. })| Negotiation::Loan {
+                loan_txid: *loan_txid,
+                principal_amount: terms.principal_amount,
+                repayment_amount: terms.repayment_amount,
+                collateral_amount: terms.collateral_amount,
+                timelock: terms.timelock,
+            });
+        let repayments = self.repayment_states.iter().map(
+            |(loan_txid, RepaymentState { refund, expires_at })| Negotiation::Repayment {
+                loan_txid: *loan_txid,
+                refund: *refund,
+                expires_at: *expires_at,
+            },
+        );
+
+        loans.chain(repayments).collect()
+    }
+
+    pub fn handle_pause_request(&mut self, request: PauseRequest) -> PauseState {
+        if let Some(swaps) = request.swaps {
+            self.paused.swaps = swaps;
+        }
+        if let Some(lending) = request.lending {
+            self.paused.lending = lending;
+        }
+
+        tracing::info!(
+            "swaps are {}, lending is {}",
+            if self.paused.swaps {
+                "paused"
+            } else {
+                "active"
+            },
+            if self.paused.lending {
+                "paused"
+            } else {
+                "active"
+            }
+        );
+
+        self.paused
+    }
+
+    pub fn handle_limits_update(&mut self, limits: ExposureLimits) -> ExposureLimits {
+        tracing::info!("exposure limits changed to {:?}", limits);
+        self.exposure_limits = limits;
+
+        self.exposure_limits
+    }
+
+    /// Broadcast the liquidation transaction of a loan right away.
+    ///
+    /// elementsd rejects the transaction if the timelock of the loan has
+    /// not been reached yet, but the grace period is skipped.
+    pub async fn handle_liquidation_request(&mut self, loan_txid: Txid) -> Result<Txid> {
+        let liquidation_tx = self
+            .db
+            .do_in_transaction(|conn| queries::get_liquidation_tx(conn, loan_txid))
+            .await?;
+
+        let result = self.elementsd.send_raw_transaction(&liquidation_tx).await;
+
+        let event = match &result {
+            Ok(txid) => Event::LiquidationBroadcast { txid: *txid },
+            Err(e) => Event::LiquidationFailed {
+                txid: liquidation_tx.txid(),
+                error: format!("{:#}", e),
+            },
+        };
+        self.events.publish(event).await;
+
+        result.context("failed to broadcast liquidation transaction")
+    }
+
+    pub async fn handle_unlock_request(&self, request: UnlockRequest) -> Result<()> {
+        self.elementsd.unlock_utxos(request.outpoints).await
+    }
+}
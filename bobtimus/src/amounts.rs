@@ -4,6 +4,7 @@ use rust_decimal::{
     prelude::{FromPrimitive, ToPrimitive},
     Decimal, RoundingStrategy,
 };
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::{convert::TryFrom, fmt, fmt::Debug};
 
@@ -11,14 +12,24 @@ use std::{convert::TryFrom, fmt, fmt::Debug};
 ///
 /// - The `ask` represents the minimum price for which we are willing to sell 1 L-BTC.
 /// - The `bid` represents the maximum price we are willing pay for 1 L-BTC.
+///
+/// Serialized as [`bobtimus_client::Rate`], in dollars.
 #[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(into = "bobtimus_client::Rate")]
 pub struct Rate {
-    #[serde(serialize_with = "LiquidUsdt::serialize_to_nominal")]
     pub ask: LiquidUsdt,
-    #[serde(serialize_with = "LiquidUsdt::serialize_to_nominal")]
     pub bid: LiquidUsdt,
 }
 
+impl From<Rate> for bobtimus_client::Rate {
+    fn from(rate: Rate) -> Self {
+        Self {
+            ask: rate.ask.as_nominal(),
+            bid: rate.bid.as_nominal(),
+        }
+    }
+}
+
 impl Rate {
     pub const ZERO: Rate = Rate {
         ask: LiquidUsdt(Amount::ZERO),
@@ -88,15 +99,12 @@ impl LiquidUsdt {
         Ok(Self(amount))
     }
 
-    pub fn serialize_to_nominal<S>(amount: &LiquidUsdt, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let float = &amount.0.to_float_in(Denomination::Bitcoin);
+    /// The amount in dollars, rounded to cents.
+    pub fn as_nominal(&self) -> f64 {
+        let float = self.0.to_float_in(Denomination::Bitcoin);
         let rounded = format!("{:.2}", float);
-        let rounded: f64 = rounded.parse().expect("valid float");
 
-        serializer.serialize_f64(rounded)
+        rounded.parse().expect("valid float")
     }
 }
 
@@ -125,6 +133,20 @@ impl TryFrom<f64> for LiquidUsdt {
     }
 }
 
+impl JsonSchema for LiquidUsdt {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "LiquidUsdt".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        satoshi_schema(gen, "Amount of L-USDt in satoshi")
+    }
+}
+
 impl fmt::Display for LiquidUsdt {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt_value_in(f, Denomination::Bitcoin)?;
@@ -149,6 +171,28 @@ impl From<LiquidBtc> for Amount {
     }
 }
 
+impl JsonSchema for LiquidBtc {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "LiquidBtc".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        satoshi_schema(gen, "Amount of L-BTC in satoshi")
+    }
+}
+
+/// Amounts are serialized as integer number of satoshi.
+fn satoshi_schema(gen: &mut SchemaGenerator, description: &str) -> Schema {
+    let mut schema = u64::json_schema(gen).into_object();
+    schema.metadata().description = Some(description.to_owned());
+
+    schema.into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,6 +235,25 @@ mod tests {
         assert_eq!(serialized, "{\"ask\":19313.52,\"bid\":19213.53}")
     }
 
+    #[test]
+    fn rate_is_understood_by_client() {
+        let rate = Rate {
+            ask: LiquidUsdt::try_from(19_313.52).unwrap(),
+            bid: LiquidUsdt::try_from(19_213.53).unwrap(),
+        };
+        let serialized = serde_json::to_string(&rate).unwrap();
+
+        let client_rate = serde_json::from_str::<bobtimus_client::Rate>(&serialized).unwrap();
+
+        assert_eq!(
+            client_rate,
+            bobtimus_client::Rate {
+                ask: 19_313.52,
+                bid: 19_213.53
+            }
+        )
+    }
+
     #[test]
     fn test_rounding_liquid_usdt() {
         let amount = LiquidUsdt::try_from(0.0000000123).unwrap();
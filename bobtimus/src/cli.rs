@@ -1,17 +1,38 @@
-use crate::USDT_ASSET_ID;
+use crate::{
+    loan::ExposureLimits,
+    rate_limit::{Quota, RequestLimits},
+    rpc_auth::RpcAuth,
+    swap_batch::BatchConfig,
+    swap_watcher::WatcherConfig,
+    wallets::WalletNames,
+    LiquidUsdt, USDT_ASSET_ID,
+};
 use anyhow::{bail, Context, Result};
 use directories::ProjectDirs;
-use elements::AssetId;
+use elements::{
+    bitcoin::{Amount, Denomination},
+    AssetId,
+};
 use reqwest::Url;
-use std::{net::SocketAddr, path::PathBuf};
+use rust_decimal::Decimal;
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
 use structopt::StructOpt;
 
 #[derive(structopt::StructOpt, Debug)]
 #[structopt(name = "bobtimus", about = "Auto-trader for L-BTC/L-USDt")]
 pub enum Command {
     Start {
-        #[structopt(default_value = "http://127.0.0.1:7042", long = "elementsd")]
-        elementsd_url: Url,
+        #[structopt(flatten)]
+        elementsd: ElementsdArgs,
+        /// elementsd wallet for swaps, the default wallet if not given
+        #[structopt(long)]
+        swap_wallet: Option<String>,
+        /// elementsd wallet the principal of loans is taken from, the default wallet if not given
+        #[structopt(long)]
+        lending_wallet: Option<String>,
+        /// elementsd wallet receiving repayments and liquidated collateral, the default wallet if not given
+        #[structopt(long)]
+        liquidation_wallet: Option<String>,
         #[structopt(default_value = USDT_ASSET_ID, long = "usdt")]
         usdt_asset_id: AssetId,
         #[structopt(long, parse(from_os_str))]
@@ -25,13 +46,207 @@ pub enum Command {
         tls_certificate: Option<PathBuf>,
         #[structopt(long, parse(from_os_str))]
         tls_private_key: Option<PathBuf>,
+
+        /// Maximum principal of all open loans combined, in L-USDt
+        #[structopt(long, parse(try_from_str = LiquidUsdt::from_str_in_dollar))]
+        max_total_principal: Option<LiquidUsdt>,
+        /// Maximum principal of all open loans of a single borrower, in L-USDt
+        ///
+        /// Borrowers are told apart by the public key of their loan
+        /// request, so a borrower who uses a fresh key for every loan is
+        /// not capped.
+        #[structopt(long, parse(try_from_str = LiquidUsdt::from_str_in_dollar))]
+        max_borrower_principal: Option<LiquidUsdt>,
+        /// Amount of L-USDt which is never lent out
+        #[structopt(long, default_value = "0", parse(try_from_str = LiquidUsdt::from_str_in_dollar))]
+        usdt_reserve: LiquidUsdt,
+        /// Warn borrowers once the LTV of their loan is within this distance of the maximum LTV
+        #[structopt(long, default_value = "0.05")]
+        margin_call_distance: Decimal,
+        /// Seconds after the latest rate update before we report not to be ready
+        #[structopt(long, default_value = "120")]
+        max_rate_age_secs: u64,
+        /// Hours after maturity before the loans opened from now on are liquidated, announced in the borrower notifications
+        #[structopt(long, default_value = "24")]
+        grace_period_hours: u64,
+
+        /// URL to which events about swaps, loans, borrower notifications, liquidations and inventory are posted, can be given multiple times
+        #[structopt(long = "event-webhook")]
+        event_webhooks: Vec<Url>,
+        /// Secret with which the events posted to the webhooks are signed
+        #[structopt(long, env = "BOBTIMUS_WEBHOOK_SECRET", hide_env_values = true)]
+        webhook_secret: Option<String>,
+        /// Raise an event once our L-BTC balance falls below this amount, in L-BTC
+        #[structopt(long, parse(try_from_str = parse_btc))]
+        btc_inventory_threshold: Option<Amount>,
+        /// Raise an event once our L-USDt balance falls below this amount, in L-USDt
+        #[structopt(long, parse(try_from_str = LiquidUsdt::from_str_in_dollar))]
+        usdt_inventory_threshold: Option<LiquidUsdt>,
+
+        /// Serve the admin API on a separate address, which has to be a loopback address unless an admin token is given
+        #[structopt(long)]
+        admin_http: Option<SocketAddr>,
+        /// Bearer token required for the admin API, which is served alongside the public API if no separate address is given
+        #[structopt(long, env = "BOBTIMUS_ADMIN_TOKEN", hide_env_values = true)]
+        admin_token: Option<String>,
+
+        /// Serve the Prometheus metrics on a separate address
+        #[structopt(long)]
+        metrics_http: Option<SocketAddr>,
+        /// Bearer token required for the metrics, which are served alongside the public API if no separate address is given
+        #[structopt(long, env = "BOBTIMUS_METRICS_TOKEN", hide_env_values = true)]
+        metrics_token: Option<String>,
+
+        /// Swap and loan requests per minute of a single client, 0 disables the limit
+        #[structopt(long, default_value = "10")]
+        rate_limit_per_ip: u32,
+        /// Swap and loan requests a single client may make at once
+        #[structopt(long, default_value = "5")]
+        rate_limit_per_ip_burst: u32,
+        /// Swap and loan requests per minute of all clients combined, 0 disables the limit
+        #[structopt(long, default_value = "120")]
+        rate_limit_global: u32,
+        /// Swap and loan requests all clients combined may make at once
+        #[structopt(long, default_value = "30")]
+        rate_limit_global_burst: u32,
+        /// Identify clients by the last address in the X-Forwarded-For header, only safe behind a proxy which appends to it
+        #[structopt(long)]
+        trust_forwarded_for: bool,
+        /// Loans and repayments which may be offered to borrowers without having been finalized
+        #[structopt(long, default_value = "100")]
+        max_pending_negotiations: usize,
+        /// Maximum size of request bodies in bytes
+        #[structopt(long, default_value = "65536")]
+        max_body_bytes: u64,
+        /// Hours for which the response to a swap or loan request is returned again for the same idempotency key
+        #[structopt(long, default_value = "24")]
+        idempotency_key_expiry_hours: u64,
+        /// Confirmations the inputs of takers and borrowers need before we build a transaction on them
+        #[structopt(long, default_value = "1")]
+        min_input_confirmations: u32,
+        /// Seconds a taker has to broadcast a swap transaction before our inputs are handed out again
+        #[structopt(long, default_value = "120")]
+        swap_broadcast_timeout_secs: u64,
+        /// Swaps a taker may not broadcast within the lapse window before being throttled
+        #[structopt(long, default_value = "3")]
+        max_swap_lapses: usize,
+        #[structopt(long, default_value = "24")]
+        swap_lapse_window_hours: u64,
+        /// Seconds for which swap requests are collected into a batch, enables swap batching
+        #[structopt(long)]
+        swap_batch_window_secs: Option<u64>,
+        /// Seconds the takers of a swap batch have to sign it before it is abandoned
+        #[structopt(long, default_value = "60")]
+        swap_batch_signing_timeout_secs: u64,
+        /// Takers whose swaps are settled in a single batch transaction at most
+        #[structopt(long, default_value = "20")]
+        max_swap_batch_takers: usize,
     },
     LiquidateLoans {
-        #[structopt(default_value = "http://127.0.0.1:7042", long = "elementsd")]
-        elementsd_url: Url,
+        #[structopt(flatten)]
+        elementsd: ElementsdArgs,
         #[structopt(long, parse(from_os_str))]
         db_file: Option<PathBuf>,
+        /// URL to which events about liquidations are posted, can be given multiple times
+        #[structopt(long = "event-webhook")]
+        event_webhooks: Vec<Url>,
+        /// Secret with which the events posted to the webhooks are signed
+        #[structopt(long, env = "BOBTIMUS_WEBHOOK_SECRET", hide_env_values = true)]
+        webhook_secret: Option<String>,
     },
+    /// Move funds between two elementsd wallets, e.g. from the swap wallet to the lending wallet
+    Transfer {
+        #[structopt(flatten)]
+        elementsd: ElementsdArgs,
+        #[structopt(default_value = USDT_ASSET_ID, long = "usdt")]
+        usdt_asset_id: AssetId,
+        /// Wallet to send from, the default wallet if not given
+        #[structopt(long)]
+        from_wallet: Option<String>,
+        /// Wallet to send to, the default wallet if not given
+        #[structopt(long)]
+        to_wallet: Option<String>,
+        /// Amount of L-BTC to transfer
+        #[structopt(long, parse(try_from_str = parse_btc))]
+        lbtc: Option<Amount>,
+        /// Amount of L-USDt to transfer
+        #[structopt(long, parse(try_from_str = LiquidUsdt::from_str_in_dollar))]
+        lusdt: Option<LiquidUsdt>,
+    },
+}
+
+/// Environment variable holding the RPC password of elementsd, unless
+/// it is read from a file.
+const ELEMENTSD_PASSWORD_ENV: &str = "BOBTIMUS_ELEMENTSD_PASSWORD";
+
+#[derive(structopt::StructOpt, Debug)]
+pub struct ElementsdArgs {
+    #[structopt(default_value = "http://127.0.0.1:7042", long = "elementsd")]
+    elementsd_url: Url,
+    /// Cookie file of elementsd to authenticate with, read again whenever elementsd rotates it
+    #[structopt(long, parse(from_os_str))]
+    elementsd_cookie: Option<PathBuf>,
+    /// RPC user of elementsd, whose password is read from --elementsd-password-file or BOBTIMUS_ELEMENTSD_PASSWORD
+    #[structopt(long)]
+    elementsd_user: Option<String>,
+    /// File holding the RPC password of elementsd
+    #[structopt(long, parse(from_os_str))]
+    elementsd_password_file: Option<PathBuf>,
+}
+
+/// Where elementsd is and how we authenticate with it
+pub struct Elementsd {
+    pub url: Url,
+    pub auth: RpcAuth,
+}
+
+impl ElementsdArgs {
+    fn into_config(self) -> Result<Elementsd> {
+        if self.elementsd_user.is_none() && self.elementsd_password_file.is_some() {
+            bail!("The elementsd password file has to be given with the elementsd user")
+        }
+
+        let auth = match (self.elementsd_cookie, self.elementsd_user) {
+            (None, None) => RpcAuth::None,
+            (Some(cookie), None) => RpcAuth::Cookie(cookie),
+            (None, Some(user)) => RpcAuth::UserPass {
+                user,
+                password: read_elementsd_password(self.elementsd_password_file)?,
+            },
+            (Some(_), Some(_)) => {
+                bail!("elementsd has to be authenticated with either its cookie or a user")
+            }
+        };
+
+        let url = self.elementsd_url;
+        let url_has_credentials = !url.username().is_empty() || url.password().is_some();
+        if url_has_credentials && !matches!(auth, RpcAuth::None) {
+            bail!("Credentials for elementsd have to be given either in the URL or as options")
+        }
+
+        Ok(Elementsd { url, auth })
+    }
+}
+
+fn read_elementsd_password(file: Option<PathBuf>) -> Result<String> {
+    let password = match file {
+        Some(file) => fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read elementsd password from {}", file.display()))?
+            .trim_end()
+            .to_owned(),
+        None => std::env::var(ELEMENTSD_PASSWORD_ENV).with_context(|| {
+            format!(
+                "The elementsd user needs a password file or {}",
+                ELEMENTSD_PASSWORD_ENV
+            )
+        })?,
+    };
+
+    if password.is_empty() {
+        bail!("The elementsd password must not be empty")
+    }
+
+    Ok(password)
 }
 
 pub struct Https {
@@ -40,17 +255,56 @@ pub struct Https {
     pub tls_private_key: PathBuf,
 }
 
+/// An amount to transfer between our wallets
+pub enum TransferAmount {
+    Btc(Amount),
+    Usdt(LiquidUsdt),
+}
+
+/// Balances below which we raise an event
+pub struct InventoryThresholds {
+    pub btc: Option<Amount>,
+    pub usdt: Option<LiquidUsdt>,
+}
+
 pub enum Config {
     Start {
-        elementsd_url: Url,
+        elementsd: Elementsd,
+        wallets: WalletNames,
         usdt_asset_id: AssetId,
         db_file: PathBuf,
         http: Option<SocketAddr>,
         https: Option<Https>,
+        exposure_limits: ExposureLimits,
+        margin_call_distance: Decimal,
+        max_rate_age: Duration,
+        grace_period: Duration,
+        event_webhooks: Vec<Url>,
+        webhook_secret: Option<Vec<u8>>,
+        inventory_thresholds: InventoryThresholds,
+        admin_http: Option<SocketAddr>,
+        admin_token: Option<String>,
+        metrics_http: Option<SocketAddr>,
+        metrics_token: Option<String>,
+        request_limits: RequestLimits,
+        max_pending_negotiations: usize,
+        idempotency_key_expiry: Duration,
+        min_input_confirmations: u32,
+        swap_watcher: WatcherConfig,
+        swap_batching: Option<BatchConfig>,
     },
     LiquidateLoans {
-        elementsd_url: Url,
+        elementsd: Elementsd,
         db_file: PathBuf,
+        event_webhooks: Vec<Url>,
+        webhook_secret: Option<Vec<u8>>,
+    },
+    Transfer {
+        elementsd: Elementsd,
+        usdt_asset_id: AssetId,
+        from_wallet: Option<String>,
+        to_wallet: Option<String>,
+        amount: TransferAmount,
     },
 }
 
@@ -58,13 +312,45 @@ impl Config {
     pub fn parse() -> Result<Self> {
         let config = match Command::from_args() {
             Command::Start {
-                elementsd_url,
+                elementsd,
+                swap_wallet,
+                lending_wallet,
+                liquidation_wallet,
                 listen_http,
                 listen_https,
                 usdt_asset_id,
                 db_file,
                 tls_certificate,
                 tls_private_key,
+                max_total_principal,
+                max_borrower_principal,
+                usdt_reserve,
+                margin_call_distance,
+                max_rate_age_secs,
+                grace_period_hours,
+                event_webhooks,
+                webhook_secret,
+                btc_inventory_threshold,
+                usdt_inventory_threshold,
+                admin_http,
+                admin_token,
+                metrics_http,
+                metrics_token,
+                rate_limit_per_ip,
+                rate_limit_per_ip_burst,
+                rate_limit_global,
+                rate_limit_global_burst,
+                trust_forwarded_for,
+                max_pending_negotiations,
+                max_body_bytes,
+                idempotency_key_expiry_hours,
+                min_input_confirmations,
+                swap_broadcast_timeout_secs,
+                max_swap_lapses,
+                swap_lapse_window_hours,
+                swap_batch_window_secs,
+                swap_batch_signing_timeout_secs,
+                max_swap_batch_takers,
             } => {
                 if listen_http.is_none() && listen_https.is_none() {
                     bail!("Neither listening on HTTP nor HTTPS were configured, this server is pointless");
@@ -84,27 +370,141 @@ impl Config {
                     ),
                 };
 
+                if admin_token.as_deref() == Some("") {
+                    bail!("The admin token must not be empty")
+                }
+
+                if metrics_token.as_deref() == Some("") {
+                    bail!("The metrics token must not be empty")
+                }
+
+                if let Some(admin_http) = admin_http {
+                    if admin_token.is_none() && !admin_http.ip().is_loopback() {
+                        bail!("The admin API can only be served without a token on a loopback address")
+                    }
+                }
+
+                if !event_webhooks.is_empty() && webhook_secret.is_none() {
+                    bail!("Event webhooks have to be configured with a secret to sign the events")
+                }
+
+                if swap_batch_window_secs == Some(0) || max_swap_batch_takers == 0 {
+                    bail!("Swap batches have to be collected for some time and hold at least one taker")
+                }
+
                 Config::Start {
-                    elementsd_url,
+                    elementsd: elementsd.into_config()?,
+                    wallets: WalletNames {
+                        swap: swap_wallet,
+                        lending: lending_wallet,
+                        liquidation: liquidation_wallet,
+                    },
                     http: listen_http,
                     usdt_asset_id,
                     db_file: resolve_db_file(db_file)?,
                     https,
+                    exposure_limits: ExposureLimits {
+                        max_total_principal,
+                        max_borrower_principal,
+                        usdt_reserve,
+                    },
+                    margin_call_distance,
+                    max_rate_age: Duration::from_secs(max_rate_age_secs),
+                    grace_period: hours(grace_period_hours),
+                    event_webhooks,
+                    webhook_secret: webhook_secret.map(String::into_bytes),
+                    inventory_thresholds: InventoryThresholds {
+                        btc: btc_inventory_threshold,
+                        usdt: usdt_inventory_threshold,
+                    },
+                    admin_http,
+                    admin_token,
+                    metrics_http,
+                    metrics_token,
+                    request_limits: RequestLimits {
+                        per_ip: Quota {
+                            per_minute: rate_limit_per_ip,
+                            burst: rate_limit_per_ip_burst,
+                        },
+                        global: Quota {
+                            per_minute: rate_limit_global,
+                            burst: rate_limit_global_burst,
+                        },
+                        trust_forwarded_for,
+                        max_body_bytes,
+                    },
+                    max_pending_negotiations,
+                    idempotency_key_expiry: hours(idempotency_key_expiry_hours),
+                    min_input_confirmations,
+                    swap_watcher: WatcherConfig {
+                        broadcast_timeout: Duration::from_secs(swap_broadcast_timeout_secs),
+                        max_lapses: max_swap_lapses,
+                        lapse_window: hours(swap_lapse_window_hours),
+                    },
+                    swap_batching: swap_batch_window_secs.map(|window| BatchConfig {
+                        window: Duration::from_secs(window),
+                        signing_timeout: Duration::from_secs(swap_batch_signing_timeout_secs),
+                        max_takers: max_swap_batch_takers,
+                    }),
                 }
             }
             Command::LiquidateLoans {
-                elementsd_url,
+                elementsd,
                 db_file,
-            } => Config::LiquidateLoans {
-                elementsd_url,
-                db_file: resolve_db_file(db_file)?,
-            },
+                event_webhooks,
+                webhook_secret,
+            } => {
+                if !event_webhooks.is_empty() && webhook_secret.is_none() {
+                    bail!("Event webhooks have to be configured with a secret to sign the events")
+                }
+
+                Config::LiquidateLoans {
+                    elementsd: elementsd.into_config()?,
+                    db_file: resolve_db_file(db_file)?,
+                    event_webhooks,
+                    webhook_secret: webhook_secret.map(String::into_bytes),
+                }
+            }
+            Command::Transfer {
+                elementsd,
+                usdt_asset_id,
+                from_wallet,
+                to_wallet,
+                lbtc,
+                lusdt,
+            } => {
+                if from_wallet == to_wallet {
+                    bail!("Funds have to be transferred to another wallet")
+                }
+
+                let amount = match (lbtc, lusdt) {
+                    (Some(btc), None) => TransferAmount::Btc(btc),
+                    (None, Some(usdt)) => TransferAmount::Usdt(usdt),
+                    _ => bail!("Either an amount of L-BTC or of L-USDt has to be transferred"),
+                };
+
+                Config::Transfer {
+                    elementsd: elementsd.into_config()?,
+                    usdt_asset_id,
+                    from_wallet,
+                    to_wallet,
+                    amount,
+                }
+            }
         };
 
         Ok(config)
     }
 }
 
+fn parse_btc(s: &str) -> Result<Amount> {
+    Amount::from_str_in(s, Denomination::Bitcoin).context("failed to parse L-BTC amount")
+}
+
+fn hours(hours: u64) -> Duration {
+    Duration::from_secs(hours * 60 * 60)
+}
+
 fn resolve_db_file(db_file: Option<PathBuf>) -> Result<PathBuf, anyhow::Error> {
     Ok(match db_file {
         None => {
@@ -1,11 +1,17 @@
-use std::{convert::TryFrom, path::Path, sync::Arc};
+use std::{convert::TryFrom, path::Path, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use baru::loan::Lender1;
 use diesel::{prelude::*, Connection, SqliteConnection};
 use elements::{encode::serialize_hex, Transaction, Txid};
+use reqwest::Url;
 use tokio::sync::Mutex;
 
-use crate::schema::liquidations;
+use crate::{
+    loan::{LoanTerms, UnknownLoan},
+    notification::Notification,
+    schema::{event_outbox, health_check, idempotency_keys, liquidations, loans, notifications},
+};
 
 embed_migrations!("./migrations");
 
@@ -72,19 +78,30 @@ pub struct LiquidationForm {
     id: String,
     tx_hex: String,
     locktime: i64,
+    grace_period: i64,
 }
 
 impl LiquidationForm {
-    pub fn new(loan_txid: Txid, liquidation_tx: &Transaction, locktime: u32) -> Self {
+    /// The liquidation transaction of a loan, which is broadcast
+    /// `grace_period` after the `locktime` was reached.
+    pub fn new(
+        loan_txid: Txid,
+        liquidation_tx: &Transaction,
+        locktime: u32,
+        grace_period: Duration,
+    ) -> Result<Self> {
         let id = loan_txid.to_string();
         let tx_hex = serialize_hex(liquidation_tx);
         let locktime = i64::try_from(locktime).expect("every u32 fits into a i64");
+        let grace_period = i64::try_from(grace_period.as_secs())
+            .context("grace period does not fit into a i64")?;
 
-        Self {
+        Ok(Self {
             id,
             tx_hex,
             locktime,
-        }
+            grace_period,
+        })
     }
 
     pub fn insert(self, conn: &SqliteConnection) -> Result<()> {
@@ -96,10 +113,168 @@ impl LiquidationForm {
     }
 }
 
+#[derive(Insertable)]
+#[table_name = "loans"]
+pub struct LoanForm {
+    id: String,
+    lender_state: String,
+    principal_amount: i64,
+    repayment_amount: i64,
+    collateral_amount: i64,
+    liquidation_price: i64,
+    max_ltv: String,
+    timelock: i64,
+    start: i64,
+    borrower_pk: Option<String>,
+}
+
+impl LoanForm {
+    pub fn new(loan_txid: Txid, lender: &Lender1, terms: LoanTerms) -> Result<Self> {
+        let id = loan_txid.to_string();
+        let lender_state =
+            serde_json::to_string(lender).context("failed to serialize lender state")?;
+
+        Ok(Self {
+            id,
+            lender_state,
+            principal_amount: to_i64(terms.principal_amount.as_satodollar())?,
+            repayment_amount: to_i64(terms.repayment_amount.as_satodollar())?,
+            collateral_amount: to_i64(terms.collateral_amount.0.as_sat())?,
+            liquidation_price: to_i64(terms.liquidation_price.as_satodollar())?,
+            max_ltv: terms.max_ltv.to_string(),
+            timelock: i64::from(terms.timelock),
+            start: i64::from(terms.start),
+            borrower_pk: terms.borrower_pk.map(|pk| pk.to_string()),
+        })
+    }
+
+    pub fn insert(self, conn: &SqliteConnection) -> Result<()> {
+        diesel::insert_into(loans::table)
+            .values(self)
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "notifications"]
+pub struct NotificationForm {
+    loan_txid: String,
+    kind: String,
+    payload: String,
+    created_at: i64,
+}
+
+impl NotificationForm {
+    pub fn new(notification: &Notification, created_at: u32) -> Result<Self> {
+        let payload =
+            serde_json::to_string(notification).context("failed to serialize notification")?;
+
+        Ok(Self {
+            loan_txid: notification.loan_txid.to_string(),
+            kind: notification.kind.as_str().to_string(),
+            payload,
+            created_at: i64::from(created_at),
+        })
+    }
+
+    /// Record that the notification was sent, unless the same kind of
+    /// notification has already been sent for the loan.
+    ///
+    /// Returns whether the notification was recorded, i.e. whether it
+    /// still has to be sent.
+    pub fn insert(self, conn: &SqliteConnection) -> Result<bool> {
+        let inserted = diesel::insert_or_ignore_into(notifications::table)
+            .values(self)
+            .execute(conn)?;
+
+        Ok(inserted > 0)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "event_outbox"]
+pub struct EventOutboxForm {
+    endpoint: String,
+    payload: String,
+    created_at: i64,
+    next_attempt_at: i64,
+}
+
+impl EventOutboxForm {
+    pub fn new(endpoint: &Url, payload: &str, created_at: u32) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            payload: payload.to_string(),
+            created_at: i64::from(created_at),
+            next_attempt_at: i64::from(created_at),
+        }
+    }
+
+    pub fn insert(self, conn: &SqliteConnection) -> Result<()> {
+        diesel::insert_into(event_outbox::table)
+            .values(self)
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "idempotency_keys"]
+pub struct IdempotencyKeyForm {
+    client: String,
+    key: String,
+    endpoint: String,
+    fingerprint: String,
+    response: String,
+    created_at: i64,
+}
+
+impl IdempotencyKeyForm {
+    pub fn new(
+        client: &str,
+        key: &str,
+        endpoint: &str,
+        fingerprint: &str,
+        response: &str,
+        created_at: u32,
+    ) -> Self {
+        Self {
+            client: client.to_string(),
+            key: key.to_string(),
+            endpoint: endpoint.to_string(),
+            fingerprint: fingerprint.to_string(),
+            response: response.to_string(),
+            created_at: i64::from(created_at),
+        }
+    }
+
+    pub fn insert(self, conn: &SqliteConnection) -> Result<()> {
+        diesel::insert_into(idempotency_keys::table)
+            .values(self)
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+fn to_i64(amount: u64) -> Result<i64> {
+    i64::try_from(amount).context("amount does not fit into a i64")
+}
+
 pub mod queries {
     use super::*;
 
-    use elements::encode::deserialize;
+    use crate::{LiquidBtc, LiquidUsdt};
+    use elements::{
+        bitcoin::{Amount, PublicKey},
+        encode::deserialize,
+        OutPoint,
+    };
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
 
     #[derive(Associations, Clone, Debug, Queryable, PartialEq)]
     #[table_name = "liquidations"]
@@ -107,14 +282,19 @@ pub mod queries {
         id: String,
         tx_hex: String,
         locktime: i64,
+        grace_period: i64,
     }
 
+    /// Get the liquidation transactions whose locktime was reached at
+    /// least their grace period before `secs_since_epoch`.
     pub fn get_publishable_liquidations_txs(
         conn: &SqliteConnection,
         secs_since_epoch: u64,
     ) -> Result<Vec<Transaction>> {
         let txs = liquidations::table
-            .filter(liquidations::locktime.le(secs_since_epoch as i64))
+            .filter(
+                (liquidations::locktime + liquidations::grace_period).le(secs_since_epoch as i64),
+            )
             .get_results::<Liquidation>(conn)?;
 
         let txs = txs
@@ -124,12 +304,321 @@ pub mod queries {
 
         Ok(txs)
     }
+
+    pub fn get_liquidation_tx(conn: &SqliteConnection, loan_txid: Txid) -> Result<Transaction> {
+        let liquidation = liquidations::table
+            .filter(liquidations::id.eq(loan_txid.to_string()))
+            .first::<Liquidation>(conn)
+            .optional()?
+            .ok_or(UnknownLoan(loan_txid))?;
+
+        let tx = deserialize(&hex::decode(liquidation.tx_hex)?)?;
+
+        Ok(tx)
+    }
+
+    #[derive(Clone, Debug, Queryable, PartialEq)]
+    struct Loan {
+        id: String,
+        lender_state: String,
+        principal_amount: i64,
+        repayment_amount: i64,
+        collateral_amount: i64,
+        liquidation_price: i64,
+        max_ltv: String,
+        timelock: i64,
+        start: i64,
+        borrower_pk: Option<String>,
+        collateral_spent: bool,
+    }
+
+    impl Loan {
+        fn into_lender_and_terms(self) -> Result<(Lender1, LoanTerms)> {
+            let lender = serde_json::from_str(&self.lender_state)
+                .context("failed to deserialize lender state")?;
+
+            let terms = LoanTerms {
+                principal_amount: LiquidUsdt::from_satodollar(u64::try_from(
+                    self.principal_amount,
+                )?),
+                repayment_amount: LiquidUsdt::from_satodollar(u64::try_from(
+                    self.repayment_amount,
+                )?),
+                collateral_amount: LiquidBtc::from(Amount::from_sat(u64::try_from(
+                    self.collateral_amount,
+                )?)),
+                liquidation_price: LiquidUsdt::from_satodollar(u64::try_from(
+                    self.liquidation_price,
+                )?),
+                max_ltv: Decimal::from_str(&self.max_ltv)?,
+                start: u32::try_from(self.start)?,
+                timelock: u32::try_from(self.timelock)?,
+                borrower_pk: self
+                    .borrower_pk
+                    .as_deref()
+                    .map(PublicKey::from_str)
+                    .transpose()?,
+            };
+
+            Ok((lender, terms))
+        }
+    }
+
+    pub fn get_loan(conn: &SqliteConnection, loan_txid: Txid) -> Result<(Lender1, LoanTerms)> {
+        let loan = loans::table
+            .filter(loans::id.eq(loan_txid.to_string()))
+            .first::<Loan>(conn)
+            .optional()?
+            .ok_or(UnknownLoan(loan_txid))?;
+
+        loan.into_lender_and_terms()
+    }
+
+    /// Get the terms of all loans whose timelock is reached by `to`
+    /// and whose collateral has not been spent, together with the Unix
+    /// timestamp after which they are liquidated.
+    pub fn get_open_loans_maturing_by(
+        conn: &SqliteConnection,
+        to: u32,
+    ) -> Result<Vec<(Txid, LoanTerms, u32)>> {
+        let loans = loans::table
+            .filter(loans::collateral_spent.eq(false))
+            .filter(loans::timelock.le(i64::from(to)))
+            .get_results::<Loan>(conn)?;
+
+        loans
+            .into_iter()
+            .map(|loan| {
+                let liquidation = liquidations::table
+                    .filter(liquidations::id.eq(&loan.id))
+                    .first::<Liquidation>(conn)?;
+                let liquidation_at = u32::try_from(liquidation.locktime + liquidation.grace_period)
+                    .context("liquidation time does not fit into a u32")?;
+
+                let loan_txid = Txid::from_str(&loan.id)?;
+                let (_, terms) = loan.into_lender_and_terms()?;
+
+                Ok((loan_txid, terms, liquidation_at))
+            })
+            .collect()
+    }
+
+    /// Record that we were able to write to the database at `now`.
+    pub fn record_health_check(conn: &SqliteConnection, now: u32) -> Result<()> {
+        diesel::replace_into(health_check::table)
+            .values((
+                health_check::id.eq(1),
+                health_check::checked_at.eq(i64::from(now)),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Count the loans whose collateral has not been spent.
+    pub fn count_open_loans(conn: &SqliteConnection) -> Result<i64> {
+        let count = loans::table
+            .filter(loans::collateral_spent.eq(false))
+            .count()
+            .get_result(conn)?;
+
+        Ok(count)
+    }
+
+    /// Sum up the principal of all loans whose collateral has not been
+    /// spent, optionally only those of the given borrower.
+    ///
+    /// Loans past their timelock count until they are liquidated, and
+    /// loans which the borrower repaid stop counting as soon as the
+    /// repayment transaction is seen, see [`crate::loan_watcher`].
+    pub fn get_outstanding_principal(
+        conn: &SqliteConnection,
+        borrower_pk: Option<PublicKey>,
+    ) -> Result<LiquidUsdt> {
+        let mut query = loans::table
+            .select(loans::principal_amount)
+            .filter(loans::collateral_spent.eq(false))
+            .into_boxed();
+        if let Some(borrower_pk) = borrower_pk {
+            query = query.filter(loans::borrower_pk.eq(borrower_pk.to_string()));
+        }
+
+        let principal =
+            query
+                .load::<i64>(conn)?
+                .into_iter()
+                .try_fold(0u64, |sum, principal_amount| {
+                    sum.checked_add(u64::try_from(principal_amount)?)
+                        .context("addition overflow")
+                })?;
+
+        Ok(LiquidUsdt::from_satodollar(principal))
+    }
+
+    /// Get the collateral output of every loan, as spent by its
+    /// liquidation transaction.
+    pub fn get_loan_collaterals(conn: &SqliteConnection) -> Result<Vec<(Txid, OutPoint)>> {
+        let liquidations = liquidations::table.get_results::<Liquidation>(conn)?;
+
+        liquidations
+            .into_iter()
+            .map(|liquidation| {
+                let loan_txid = Txid::from_str(&liquidation.id)?;
+                let liquidation_tx = deserialize::<Transaction>(&hex::decode(liquidation.tx_hex)?)?;
+                let collateral = collateral_outpoint(loan_txid, &liquidation_tx)?;
+
+                Ok((loan_txid, collateral))
+            })
+            .collect()
+    }
+
+    /// Get the collateral output of a loan, as spent by its liquidation
+    /// transaction.
+    pub fn get_loan_collateral(conn: &SqliteConnection, loan_txid: Txid) -> Result<OutPoint> {
+        let liquidation_tx = get_liquidation_tx(conn, loan_txid)?;
+
+        collateral_outpoint(loan_txid, &liquidation_tx)
+    }
+
+    fn collateral_outpoint(loan_txid: Txid, liquidation_tx: &Transaction) -> Result<OutPoint> {
+        liquidation_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .find(|outpoint| outpoint.txid == loan_txid)
+            .with_context(|| {
+                format!(
+                    "liquidation transaction of loan {} does not spend its collateral",
+                    loan_txid
+                )
+            })
+    }
+
+    /// Record whether the collateral of a loan is spent.
+    pub fn set_collateral_spent(
+        conn: &SqliteConnection,
+        loan_txid: Txid,
+        collateral_spent: bool,
+    ) -> Result<()> {
+        diesel::update(loans::table.filter(loans::id.eq(loan_txid.to_string())))
+            .set(loans::collateral_spent.eq(collateral_spent))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    #[derive(Clone, Debug, Queryable, PartialEq)]
+    pub struct WebhookDelivery {
+        pub id: i32,
+        pub endpoint: String,
+        pub payload: String,
+        pub attempts: i32,
+    }
+
+    /// Get all events in the outbox which are due to be delivered at
+    /// `now` and have been attempted less than `max_attempts` times.
+    pub fn get_due_webhook_deliveries(
+        conn: &SqliteConnection,
+        now: u32,
+        max_attempts: i32,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let deliveries = event_outbox::table
+            .select((
+                event_outbox::id,
+                event_outbox::endpoint,
+                event_outbox::payload,
+                event_outbox::attempts,
+            ))
+            .filter(event_outbox::delivered_at.is_null())
+            .filter(event_outbox::attempts.lt(max_attempts))
+            .filter(event_outbox::next_attempt_at.le(i64::from(now)))
+            .order(event_outbox::id.asc())
+            .get_results::<WebhookDelivery>(conn)?;
+
+        Ok(deliveries)
+    }
+
+    pub fn mark_webhook_delivered(
+        conn: &SqliteConnection,
+        id: i32,
+        delivered_at: u32,
+    ) -> Result<()> {
+        diesel::update(event_outbox::table.filter(event_outbox::id.eq(id)))
+            .set(event_outbox::delivered_at.eq(i64::from(delivered_at)))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    pub fn reschedule_webhook_delivery(
+        conn: &SqliteConnection,
+        id: i32,
+        attempts: i32,
+        next_attempt_at: u32,
+    ) -> Result<()> {
+        diesel::update(event_outbox::table.filter(event_outbox::id.eq(id)))
+            .set((
+                event_outbox::attempts.eq(attempts),
+                event_outbox::next_attempt_at.eq(i64::from(next_attempt_at)),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    #[derive(Clone, Debug, Queryable, PartialEq)]
+    pub struct StoredResponse {
+        pub client: String,
+        pub key: String,
+        pub endpoint: String,
+        pub fingerprint: String,
+        pub response: String,
+        pub created_at: i64,
+    }
+
+    pub fn get_stored_response(
+        conn: &SqliteConnection,
+        client: &str,
+        key: &str,
+    ) -> Result<Option<StoredResponse>> {
+        let stored_response = idempotency_keys::table
+            .filter(idempotency_keys::client.eq(client))
+            .filter(idempotency_keys::key.eq(key))
+            .first::<StoredResponse>(conn)
+            .optional()?;
+
+        Ok(stored_response)
+    }
+
+    /// Forget the responses stored before `cutoff`, freeing up their
+    /// keys.
+    pub fn delete_stored_responses_before(conn: &SqliteConnection, cutoff: u32) -> Result<()> {
+        diesel::delete(
+            idempotency_keys::table.filter(idempotency_keys::created_at.lt(i64::from(cutoff))),
+        )
+        .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Remove a loan and its liquidation transaction, e.g. because it
+    /// has been replaced by a new loan transaction.
+    pub fn delete_loan(conn: &SqliteConnection, loan_txid: Txid) -> Result<()> {
+        let id = loan_txid.to_string();
+
+        diesel::delete(loans::table.filter(loans::id.eq(&id))).execute(conn)?;
+        diesel::delete(liquidations::table.filter(liquidations::id.eq(&id))).execute(conn)?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
+    use crate::LiquidUsdt;
+    use elements::{bitcoin::PublicKey, OutPoint, TxIn};
+    use std::{path::PathBuf, str::FromStr};
 
     fn temp_db() -> PathBuf {
         let temp_file = tempfile::Builder::new()
@@ -140,6 +629,110 @@ mod tests {
         temp_file.into_temp_path().to_path_buf()
     }
 
+    #[tokio::test]
+    async fn outstanding_principal_of_a_borrower_only_counts_their_open_loans() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let borrower_pk = PublicKey::from_str(
+            "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+        let other_borrower_pk = PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+
+        let (total_principal, borrower_principal) = db
+            .do_in_transaction(|conn| {
+                insert_loan(conn, 1, "1000", Some(borrower_pk), false)?;
+                insert_loan(conn, 2, "2000", Some(borrower_pk), false)?;
+                insert_loan(conn, 3, "4000", Some(borrower_pk), true)?;
+                insert_loan(conn, 4, "8000", Some(other_borrower_pk), false)?;
+                insert_loan(conn, 5, "16000", None, false)?;
+
+                let total_principal = queries::get_outstanding_principal(conn, None)?;
+                let borrower_principal =
+                    queries::get_outstanding_principal(conn, Some(borrower_pk))?;
+
+                Ok((total_principal, borrower_principal))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            total_principal,
+            LiquidUsdt::from_str_in_dollar("27000").unwrap()
+        );
+        assert_eq!(
+            borrower_principal,
+            LiquidUsdt::from_str_in_dollar("3000").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn collateral_of_a_loan_is_the_loan_output_spent_by_its_liquidation_transaction() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let loan_txid = Txid::from_str(&hex::encode([1; 32])).unwrap();
+        let other_txid = Txid::from_str(&hex::encode([2; 32])).unwrap();
+        let liquidation_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint::new(other_txid, 0),
+                    ..Default::default()
+                },
+                TxIn {
+                    previous_output: OutPoint::new(loan_txid, 1),
+                    ..Default::default()
+                },
+            ],
+            output: vec![],
+        };
+
+        let collateral = db
+            .do_in_transaction(|conn| {
+                LiquidationForm::new(loan_txid, &liquidation_tx, 0, Duration::from_secs(0))?
+                    .insert(conn)?;
+
+                queries::get_loan_collateral(conn, loan_txid)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(collateral, OutPoint::new(loan_txid, 1));
+    }
+
+    fn insert_loan(
+        conn: &SqliteConnection,
+        id: u8,
+        principal_amount: &str,
+        borrower_pk: Option<PublicKey>,
+        collateral_spent: bool,
+    ) -> Result<()> {
+        let loan_txid = Txid::from_str(&hex::encode([id; 32]))?;
+        let principal_amount =
+            to_i64(LiquidUsdt::from_str_in_dollar(principal_amount)?.as_satodollar())?;
+
+        diesel::insert_into(loans::table)
+            .values(LoanForm {
+                id: loan_txid.to_string(),
+                // not read when summing up the principal
+                lender_state: String::new(),
+                principal_amount,
+                repayment_amount: principal_amount,
+                collateral_amount: 0,
+                liquidation_price: 0,
+                max_ltv: "0.8".to_owned(),
+                timelock: 0,
+                start: 0,
+                borrower_pk: borrower_pk.map(|pk| pk.to_string()),
+            })
+            .execute(conn)?;
+        queries::set_collateral_spent(conn, loan_txid, collateral_spent)?;
+
+        Ok(())
+    }
+
     #[test]
     fn can_create_a_new_temp_db() {
         let path = temp_db();
@@ -1,19 +1,24 @@
-use anyhow::{bail, Context, Result};
+use crate::{
+    metrics::time_rpc,
+    rpc_auth::{RpcAuth, Transport},
+};
+use anyhow::{anyhow, bail, Context, Result};
 use bitcoin_hashes::hex::FromHex;
 use elements::{
-    bitcoin::Amount,
+    bitcoin::{util::misc::MessageSignature, Amount},
     confidential::{Asset, Nonce, Value},
     encode::serialize_hex,
-    secp256k1_zkp::{SecretKey, Signature},
+    secp256k1_zkp::SecretKey,
     Address, AssetId, OutPoint, Transaction, TxOut, TxOutWitness, Txid,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
 
 #[jsonrpc_client::api(version = "1.0")]
 pub trait ElementsRpc {
     async fn getblockchaininfo(&self) -> BlockchainInfo;
     async fn getblockcount(&self) -> u32;
+    async fn getwalletinfo(&self) -> WalletInfo;
     async fn getnewaddress(&self, label: &str, address_type: Option<&str>) -> Address;
     #[allow(clippy::too_many_arguments)]
     async fn sendtoaddress(
@@ -31,6 +36,17 @@ pub trait ElementsRpc {
     ) -> Txid;
     async fn dumpassetlabels(&self) -> HashMap<String, AssetId>;
     async fn getrawtransaction(&self, txid: Txid) -> String;
+    async fn gettransaction(&self, txid: Txid) -> GetTransactionResponse;
+    async fn createrawtransaction(
+        &self,
+        inputs: Vec<CreateRawTransactionInput>,
+        outputs: Vec<HashMap<String, f64>>,
+        locktime: Option<u32>,
+        replaceable: Option<bool>,
+        output_assets: Option<HashMap<String, AssetId>>,
+    ) -> String;
+    async fn blindrawtransaction(&self, tx_hex: String) -> String;
+    async fn gettxout(&self, txid: Txid, n: u32, include_mempool: bool) -> Option<TxOutInfo>;
     async fn sendrawtransaction(&self, tx_hex: String) -> Txid;
     async fn issueasset(
         &self,
@@ -62,7 +78,7 @@ pub trait ElementsRpc {
     async fn generatetoaddress(&self, nblocks: u32, address: &Address) -> Vec<String>;
     async fn dumpmasterblindingkey(&self) -> String;
     async fn unblindrawtransaction(&self, tx_hex: String) -> UnblindRawTransactionResponse;
-    async fn lockunspent(&self, unlock: bool, utxos: Vec<OutPoint>) -> bool;
+    async fn lockunspent(&self, unlock: bool, utxos: Option<Vec<OutPoint>>) -> bool;
     async fn reissueasset(&self, asset: AssetId, amount: f64) -> ReissueAssetResponse;
     async fn getaddressinfo(&self, address: &Address) -> GetAddressInfoResponse;
     async fn listreceivedbyaddress(
@@ -87,16 +103,32 @@ pub trait ElementsRpc {
     ) -> WalletSignPsbtResponse;
     async fn finalizepsbt(&self, psbt: String, extract: Option<bool>) -> FinalizePsbtResponse;
     async fn signmessage(&self, address: &Address, message: String) -> String;
+    async fn listlabels(&self) -> Vec<String>;
+    async fn getaddressesbylabel(&self, label: &str) -> HashMap<String, AddressPurpose>;
     async fn dumpprivkey(&self, address: &Address) -> String;
+    async fn estimatesmartfee(&self, conf_target: u32) -> EstimateSmartFeeResponse;
 }
 
 #[jsonrpc_client::implement(ElementsRpc)]
 #[derive(Clone, Debug)]
 pub struct Client {
-    inner: reqwest::Client,
+    inner: Transport,
     base_url: reqwest::Url,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GetTransactionResponse {
+    /// Negative if the transaction conflicts with one in the chain
+    pub confirmations: i64,
+    hex: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CreateRawTransactionInput {
+    pub txid: Txid,
+    pub vout: u32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UnblindRawTransactionResponse {
     hex: String,
@@ -127,6 +159,11 @@ pub struct GetAddressInfoResponse {
     pub pubkey: Option<String>,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct AddressPurpose {
+    pub purpose: String,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ListReceivedByAddressResponse {
     pub address: Address,
@@ -149,14 +186,35 @@ pub struct FinalizePsbtResponse {
 
 impl Client {
     pub fn new(base_url: String) -> Result<Self> {
+        Self::with_auth(base_url, RpcAuth::None)
+    }
+
+    pub fn with_auth(base_url: String, auth: RpcAuth) -> Result<Self> {
         Ok(Self {
-            inner: reqwest::Client::new(),
+            inner: Transport::new(auth),
             base_url: base_url.parse()?,
         })
     }
 
+    /// A client for the wallet with the given name, which has to be
+    /// loaded in elementsd. Calls which do not depend on a wallet go to
+    /// the same node.
+    pub fn with_wallet(&self, name: &str) -> Result<Self> {
+        let mut base_url = self.base_url.clone();
+        base_url
+            .path_segments_mut()
+            .map_err(|_| anyhow!("{} cannot have a wallet path", self.base_url))?
+            .pop_if_empty()
+            .extend(&["wallet", name]);
+
+        Ok(Self {
+            inner: self.inner.clone(),
+            base_url,
+        })
+    }
+
     async fn get_new_address(&self, address_type: Option<&str>) -> Result<Address> {
-        let address = self.getnewaddress("", address_type).await?;
+        let address = time_rpc("getnewaddress", self.getnewaddress("", address_type)).await?;
 
         Ok(address)
     }
@@ -166,7 +224,7 @@ impl Client {
     }
 
     pub async fn get_bitcoin_asset_id(&self) -> Result<AssetId> {
-        let labels = self.dumpassetlabels().await?;
+        let labels = time_rpc("dumpassetlabels", self.dumpassetlabels()).await?;
         let bitcoin_asset_tag = "bitcoin";
         let bitcoin_asset_id = labels
             .get(bitcoin_asset_tag)
@@ -181,8 +239,9 @@ impl Client {
         amount: Amount,
         asset_id: Option<AssetId>,
     ) -> Result<Txid> {
-        let txid = self
-            .sendtoaddress(
+        let txid = time_rpc(
+            "sendtoaddress",
+            self.sendtoaddress(
                 address,
                 amount.as_btc(),
                 None,
@@ -193,28 +252,106 @@ impl Client {
                 None,
                 asset_id,
                 true,
-            )
-            .await?;
+            ),
+        )
+        .await?;
 
         Ok(txid)
     }
 
     pub async fn get_raw_transaction(&self, txid: Txid) -> Result<Transaction> {
-        let tx_hex = self.getrawtransaction(txid).await?;
+        let tx_hex = time_rpc("getrawtransaction", self.getrawtransaction(txid)).await?;
         let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&tx_hex).unwrap())?;
 
         Ok(tx)
     }
 
+    /// Look up a transaction of the wallet, unlike
+    /// `get_raw_transaction` without depending on elementsd indexing
+    /// all transactions.
+    ///
+    /// Fails if the transaction is unknown to the wallet.
+    pub async fn get_wallet_transaction(&self, txid: Txid) -> Result<WalletTransaction> {
+        let res = time_rpc("gettransaction", self.gettransaction(txid)).await?;
+        let transaction = elements::encode::deserialize(&Vec::<u8>::from_hex(&res.hex)?)?;
+
+        Ok(WalletTransaction {
+            confirmations: res.confirmations,
+            transaction,
+        })
+    }
+
+    /// Create an unsigned transaction spending `inputs`, paying each
+    /// amount of an asset to its address.
+    pub async fn create_raw_transaction(
+        &self,
+        inputs: &[OutPoint],
+        outputs: &[(Address, AssetId, Amount)],
+    ) -> Result<Transaction> {
+        let inputs = inputs
+            .iter()
+            .map(|outpoint| CreateRawTransactionInput {
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+            })
+            .collect();
+        let output_assets = outputs
+            .iter()
+            .map(|(address, asset_id, _)| (address.to_string(), *asset_id))
+            .collect();
+        let outputs = outputs
+            .iter()
+            .map(|(address, _, amount)| {
+                std::iter::once((address.to_string(), amount.as_btc())).collect()
+            })
+            .collect();
+
+        let tx_hex = time_rpc(
+            "createrawtransaction",
+            self.createrawtransaction(inputs, outputs, None, None, Some(output_assets)),
+        )
+        .await?;
+        let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&tx_hex)?)?;
+
+        Ok(tx)
+    }
+
+    /// Blind the outputs of a transaction paying to confidential
+    /// addresses, which requires the inputs to be ours.
+    pub async fn blind_raw_transaction(&self, tx: &Transaction) -> Result<Transaction> {
+        let tx_hex = serialize_hex(tx);
+        let res = time_rpc("blindrawtransaction", self.blindrawtransaction(tx_hex)).await?;
+        let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&res)?)?;
+
+        Ok(tx)
+    }
+
+    /// Look up an unspent transaction output, `None` if it does not
+    /// exist or is spent, also by a transaction in the mempool if
+    /// `include_mempool` is set.
+    pub async fn get_tx_out(
+        &self,
+        outpoint: OutPoint,
+        include_mempool: bool,
+    ) -> Result<Option<TxOutInfo>> {
+        let txout = time_rpc(
+            "gettxout",
+            self.gettxout(outpoint.txid, outpoint.vout, include_mempool),
+        )
+        .await?;
+
+        Ok(txout)
+    }
+
     pub async fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid> {
         let tx_hex = serialize_hex(tx);
-        let txid = self.sendrawtransaction(tx_hex).await?;
+        let txid = time_rpc("sendrawtransaction", self.sendrawtransaction(tx_hex)).await?;
         Ok(txid)
     }
 
     pub async fn unblind_raw_transaction(&self, tx: &Transaction) -> Result<Transaction> {
         let tx_hex = serialize_hex(tx);
-        let res = self.unblindrawtransaction(tx_hex).await?;
+        let res = time_rpc("unblindrawtransaction", self.unblindrawtransaction(tx_hex)).await?;
         let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&res.hex).unwrap())?;
 
         Ok(tx)
@@ -246,8 +383,7 @@ impl Client {
         };
 
         let tx_hex = serialize_hex(&tx);
-        let res = self
-            .fundrawtransaction(tx_hex)
+        let res = time_rpc("fundrawtransaction", self.fundrawtransaction(tx_hex))
             .await
             .context("cannot fund raw transaction")?;
 
@@ -294,9 +430,24 @@ impl Client {
         Ok(utxos)
     }
 
+    pub async fn get_balance(&self, asset_id: AssetId) -> Result<Amount> {
+        let balance = time_rpc(
+            "getbalance",
+            self.getbalance(None, None, None, Some(asset_id)),
+        )
+        .await?;
+        let balance = Amount::from_btc(balance)?;
+
+        Ok(balance)
+    }
+
     pub async fn sign_raw_transaction(&self, tx: &Transaction) -> Result<Transaction> {
         let tx_hex = serialize_hex(tx);
-        let res = self.signrawtransactionwithwallet(tx_hex).await?;
+        let res = time_rpc(
+            "signrawtransactionwithwallet",
+            self.signrawtransactionwithwallet(tx_hex),
+        )
+        .await?;
         let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&res.hex).unwrap())?;
 
         Ok(tx)
@@ -304,14 +455,14 @@ impl Client {
 
     pub async fn fund_raw_transaction(&self, tx: &Transaction) -> Result<Transaction> {
         let tx_hex = serialize_hex(tx);
-        let res = self.fundrawtransaction(tx_hex).await?;
+        let res = time_rpc("fundrawtransaction", self.fundrawtransaction(tx_hex)).await?;
         let tx = elements::encode::deserialize(&Vec::<u8>::from_hex(&res.hex).unwrap())?;
 
         Ok(tx)
     }
 
     pub async fn lock_utxos(&self, utxos: Vec<OutPoint>) -> Result<()> {
-        let res = self.lockunspent(false, utxos).await?;
+        let res = time_rpc("lockunspent", self.lockunspent(false, Some(utxos))).await?;
 
         if res {
             Ok(())
@@ -320,42 +471,151 @@ impl Client {
         }
     }
 
+    /// Make UTXOs available for coin selection again, all locked UTXOs
+    /// if `utxos` is `None`.
+    pub async fn unlock_utxos(&self, utxos: Option<Vec<OutPoint>>) -> Result<()> {
+        let res = time_rpc("lockunspent", self.lockunspent(true, utxos)).await?;
+
+        if res {
+            Ok(())
+        } else {
+            bail!("Could not unlock outputs")
+        }
+    }
+
     pub async fn list_received_by_address(
         &self,
         address: &Address,
     ) -> Result<Vec<ListReceivedByAddressResponse>> {
-        let res = self
-            .listreceivedbyaddress(Some(0), None, None, Some(address), None)
-            .await?;
+        let res = time_rpc(
+            "listreceivedbyaddress",
+            self.listreceivedbyaddress(Some(0), None, None, Some(address), None),
+        )
+        .await?;
 
         Ok(res)
     }
 
-    pub async fn sign_message(&self, address: &Address, message: String) -> Result<Signature> {
-        let sig = self.signmessage(address, message).await?;
-        let sig = Signature::from_str(&sig)?;
+    /// Sign a message with the key of a legacy address, as a Bitcoin
+    /// signed message.
+    pub async fn sign_message(
+        &self,
+        address: &Address,
+        message: String,
+    ) -> Result<MessageSignature> {
+        let sig = time_rpc("signmessage", self.signmessage(address, message)).await?;
+        let sig = MessageSignature::from_base64(&sig).context("invalid signed message")?;
 
         Ok(sig)
     }
 
+    /// The addresses of the wallet with the given label, none if the
+    /// label is unknown.
+    pub async fn get_addresses_by_label(&self, label: &str) -> Result<Vec<Address>> {
+        let labels = time_rpc("listlabels", self.listlabels()).await?;
+        if !labels.iter().any(|known| known == label) {
+            return Ok(Vec::new());
+        }
+
+        let addresses = time_rpc("getaddressesbylabel", self.getaddressesbylabel(label)).await?;
+        let addresses = addresses
+            .keys()
+            .map(|address| address.parse())
+            .collect::<Result<Vec<Address>, _>>()?;
+
+        Ok(addresses)
+    }
+
+    /// Generate a new legacy address with the given label, whose key
+    /// can sign messages.
+    pub async fn get_new_labelled_legacy_address(&self, label: &str) -> Result<Address> {
+        let address = time_rpc("getnewaddress", self.getnewaddress(label, Some("legacy"))).await?;
+
+        Ok(address)
+    }
+
+    pub async fn get_address_info(&self, address: &Address) -> Result<GetAddressInfoResponse> {
+        let info = time_rpc("getaddressinfo", self.getaddressinfo(address)).await?;
+
+        Ok(info)
+    }
+
     pub async fn get_blockcount(&self) -> Result<u32> {
-        let blockcount = self.getblockcount().await?;
+        let blockcount = time_rpc("getblockcount", self.getblockcount()).await?;
 
         Ok(blockcount)
     }
 
+    pub async fn get_blockchain_info(&self) -> Result<BlockchainInfo> {
+        let info = time_rpc("getblockchaininfo", self.getblockchaininfo()).await?;
+
+        Ok(info)
+    }
+
+    pub async fn get_wallet_info(&self) -> Result<WalletInfo> {
+        let info = time_rpc("getwalletinfo", self.getwalletinfo()).await?;
+
+        Ok(info)
+    }
+
+    /// The fee rate needed for a transaction to confirm within
+    /// `conf_target` blocks, `None` if elementsd has not seen enough
+    /// transactions to estimate it.
+    pub async fn estimate_fee_rate(&self, conf_target: u32) -> Result<Option<Amount>> {
+        let response = time_rpc("estimatesmartfee", self.estimatesmartfee(conf_target)).await?;
+
+        let fee_rate = match response.feerate {
+            Some(fee_rate) => fee_rate,
+            None => return Ok(None),
+        };
+        let btc_per_kvbyte = Amount::from_btc(fee_rate)?;
+        let sats_per_vbyte = (btc_per_kvbyte.as_sat() + 999) / 1000;
+
+        Ok(Some(Amount::from_sat(sats_per_vbyte)))
+    }
+
     pub async fn get_address_blinding_key(&self, address: &Address) -> Result<SecretKey> {
-        let key = self.dumpblindingkey(address).await?;
+        let key = time_rpc("dumpblindingkey", self.dumpblindingkey(address)).await?;
         Ok(key)
     }
 }
 
+#[derive(Debug)]
+pub struct WalletTransaction {
+    /// 0 for transactions in the mempool, negative for transactions
+    /// conflicting with one in the chain
+    pub confirmations: i64,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TxOutInfo {
+    /// 0 for outputs of transactions in the mempool
+    pub confirmations: u32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BlockchainInfo {
     pub chain: String,
+    pub blocks: u32,
     mediantime: u32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct WalletInfo {
+    pub walletname: String,
+    /// Unix timestamp until which the wallet is unlocked, only present
+    /// for encrypted wallets and 0 if the wallet is locked
+    pub unlocked_until: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EstimateSmartFeeResponse {
+    /// In BTC per kvB
+    pub feerate: Option<f64>,
+    pub errors: Option<Vec<String>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct IssueAssetResponse {
     pub txid: Txid,
@@ -396,6 +656,18 @@ mod test {
     use elements_harness::Elementsd;
     use testcontainers::clients::Cli;
 
+    #[test]
+    fn wallet_path_is_appended_to_node_url() {
+        let client = Client::new("http://127.0.0.1:7042".to_owned()).unwrap();
+
+        let wallet = client.with_wallet("lending").unwrap();
+
+        assert_eq!(
+            wallet.base_url.as_str(),
+            "http://127.0.0.1:7042/wallet/lending"
+        );
+    }
+
     #[tokio::test]
     async fn get_network_info() {
         let tc_client = Cli::default();
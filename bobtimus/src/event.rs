@@ -0,0 +1,477 @@
+use crate::{
+    database::{queries, EventOutboxForm, Sqlite},
+    elements_rpc::Client,
+    metrics,
+    notification::Notification,
+    unix_timestamp, LiquidBtc, LiquidUsdt,
+};
+use anyhow::{Context, Result};
+use elements::{bitcoin::Amount, AssetId, Txid};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::{header::CONTENT_TYPE, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::broadcast;
+
+/// How many events are buffered for slow subscribers
+const SUBSCRIPTION_CAPACITY: usize = 256;
+
+/// How often the delivery of an event to a webhook is attempted before
+/// we give up on it
+const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+
+/// The longest we wait before retrying a failed delivery
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+pub const SIGNATURE_HEADER: &str = "X-Bobtimus-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Bobtimus-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-Bobtimus-Delivery";
+
+/// Something that happened which operations staff may want to know about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// We signed a swap transaction and handed it to the taker, who is
+    /// expected to broadcast it
+    SwapSigned {
+        txid: Txid,
+        side: SwapSide,
+        btc_amount: LiquidBtc,
+        usdt_amount: LiquidUsdt,
+    },
+    /// The taker did not broadcast a swap transaction in time, our
+    /// inputs were made available again
+    SwapLapsed {
+        txid: Txid,
+    },
+    /// The taker spent the inputs of a swap transaction in another
+    /// transaction, our inputs were made available again
+    SwapConflicted {
+        txid: Txid,
+    },
+    /// We broadcast a loan transaction
+    LoanOpened {
+        loan_txid: Txid,
+        principal_amount: LiquidUsdt,
+        repayment_amount: LiquidUsdt,
+        collateral_amount: LiquidBtc,
+        timelock: u32,
+    },
+    /// A borrower was reminded that their loan is about to mature
+    BorrowerNotified(Notification),
+    LiquidationBroadcast {
+        txid: Txid,
+    },
+    LiquidationFailed {
+        txid: Txid,
+        error: String,
+    },
+    /// Our balance of an asset fell below the configured threshold
+    InventoryLow {
+        asset_id: AssetId,
+        #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+        balance: Amount,
+        #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
+        threshold: Amount,
+    },
+}
+
+/// The side of a swap from the taker's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapSide {
+    /// The taker buys L-BTC with L-USDt
+    Buy,
+    /// The taker sells L-BTC for L-USDt
+    Sell,
+}
+
+/// The body of a webhook request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Unix timestamp at which the event happened
+    pub created_at: u32,
+    pub event: Event,
+}
+
+/// Hands out events to subscribers within Bobtimus and stores them in
+/// the outbox for delivery to the configured webhooks.
+#[derive(Clone)]
+pub struct EventBus {
+    db: Sqlite,
+    webhooks: Vec<Url>,
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(db: Sqlite, webhooks: Vec<Url>) -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIPTION_CAPACITY);
+
+        Self {
+            db,
+            webhooks,
+            sender,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Publish an event.
+    ///
+    /// Failing to store the event in the outbox is only logged, so that
+    /// the action which caused the event does not fail because of it.
+    pub async fn publish(&self, event: Event) {
+        metrics::observe_event(&event);
+
+        // Not having any subscribers is not an error
+        let _ = self.sender.send(event.clone());
+
+        if self.webhooks.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.store(event).await {
+            tracing::error!("failed to store event in outbox: {:#}", e);
+        }
+    }
+
+    async fn store(&self, event: Event) -> Result<()> {
+        let created_at = unix_timestamp(SystemTime::now())?;
+        let payload = serde_json::to_string(&Envelope { created_at, event })
+            .context("failed to serialize event")?;
+
+        self.db
+            .do_in_transaction(|conn| {
+                for webhook in self.webhooks.iter() {
+                    EventOutboxForm::new(webhook, &payload, created_at).insert(conn)?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+}
+
+/// Delivers the events in the outbox to the webhooks they are destined
+/// for.
+///
+/// Every request carries a signature over its timestamp and body, so
+/// that receivers can verify that it was sent by us. Failed deliveries
+/// are retried with exponential backoff.
+pub struct WebhookDispatcher {
+    db: Sqlite,
+    client: reqwest::Client,
+    secret: Vec<u8>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: Sqlite, secret: Vec<u8>) -> Self {
+        Self {
+            db,
+            client: reqwest::Client::new(),
+            secret,
+        }
+    }
+
+    /// Deliver events every `interval` until the process is stopped.
+    pub async fn run(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.dispatch_due().await {
+                tracing::error!("failed to dispatch events: {:#}", e);
+            }
+        }
+    }
+
+    /// Attempt to deliver all events whose delivery is due.
+    pub async fn dispatch_due(&self) -> Result<()> {
+        let now = unix_timestamp(SystemTime::now())?;
+
+        self.dispatch(now).await
+    }
+
+    /// Attempt to deliver all events whose delivery is due at `now`.
+    pub async fn dispatch(&self, now: u32) -> Result<()> {
+        let deliveries = self
+            .db
+            .do_in_transaction(|conn| {
+                queries::get_due_webhook_deliveries(conn, now, MAX_DELIVERY_ATTEMPTS)
+            })
+            .await?;
+
+        for delivery in deliveries {
+            match self.deliver(&delivery, now).await {
+                Ok(()) => {
+                    self.db
+                        .do_in_transaction(|conn| {
+                            queries::mark_webhook_delivered(conn, delivery.id, now)
+                        })
+                        .await?;
+                }
+                Err(e) => {
+                    let attempts = delivery.attempts + 1;
+                    if attempts >= MAX_DELIVERY_ATTEMPTS {
+                        tracing::error!(
+                            "giving up on delivering event {} to {}: {:#}",
+                            delivery.id,
+                            delivery.endpoint,
+                            e
+                        );
+                    } else {
+                        tracing::warn!(
+                            "failed to deliver event {} to {}: {:#}",
+                            delivery.id,
+                            delivery.endpoint,
+                            e
+                        );
+                    }
+
+                    let next_attempt_at = now.saturating_add(retry_delay(attempts));
+                    self.db
+                        .do_in_transaction(|conn| {
+                            queries::reschedule_webhook_delivery(
+                                conn,
+                                delivery.id,
+                                attempts,
+                                next_attempt_at,
+                            )
+                        })
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn deliver(&self, delivery: &queries::WebhookDelivery, now: u32) -> Result<()> {
+        let endpoint = Url::parse(&delivery.endpoint).context("invalid webhook url")?;
+
+        self.client
+            .post(endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(TIMESTAMP_HEADER, now)
+            .header(SIGNATURE_HEADER, sign(&self.secret, now, &delivery.payload))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .context("failed to send event")?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Sign a webhook request as `sha256=<hex encoded HMAC>`.
+///
+/// The HMAC is calculated over `<timestamp>.<body>` so that a captured
+/// request cannot be replayed with a different timestamp.
+pub fn sign(secret: &[u8], timestamp: u32, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn retry_delay(attempts: i32) -> u32 {
+    let delay = 30u64.saturating_mul(2u64.saturating_pow(attempts as u32));
+
+    delay.min(MAX_RETRY_DELAY.as_secs()) as u32
+}
+
+/// Keeps track of which assets fell below their inventory threshold, so
+/// that we only raise an event when the balance crosses the threshold.
+#[derive(Debug, Default)]
+pub struct InventoryMonitor {
+    thresholds: HashMap<AssetId, Amount>,
+    low: HashSet<AssetId>,
+}
+
+impl InventoryMonitor {
+    pub fn new(thresholds: HashMap<AssetId, Amount>) -> Self {
+        Self {
+            thresholds,
+            low: HashSet::new(),
+        }
+    }
+
+    /// Check our balances every `interval` until the process is stopped.
+    ///
+    /// The balances are polled rather than checked after every swap, so
+    /// that handing out swaps does not wait for elementsd.
+    pub async fn run(mut self, elementsd: Client, events: EventBus, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            self.check_balances(&elementsd, &events).await;
+        }
+    }
+
+    /// Raise an event for every asset whose balance just fell below its
+    /// inventory threshold.
+    async fn check_balances(&mut self, elementsd: &Client, events: &EventBus) {
+        let assets = self.thresholds.keys().copied().collect::<Vec<_>>();
+
+        for asset_id in assets {
+            let balance = match elementsd.get_balance(asset_id).await {
+                Ok(balance) => balance,
+                Err(e) => {
+                    tracing::warn!("failed to get balance of asset {}: {:#}", asset_id, e);
+                    continue;
+                }
+            };
+
+            if let Some(event) = self.check(asset_id, balance) {
+                events.publish(event).await;
+            }
+        }
+    }
+
+    /// Record our current balance of an asset, returning an event if it
+    /// just fell below the threshold.
+    pub fn check(&mut self, asset_id: AssetId, balance: Amount) -> Option<Event> {
+        let threshold = *self.thresholds.get(&asset_id)?;
+
+        if balance >= threshold {
+            self.low.remove(&asset_id);
+            return None;
+        }
+
+        if !self.low.insert(asset_id) {
+            return None;
+        }
+
+        Some(Event::InventoryLow {
+            asset_id,
+            balance,
+            threshold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tokio::sync::mpsc;
+    use warp::{http::StatusCode, Filter};
+
+    fn asset_id() -> AssetId {
+        AssetId::from_str("ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2")
+            .unwrap()
+    }
+
+    #[test]
+    fn given_balance_falls_below_threshold_then_event_is_raised_once() {
+        let asset_id = asset_id();
+        let threshold = Amount::from_sat(1_000);
+        let mut monitor = InventoryMonitor::new(vec![(asset_id, threshold)].into_iter().collect());
+
+        assert_eq!(monitor.check(asset_id, Amount::from_sat(1_000)), None);
+        assert_eq!(
+            monitor.check(asset_id, Amount::from_sat(999)),
+            Some(Event::InventoryLow {
+                asset_id,
+                balance: Amount::from_sat(999),
+                threshold
+            })
+        );
+        assert_eq!(monitor.check(asset_id, Amount::from_sat(500)), None);
+    }
+
+    #[test]
+    fn given_balance_recovered_then_event_is_raised_again() {
+        let asset_id = asset_id();
+        let threshold = Amount::from_sat(1_000);
+        let mut monitor = InventoryMonitor::new(vec![(asset_id, threshold)].into_iter().collect());
+
+        assert!(monitor.check(asset_id, Amount::from_sat(999)).is_some());
+        assert!(monitor.check(asset_id, Amount::from_sat(2_000)).is_none());
+        assert!(monitor.check(asset_id, Amount::from_sat(999)).is_some());
+    }
+
+    #[test]
+    fn signature_covers_timestamp() {
+        let body = r#"{"created_at":0}"#;
+
+        assert_eq!(sign(b"secret", 1, body), sign(b"secret", 1, body));
+        assert_ne!(sign(b"secret", 1, body), sign(b"secret", 2, body));
+        assert_ne!(sign(b"secret", 1, body), sign(b"other secret", 1, body));
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay(1), 60);
+        assert_eq!(retry_delay(2), 120);
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS), 60 * 60);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried_with_signature() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        // The first delivery fails, the second one succeeds
+        let statuses = std::sync::Arc::new(std::sync::Mutex::new(vec![
+            StatusCode::OK,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ]));
+
+        let webhook = warp::post()
+            .and(warp::header::<String>(TIMESTAMP_HEADER))
+            .and(warp::header::<String>(SIGNATURE_HEADER))
+            .and(warp::body::bytes())
+            .map(
+                move |timestamp: String, signature: String, body: warp::hyper::body::Bytes| {
+                    sender.send((timestamp, signature, body.to_vec())).unwrap();
+                    let status = statuses.lock().unwrap().pop().unwrap_or(StatusCode::OK);
+
+                    warp::reply::with_status(warp::reply(), status)
+                },
+            );
+        let (address, server) = warp::serve(webhook).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let endpoint = Url::parse(&format!("http://{}/", address)).unwrap();
+        let payload = serde_json::to_string(&Envelope {
+            created_at: 0,
+            event: Event::LiquidationBroadcast {
+                txid: Txid::default(),
+            },
+        })
+        .unwrap();
+        db.do_in_transaction(|conn| EventOutboxForm::new(&endpoint, &payload, 0).insert(conn))
+            .await
+            .unwrap();
+
+        let dispatcher = WebhookDispatcher::new(db, b"secret".to_vec());
+        dispatcher.dispatch(0).await.unwrap();
+        // not due yet
+        dispatcher.dispatch(1).await.unwrap();
+        dispatcher.dispatch(retry_delay(1)).await.unwrap();
+        // delivered
+        dispatcher.dispatch(retry_delay(10)).await.unwrap();
+
+        for timestamp in &[0, retry_delay(1)] {
+            let (received_timestamp, signature, body) = receiver.recv().await.unwrap();
+
+            assert_eq!(received_timestamp, timestamp.to_string());
+            assert_eq!(signature, sign(b"secret", *timestamp, &payload));
+            assert_eq!(body, payload.as_bytes());
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+}
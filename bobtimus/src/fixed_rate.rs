@@ -1,5 +1,8 @@
 use crate::{LatestRate, LiquidUsdt, Rate, RateSubscription};
-use std::{convert::TryFrom, time::Duration};
+use std::{
+    convert::TryFrom,
+    time::{Duration, SystemTime},
+};
 use tokio::{
     sync::watch::{self, Receiver},
     time::sleep,
@@ -39,6 +42,11 @@ impl LatestRate for Service {
     fn latest_rate(&mut self) -> Rate {
         fixed_rate()
     }
+
+    /// The fixed rate is always up to date.
+    fn updated_at(&self) -> Option<SystemTime> {
+        Some(SystemTime::now())
+    }
 }
 
 fn fixed_rate() -> Rate {
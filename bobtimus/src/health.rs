@@ -0,0 +1,397 @@
+//! Liveness and readiness of Bobtimus.
+//!
+//! The readiness checks call elementsd and write to the database, so
+//! they are run in the background and not while serving a request. The
+//! public readiness route says which checks passed, the detail of every
+//! check, including error messages, is only served on the admin API.
+
+use crate::{
+    database::{queries, Sqlite},
+    elements_rpc::{Client, WalletInfo},
+    unix_timestamp, LatestRate,
+};
+use anyhow::{bail, Context, Result};
+use elements::{bitcoin::Amount, AssetId};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+pub struct Liveness {
+    pub alive: bool,
+}
+
+/// Whether we are able to build transactions, as served publicly
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ReadinessStatus {
+    pub ready: bool,
+    /// Whether each check passed in the latest run, empty if the checks
+    /// have not run yet
+    pub checks: BTreeMap<&'static str, CheckStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+/// Whether we are able to build transactions, with the outcome of every
+/// check that went into it
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Unix timestamp of when the checks were run
+    pub checked_at: u32,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl From<Result<String>> for Check {
+    fn from(result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Check { ok: true, detail },
+            Err(e) => Check {
+                ok: false,
+                detail: format!("{:#}", e),
+            },
+        }
+    }
+}
+
+/// The outcome of the latest readiness checks, shared between the task
+/// running them and the routes serving it.
+#[derive(Debug, Clone)]
+pub struct ReadinessReport {
+    latest: Arc<RwLock<Option<Readiness>>>,
+    /// How old the outcome may be for us to be ready, in case the
+    /// checks got stuck
+    max_age: Duration,
+}
+
+impl ReadinessReport {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            latest: Arc::new(RwLock::new(None)),
+            max_age,
+        }
+    }
+
+    pub fn latest(&self) -> Option<Readiness> {
+        self.latest.read().expect("lock is not poisoned").clone()
+    }
+
+    fn set(&self, readiness: Readiness) {
+        *self.latest.write().expect("lock is not poisoned") = Some(readiness);
+    }
+
+    /// We are only ready if the latest checks passed and are recent
+    /// enough at `now`.
+    pub fn status(&self, now: u32) -> ReadinessStatus {
+        let latest = match self.latest() {
+            Some(readiness) => readiness,
+            None => {
+                return ReadinessStatus {
+                    ready: false,
+                    checks: BTreeMap::new(),
+                }
+            }
+        };
+
+        let ready = latest.ready
+            && u64::from(now.saturating_sub(latest.checked_at)) <= self.max_age.as_secs();
+        let checks = latest
+            .checks
+            .iter()
+            .map(|(name, check)| {
+                let status = if check.ok {
+                    CheckStatus::Ok
+                } else {
+                    CheckStatus::Failed
+                };
+
+                (*name, status)
+            })
+            .collect();
+
+        ReadinessStatus { ready, checks }
+    }
+}
+
+/// Everything the readiness checks need, kept apart from `Bobtimus` so
+/// that they do not hold its lock.
+pub struct ReadinessChecks<RS> {
+    pub rate_service: RS,
+    pub elementsd: Client,
+    pub lending_wallet: Client,
+    pub liquidation_wallet: Client,
+    pub db: Sqlite,
+    pub btc_asset_id: AssetId,
+    pub usdt_asset_id: AssetId,
+    /// How old the latest rate may be for us to be ready
+    pub max_rate_age: Duration,
+    /// The balances below which an asset is considered as not available
+    pub inventory_thresholds: HashMap<AssetId, Amount>,
+}
+
+impl<RS> ReadinessChecks<RS>
+where
+    RS: LatestRate,
+{
+    /// Run the checks every `interval` until the process is stopped,
+    /// storing their outcome in `report`.
+    pub async fn run(self, report: ReadinessReport, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match self.check(SystemTime::now()).await {
+                Ok(readiness) => report.set(readiness),
+                Err(e) => tracing::error!("failed to check readiness: {:#}", e),
+            }
+        }
+    }
+
+    pub async fn check(&self, now: SystemTime) -> Result<Readiness> {
+        let mut checks = BTreeMap::<&'static str, Check>::new();
+        checks.insert("elementsd", self.check_elementsd().await.into());
+        checks.insert("wallet", self.check_wallets(now).await.into());
+        checks.insert(
+            "rate",
+            check_rate(self.rate_service.updated_at(), self.max_rate_age, now).into(),
+        );
+        checks.insert("database", self.check_database(now).await.into());
+        checks.insert(
+            "inventory_lbtc",
+            self.check_inventory_available(self.btc_asset_id)
+                .await
+                .into(),
+        );
+        checks.insert(
+            "inventory_lusdt",
+            self.check_inventory_available(self.usdt_asset_id)
+                .await
+                .into(),
+        );
+
+        Ok(Readiness {
+            ready: checks.values().all(|check| check.ok),
+            checked_at: unix_timestamp(now)?,
+            checks,
+        })
+    }
+
+    async fn check_elementsd(&self) -> Result<String> {
+        let info = self.elementsd.get_blockchain_info().await?;
+
+        Ok(format!("{} chain at height {}", info.chain, info.blocks))
+    }
+
+    /// Encrypted wallets have to be unlocked for us to sign transactions.
+    async fn check_wallets(&self, now: SystemTime) -> Result<String> {
+        let mut names = Vec::new();
+        let mut details = Vec::new();
+        for wallet in vec![
+            &self.elementsd,
+            &self.lending_wallet,
+            &self.liquidation_wallet,
+        ] {
+            let info = wallet.get_wallet_info().await?;
+
+            // A wallet used for several purposes is only checked once
+            if names.contains(&info.walletname) {
+                continue;
+            }
+
+            details.push(check_unlocked(&info, now)?);
+            names.push(info.walletname);
+        }
+
+        Ok(details.join(", "))
+    }
+
+    async fn check_database(&self, now: SystemTime) -> Result<String> {
+        let now = unix_timestamp(now)?;
+
+        self.db
+            .do_in_transaction(|conn| queries::record_health_check(conn, now))
+            .await
+            .context("database is not writable")?;
+
+        Ok("database is writable".to_owned())
+    }
+
+    async fn check_inventory_available(&self, asset_id: AssetId) -> Result<String> {
+        let balance = self.elementsd.get_balance(asset_id).await?;
+
+        check_balance(balance, self.inventory_thresholds.get(&asset_id).copied())
+    }
+}
+
+fn check_unlocked(info: &WalletInfo, now: SystemTime) -> Result<String> {
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    match info.unlocked_until {
+        None => Ok(format!("wallet {} is not encrypted", info.walletname)),
+        Some(unlocked_until) if unlocked_until > now => Ok(format!(
+            "wallet {} is unlocked for another {}s",
+            info.walletname,
+            unlocked_until - now
+        )),
+        Some(_) => bail!("wallet {} is locked", info.walletname),
+    }
+}
+
+fn check_rate(
+    updated_at: Option<SystemTime>,
+    max_rate_age: Duration,
+    now: SystemTime,
+) -> Result<String> {
+    let updated_at = updated_at.context("no rate received yet")?;
+    let age = now.duration_since(updated_at).unwrap_or_default();
+
+    if age > max_rate_age {
+        bail!(
+            "rate is {}s old, more than the maximum of {}s",
+            age.as_secs(),
+            max_rate_age.as_secs()
+        )
+    }
+
+    Ok(format!("rate is {}s old", age.as_secs()))
+}
+
+/// We need a balance of an asset to give it to takers and borrowers.
+///
+/// If a threshold is configured for the asset, a balance below it is
+/// considered as not available.
+fn check_balance(balance: Amount, threshold: Option<Amount>) -> Result<String> {
+    if balance == Amount::from_sat(0) {
+        bail!("balance is empty")
+    }
+
+    if let Some(threshold) = threshold {
+        if balance < threshold {
+            bail!(
+                "balance of {} sats is below the threshold of {} sats",
+                balance.as_sat(),
+                threshold.as_sat()
+            )
+        }
+    }
+
+    Ok(format!("balance of {} sats", balance.as_sat()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(NOW)
+    }
+
+    fn wallet(unlocked_until: Option<u64>) -> WalletInfo {
+        WalletInfo {
+            walletname: "swap".to_owned(),
+            unlocked_until,
+        }
+    }
+
+    fn readiness(ready: bool, checked_at: u32) -> Readiness {
+        Readiness {
+            ready,
+            checked_at,
+            checks: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn unencrypted_and_unlocked_wallets_pass() {
+        assert!(check_unlocked(&wallet(None), now()).is_ok());
+        assert!(check_unlocked(&wallet(Some(NOW + 60)), now()).is_ok());
+    }
+
+    #[test]
+    fn locked_wallet_fails() {
+        assert!(check_unlocked(&wallet(Some(0)), now()).is_err());
+        assert!(check_unlocked(&wallet(Some(NOW)), now()).is_err());
+    }
+
+    #[test]
+    fn rate_fails_if_missing_or_too_old() {
+        let max_age = Duration::from_secs(120);
+
+        assert!(check_rate(None, max_age, now()).is_err());
+        assert!(check_rate(Some(now() - Duration::from_secs(121)), max_age, now()).is_err());
+        assert!(check_rate(Some(now() - Duration::from_secs(120)), max_age, now()).is_ok());
+    }
+
+    #[test]
+    fn balance_fails_if_empty_or_below_threshold() {
+        assert!(check_balance(Amount::from_sat(0), None).is_err());
+        assert!(check_balance(Amount::from_sat(99), Some(Amount::from_sat(100))).is_err());
+        assert!(check_balance(Amount::from_sat(100), Some(Amount::from_sat(100))).is_ok());
+        assert!(check_balance(Amount::from_sat(1), None).is_ok());
+    }
+
+    #[test]
+    fn not_ready_without_recent_passing_checks() {
+        let now = NOW as u32;
+        let report = ReadinessReport::new(Duration::from_secs(60));
+
+        assert!(!report.status(now).ready);
+
+        report.set(readiness(true, now - 60));
+        assert!(report.status(now).ready);
+
+        report.set(readiness(true, now - 61));
+        assert!(!report.status(now).ready);
+
+        report.set(readiness(false, now));
+        assert!(!report.status(now).ready);
+    }
+
+    #[test]
+    fn status_lists_the_checks_without_their_detail() {
+        let now = NOW as u32;
+        let report = ReadinessReport::new(Duration::from_secs(60));
+        let mut checks = BTreeMap::new();
+        checks.insert("elementsd", Check::from(Ok("chain liquidv1".to_owned())));
+        checks.insert("rate", Check::from(Err(anyhow::anyhow!("no rate yet"))));
+        report.set(Readiness {
+            ready: false,
+            checked_at: now,
+            checks,
+        });
+
+        let status = serde_json::to_value(report.status(now)).unwrap();
+
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "ready": false,
+                "checks": {
+                    "elementsd": "ok",
+                    "rate": "failed"
+                }
+            })
+        );
+    }
+}
@@ -1,17 +1,48 @@
-use crate::{problem, Bobtimus, LatestRate, RateSubscription};
+use crate::{
+    admin::{PauseRequest, UnlockRequest},
+    event::SwapSide,
+    health::{Liveness, ReadinessReport},
+    idempotency::IdempotencyKey,
+    loan::{ExposureLimits, LoanDetails, LoanRequest, LoanStatus},
+    metrics,
+    notification::{Notification, NotificationSubscription},
+    openapi, problem,
+    rate_limit::RateLimiter,
+    swap_batch::SwapBatchError,
+    unix_timestamp, Bobtimus, LatestRate, RateSubscription,
+};
 use anyhow::Context;
+use baru::{input::Input, loan::LoanResponse};
+use bobtimus_client::{
+    pset, AliceInput, CreateSwapPayload, FinalizeLoanPayload, PsetError, PsetLoanResponse, Receipt,
+    Statement, IDEMPOTENCY_KEY_HEADER, IDENTITY_HEADER, PSET_CONTENT_TYPE, SIGNATURE_HEADER,
+};
 use elements::{
     encode::serialize_hex,
     secp256k1_zkp::rand::{thread_rng, CryptoRng, RngCore},
-    Transaction,
+    OutPoint, Transaction, Txid,
 };
-use futures::{StreamExt, TryStreamExt};
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
+use http_api_problem::HttpApiProblem;
 use rust_embed::RustEmbed;
-use std::{error::Error, fmt, sync::Arc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 use tokio::sync::Mutex;
 use warp::{
     filters::BoxedFilter,
-    http::{header::HeaderValue, HeaderMap},
+    http::{
+        header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    hyper::body::Bytes,
     path::Tail,
     reply::Response,
     Filter, Rejection, Reply,
@@ -21,14 +52,75 @@ use warp::{
 #[folder = "../waves/dist/"]
 struct Waves;
 
+/// How requests to the admin API or the metrics are authenticated
+#[derive(Debug, Clone)]
+pub enum AdminAuth {
+    /// The routes are not served
+    Disabled,
+    /// Every request is accepted, for the admin API only on a loopback address
+    Open,
+    /// Requests have to carry the token as `Authorization: Bearer <token>`
+    Token(String),
+}
+
 pub fn routes<R, RS>(
     bobtimus: Arc<Mutex<Bobtimus<R, RS>>>,
     latest_rate_subscription: RateSubscription,
+    notification_subscription: NotificationSubscription,
+    readiness_report: ReadinessReport,
+    admin_auth: AdminAuth,
+    metrics_auth: AdminAuth,
+    rate_limiter: Arc<RateLimiter>,
 ) -> BoxedFilter<(impl Reply,)>
 where
     R: RngCore + CryptoRng + Clone + Send + Sync + 'static,
     RS: LatestRate + Clone + Send + Sync + 'static,
 {
+    // Has to come before `index_html`, which matches every path
+    let max_body_bytes = rate_limiter.max_body_bytes();
+    let admin = admin_routes(
+        bobtimus.clone(),
+        readiness_report.clone(),
+        admin_auth,
+        max_body_bytes,
+    );
+    let metrics = metrics_routes(bobtimus.clone(), metrics_auth);
+
+    // Applied to the routes which make us build transactions, after the
+    // path has matched so that other routes do not use up the quota
+    let client_ip = client_ip(rate_limiter.clone());
+    let rate_limit = rate_limit(rate_limiter);
+
+    let liveness = warp::get()
+        .and(warp::path!("health" / "live"))
+        .map(|| warp::reply::json(&Liveness { alive: true }));
+
+    let readiness = warp::get()
+        .and(warp::path!("health" / "ready"))
+        .and_then(move || {
+            let readiness_report = readiness_report.clone();
+            async move {
+                let now = unix_timestamp(SystemTime::now())
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)?;
+                let readiness = readiness_report.status(now);
+                let status = if readiness.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+
+                Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&readiness),
+                    status,
+                ))
+            }
+        });
+    let openapi = openapi::document();
+    let openapi = warp::get()
+        .and(warp::path!("api" / "openapi.json"))
+        .map(move || warp::reply::json(&openapi));
+
     let index_html = warp::get().and(warp::path::tail()).and_then(serve_index);
     let waves_resources = warp::get()
         .and(warp::path("app"))
@@ -41,23 +133,142 @@ where
 
     let latest_rate = warp::get()
         .and(warp::path!("api" / "rate" / "lbtc-lusdt"))
-        .map(move || latest_rate(latest_rate_subscription.clone()))
-        .with(warp::reply::with::headers(sse_headers));
+        .map({
+            let latest_rate_subscription = latest_rate_subscription.clone();
+            move || latest_rate(latest_rate_subscription.clone())
+        })
+        .with(warp::reply::with::headers(sse_headers.clone()));
 
     let create_buy_swap = warp::post()
         .and(warp::path!("api" / "swap" / "lbtc-lusdt" / "buy"))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(idempotency_key())
+        .and(client_ip.clone())
+        .and(transaction_format())
         .and(warp::body::json())
         .and_then({
             let bobtimus = bobtimus.clone();
-            move |payload| {
+            move |idempotency_key, taker, format, payload| {
                 let bobtimus = bobtimus.clone();
                 async move {
-                    bobtimus
+                    let mut bobtimus = bobtimus.lock().await;
+
+                    swap_reply(
+                        &mut bobtimus,
+                        SwapSide::Buy,
+                        idempotency_key,
+                        taker,
+                        format,
+                        payload,
+                    )
+                    .await
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let create_sell_swap = warp::post()
+        .and(warp::path!("api" / "swap" / "lbtc-lusdt" / "sell"))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(idempotency_key())
+        .and(client_ip.clone())
+        .and(transaction_format())
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |idempotency_key, taker, format, payload| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    let mut bobtimus = bobtimus.lock().await;
+
+                    swap_reply(
+                        &mut bobtimus,
+                        SwapSide::Sell,
+                        idempotency_key,
+                        taker,
+                        format,
+                        payload,
+                    )
+                    .await
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let create_batched_buy_swap = warp::post()
+        .and(warp::path!("api" / "swap" / "lbtc-lusdt" / "buy" / "batch"))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(client_ip.clone())
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |taker, payload| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    batch_swap_reply(&bobtimus, SwapSide::Buy, taker, payload)
+                        .await
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let create_batched_sell_swap = warp::post()
+        .and(warp::path!(
+            "api" / "swap" / "lbtc-lusdt" / "sell" / "batch"
+        ))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(client_ip.clone())
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |taker, payload| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    batch_swap_reply(&bobtimus, SwapSide::Sell, taker, payload)
+                        .await
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let sign_swap_batch = warp::post()
+        .and(warp::path!(
+            "api" / "swap" / "lbtc-lusdt" / "batch" / "sign"
+        ))
+        .and(signed_transaction(max_body_bytes))
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |transaction: SignedTransaction| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    let transaction = transaction
+                        .into_transaction()
+                        .map_err(anyhow::Error::from)
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)?;
+
+                    // The lock is released before waiting for the other
+                    // takers of the batch to sign
+                    let broadcast = bobtimus
                         .lock()
                         .await
-                        .handle_create_buy_swap(payload)
+                        .sign_swap_batch(transaction)
                         .await
-                        .map(|transaction| serialize_hex(&transaction))
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)?;
+
+                    broadcast
+                        .await
+                        .unwrap_or(Err(SwapBatchError::NotBuilt))
+                        .map(|txid| warp::reply::json(&txid))
                         .map_err(anyhow::Error::from)
                         .map_err(problem::from_anyhow)
                         .map_err(warp::reject::custom)
@@ -65,20 +276,91 @@ where
             }
         });
 
-    let create_sell_swap = warp::post()
-        .and(warp::path!("api" / "swap" / "lbtc-lusdt" / "sell"))
+    let offer_loan = warp::get()
+        .and(warp::path!("api" / "loan" / "lbtc-lusdt"))
+        .and(rate_limit.clone())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move || {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    let mut bobtimus = bobtimus.lock().await;
+
+                    let loan_offer = bobtimus.handle_loan_offer_request().await;
+                    quote_reply(&bobtimus, loan_offer)
+                        .await
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let take_loan = warp::post()
+        .and(warp::path!("api" / "loan" / "lbtc-lusdt"))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(idempotency_key())
+        .and(client_ip.clone())
+        .and(transaction_format())
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |idempotency_key, borrower, format, payload: LoanRequest| {
+                let bobtimus = bobtimus.clone();
+
+                async move {
+                    let mut bobtimus = bobtimus.lock().await;
+                    let borrower_inputs = borrower_inputs(&payload.collateral_inputs);
+
+                    let loan_response = bobtimus
+                        .handle_idempotent_loan_request(idempotency_key, borrower, payload)
+                        .await;
+                    loan_reply(&bobtimus, format, loan_response, &borrower_inputs)
+                        .await
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let repayment_quote = warp::get()
+        .and(warp::path!(
+            "api" / "loan" / "lbtc-lusdt" / Txid / "repayment"
+        ))
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |loan_txid| {
+                let bobtimus = bobtimus.clone();
+
+                async move {
+                    let mut bobtimus = bobtimus.lock().await;
+
+                    let quote = bobtimus.handle_repayment_quote_request(loan_txid).await;
+                    quote_reply(&bobtimus, quote)
+                        .await
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let repay_loan = warp::post()
+        .and(warp::path!("api" / "loan" / "lbtc-lusdt" / "repay"))
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(max_body_bytes))
         .and(warp::body::json())
         .and_then({
             let bobtimus = bobtimus.clone();
             move |payload| {
                 let bobtimus = bobtimus.clone();
+
                 async move {
                     bobtimus
                         .lock()
                         .await
-                        .handle_create_sell_swap(payload)
+                        .handle_repayment_request(payload)
                         .await
-                        .map(|transaction| serialize_hex(&transaction))
+                        .map(|repayment_response| warp::reply::json(&repayment_response))
                         .map_err(anyhow::Error::from)
                         .map_err(problem::from_anyhow)
                         .map_err(warp::reject::custom)
@@ -86,19 +368,29 @@ where
             }
         });
 
-    let offer_loan = warp::get()
-        .and(warp::path!("api" / "loan" / "lbtc-lusdt"))
+    let finalize_repayment = warp::post()
+        .and(warp::path!(
+            "api" / "loan" / "lbtc-lusdt" / "repay" / "finalize"
+        ))
+        .and(signed_transaction(max_body_bytes))
         .and_then({
             let bobtimus = bobtimus.clone();
-            move || {
+            move |transaction: SignedTransaction| {
                 let bobtimus = bobtimus.clone();
+
                 async move {
+                    let transaction = transaction
+                        .into_transaction()
+                        .map_err(anyhow::Error::from)
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)?;
+
                     bobtimus
                         .lock()
                         .await
-                        .handle_loan_offer_request()
+                        .finalize_repayment(transaction)
                         .await
-                        .map(|loan_offer| warp::reply::json(&loan_offer))
+                        .map(|txid| warp::reply::json(&txid))
                         .map_err(anyhow::Error::from)
                         .map_err(problem::from_anyhow)
                         .map_err(warp::reject::custom)
@@ -106,21 +398,20 @@ where
             }
         });
 
-    let take_loan = warp::post()
-        .and(warp::path!("api" / "loan" / "lbtc-lusdt"))
-        .and(warp::body::json())
+    let loan_status = warp::get()
+        .and(warp::path!("api" / "loan" / Txid))
         .and_then({
             let bobtimus = bobtimus.clone();
-            move |payload| {
+            move |loan_txid| {
                 let bobtimus = bobtimus.clone();
 
                 async move {
                     bobtimus
                         .lock()
                         .await
-                        .handle_loan_request(payload)
+                        .handle_loan_status_request(loan_txid)
                         .await
-                        .map(|loan_response| warp::reply::json(&loan_response))
+                        .map(|loan_details| warp::reply::json(&loan_details))
                         .map_err(anyhow::Error::from)
                         .map_err(problem::from_anyhow)
                         .map_err(warp::reject::custom)
@@ -128,16 +419,49 @@ where
             }
         });
 
+    let loan_status_updates = warp::get()
+        .and(warp::path!("api" / "loan" / Txid / "status"))
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |loan_txid| {
+                let bobtimus = bobtimus.clone();
+                let latest_rate_subscription = latest_rate_subscription.clone();
+                let notifications = notification_subscription
+                    .clone()
+                    .into_stream()
+                    .filter(move |notification| future::ready(notification.loan_txid == loan_txid));
+
+                async move {
+                    bobtimus
+                        .lock()
+                        .await
+                        .subscribe_to_loan_status(loan_txid, latest_rate_subscription)
+                        .await
+                        .map(|updates| loan_status_updates(updates, notifications))
+                        .map_err(anyhow::Error::from)
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        })
+        .with(warp::reply::with::headers(sse_headers));
+
     let finalize_loan = warp::post()
         .and(warp::path!("api" / "loan" / "lbtc-lusdt" / "finalize"))
-        .and(warp::body::json())
-        .and_then(move |payload: FinalizeLoanPayload| {
+        .and(signed_transaction(max_body_bytes))
+        .and_then(move |transaction: SignedTransaction| {
             let bobtimus = bobtimus.clone();
             async move {
+                let transaction = transaction
+                    .into_transaction()
+                    .map_err(anyhow::Error::from)
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)?;
+
                 bobtimus
                     .lock()
                     .await
-                    .finalize_loan(payload.tx_hex)
+                    .finalize_loan(transaction)
                     .await
                     .map(|loan_response| warp::reply::json(&loan_response))
                     .map_err(anyhow::Error::from)
@@ -149,61 +473,605 @@ where
     latest_rate
         .or(create_sell_swap)
         .or(create_buy_swap)
+        .or(create_batched_sell_swap)
+        .or(create_batched_buy_swap)
+        .or(sign_swap_batch)
         .or(offer_loan)
         .or(take_loan)
+        .or(repayment_quote)
+        .or(repay_loan)
+        .or(finalize_repayment)
         .or(finalize_loan)
+        .or(loan_status)
+        .or(loan_status_updates)
+        .or(liveness)
+        .or(readiness)
+        .or(openapi)
+        .or(admin)
+        .or(metrics)
         .or(waves_resources)
         .or(index_html)
         .recover(problem::unpack_problem)
+        .with(record_responses())
+        .boxed()
+}
+
+/// Routes for operating Bobtimus, all under `/admin`.
+pub fn admin_routes<R, RS>(
+    bobtimus: Arc<Mutex<Bobtimus<R, RS>>>,
+    readiness_report: ReadinessReport,
+    auth: AdminAuth,
+    max_body_bytes: u64,
+) -> BoxedFilter<(impl Reply,)>
+where
+    R: RngCore + CryptoRng + Clone + Send + Sync + 'static,
+    RS: LatestRate + Clone + Send + Sync + 'static,
+{
+    let balances = warp::get().and(warp::path!("balances")).and_then({
+        let bobtimus = bobtimus.clone();
+        move || {
+            let bobtimus = bobtimus.clone();
+            async move {
+                bobtimus
+                    .lock()
+                    .await
+                    .handle_balances_request()
+                    .await
+                    .map(|balances| warp::reply::json(&balances))
+                    .map_err(anyhow::Error::from)
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+            }
+        }
+    });
+
+    let health =
+        warp::get()
+            .and(warp::path!("health"))
+            .map(move || match readiness_report.latest() {
+                Some(readiness) => {
+                    warp::reply::with_status(warp::reply::json(&readiness), StatusCode::OK)
+                }
+                None => warp::reply::with_status(
+                    warp::reply::json(&"the readiness checks have not run yet"),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+            });
+
+    let negotiations = warp::get().and(warp::path!("negotiations")).and_then({
+        let bobtimus = bobtimus.clone();
+        move || {
+            let bobtimus = bobtimus.clone();
+            async move {
+                let negotiations = bobtimus.lock().await.handle_negotiations_request();
+
+                Ok::<_, Rejection>(warp::reply::json(&negotiations))
+            }
+        }
+    });
+
+    let pause_state = warp::get().and(warp::path!("pause")).and_then({
+        let bobtimus = bobtimus.clone();
+        move || {
+            let bobtimus = bobtimus.clone();
+            async move { Ok::<_, Rejection>(warp::reply::json(&bobtimus.lock().await.paused)) }
+        }
+    });
+
+    let pause = warp::put()
+        .and(warp::path!("pause"))
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |request: PauseRequest| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    let paused = bobtimus.lock().await.handle_pause_request(request);
+
+                    Ok::<_, Rejection>(warp::reply::json(&paused))
+                }
+            }
+        });
+
+    let limits =
+        warp::get().and(warp::path!("limits")).and_then({
+            let bobtimus = bobtimus.clone();
+            move || {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    Ok::<_, Rejection>(warp::reply::json(&bobtimus.lock().await.exposure_limits))
+                }
+            }
+        });
+
+    let update_limits = warp::put()
+        .and(warp::path!("limits"))
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::json())
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |limits: ExposureLimits| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    let limits = bobtimus.lock().await.handle_limits_update(limits);
+
+                    Ok::<_, Rejection>(warp::reply::json(&limits))
+                }
+            }
+        });
+
+    let liquidate_loan = warp::post()
+        .and(warp::path!("loans" / Txid / "liquidate"))
+        .and_then({
+            let bobtimus = bobtimus.clone();
+            move |loan_txid| {
+                let bobtimus = bobtimus.clone();
+                async move {
+                    bobtimus
+                        .lock()
+                        .await
+                        .handle_liquidation_request(loan_txid)
+                        .await
+                        .map(|txid| warp::reply::json(&txid))
+                        .map_err(anyhow::Error::from)
+                        .map_err(problem::from_anyhow)
+                        .map_err(warp::reject::custom)
+                }
+            }
+        });
+
+    let unlock_utxos = warp::post()
+        .and(warp::path!("utxos" / "unlock"))
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::json())
+        .and_then(move |request: UnlockRequest| {
+            let bobtimus = bobtimus.clone();
+            async move {
+                bobtimus
+                    .lock()
+                    .await
+                    .handle_unlock_request(request)
+                    .await
+                    .map(|()| StatusCode::NO_CONTENT)
+                    .map_err(anyhow::Error::from)
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+            }
+        });
+
+    warp::path("admin")
+        .and(authenticate(auth))
+        .and(
+            balances
+                .or(health)
+                .or(negotiations)
+                .or(pause_state)
+                .or(pause)
+                .or(limits)
+                .or(update_limits)
+                .or(liquidate_loan)
+                .or(unlock_utxos),
+        )
+        .recover(problem::unpack_problem)
+        .boxed()
+}
+
+/// The Prometheus metrics under `/metrics`, authenticated
+/// independently of the admin API.
+pub fn metrics_routes<R, RS>(
+    bobtimus: Arc<Mutex<Bobtimus<R, RS>>>,
+    auth: AdminAuth,
+) -> BoxedFilter<(impl Reply,)>
+where
+    R: RngCore + CryptoRng + Clone + Send + Sync + 'static,
+    RS: LatestRate + Clone + Send + Sync + 'static,
+{
+    warp::get()
+        .and(warp::path!("metrics"))
+        .and(authenticate(auth))
+        .and_then(move || {
+            let bobtimus = bobtimus.clone();
+            async move {
+                bobtimus
+                    .lock()
+                    .await
+                    .handle_metrics_request()
+                    .await
+                    .map(|metrics| {
+                        warp::reply::with_header(metrics, CONTENT_TYPE, "text/plain; version=0.0.4")
+                    })
+                    .map_err(anyhow::Error::from)
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+            }
+        })
+        .recover(problem::unpack_problem)
+        .boxed()
+}
+
+/// Count the responses by route and status code.
+pub fn record_responses() -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(|info| metrics::observe_http_response(info.path(), info.status()))
+}
+
+/// Reject requests of clients which exceeded their quota.
+fn rate_limit(rate_limiter: Arc<RateLimiter>) -> BoxedFilter<()> {
+    client_ip(rate_limiter.clone())
+        .and_then(move |ip: Option<IpAddr>| {
+            let rate_limiter = rate_limiter.clone();
+            async move {
+                rate_limiter
+                    .check(ip, Instant::now())
+                    .map_err(anyhow::Error::from)
+                    .map_err(problem::from_anyhow)
+                    .map_err(warp::reject::custom)
+            }
+        })
+        .untuple_one()
+        .boxed()
+}
+
+/// The address of the client, as far as we can tell.
+fn client_ip(rate_limiter: Arc<RateLimiter>) -> BoxedFilter<(Option<IpAddr>,)> {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>, forwarded_for: Option<String>| {
+                rate_limiter.client_ip(remote, forwarded_for.as_deref())
+            },
+        )
+        .boxed()
+}
+
+/// The idempotency key of the request, if the client sent one.
+fn idempotency_key() -> BoxedFilter<(Option<IdempotencyKey>,)> {
+    warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key: Option<String>| async move {
+            key.map(|key| key.parse::<IdempotencyKey>())
+                .transpose()
+                .map_err(anyhow::Error::from)
+                .map_err(problem::from_anyhow)
+                .map_err(warp::reject::custom)
+        })
+        .boxed()
+}
+
+/// How the transaction in the response is encoded, as requested through
+/// the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TransactionFormat {
+    /// Hex-encoded transaction, or our own JSON types for loans
+    Plain,
+    /// Base64-encoded PSET, alongside our own JSON types for loans
+    Pset,
+}
+
+fn transaction_format() -> BoxedFilter<(TransactionFormat,)> {
+    warp::header::optional::<String>("accept")
+        .map(|accept: Option<String>| match accept {
+            Some(accept) if accept.contains(PSET_CONTENT_TYPE) => TransactionFormat::Pset,
+            _ => TransactionFormat::Plain,
+        })
+        .boxed()
+}
+
+/// A transaction signed by the borrower, to be finalized by us.
+enum SignedTransaction {
+    Json(Transaction),
+    Pset(String),
+}
+
+impl SignedTransaction {
+    fn into_transaction(self) -> Result<Transaction, PsetError> {
+        match self {
+            SignedTransaction::Json(transaction) => Ok(transaction),
+            SignedTransaction::Pset(pset) => pset::extract_transaction(&pset::decode(&pset)?),
+        }
+    }
+}
+
+/// The signed transaction, as PSET if the body is declared as such and
+/// as [`FinalizeLoanPayload`] otherwise.
+fn signed_transaction(max_body_bytes: u64) -> BoxedFilter<(SignedTransaction,)> {
+    let pset = warp::header::exact_ignore_case("content-type", PSET_CONTENT_TYPE)
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .map(|body: Bytes| SignedTransaction::Pset(String::from_utf8_lossy(&body).into_owned()));
+    let json = warp::body::content_length_limit(max_body_bytes)
+        .and(warp::body::json())
+        .map(|payload: FinalizeLoanPayload| SignedTransaction::Json(payload.tx_hex));
+
+    pset.or(json).unify().boxed()
+}
+
+async fn swap_reply<R, RS>(
+    bobtimus: &mut Bobtimus<R, RS>,
+    side: SwapSide,
+    idempotency_key: Option<IdempotencyKey>,
+    taker: Option<IpAddr>,
+    format: TransactionFormat,
+    payload: CreateSwapPayload,
+) -> anyhow::Result<Response>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    let taker_inputs = payload
+        .alice_inputs
+        .iter()
+        .map(|input| (input.outpoint, *input))
+        .collect::<HashMap<_, _>>();
+
+    let transaction = bobtimus
+        .handle_idempotent_swap(side, idempotency_key, taker, payload)
+        .await?;
+    let receipt = bobtimus
+        .sign_statement(Statement::Swap {
+            txid: transaction.txid(),
+        })
+        .await?;
+
+    let response = match format {
+        TransactionFormat::Plain => serialize_hex(&transaction).into_response(),
+        TransactionFormat::Pset => {
+            let pset = bobtimus
+                .describe_as_pset(&transaction, &taker_inputs)
+                .await?;
+
+            warp::reply::with_header(pset::encode(&pset), CONTENT_TYPE, PSET_CONTENT_TYPE)
+                .into_response()
+        }
+    };
+
+    Ok(with_receipt(response, &receipt))
+}
+
+/// Queue the swap request for the next batch and reply with the batch
+/// transaction once it is built.
+async fn batch_swap_reply<R, RS>(
+    bobtimus: &Mutex<Bobtimus<R, RS>>,
+    side: SwapSide,
+    taker: Option<IpAddr>,
+    payload: CreateSwapPayload,
+) -> anyhow::Result<Response>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    // The lock is released while waiting, the batch is built by another
+    // task which needs it
+    let transaction = bobtimus
+        .lock()
+        .await
+        .queue_batched_swap(side, taker, payload)
+        .await?;
+    let transaction = transaction.await.unwrap_or(Err(SwapBatchError::NotBuilt))?;
+
+    let receipt = bobtimus
+        .lock()
+        .await
+        .sign_statement(Statement::Swap {
+            txid: transaction.txid(),
+        })
+        .await?;
+
+    Ok(with_receipt(
+        serialize_hex(&transaction).into_response(),
+        &receipt,
+    ))
+}
+
+async fn loan_reply<R, RS>(
+    bobtimus: &Bobtimus<R, RS>,
+    format: TransactionFormat,
+    loan_response: anyhow::Result<LoanResponse>,
+    borrower_inputs: &HashMap<OutPoint, AliceInput>,
+) -> anyhow::Result<Response>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    let loan_response = loan_response?;
+    let receipt = bobtimus
+        .sign_statement(Statement::Loan {
+            txid: loan_response.transaction.txid(),
+        })
+        .await?;
+
+    let response = match format {
+        TransactionFormat::Plain => warp::reply::json(&loan_response).into_response(),
+        TransactionFormat::Pset => {
+            let pset = bobtimus
+                .describe_as_pset(&loan_response.transaction, borrower_inputs)
+                .await?;
+
+            warp::reply::json(&PsetLoanResponse {
+                loan_response,
+                pset: pset::encode(&pset),
+            })
+            .into_response()
+        }
+    };
+
+    Ok(with_receipt(response, &receipt))
+}
+
+/// Reply with a quote, signed over the exact bytes of the body so that
+/// the borrower does not depend on how we serialize it.
+async fn quote_reply<R, RS, T>(
+    bobtimus: &Bobtimus<R, RS>,
+    quote: anyhow::Result<T>,
+) -> anyhow::Result<Response>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+    T: Serialize,
+{
+    let body = serde_json::to_vec(&quote?).context("failed to serialize quote")?;
+    let receipt = bobtimus.sign_statement(Statement::quote(&body)).await?;
+
+    let response = warp::reply::with_header(body, CONTENT_TYPE, "application/json");
+
+    Ok(with_receipt(response.into_response(), &receipt))
+}
+
+/// Add our identity and the signature of the receipt to the response.
+fn with_receipt(mut response: Response, receipt: &Receipt) -> Response {
+    let headers = response.headers_mut();
+    if let Ok(maker) = HeaderValue::from_str(&receipt.maker.to_string()) {
+        headers.insert(IDENTITY_HEADER, maker);
+    }
+    if let Ok(signature) = HeaderValue::from_str(&receipt.signature) {
+        headers.insert(SIGNATURE_HEADER, signature);
+    }
+
+    response
+}
+
+/// The inputs of a borrower, by the output they spend.
+fn borrower_inputs(inputs: &[Input]) -> HashMap<OutPoint, AliceInput> {
+    inputs
+        .iter()
+        .map(|input| {
+            (
+                input.txin,
+                AliceInput::with_blinding_key(input.txin, input.blinding_key),
+            )
+        })
+        .collect()
+}
+
+/// Reject requests to the admin API or the metrics which are not
+/// authorized.
+///
+/// If the routes are disabled, requests are rejected as not found so
+/// that they fall through to the remaining routes.
+fn authenticate(auth: AdminAuth) -> BoxedFilter<()> {
+    warp::header::optional::<String>(AUTHORIZATION.as_str())
+        .and_then(move |authorization: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                let token = match auth {
+                    AdminAuth::Disabled => return Err(warp::reject::not_found()),
+                    AdminAuth::Open => return Ok(()),
+                    AdminAuth::Token(token) => token,
+                };
+
+                let given = authorization
+                    .as_deref()
+                    .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                    .unwrap_or_default();
+
+                if !constant_time_eq(given.as_bytes(), token.as_bytes()) {
+                    return Err(warp::reject::custom(
+                        HttpApiProblem::new("Unauthorized.").set_status(StatusCode::UNAUTHORIZED),
+                    ));
+                }
+
+                Ok(())
+            }
+        })
+        .untuple_one()
         .boxed()
 }
 
-#[derive(serde::Deserialize)]
-struct FinalizeLoanPayload {
-    #[serde(with = "baru::loan::transaction_as_string")]
-    tx_hex: Transaction,
+/// Compare two secrets without leaking the position of the first
+/// difference or their length through the time it takes.
+///
+/// The secrets are hashed first, so that digests of the same length
+/// are compared.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let a = Sha256::digest(a);
+    let b = Sha256::digest(b);
+
+    a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
 }
 
 fn latest_rate(subscription: RateSubscription) -> impl Reply {
     let stream = subscription
         .into_stream()
-        .map_ok(|data| {
-            let event = warp::sse::Event::default()
-                .id(thread_rng().next_u32().to_string())
-                .event("rate")
-                .json_data(data)
-                .context("failed to attach json data to sse event")?;
-
-            Ok(event)
+        .map_ok(|data| sse_event("rate", data))
+        .map(|result| match result {
+            Ok(Ok(ok)) => Ok(ok),
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e),
+        })
+        .err_into::<EventStreamError>();
+    let stream = metrics::track_subscriber("rate", stream);
+
+    warp::sse::reply(warp::sse::keep_alive().stream(stream))
+}
+
+/// Push every status update of a loan as `loan` event.
+///
+/// Additionally, a `margin_call` event is pushed whenever the loan
+/// enters a state in which the borrower has to act to avoid
+/// liquidation, and a `notification` event for every reminder of the
+/// loan's maturity.
+fn loan_status_updates(
+    updates: impl Stream<Item = anyhow::Result<LoanDetails>> + Send + 'static,
+    notifications: impl Stream<Item = Notification> + Send + 'static,
+) -> impl Reply {
+    let mut previous_status = None;
+
+    let stream = updates
+        .map_ok(move |details| {
+            let mut events = vec![sse_event("loan", details)?];
+
+            let is_margin_call = matches!(
+                details.status,
+                LoanStatus::MarginCall | LoanStatus::Undercollateralized
+            );
+            if is_margin_call && previous_status != Some(details.status) {
+                events.push(sse_event("margin_call", details)?);
+            }
+            previous_status = Some(details.status);
+
+            Ok(stream::iter(events.into_iter().map(Ok)))
         })
         .map(|result| match result {
             Ok(Ok(ok)) => Ok(ok),
             Ok(Err(e)) => Err(e),
             Err(e) => Err(e),
         })
-        .err_into::<RateStreamError>();
+        .try_flatten();
+    let notifications = notifications.map(|notification| sse_event("notification", notification));
+
+    let stream = stream::select(stream, notifications).err_into::<EventStreamError>();
+    let stream = metrics::track_subscriber("loan_status", stream);
 
     warp::sse::reply(warp::sse::keep_alive().stream(stream))
 }
 
+fn sse_event(name: &str, data: impl Serialize) -> anyhow::Result<warp::sse::Event> {
+    let event = warp::sse::Event::default()
+        .id(thread_rng().next_u32().to_string())
+        .event(name)
+        .json_data(data)
+        .context("failed to attach json data to sse event")?;
+
+    Ok(event)
+}
+
 #[derive(Debug)]
-struct RateStreamError(anyhow::Error);
+struct EventStreamError(anyhow::Error);
 
-impl fmt::Display for RateStreamError {
+impl fmt::Display for EventStreamError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:#}", self.0)
     }
 }
 
-impl std::error::Error for RateStreamError {
+impl std::error::Error for EventStreamError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         self.0.source()
     }
 }
 
-impl From<anyhow::Error> for RateStreamError {
+impl From<anyhow::Error> for EventStreamError {
     fn from(e: anyhow::Error) -> Self {
-        RateStreamError(e)
+        EventStreamError(e)
     }
 }
 
@@ -226,3 +1094,189 @@ fn serve_impl(path: &str) -> Result<impl Reply, Rejection> {
     );
     Ok(res)
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        admin::PauseState,
+        database::Sqlite,
+        elements_rpc::Client,
+        event::EventBus,
+        fixed_rate,
+        identity::MakerIdentity,
+        rate_limit::{Quota, RequestLimits},
+        swap_watcher::{SwapWatcher, WatcherConfig},
+    };
+    use bobtimus_client::ProblemType;
+    use elements::{
+        bitcoin::{
+            secp256k1::{PublicKey, Secp256k1, SecretKey},
+            PublicKey as BitcoinPublicKey,
+        },
+        secp256k1_zkp::rand::{rngs::StdRng, SeedableRng},
+        Address, AddressParams, AssetId,
+    };
+    use rust_decimal_macros::dec;
+    use std::{str::FromStr, time::Duration};
+
+    /// The public routes of a Bobtimus whose elementsd cannot be
+    /// reached, so every request which needs it fails.
+    pub(crate) fn offline_routes() -> BoxedFilter<(impl Reply,)> {
+        offline_routes_with_metrics(AdminAuth::Disabled)
+    }
+
+    fn offline_routes_with_metrics(metrics_auth: AdminAuth) -> BoxedFilter<(impl Reply,)> {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let rate_service = fixed_rate::Service::new();
+        let rate_subscription = rate_service.subscribe();
+        let events = EventBus::new(db.clone(), Vec::new());
+        let notification_subscription = NotificationSubscription::new(events.clone());
+        let identity_key = BitcoinPublicKey::new(PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[1; 32]).unwrap(),
+        ));
+        // nothing is listening, every request to elementsd fails
+        let elementsd = Client::new("http://127.0.0.1:1".to_owned()).unwrap();
+        let bobtimus = Bobtimus {
+            rng: StdRng::seed_from_u64(0),
+            rate_service,
+            secp: Secp256k1::new(),
+            elementsd: elementsd.clone(),
+            lending_wallet: elementsd.clone(),
+            liquidation_wallet: elementsd,
+            btc_asset_id: AssetId::default(),
+            usdt_asset_id: AssetId::default(),
+            db,
+            loan_offers: HashMap::new(),
+            lender_states: HashMap::new(),
+            repayment_states: HashMap::new(),
+            exposure_limits: ExposureLimits::default(),
+            margin_call_distance: dec!(0.05),
+            grace_period: Duration::from_secs(24 * 60 * 60),
+            max_pending_negotiations: 100,
+            idempotency_key_expiry: Duration::from_secs(60 * 60),
+            min_input_confirmations: 0,
+            input_reservations: Default::default(),
+            swap_watcher: SwapWatcher::new(WatcherConfig {
+                broadcast_timeout: Duration::from_secs(60),
+                max_lapses: 3,
+                lapse_window: Duration::from_secs(60 * 60),
+            }),
+            events,
+            paused: PauseState::default(),
+            identity: MakerIdentity::new(
+                Address::p2pkh(&identity_key, None, &AddressParams::ELEMENTS),
+                identity_key,
+            ),
+            swap_batcher: None,
+        };
+
+        routes(
+            Arc::new(Mutex::new(bobtimus)),
+            rate_subscription,
+            notification_subscription,
+            ReadinessReport::new(Duration::from_secs(30)),
+            AdminAuth::Disabled,
+            metrics_auth,
+            Arc::new(RateLimiter::new(RequestLimits {
+                per_ip: Quota {
+                    per_minute: 0,
+                    burst: 0,
+                },
+                global: Quota {
+                    per_minute: 0,
+                    burst: 0,
+                },
+                trust_forwarded_for: false,
+                max_body_bytes: 1024,
+            })),
+        )
+    }
+
+    /// Serve the offline routes and return a client talking to them.
+    fn client() -> bobtimus_client::Client {
+        let (address, server) = warp::serve(offline_routes()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        bobtimus_client::Client::new(reqwest::Url::parse(&format!("http://{}/", address)).unwrap())
+    }
+
+    fn problem_type(error: bobtimus_client::Error) -> Option<ProblemType> {
+        match error {
+            bobtimus_client::Error::Problem(problem) => problem.problem_type(),
+            e => panic!("expected problem, got {:#}", anyhow::Error::from(e)),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_receives_rate_updates() {
+        let client = client();
+
+        let rate = client.latest_rate().next().await.unwrap().unwrap();
+
+        assert_eq!(
+            rate,
+            bobtimus_client::Rate {
+                ask: 20_000.0,
+                bid: 19_000.0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn client_request_is_understood_and_rejection_is_reported() {
+        let client = client();
+        let request = bobtimus_client::LoanRequest {
+            offer_id: "00".repeat(16),
+            term: 30,
+            principal_amount: elements::bitcoin::Amount::from_sat(1_000_000_000_000),
+            collateralization: 1.5,
+            collateral_amount: elements::bitcoin::Amount::from_sat(30_000_000),
+            collateral_inputs: vec![],
+            borrower_pk: BitcoinPublicKey::from_str(
+                "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+            )
+            .unwrap(),
+            borrower_address: Address::from_str("el1qq0zel5lg55nvhv9kkrq8gme8hnvp0lemuzcmu086dn2m8laxjgkewkhqnh8vxdnlp4cejs3925j0gu9n9krdgmqm89vku0kc8").unwrap(),
+        };
+
+        let error = client.request_loan(&request, None).await.unwrap_err();
+
+        assert_eq!(problem_type(error), Some(ProblemType::UnknownOffer));
+    }
+
+    #[tokio::test]
+    async fn client_reports_failure_to_reach_elementsd() {
+        let client = client();
+
+        let error = client.loan_offer().await.unwrap_err();
+
+        assert!(!error.is_recoverable());
+        assert!(matches!(error, bobtimus_client::Error::Problem(_)));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_without_the_admin_api() {
+        let routes = offline_routes_with_metrics(AdminAuth::Token("secret".to_owned()));
+
+        let unauthorized = warp::test::request().path("/metrics").reply(&routes).await;
+        let authorized = warp::test::request()
+            .path("/metrics")
+            .header(AUTHORIZATION, "Bearer secret")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(authorized.status(), StatusCode::OK);
+        assert!(String::from_utf8_lossy(authorized.body()).contains("bobtimus_open_loans"));
+    }
+
+    #[test]
+    fn secrets_are_equal_only_if_identical() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(!constant_time_eq(b"", b"token"));
+    }
+}
@@ -0,0 +1,372 @@
+//! Idempotency keys for the requests which make us build transactions.
+//!
+//! A client which does not learn about the response to such a request,
+//! e.g. because the connection dropped, can repeat it with the same key
+//! and gets the original transaction back instead of a new one built
+//! from different inputs and possibly at a different rate.
+//!
+//! Keys are scoped to the address of the client, so that clients
+//! cannot run into each other's keys, let alone receive each other's
+//! responses.
+
+use crate::{
+    database::{queries, IdempotencyKeyForm, Sqlite},
+    event::SwapSide,
+    loan::LoanRequest,
+    unix_timestamp, Bobtimus, LatestRate,
+};
+use anyhow::{Context, Result};
+use baru::loan::LoanResponse;
+use bobtimus_client::CreateSwapPayload;
+use elements::{
+    encode::{deserialize, serialize_hex},
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    Transaction,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{convert::TryFrom, net::IpAddr, str::FromStr, time::SystemTime};
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Chosen by the client to identify a request across retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotencyKey(String);
+
+impl FromStr for IdempotencyKey {
+    type Err = InvalidIdempotencyKey;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_printable = s.bytes().all(|byte| byte.is_ascii_graphic());
+
+        if s.is_empty() || s.len() > MAX_KEY_LENGTH || !is_printable {
+            return Err(InvalidIdempotencyKey);
+        }
+
+        Ok(Self(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error(
+    "Idempotency key must consist of 1 to {} printable ASCII characters",
+    MAX_KEY_LENGTH
+)]
+pub struct InvalidIdempotencyKey;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Idempotency key {key} was already used for a different request")]
+pub struct IdempotencyKeyReused {
+    pub key: String,
+}
+
+/// The requests which accept an idempotency key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    BuySwap,
+    SellSwap,
+    Loan,
+}
+
+impl Endpoint {
+    fn as_str(self) -> &'static str {
+        match self {
+            Endpoint::BuySwap => "swap/lbtc-lusdt/buy",
+            Endpoint::SellSwap => "swap/lbtc-lusdt/sell",
+            Endpoint::Loan => "loan/lbtc-lusdt",
+        }
+    }
+}
+
+/// A request made with an idempotency key.
+///
+/// Reusing a key is only allowed for the exact same request, so that a
+/// client with a bug in how it generates keys does not receive a
+/// transaction it did not ask for.
+#[derive(Debug)]
+struct KeyedRequest<'a> {
+    /// The address of the client, empty if it is unknown
+    client: String,
+    key: &'a IdempotencyKey,
+    endpoint: Endpoint,
+    fingerprint: String,
+}
+
+impl<'a> KeyedRequest<'a> {
+    fn new<T>(
+        client: Option<IpAddr>,
+        key: &'a IdempotencyKey,
+        endpoint: Endpoint,
+        payload: &T,
+    ) -> Result<Self>
+    where
+        T: Serialize,
+    {
+        let payload = serde_json::to_vec(payload).context("failed to serialize request")?;
+        let fingerprint = hex::encode(Sha256::digest(&payload));
+
+        Ok(Self {
+            client: client.map(|ip| ip.to_string()).unwrap_or_default(),
+            key,
+            endpoint,
+            fingerprint,
+        })
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Handle a swap request, returning the transaction created for an
+    /// earlier request with the same key if there is one.
+    pub async fn handle_idempotent_swap(
+        &mut self,
+        side: SwapSide,
+        key: Option<IdempotencyKey>,
+        taker: Option<IpAddr>,
+        payload: CreateSwapPayload,
+    ) -> Result<Transaction> {
+        let endpoint = match side {
+            SwapSide::Buy => Endpoint::BuySwap,
+            SwapSide::Sell => Endpoint::SellSwap,
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return self.handle_watched_swap(side, taker, payload).await,
+        };
+        let request = KeyedRequest::new(taker, &key, endpoint, &payload)?;
+
+        if let Some(tx_hex) = self.stored_response(&request).await? {
+            let transaction = deserialize(&hex::decode(tx_hex)?)?;
+            return Ok(transaction);
+        }
+
+        let transaction = self.handle_watched_swap(side, taker, payload).await?;
+        self.store_response(&request, &serialize_hex(&transaction))
+            .await;
+
+        Ok(transaction)
+    }
+
+    /// Handle a loan request, returning the response to an earlier
+    /// request with the same key if there is one.
+    ///
+    /// The state of the lender is only kept in memory, so the response
+    /// can only be finalized if we have not been restarted since.
+    pub async fn handle_idempotent_loan_request(
+        &mut self,
+        key: Option<IdempotencyKey>,
+        borrower: Option<IpAddr>,
+        loan_request: LoanRequest,
+    ) -> Result<LoanResponse> {
+        let key = match key {
+            Some(key) => key,
+            None => return self.handle_loan_request(loan_request).await,
+        };
+        let request = KeyedRequest::new(borrower, &key, Endpoint::Loan, &loan_request)?;
+
+        if let Some(loan_response) = self.stored_response(&request).await? {
+            let loan_response = serde_json::from_str(&loan_response)
+                .context("failed to deserialize stored loan response")?;
+            return Ok(loan_response);
+        }
+
+        let loan_response = self.handle_loan_request(loan_request).await?;
+        let serialized =
+            serde_json::to_string(&loan_response).context("failed to serialize loan response")?;
+        self.store_response(&request, &serialized).await;
+
+        Ok(loan_response)
+    }
+
+    async fn stored_response(&self, request: &KeyedRequest<'_>) -> Result<Option<String>> {
+        let now = unix_timestamp(SystemTime::now())?;
+        let expiry = u32::try_from(self.idempotency_key_expiry.as_secs()).unwrap_or(u32::MAX);
+
+        stored_response(&self.db, request, now.saturating_sub(expiry)).await
+    }
+
+    /// Failing to store the response does not fail the request, the
+    /// client would otherwise never learn about a transaction which
+    /// has already been signed.
+    async fn store_response(&self, request: &KeyedRequest<'_>, response: &str) {
+        let result = async {
+            let now = unix_timestamp(SystemTime::now())?;
+            store_response(&self.db, request, response, now).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!(
+                "Failed to store response for idempotency key {}: {:#}",
+                request.key.0,
+                e
+            );
+        }
+    }
+}
+
+/// The response stored for the key of the request, ignoring responses
+/// stored before `cutoff`.
+async fn stored_response(
+    db: &Sqlite,
+    request: &KeyedRequest<'_>,
+    cutoff: u32,
+) -> Result<Option<String>> {
+    let stored = db
+        .do_in_transaction(|conn| {
+            queries::delete_stored_responses_before(conn, cutoff)?;
+            queries::get_stored_response(conn, &request.client, &request.key.0)
+        })
+        .await?;
+
+    match stored {
+        None => Ok(None),
+        Some(stored)
+            if stored.endpoint == request.endpoint.as_str()
+                && stored.fingerprint == request.fingerprint =>
+        {
+            Ok(Some(stored.response))
+        }
+        Some(_) => Err(IdempotencyKeyReused {
+            key: request.key.0.clone(),
+        }
+        .into()),
+    }
+}
+
+async fn store_response(
+    db: &Sqlite,
+    request: &KeyedRequest<'_>,
+    response: &str,
+    now: u32,
+) -> Result<()> {
+    db.do_in_transaction(|conn| {
+        IdempotencyKeyForm::new(
+            &request.client,
+            &request.key.0,
+            request.endpoint.as_str(),
+            &request.fingerprint,
+            response,
+            now,
+        )
+        .insert(conn)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> IdempotencyKey {
+        key.parse().unwrap()
+    }
+
+    #[test]
+    fn keys_have_to_be_printable_and_not_too_long() {
+        assert!("5f0c6a1e-8d2b-4a51-9f0e-3c1b7d2e4a90"
+            .parse::<IdempotencyKey>()
+            .is_ok());
+
+        assert_eq!("".parse::<IdempotencyKey>(), Err(InvalidIdempotencyKey));
+        assert_eq!(
+            "with space".parse::<IdempotencyKey>(),
+            Err(InvalidIdempotencyKey)
+        );
+        assert_eq!(
+            "a".repeat(MAX_KEY_LENGTH + 1).parse::<IdempotencyKey>(),
+            Err(InvalidIdempotencyKey)
+        );
+    }
+
+    #[tokio::test]
+    async fn stored_response_is_returned_for_same_request() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let key = key("key");
+        let request = KeyedRequest::new(None, &key, Endpoint::BuySwap, &"payload").unwrap();
+
+        assert_eq!(stored_response(&db, &request, 0).await.unwrap(), None);
+
+        store_response(&db, &request, "response", 100)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stored_response(&db, &request, 0).await.unwrap(),
+            Some("response".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn key_cannot_be_reused_for_different_request() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let key = key("key");
+        let request = KeyedRequest::new(None, &key, Endpoint::BuySwap, &"payload").unwrap();
+        store_response(&db, &request, "response", 100)
+            .await
+            .unwrap();
+
+        let other_payload = KeyedRequest::new(None, &key, Endpoint::BuySwap, &"other").unwrap();
+        let other_endpoint = KeyedRequest::new(None, &key, Endpoint::SellSwap, &"payload").unwrap();
+
+        for request in [other_payload, other_endpoint].iter() {
+            let error = stored_response(&db, request, 0).await.unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<IdempotencyKeyReused>(),
+                Some(&IdempotencyKeyReused {
+                    key: "key".to_owned()
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_keys_can_be_used_again() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let key = key("key");
+        let request = KeyedRequest::new(None, &key, Endpoint::BuySwap, &"payload").unwrap();
+        store_response(&db, &request, "response", 100)
+            .await
+            .unwrap();
+
+        let other_request = KeyedRequest::new(None, &key, Endpoint::Loan, &"other").unwrap();
+
+        assert_eq!(
+            stored_response(&db, &other_request, 101).await.unwrap(),
+            None
+        );
+        store_response(&db, &other_request, "other response", 200)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_are_scoped_to_the_client() {
+        let db = Sqlite::new_ephemeral_db().unwrap();
+        let key = key("key");
+        let client = Some(IpAddr::from([203, 0, 113, 7]));
+        let other_client = Some(IpAddr::from([198, 51, 100, 1]));
+        let request = KeyedRequest::new(client, &key, Endpoint::BuySwap, &"payload").unwrap();
+        store_response(&db, &request, "response", 100)
+            .await
+            .unwrap();
+
+        let same_payload =
+            KeyedRequest::new(other_client, &key, Endpoint::BuySwap, &"payload").unwrap();
+        let other_payload =
+            KeyedRequest::new(other_client, &key, Endpoint::BuySwap, &"other").unwrap();
+
+        assert_eq!(stored_response(&db, &same_payload, 0).await.unwrap(), None);
+        assert_eq!(stored_response(&db, &other_payload, 0).await.unwrap(), None);
+        store_response(&db, &other_payload, "other response", 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            stored_response(&db, &request, 0).await.unwrap(),
+            Some("response".to_owned())
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! The long-term identity key of Bobtimus, with which it signs the
+//! quotes, swap transactions and loan responses it hands out.
+//!
+//! The key belongs to a legacy address of the elementsd wallet, so that
+//! it is backed up with the wallet and never leaves elementsd. The
+//! address is found again through its label after a restart.
+
+use crate::{elements_rpc::Client, Bobtimus, LatestRate};
+use anyhow::{Context, Result};
+use bobtimus_client::{Receipt, Statement};
+use elements::{
+    bitcoin::PublicKey,
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    Address,
+};
+use std::str::FromStr;
+
+/// Label of the address whose key is our identity.
+const IDENTITY_LABEL: &str = "bobtimus-identity";
+
+#[derive(Debug, Clone)]
+pub struct MakerIdentity {
+    address: Address,
+    public_key: PublicKey,
+}
+
+impl MakerIdentity {
+    pub fn new(address: Address, public_key: PublicKey) -> Self {
+        Self {
+            address,
+            public_key,
+        }
+    }
+
+    /// Load our identity from the wallet of elementsd, creating it if
+    /// the wallet does not have one yet.
+    pub async fn load(elementsd: &Client) -> Result<Self> {
+        let address = match elementsd
+            .get_addresses_by_label(IDENTITY_LABEL)
+            .await?
+            .into_iter()
+            .next()
+        {
+            Some(address) => address,
+            None => {
+                tracing::info!("Creating new maker identity");
+                elementsd
+                    .get_new_labelled_legacy_address(IDENTITY_LABEL)
+                    .await?
+            }
+        };
+
+        let info = elementsd.get_address_info(&address).await?;
+        let public_key = info.pubkey.context("identity address has no public key")?;
+        let public_key = PublicKey::from_str(&public_key)?;
+
+        Ok(Self {
+            address: info.unconfidential,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Sign a statement about a response with our identity key.
+    pub async fn sign_statement(&self, statement: Statement) -> Result<Receipt> {
+        let signature = self
+            .elementsd
+            .sign_message(&self.identity.address, statement.message())
+            .await
+            .with_context(|| format!("failed to sign statement \"{}\"", statement))?;
+
+        Ok(Receipt {
+            maker: self.identity.public_key,
+            statement,
+            signature: signature.to_base64(),
+        })
+    }
+}
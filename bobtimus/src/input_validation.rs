@@ -0,0 +1,299 @@
+//! Checks on the inputs takers and borrowers bring into a transaction,
+//! made before we select inputs of our own for it.
+//!
+//! Without them, a transaction built on an input which is already spent
+//! only fails once it is broadcast, after our inputs have been handed
+//! out to the taker.
+
+use crate::{AliceInput, Bobtimus, LatestRate};
+use anyhow::{Context, Result};
+use elements::{
+    confidential::{Asset, Value},
+    secp256k1_zkp::{
+        rand::{CryptoRng, RngCore},
+        Secp256k1, Signing,
+    },
+    OutPoint, TxOut, TxOutSecrets,
+};
+use futures::future;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+/// How long an input is considered to be part of a transaction we handed
+/// out, after which we assume the transaction was abandoned.
+const RESERVATION_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum InputValidationError {
+    #[error("Input {0} is given more than once")]
+    Duplicate(OutPoint),
+    #[error("Input {0} does not exist or is already spent")]
+    Spent(OutPoint),
+    #[error(
+        "Input {outpoint} has {confirmations} confirmations, but {min_confirmations} are required"
+    )]
+    Unconfirmed {
+        outpoint: OutPoint,
+        confirmations: u32,
+        min_confirmations: u32,
+    },
+    #[error("Input {0} is already part of a pending swap or loan")]
+    AlreadyReserved(OutPoint),
+    #[error("Input {0} has to come with either its blinding key or its secrets")]
+    SecretsMissing(OutPoint),
+    #[error("Secrets of input {0} do not match the commitments of the output it spends")]
+    SecretsMismatch(OutPoint),
+}
+
+/// The inputs of takers and borrowers in transactions we handed out,
+/// but have not seen being broadcast.
+#[derive(Debug, Default)]
+pub struct InputReservations {
+    reserved_until: HashMap<OutPoint, SystemTime>,
+}
+
+impl InputReservations {
+    fn is_reserved(&self, outpoint: &OutPoint, now: SystemTime) -> bool {
+        self.reserved_until
+            .get(outpoint)
+            .map(|until| *until > now)
+            .unwrap_or(false)
+    }
+
+    fn reserve(&mut self, outpoints: impl IntoIterator<Item = OutPoint>, now: SystemTime) {
+        let until = now + RESERVATION_PERIOD;
+
+        self.reserved_until
+            .retain(|_, reserved_until| *reserved_until > now);
+        self.reserved_until
+            .extend(outpoints.into_iter().map(|outpoint| (outpoint, until)));
+    }
+
+    /// Make the inputs available again, e.g. because the transaction
+    /// they were reserved for conflicts with another one.
+    pub fn release(&mut self, outpoints: impl IntoIterator<Item = OutPoint>) {
+        for outpoint in outpoints {
+            self.reserved_until.remove(&outpoint);
+        }
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Ensure that the inputs of a taker or borrower are unspent,
+    /// confirmed and not part of another transaction we handed out.
+    pub(crate) async fn validate_taker_inputs(
+        &self,
+        outpoints: &[OutPoint],
+        now: SystemTime,
+    ) -> Result<()> {
+        let mut seen = HashSet::new();
+        for outpoint in outpoints {
+            if !seen.insert(outpoint) {
+                return Err(InputValidationError::Duplicate(*outpoint).into());
+            }
+            if self.input_reservations.is_reserved(outpoint, now) {
+                return Err(InputValidationError::AlreadyReserved(*outpoint).into());
+            }
+        }
+
+        let min_confirmations = self.min_input_confirmations;
+        let checks = outpoints.iter().map(|outpoint| async move {
+            // Outputs spent by a transaction in the mempool count as spent
+            let txout = self
+                .elementsd
+                .get_tx_out(*outpoint, true)
+                .await
+                .with_context(|| format!("failed to look up input {}", outpoint))?
+                .ok_or(InputValidationError::Spent(*outpoint))?;
+
+            if txout.confirmations < min_confirmations {
+                return Err(InputValidationError::Unconfirmed {
+                    outpoint: *outpoint,
+                    confirmations: txout.confirmations,
+                    min_confirmations,
+                }
+                .into());
+            }
+
+            Result::<_, anyhow::Error>::Ok(())
+        });
+        future::try_join_all(checks).await?;
+
+        Ok(())
+    }
+
+    /// The asset and value of the output spent by an input of a taker,
+    /// with the factors they are blinded with.
+    ///
+    /// Secrets given by the taker are only trusted if they open the
+    /// commitments of the output.
+    pub(crate) fn unblind_taker_input(
+        &self,
+        input: &AliceInput,
+        txout: &TxOut,
+    ) -> Result<TxOutSecrets> {
+        let outpoint = input.outpoint;
+
+        match (input.blinding_key, input.secrets) {
+            (Some(blinding_key), None) => {
+                let secrets = txout
+                    .unblind(&self.secp, blinding_key)
+                    .with_context(|| format!("failed to unblind input {}", outpoint))?;
+
+                Ok(secrets)
+            }
+            (None, Some(secrets)) => {
+                let secrets = TxOutSecrets::from(secrets);
+                if !opens_commitments(&self.secp, txout, &secrets) {
+                    return Err(InputValidationError::SecretsMismatch(outpoint).into());
+                }
+
+                Ok(secrets)
+            }
+            _ => Err(InputValidationError::SecretsMissing(outpoint).into()),
+        }
+    }
+
+    /// Mark the inputs as part of a transaction we handed out, so that
+    /// they cannot be used in another one for a while.
+    pub(crate) fn reserve_taker_inputs(&mut self, outpoints: &[OutPoint], now: SystemTime) {
+        self.input_reservations
+            .reserve(outpoints.iter().copied(), now);
+    }
+}
+
+/// Whether the asset and value of the output are the ones given,
+/// blinded with the given factors.
+fn opens_commitments<C>(secp: &Secp256k1<C>, txout: &TxOut, secrets: &TxOutSecrets) -> bool
+where
+    C: Signing,
+{
+    if let Asset::Explicit(asset) = txout.asset {
+        return asset == secrets.asset && txout.value == Value::Explicit(secrets.value);
+    }
+
+    let asset = Asset::new_confidential(secp, secrets.asset, secrets.asset_bf);
+    let generator = match asset.commitment() {
+        Some(generator) => generator,
+        None => return false,
+    };
+    let value = Value::new_confidential(secp, secrets.value, generator, secrets.value_bf);
+
+    txout.asset == asset && txout.value == value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::{
+        confidential::{AssetBlindingFactor, Nonce, ValueBlindingFactor},
+        secp256k1_zkp::{rand::thread_rng, SECP256K1},
+        AssetId, Script, TxOutWitness, Txid,
+    };
+    use std::str::FromStr;
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_str(
+                "8f1f6c8a5f1dc1b5a2b1a6c6fb4fce4b59e3dd4c57ad8b1e0a5a1d7a8ab2ef31",
+            )
+            .unwrap(),
+            vout,
+        }
+    }
+
+    fn confidential_txout(secrets: &TxOutSecrets) -> TxOut {
+        let asset = Asset::new_confidential(SECP256K1, secrets.asset, secrets.asset_bf);
+        let value = Value::new_confidential(
+            SECP256K1,
+            secrets.value,
+            asset.commitment().unwrap(),
+            secrets.value_bf,
+        );
+
+        TxOut {
+            asset,
+            value,
+            nonce: Nonce::Null,
+            script_pubkey: Script::default(),
+            witness: TxOutWitness::default(),
+        }
+    }
+
+    #[test]
+    fn secrets_have_to_open_the_commitments() {
+        let asset =
+            AssetId::from_str("6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d")
+                .unwrap();
+        let secrets = TxOutSecrets::new(
+            asset,
+            AssetBlindingFactor::new(&mut thread_rng()),
+            100_000,
+            ValueBlindingFactor::new(&mut thread_rng()),
+        );
+        let txout = confidential_txout(&secrets);
+
+        assert!(opens_commitments(SECP256K1, &txout, &secrets));
+
+        let other_value = TxOutSecrets::new(
+            secrets.asset,
+            secrets.asset_bf,
+            secrets.value + 1,
+            secrets.value_bf,
+        );
+        assert!(!opens_commitments(SECP256K1, &txout, &other_value));
+
+        let other_blinding_factor = TxOutSecrets::new(
+            secrets.asset,
+            AssetBlindingFactor::new(&mut thread_rng()),
+            secrets.value,
+            secrets.value_bf,
+        );
+        assert!(!opens_commitments(
+            SECP256K1,
+            &txout,
+            &other_blinding_factor
+        ));
+    }
+
+    #[test]
+    fn reserved_inputs_are_released_after_reservation_period() {
+        let mut reservations = InputReservations::default();
+        let now = SystemTime::now();
+
+        reservations.reserve(vec![outpoint(0)], now);
+
+        assert!(reservations.is_reserved(&outpoint(0), now));
+        assert!(!reservations.is_reserved(&outpoint(1), now));
+        assert!(!reservations.is_reserved(&outpoint(0), now + RESERVATION_PERIOD));
+    }
+
+    #[test]
+    fn released_inputs_are_no_longer_reserved() {
+        let mut reservations = InputReservations::default();
+        let now = SystemTime::now();
+        reservations.reserve(vec![outpoint(0), outpoint(1)], now);
+
+        reservations.release(vec![outpoint(0)]);
+
+        assert!(!reservations.is_reserved(&outpoint(0), now));
+        assert!(reservations.is_reserved(&outpoint(1), now));
+    }
+
+    #[test]
+    fn expired_reservations_are_forgotten() {
+        let mut reservations = InputReservations::default();
+        let now = SystemTime::now();
+        reservations.reserve(vec![outpoint(0)], now);
+
+        reservations.reserve(vec![outpoint(1)], now + RESERVATION_PERIOD);
+
+        assert_eq!(reservations.reserved_until.len(), 1);
+    }
+}
@@ -1,15 +1,24 @@
-use crate::{LatestRate, LiquidUsdt, Rate, RateSubscription};
+use crate::{metrics, LatestRate, LiquidUsdt, Rate, RateSubscription};
 use anyhow::{anyhow, bail, Result};
-use futures::{SinkExt, StreamExt};
+use futures::{stream::BoxStream, SinkExt, StreamExt};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::sync::watch;
-use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
 use watch::Receiver;
 
 const KRAKEN_WS_URL: &str = "wss://ws.kraken.com";
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
 const SUBSCRIBE_XBT_USD_TICKER_PAYLOAD: &str = r#"
 { "event": "subscribe",
   "pair": [ "XBT/USD" ],
@@ -21,54 +30,75 @@ const SUBSCRIBE_XBT_USD_TICKER_PAYLOAD: &str = r#"
 #[derive(Clone)]
 pub struct RateService {
     receiver: Receiver<Rate>,
+    /// Unix timestamp of the latest rate update, 0 before the first one
+    updated_at: Arc<AtomicU64>,
 }
 
 impl LatestRate for RateService {
     fn latest_rate(&mut self) -> Rate {
         *self.receiver.borrow()
     }
+
+    fn updated_at(&self) -> Option<SystemTime> {
+        match self.updated_at.load(Ordering::Relaxed) {
+            0 => None,
+            timestamp => Some(UNIX_EPOCH + Duration::from_secs(timestamp)),
+        }
+    }
 }
 
 impl RateService {
     pub async fn new() -> Result<Self> {
         let (tx, rx) = watch::channel(Rate::ZERO);
-
-        let (ws, _response) =
-            tokio_tungstenite::connect_async(Url::parse(KRAKEN_WS_URL).expect("valid url")).await?;
-
-        let (mut write, mut read) = ws.split();
-
-        // TODO: Handle the possibility of losing the connection
-        // to the Kraken WS. Currently the stream would produce no
-        // further items, and consumers would assume that the rate
-        // is up to date
-        tokio::spawn(async move {
-            while let Some(msg) = read.next().await {
-                let msg = match msg {
-                    Ok(Message::Text(msg)) => msg,
-                    _ => continue,
-                };
-
-                let ticker = match serde_json::from_str::<TickerUpdate>(&msg) {
-                    Ok(ticker) => ticker,
-                    _ => continue,
-                };
-
-                let rate = match Rate::try_from(ticker) {
-                    Ok(rate) => rate,
-                    Err(e) => {
-                        tracing::error!("could not get rate from ticker update: {}", e);
-                        continue;
+        let updated_at = Arc::new(AtomicU64::new(0));
+
+        let mut read = connect().await?;
+
+        // TODO: Consumers still assume that the rate is up to date
+        // while we are reconnecting. Its age is exposed as metric, so
+        // that a stale rate can at least be noticed.
+        tokio::spawn({
+            let updated_at = updated_at.clone();
+            async move {
+                loop {
+                    while let Some(msg) = read.next().await {
+                        let msg = match msg {
+                            Ok(Message::Text(msg)) => msg,
+                            _ => continue,
+                        };
+
+                        let ticker = match serde_json::from_str::<TickerUpdate>(&msg) {
+                            Ok(ticker) => ticker,
+                            _ => continue,
+                        };
+
+                        let rate = match Rate::try_from(ticker) {
+                            Ok(rate) => rate,
+                            Err(e) => {
+                                tracing::error!("could not get rate from ticker update: {}", e);
+                                continue;
+                            }
+                        };
+
+                        let now = SystemTime::now();
+                        if let Ok(since_the_epoch) = now.duration_since(UNIX_EPOCH) {
+                            updated_at.store(since_the_epoch.as_secs(), Ordering::Relaxed);
+                        }
+                        metrics::rate_updated(now);
+                        let _ = tx.send(rate);
                     }
-                };
 
-                let _ = tx.send(rate);
+                    tracing::warn!("lost connection to Kraken, reconnecting");
+                    read = reconnect().await;
+                    metrics::rate_feed_reconnected();
+                }
             }
         });
 
-        write.send(SUBSCRIBE_XBT_USD_TICKER_PAYLOAD.into()).await?;
-
-        Ok(Self { receiver: rx })
+        Ok(Self {
+            receiver: rx,
+            updated_at,
+        })
     }
 
     pub fn subscribe(&self) -> RateSubscription {
@@ -76,6 +106,34 @@ impl RateService {
     }
 }
 
+/// Connect to the Kraken WS and subscribe to the ticker.
+async fn connect() -> Result<BoxStream<'static, Result<Message, WsError>>> {
+    let (ws, _response) =
+        tokio_tungstenite::connect_async(Url::parse(KRAKEN_WS_URL).expect("valid url")).await?;
+
+    let (mut write, read) = ws.split();
+    write.send(SUBSCRIBE_XBT_USD_TICKER_PAYLOAD.into()).await?;
+
+    Ok(read.boxed())
+}
+
+/// Connect to the Kraken WS again, retrying until it succeeds.
+async fn reconnect() -> BoxStream<'static, Result<Message, WsError>> {
+    let mut delay = RECONNECT_DELAY;
+
+    loop {
+        match connect().await {
+            Ok(read) => return read,
+            Err(e) => {
+                tracing::warn!("could not reconnect to Kraken: {:#}", e);
+
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(transparent)]
 struct TickerUpdate(Vec<TickerField>);
@@ -9,13 +9,13 @@ use crate::{
     database::{queries, Sqlite},
     elements_rpc::{Client, ElementsRpc},
 };
-use anyhow::{Context, Result};
+use admin::{PauseState, ServicePaused};
+use anyhow::{bail, Context, Result};
 use baru::{
     input::Input,
     loan::{Lender0, Lender1, LoanResponse},
-    swap,
 };
-use database::LiquidationForm;
+use database::{LiquidationForm, LoanForm};
 use elements::{
     bitcoin::{
         secp256k1::{All, Secp256k1},
@@ -25,26 +25,56 @@ use elements::{
         rand::{CryptoRng, RngCore},
         SecretKey, SECP256K1,
     },
-    Address, AssetId, OutPoint, Transaction, Txid,
+    Address, AssetId, OutPoint, Transaction, TxOut, TxOutSecrets, Txid,
 };
-use futures::{stream, stream::FuturesUnordered, Stream, TryStreamExt};
-use serde::{Deserialize, Serialize};
+use event::{Event, EventBus, SwapSide};
+use futures::{
+    future,
+    stream::{self, BoxStream, FuturesUnordered},
+    Stream, StreamExt, TryStreamExt,
+};
+use identity::MakerIdentity;
+use input_validation::InputReservations;
+use swap_batch::SwapBatcher;
+use swap_watcher::SwapWatcher;
 use tokio::sync::watch::Receiver;
 
 mod amounts;
 
+pub mod admin;
 pub mod cli;
 pub mod database;
 pub mod elements_rpc;
+pub mod event;
 pub mod fixed_rate;
+pub mod health;
 pub mod http;
+pub mod idempotency;
+pub mod identity;
+pub mod input_validation;
 pub mod kraken;
 pub mod loan;
+pub mod loan_watcher;
+pub mod metrics;
+pub mod notification;
+pub mod openapi;
 pub mod problem;
+pub mod pset;
+pub mod rate_limit;
+pub mod rpc_auth;
 pub mod schema;
+pub mod swap_batch;
+pub mod swap_transaction;
+pub mod swap_watcher;
+pub mod wallets;
+
+pub use bobtimus_client::{AliceInput, CreateSwapPayload};
 
 use crate::loan::{
-    loan_calculation_and_validation, Collateralization, LoanOffer, LoanRequest, Term, ValidatedLoan,
+    calculate_loan_details, calculate_repayment_quote, loan_calculation_and_validation,
+    validate_exposure, Collateralization, Exposure, ExposureLimits, LoanDetails, LoanOffer,
+    LoanRequest, LoanTerms, LoanValidationError, OfferId, RepaymentQuote, RepaymentRequest,
+    RepaymentResponse, Term, ValidatedLoan,
 };
 pub use amounts::*;
 use elements::bitcoin::PublicKey;
@@ -57,28 +87,81 @@ use std::{
 
 pub const USDT_ASSET_ID: &str = "ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2";
 
+/// How long a loan offer can be taken after it was handed out
+const LOAN_OFFER_VALIDITY: Duration = Duration::from_secs(2 * 60);
+
+/// How many unexpired loan offers we remember at most
+///
+/// Once reached, the oldest offer is forgotten for every new one.
+const MAX_LOAN_OFFERS: usize = 1_000;
+
+/// How long an early repayment in full can be finalized after it was
+/// handed out
+const REPAYMENT_VALIDITY: Duration = Duration::from_secs(2 * 60);
+
+/// The number of blocks within which our loan transactions should confirm
+const FEE_CONF_TARGET: u32 = 2;
+
+/// The fee rate in sats per vbyte if elementsd cannot estimate one, which
+/// is the case as long as blocks on Liquid are not full
+const FALLBACK_FEE_RATE: u64 = 1;
+
 pub struct Bobtimus<R, RS> {
     pub rng: R,
     pub rate_service: RS,
     pub secp: Secp256k1<All>,
+    /// The swap wallet, also used for calls which do not depend on a
+    /// wallet
     pub elementsd: Client,
+    /// The wallet the principal of loans is taken from
+    pub lending_wallet: Client,
+    /// The wallet receiving repayments and liquidated collateral
+    pub liquidation_wallet: Client,
     pub btc_asset_id: AssetId,
     pub usdt_asset_id: AssetId,
     pub db: Sqlite,
-    pub lender_states: HashMap<Txid, Lender1>,
+    pub loan_offers: HashMap<OfferId, LoanOffer>,
+    pub lender_states: HashMap<Txid, LenderState>,
+    /// The latest early repayment handed out for each open loan
+    pub repayment_states: HashMap<Txid, RepaymentState>,
+    pub exposure_limits: ExposureLimits,
+    /// How close to the maximum LTV the LTV of a loan has to get for the
+    /// borrower to be warned
+    pub margin_call_distance: Decimal,
+    /// How long after maturity the loans we open are liquidated
+    pub grace_period: Duration,
+    /// How many loans and repayments may be offered to borrowers
+    /// without having been finalized
+    pub max_pending_negotiations: usize,
+    /// How long the response to a swap or loan request is returned
+    /// again for the same idempotency key
+    pub idempotency_key_expiry: Duration,
+    /// How many confirmations the inputs of takers and borrowers need
+    pub min_input_confirmations: u32,
+    pub input_reservations: InputReservations,
+    pub swap_watcher: SwapWatcher,
+    pub events: EventBus,
+    pub paused: PauseState,
+    /// The key with which we sign what we hand out
+    pub identity: MakerIdentity,
+    /// Collects swaps into batches, if swaps are batched
+    pub swap_batcher: Option<SwapBatcher>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CreateSwapPayload {
-    pub alice_inputs: Vec<AliceInput>,
-    pub address: Address,
-    pub amount: u64,
+/// A loan that was offered to a borrower, but has not been finalized yet
+pub struct LenderState {
+    pub lender: Lender1,
+    pub terms: LoanTerms,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
-pub struct AliceInput {
-    pub outpoint: OutPoint,
-    pub blinding_key: SecretKey,
+/// An early repayment of a loan in full that was offered to a borrower,
+/// but has not been finalized yet
+pub struct RepaymentState {
+    /// Our input refunding the interest which has not accrued yet,
+    /// locked in the lending wallet
+    pub refund: Option<OutPoint>,
+    /// Unix timestamp after which the repayment can no longer be finalized
+    pub expires_at: u32,
 }
 
 impl<R, RS> Bobtimus<R, RS>
@@ -92,6 +175,10 @@ where
         &mut self,
         payload: CreateSwapPayload,
     ) -> Result<Transaction> {
+        if self.paused.swaps {
+            return Err(ServicePaused::Swaps.into());
+        }
+
         let usdt_amount = LiquidUsdt::from_satodollar(payload.amount);
         let latest_rate = self.rate_service.latest_rate();
         let btc_amount = latest_rate.sell_base(usdt_amount)?;
@@ -106,6 +193,15 @@ where
             )
             .await?;
 
+        self.events
+            .publish(Event::SwapSigned {
+                txid: transaction.txid(),
+                side: SwapSide::Buy,
+                btc_amount,
+                usdt_amount,
+            })
+            .await;
+
         Ok(transaction)
     }
 
@@ -115,6 +211,10 @@ where
         &mut self,
         payload: CreateSwapPayload,
     ) -> Result<Transaction> {
+        if self.paused.swaps {
+            return Err(ServicePaused::Swaps.into());
+        }
+
         let btc_amount = Amount::from_sat(payload.amount);
         let latest_rate = self.rate_service.latest_rate();
         let usdt_amount = latest_rate.buy_quote(btc_amount.into())?;
@@ -129,6 +229,15 @@ where
             )
             .await?;
 
+        self.events
+            .publish(Event::SwapSigned {
+                txid: transaction.txid(),
+                side: SwapSide::Sell,
+                btc_amount: btc_amount.into(),
+                usdt_amount,
+            })
+            .await;
+
         Ok(transaction)
     }
 
@@ -173,6 +282,55 @@ where
         Ok(bob_inputs)
     }
 
+    /// The outputs spent by the inputs of a taker, with their secrets.
+    async fn unblind_taker_inputs(
+        &self,
+        inputs: &[AliceInput],
+    ) -> Result<Vec<(OutPoint, TxOut, TxOutSecrets)>> {
+        let inputs = inputs
+            .iter()
+            .copied()
+            .map(|input| {
+                let client = self.elementsd.clone();
+                async move {
+                    let outpoint = input.outpoint;
+                    let transaction = client
+                        .get_raw_transaction(outpoint.txid)
+                        .await
+                        .with_context(|| {
+                            format!("failed to fetch transaction {}", outpoint.txid)
+                        })?;
+
+                    let txout = transaction
+                        .output
+                        .get(outpoint.vout as usize)
+                        .with_context(|| {
+                            format!(
+                                "vout index {} is not valid for transaction {}",
+                                outpoint.vout, outpoint.txid
+                            )
+                        })?
+                        .clone();
+
+                    Result::<_, anyhow::Error>::Ok((input, txout))
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // The taker may only disclose the secrets of the inputs instead
+        // of the key which unblinds all outputs sent to their address
+        inputs
+            .into_iter()
+            .map(|(input, txout)| {
+                let secrets = self.unblind_taker_input(&input, &txout)?;
+
+                Ok((input.outpoint, txout, secrets))
+            })
+            .collect()
+    }
+
     async fn swap_transaction(
         &mut self,
         (alice_input_asset_id, alice_input_amount): (AssetId, Amount),
@@ -181,6 +339,13 @@ where
         alice_address: Address,
         btc_asset_id: AssetId,
     ) -> Result<Transaction> {
+        let now = SystemTime::now();
+        let alice_outpoints = alice_inputs
+            .iter()
+            .map(|input| input.outpoint)
+            .collect::<Vec<_>>();
+        self.validate_taker_inputs(&alice_outpoints, now).await?;
+
         let bob_inputs = Self::find_inputs(&self.elementsd, bob_input_asset_id, bob_input_amount)
             .await
             .context("could not find transaction inputs for Bob")?;
@@ -191,66 +356,24 @@ where
             .await
             .context("failed to get redeem address")?;
 
-        let alice_inputs = alice_inputs
-            .iter()
-            .copied()
-            .map(
-                |AliceInput {
-                     outpoint,
-                     blinding_key,
-                 }| {
-                    let client = self.elementsd.clone();
-                    async move {
-                        let transaction = client
-                            .get_raw_transaction(outpoint.txid)
-                            .await
-                            .with_context(|| {
-                                format!("failed to fetch transaction {}", outpoint.txid)
-                            })?;
-
-                        let txout = transaction
-                            .output
-                            .get(outpoint.vout as usize)
-                            .with_context(|| {
-                                format!(
-                                    "vout index {} is not valid for transaction {}",
-                                    outpoint.vout, outpoint.txid
-                                )
-                            })?
-                            .clone();
-
-                        Result::<_, anyhow::Error>::Ok(Input {
-                            txin: outpoint,
-                            original_txout: txout,
-                            blinding_key,
-                        })
-                    }
-                },
-            )
-            .collect::<FuturesUnordered<_>>()
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        let alice = swap::Actor::new(
-            &self.secp,
-            alice_inputs,
-            alice_address,
-            bob_input_asset_id,
-            bob_input_amount,
-        )?;
-
-        let bob = swap::Actor::new(
+        let alice = swap_transaction::Party {
+            inputs: self.unblind_taker_inputs(&alice_inputs).await?,
+            address: alice_address,
+            pays: (alice_input_asset_id, alice_input_amount),
+            receives: (bob_input_asset_id, bob_input_amount),
+        };
+        let bob = swap_transaction::Party::unblind(
             &self.secp,
             bob_inputs,
             bob_address,
-            alice_input_asset_id,
-            alice_input_amount,
+            (bob_input_asset_id, bob_input_amount),
+            (alice_input_asset_id, alice_input_amount),
         )?;
 
-        let transaction = swap::bob_create_transaction(
+        let transaction = swap_transaction::create_transaction(
             &mut self.rng,
             &self.secp,
-            alice,
+            vec![alice],
             bob,
             btc_asset_id,
             Amount::from_sat(1), // TODO: Make this dynamic once there is something going on on Liquid
@@ -265,6 +388,8 @@ where
         )
         .await?;
 
+        self.reserve_taker_inputs(&alice_outpoints, now);
+
         Ok(transaction)
     }
 
@@ -272,15 +397,42 @@ where
     ///
     /// We return the range of possible loan terms to the borrower.
     /// The borrower can then request a loan using parameters that are within our terms.
+    /// The offer is stored so that the loan request can be validated
+    /// against it until it expires or, if too many offers are handed
+    /// out, until it is the oldest one.
     pub async fn handle_loan_offer_request(&mut self) -> Result<LoanOffer> {
-        Ok(self.current_loan_offer())
+        if self.paused.lending {
+            return Err(ServicePaused::Lending.into());
+        }
+
+        let now = SystemTime::now();
+        let loan_offer = self.current_loan_offer(now).await?;
+
+        let now = unix_timestamp(now)?;
+        self.loan_offers.retain(|_, offer| !offer.is_expired(now));
+        if self.loan_offers.len() >= MAX_LOAN_OFFERS {
+            let oldest = self
+                .loan_offers
+                .values()
+                .min_by_key(|offer| offer.expires_at)
+                .map(|offer| offer.id);
+            if let Some(oldest) = oldest {
+                self.loan_offers.remove(&oldest);
+            }
+        }
+        self.loan_offers.insert(loan_offer.id, loan_offer.clone());
+
+        Ok(loan_offer)
     }
 
-    fn current_loan_offer(&mut self) -> LoanOffer {
-        LoanOffer {
+    async fn current_loan_offer(&mut self, now: SystemTime) -> Result<LoanOffer> {
+        let fee_sats_per_vbyte = self.fee_rate().await?;
+
+        Ok(LoanOffer {
+            id: OfferId::random(&mut self.rng),
+            expires_at: unix_timestamp(now + LOAN_OFFER_VALIDITY)?,
             rate: self.rate_service.latest_rate(),
-            // TODO: Dynamic fee estimation
-            fee_sats_per_vbyte: Amount::from_sat(1),
+            fee_sats_per_vbyte,
             min_principal: LiquidUsdt::from_str_in_dollar("100")
                 .expect("static value to be convertible"),
             max_principal: LiquidUsdt::from_str_in_dollar("10000")
@@ -314,29 +466,62 @@ where
                     interest_mod: Decimal::ZERO,
                 },
             ],
-        }
+        })
+    }
+
+    /// The fee rate of the loan transactions we build, in sats per vbyte.
+    async fn fee_rate(&self) -> Result<Amount> {
+        let fee_rate = self
+            .elementsd
+            .estimate_fee_rate(FEE_CONF_TARGET)
+            .await
+            .context("failed to estimate fee rate")?
+            .unwrap_or_else(|| Amount::from_sat(FALLBACK_FEE_RATE));
+
+        Ok(fee_rate)
     }
 
     /// Handle the borrower's loan request in which she puts up L-BTC as
     /// collateral and we lend L-USDt to her which she will have to
     /// repay in the future.
     pub async fn handle_loan_request(&mut self, loan_request: LoanRequest) -> Result<LoanResponse> {
-        let loan_offer = self.current_loan_offer();
-        // TODO: Make configurable
-        let price_fluctuation_interval = (dec!(0.99), dec!(1.01));
+        if self.paused.lending {
+            return Err(ServicePaused::Lending.into());
+        }
+        self.ensure_negotiation_capacity()?;
 
-        // The bid price is used so the lender is covered under the assumption of selling the asset
-        let current_price = self.rate_service.latest_rate().bid;
+        let now = SystemTime::now();
+        let start = unix_timestamp(now)?;
+
+        let offer_id = loan_request.offer_id;
+        let loan_offer = self
+            .loan_offers
+            .get(&offer_id)
+            .cloned()
+            .ok_or(LoanValidationError::UnknownOffer { offer_id })?;
+        if loan_offer.is_expired(start) {
+            return Err(LoanValidationError::OfferExpired {
+                offer_id,
+                expired_at: loan_offer.expires_at,
+            }
+            .into());
+        }
 
         let ValidatedLoan {
             repayment_amount,
             liquidation_price,
-        } = loan_calculation_and_validation(
-            &loan_request,
-            &loan_offer,
-            price_fluctuation_interval,
-            current_price,
-        )?;
+        } = loan_calculation_and_validation(&loan_request, &loan_offer)?;
+
+        self.check_exposure(loan_request.principal_amount, loan_request.borrower_pk)
+            .await?;
+
+        let collateral_outpoints = loan_request
+            .collateral_inputs
+            .iter()
+            .map(|input| input.txin)
+            .collect::<Vec<_>>();
+        self.validate_taker_inputs(&collateral_outpoints, now)
+            .await?;
 
         let oracle_secret_key = elements::secp256k1_zkp::key::ONE_KEY;
         let oralce_priv_key = elements::bitcoin::PrivateKey::new(
@@ -345,16 +530,16 @@ where
         );
         let oracle_pk = PublicKey::from_private_key(&self.secp, &oralce_priv_key);
 
-        let timelock = days_to_unix_timestamp_timelock(loan_request.term, SystemTime::now())?;
+        let timelock = days_to_unix_timestamp_timelock(loan_request.term, now)?;
 
         let lender_address = self
-            .elementsd
+            .liquidation_wallet
             .get_new_segwit_confidential_address()
             .await
             .context("failed to get lender address")?;
 
         let address_blinder = self
-            .elementsd
+            .liquidation_wallet
             .get_address_blinding_key(&lender_address)
             .await?;
 
@@ -368,9 +553,9 @@ where
         )
         .unwrap();
 
-        let elementsd_client = self.elementsd.clone();
+        let lending_wallet = self.lending_wallet.clone();
         let principal_inputs = Self::find_inputs(
-            &elementsd_client,
+            &lending_wallet,
             self.usdt_asset_id,
             loan_request.principal_amount.into(),
         )
@@ -396,12 +581,329 @@ where
 
         let loan_response = lender1.loan_response();
 
-        self.lender_states
-            .insert(loan_response.transaction().txid(), lender1);
+        self.lender_states.insert(
+            loan_response.transaction().txid(),
+            LenderState {
+                lender: lender1,
+                terms: LoanTerms {
+                    principal_amount: loan_request.principal_amount,
+                    repayment_amount,
+                    collateral_amount: loan_request.collateral_amount,
+                    liquidation_price,
+                    max_ltv: loan_offer.max_ltv,
+                    start,
+                    timelock,
+                    borrower_pk: Some(loan_request.borrower_pk),
+                },
+            },
+        );
+        self.reserve_taker_inputs(&collateral_outpoints, now);
 
         Ok(loan_response)
     }
 
+    /// Ensure that lending `principal_amount` to the borrower keeps us
+    /// within our exposure limits.
+    ///
+    /// Loans which were offered, but have not been finalized yet are
+    /// taken into account as if they were open already.
+    async fn check_exposure(
+        &self,
+        principal_amount: LiquidUsdt,
+        borrower_pk: PublicKey,
+    ) -> Result<()> {
+        let (total_principal, borrower_principal) = self
+            .db
+            .do_in_transaction(|conn| {
+                let total_principal = queries::get_outstanding_principal(conn, None)?;
+                let borrower_principal =
+                    queries::get_outstanding_principal(conn, Some(borrower_pk))?;
+
+                Ok((total_principal, borrower_principal))
+            })
+            .await?;
+
+        let pending_loans = self.lender_states.values().map(|state| state.terms);
+        let pending_total_principal = sum_principal(pending_loans.clone())?;
+        let pending_borrower_principal =
+            sum_principal(pending_loans.filter(|terms| terms.borrower_pk == Some(borrower_pk)))?;
+
+        // The principal of pending loans has not left our wallet yet
+        let usdt_balance = self.lending_wallet.get_balance(self.usdt_asset_id).await?;
+        let usdt_balance = usdt_balance
+            .as_sat()
+            .saturating_sub(pending_total_principal);
+
+        let exposure = Exposure {
+            total_principal: LiquidUsdt::from_satodollar(
+                total_principal
+                    .as_satodollar()
+                    .checked_add(pending_total_principal)
+                    .context("addition overflow")?,
+            ),
+            borrower_principal: LiquidUsdt::from_satodollar(
+                borrower_principal
+                    .as_satodollar()
+                    .checked_add(pending_borrower_principal)
+                    .context("addition overflow")?,
+            ),
+            usdt_balance: LiquidUsdt::from_satodollar(usdt_balance),
+        };
+
+        validate_exposure(principal_amount, exposure, &self.exposure_limits)??;
+
+        Ok(())
+    }
+
+    /// Handle the borrower's request for the amount needed to repay an
+    /// open loan in full today.
+    pub async fn handle_repayment_quote_request(
+        &mut self,
+        loan_txid: Txid,
+    ) -> Result<RepaymentQuote> {
+        let (_, terms) = self
+            .db
+            .do_in_transaction(|conn| queries::get_loan(conn, loan_txid))
+            .await?;
+
+        let now = unix_timestamp(SystemTime::now())?;
+        let quote = calculate_repayment_quote(loan_txid, &terms, now)?;
+
+        Ok(quote)
+    }
+
+    /// Handle the borrower's request for the status of an open loan.
+    ///
+    /// The current LTV is calculated with the latest bid price.
+    pub async fn handle_loan_status_request(&mut self, loan_txid: Txid) -> Result<LoanDetails> {
+        let (_, terms) = self
+            .db
+            .do_in_transaction(|conn| queries::get_loan(conn, loan_txid))
+            .await?;
+
+        let current_price = self.rate_service.latest_rate().bid;
+        let now = unix_timestamp(SystemTime::now())?;
+
+        calculate_loan_details(
+            loan_txid,
+            &terms,
+            current_price,
+            self.margin_call_distance,
+            now,
+        )
+    }
+
+    /// Subscribe to the status of an open loan.
+    ///
+    /// The stream starts with the status at the latest rate and yields
+    /// the status again on every rate update. The terms of the loan are
+    /// only read once, so the stream does not notice if the loan is
+    /// repaid or replaced.
+    pub async fn subscribe_to_loan_status(
+        &mut self,
+        loan_txid: Txid,
+        rate_subscription: RateSubscription,
+    ) -> Result<BoxStream<'static, Result<LoanDetails>>> {
+        let (_, terms) = self
+            .db
+            .do_in_transaction(|conn| queries::get_loan(conn, loan_txid))
+            .await?;
+
+        let margin_call_distance = self.margin_call_distance;
+        let latest_rate = self.rate_service.latest_rate();
+
+        let rates = stream::once(future::ok(latest_rate)).chain(rate_subscription.into_stream());
+        let updates = rates.and_then(move |rate| async move {
+            let now = unix_timestamp(SystemTime::now())?;
+
+            calculate_loan_details(loan_txid, &terms, rate.bid, margin_call_distance, now)
+        });
+
+        Ok(updates.boxed())
+    }
+
+    /// Handle the borrower's request to repay an open loan in full
+    /// before the end of its term.
+    ///
+    /// The borrower pays the principal plus the interest accrued until
+    /// today. The collateral contract only releases the collateral
+    /// against the repayment amount at maturity, so we refund the
+    /// difference through an input of the lending wallet. The borrower
+    /// spends it in the repayment transaction, which is finalized
+    /// through [`Bobtimus::finalize_repayment`].
+    ///
+    /// Only the latest repayment handed out for a loan can be finalized.
+    pub async fn handle_repayment_request(
+        &mut self,
+        repayment_request: RepaymentRequest,
+    ) -> Result<RepaymentResponse> {
+        let now = SystemTime::now();
+        let expires_at = unix_timestamp(now + REPAYMENT_VALIDITY)?;
+        let now = unix_timestamp(now)?;
+
+        self.prune_repayment_states(now).await?;
+        self.ensure_negotiation_capacity()?;
+
+        let loan_txid = repayment_request.loan_txid;
+        let (_, terms) = self
+            .db
+            .do_in_transaction(|conn| queries::get_loan(conn, loan_txid))
+            .await?;
+
+        let quote = calculate_repayment_quote(loan_txid, &terms, now)?;
+        let refund_input = if quote.refund_amount == LiquidUsdt::default() {
+            None
+        } else {
+            Some(self.refund_input(quote.refund_amount).await?)
+        };
+
+        let replaced = self.repayment_states.insert(
+            loan_txid,
+            RepaymentState {
+                refund: refund_input.as_ref().map(|input| input.txin),
+                expires_at,
+            },
+        );
+        if let Some(refund) = replaced.and_then(|state| state.refund) {
+            self.lending_wallet.unlock_utxos(Some(vec![refund])).await?;
+        }
+
+        Ok(RepaymentResponse {
+            loan_txid,
+            repayment_amount: quote.repayment_amount,
+            refund_input,
+            expires_at,
+        })
+    }
+
+    /// Send `amount` of L-USDt to a new address of the lending wallet,
+    /// so that it can be handed to a borrower as a single input.
+    ///
+    /// The output is locked, so that the lending wallet does not spend
+    /// it before the repayment is finalized or expires.
+    async fn refund_input(&self, amount: LiquidUsdt) -> Result<Input> {
+        let address = self
+            .lending_wallet
+            .get_new_segwit_confidential_address()
+            .await
+            .context("failed to get refund address")?;
+        let txid = self
+            .lending_wallet
+            .send_asset_to_address(&address, amount.into(), Some(self.usdt_asset_id))
+            .await
+            .context("failed to send refund")?;
+
+        let transaction = self
+            .lending_wallet
+            .get_wallet_transaction(txid)
+            .await?
+            .transaction;
+        let (vout, original_txout) = transaction
+            .output
+            .into_iter()
+            .enumerate()
+            .find(|(_, txout)| txout.script_pubkey == address.script_pubkey())
+            .context("refund transaction does not pay to the refund address")?;
+        let txin = OutPoint {
+            txid,
+            vout: u32::try_from(vout)?,
+        };
+
+        let blinding_key = self
+            .lending_wallet
+            .get_address_blinding_key(&address)
+            .await?;
+
+        self.lending_wallet.lock_utxos(vec![txin]).await?;
+
+        Ok(Input {
+            txin,
+            original_txout,
+            blinding_key,
+        })
+    }
+
+    /// Handle the borrower's request to finalize an early repayment.
+    ///
+    /// We only sign our refund input if the transaction spends the
+    /// collateral of the loan, in which case the collateral contract
+    /// ensures that we are paid the repayment amount. Once the
+    /// transaction is broadcast, we forget about the loan, including its
+    /// liquidation transaction.
+    pub async fn finalize_repayment(&mut self, transaction: Transaction) -> Result<Txid> {
+        let now = unix_timestamp(SystemTime::now())?;
+        self.prune_repayment_states(now).await?;
+
+        // The collateral output of a loan is an output of the loan transaction
+        let (loan_txid, refund) = transaction
+            .input
+            .iter()
+            .find_map(|input| {
+                let loan_txid = input.previous_output.txid;
+                let state = self.repayment_states.get(&loan_txid)?;
+
+                Some((loan_txid, state.refund))
+            })
+            .context("unknown repayment transaction")?;
+
+        let collateral = self
+            .db
+            .do_in_transaction(|conn| queries::get_loan_collateral(conn, loan_txid))
+            .await?;
+        let spends = |outpoint: OutPoint| {
+            transaction
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+        };
+        if !spends(collateral) {
+            bail!(
+                "repayment transaction does not spend the collateral of loan {}",
+                loan_txid
+            );
+        }
+
+        let transaction = match refund {
+            Some(refund) if spends(refund) => {
+                self.lending_wallet
+                    .sign_raw_transaction(&transaction)
+                    .await?
+            }
+            Some(_) => bail!("repayment transaction does not spend our refund"),
+            None => transaction,
+        };
+
+        let txid = self.elementsd.send_raw_transaction(&transaction).await?;
+
+        self.db
+            .do_in_transaction(|conn| queries::delete_loan(conn, loan_txid))
+            .await?;
+        self.repayment_states.remove(&loan_txid);
+
+        Ok(txid)
+    }
+
+    /// Forget the repayments which were offered to borrowers, but have
+    /// not been finalized in time, and let the lending wallet spend
+    /// their refunds again.
+    async fn prune_repayment_states(&mut self, now: u32) -> Result<()> {
+        let mut refunds = Vec::new();
+        self.repayment_states.retain(|_, state| {
+            let is_expired = state.expires_at <= now;
+            if is_expired {
+                refunds.extend(state.refund);
+            }
+
+            !is_expired
+        });
+
+        if !refunds.is_empty() {
+            self.lending_wallet.unlock_utxos(Some(refunds)).await?;
+        }
+
+        Ok(())
+    }
+
     /// Handle the borrower's request to finalize a loan.
     ///
     /// If we still agree with the loan transaction sent by the borrower, we
@@ -415,15 +917,15 @@ where
         // borrower to quickly perform the protocol and let us broadcast
         // the loan transaction
 
-        let lender = self
+        let LenderState { lender, terms } = self
             .lender_states
             .get(&transaction.txid())
             .context("unknown loan transaction")?;
 
         let transaction = lender
             .finalise_loan(transaction, {
-                let elementsd = self.elementsd.clone();
-                |transaction| async move { elementsd.sign_raw_transaction(&transaction).await }
+                let lending_wallet = self.lending_wallet.clone();
+                |transaction| async move { lending_wallet.sign_raw_transaction(&transaction).await }
             })
             .await?;
 
@@ -436,18 +938,36 @@ where
 
         self.db
             .do_in_transaction(|conn| {
-                LiquidationForm::new(txid, &liquidation_tx, *locktime).insert(conn)?;
+                LiquidationForm::new(txid, &liquidation_tx, *locktime, self.grace_period)?
+                    .insert(conn)?;
+                LoanForm::new(txid, lender, *terms)?.insert(conn)?;
 
                 Ok(())
             })
             .await?;
 
+        self.events
+            .publish(Event::LoanOpened {
+                loan_txid: txid,
+                principal_amount: terms.principal_amount,
+                repayment_amount: terms.repayment_amount,
+                collateral_amount: terms.collateral_amount,
+                timelock: terms.timelock,
+            })
+            .await;
+
+        self.lender_states.remove(&txid);
+
         Ok(txid)
     }
 }
 
 pub trait LatestRate {
     fn latest_rate(&mut self) -> Rate;
+
+    /// When the latest rate was received, `None` if no rate was
+    /// received yet.
+    fn updated_at(&self) -> Option<SystemTime>;
 }
 
 #[derive(Clone)]
@@ -476,28 +996,59 @@ impl RateSubscription {
     }
 }
 
-pub async fn liquidate_loans(elementsd: &Client, db: Sqlite) -> Result<()> {
+/// Broadcast the liquidation transactions of all loans whose timelock
+/// was reached at least their grace period ago.
+///
+/// The grace period gives borrowers the chance to repay their loan
+/// after it matured, once they have been notified. It is stored with
+/// every loan when the loan is opened.
+pub async fn liquidate_loans(elementsd: &Client, db: Sqlite, events: &EventBus) -> Result<()> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let secs_since_epoch = now.as_secs();
+        .expect("Time went backwards")
+        .as_secs();
 
     let liquidation_txs = db
         .do_in_transaction(|conn| {
-            let txs = queries::get_publishable_liquidations_txs(conn, secs_since_epoch)?;
+            let txs = queries::get_publishable_liquidations_txs(conn, now)?;
             Ok(txs)
         })
         .await?;
 
     for tx in liquidation_txs.iter() {
-        match elementsd.send_raw_transaction(tx).await {
-            Ok(txid) => log::info!("Broadcast liquidation transaction {}", txid),
-            Err(e) => log::error!("Failed to broadcast liquidation transaction: {}", e),
+        let event = match elementsd.send_raw_transaction(tx).await {
+            Ok(txid) => {
+                log::info!("Broadcast liquidation transaction {}", txid);
+                Event::LiquidationBroadcast { txid }
+            }
+            Err(e) => {
+                log::error!("Failed to broadcast liquidation transaction: {}", e);
+                Event::LiquidationFailed {
+                    txid: tx.txid(),
+                    error: format!("{:#}", e),
+                }
+            }
         };
+
+        events.publish(event).await;
     }
 
     Ok(())
 }
+
+fn sum_principal(loans: impl Iterator<Item = LoanTerms>) -> Result<u64> {
+    loans.try_fold(0u64, |sum, terms| {
+        sum.checked_add(terms.principal_amount.as_satodollar())
+            .context("addition overflow")
+    })
+}
+
+pub(crate) fn unix_timestamp(now: SystemTime) -> Result<u32> {
+    let since_the_epoch = now.duration_since(UNIX_EPOCH).expect("Time went backwards");
+
+    u32::try_from(since_the_epoch.as_secs()).context("Overflow, timestamp does not fit into a u32")
+}
+
 /// Calculates the absolute timelock from the loan term in days
 ///
 /// The timelock is represented as Unix timestamp (seconds since the epoch).
@@ -522,7 +1073,7 @@ mod tests {
         fixed_rate,
     };
     use anyhow::{Context, Result};
-    use baru::swap::sign_with_key;
+    use baru::swap::{self, sign_with_key};
     use elements::{
         bitcoin::{secp256k1::Secp256k1, Amount, Network, PrivateKey, PublicKey},
         secp256k1_zkp::{rand::thread_rng, SecretKey, SECP256K1},
@@ -632,18 +1183,41 @@ mod tests {
             rate_service,
             secp: Secp256k1::new(),
             elementsd: client.clone(),
+            lending_wallet: client.clone(),
+            liquidation_wallet: client.clone(),
             btc_asset_id: have_asset_id_alice,
             usdt_asset_id: have_asset_id_bob,
-            db,
+            db: db.clone(),
+            loan_offers: HashMap::new(),
             lender_states: HashMap::new(),
+            repayment_states: HashMap::new(),
+            exposure_limits: ExposureLimits::default(),
+            margin_call_distance: dec!(0.05),
+            grace_period: Duration::from_secs(24 * 60 * 60),
+            max_pending_negotiations: 100,
+            idempotency_key_expiry: Duration::from_secs(60 * 60),
+            min_input_confirmations: 0,
+            input_reservations: InputReservations::default(),
+            swap_watcher: SwapWatcher::new(swap_watcher::WatcherConfig {
+                broadcast_timeout: Duration::from_secs(60),
+                max_lapses: 3,
+                lapse_window: Duration::from_secs(60 * 60),
+            }),
+            events: EventBus::new(db, Vec::new()),
+            paused: PauseState::default(),
+            identity: MakerIdentity::load(&client).await.unwrap(),
+            swap_batcher: None,
         };
 
         let transaction = bob
             .handle_create_sell_swap(CreateSwapPayload {
-                alice_inputs: vec![AliceInput {
-                    outpoint: input_alice.0,
-                    blinding_key: fund_blinding_sk_alice,
-                }],
+                alice_inputs: vec![AliceInput::with_secrets(
+                    input_alice.0,
+                    input_alice
+                        .1
+                        .unblind(SECP256K1, fund_blinding_sk_alice)
+                        .unwrap(),
+                )],
                 address: final_address_alice,
                 amount: redeem_amount_bob.as_sat(),
             })
@@ -752,18 +1326,38 @@ mod tests {
             rate_service,
             secp: Secp256k1::new(),
             elementsd: client.clone(),
+            lending_wallet: client.clone(),
+            liquidation_wallet: client.clone(),
             btc_asset_id: have_asset_id_bob,
             usdt_asset_id: have_asset_id_alice,
-            db,
+            db: db.clone(),
+            loan_offers: HashMap::new(),
             lender_states: HashMap::new(),
+            repayment_states: HashMap::new(),
+            exposure_limits: ExposureLimits::default(),
+            margin_call_distance: dec!(0.05),
+            grace_period: Duration::from_secs(24 * 60 * 60),
+            max_pending_negotiations: 100,
+            idempotency_key_expiry: Duration::from_secs(60 * 60),
+            min_input_confirmations: 0,
+            input_reservations: InputReservations::default(),
+            swap_watcher: SwapWatcher::new(swap_watcher::WatcherConfig {
+                broadcast_timeout: Duration::from_secs(60),
+                max_lapses: 3,
+                lapse_window: Duration::from_secs(60 * 60),
+            }),
+            events: EventBus::new(db, Vec::new()),
+            paused: PauseState::default(),
+            identity: MakerIdentity::load(&client).await.unwrap(),
+            swap_batcher: None,
         };
 
         let transaction = bob
             .handle_create_buy_swap(CreateSwapPayload {
-                alice_inputs: vec![AliceInput {
-                    outpoint: input_alice.0,
-                    blinding_key: fund_blinding_sk_alice,
-                }],
+                alice_inputs: vec![AliceInput::with_blinding_key(
+                    input_alice.0,
+                    fund_blinding_sk_alice,
+                )],
                 address: final_address_alice,
                 amount: redeem_amount_bob.as_satodollar(),
             })
@@ -812,6 +1406,100 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn refund_input_stays_locked_until_its_repayment_expires() {
+        let db = Sqlite::new_ephemeral_db().expect("A ephemeral db");
+
+        let tc_client = Cli::default();
+        let (client, _container) = {
+            let blockchain = Elementsd::new(&tc_client, "0.18.1.9").unwrap();
+
+            (
+                Client::new(blockchain.node_url.clone().into()).unwrap(),
+                blockchain,
+            )
+        };
+        let mining_address = client.get_new_segwit_confidential_address().await.unwrap();
+
+        let btc_asset_id = client.get_bitcoin_asset_id().await.unwrap();
+        let usdt_asset_id = client.issueasset(100_000.0, 0.0, true).await.unwrap().asset;
+        client.generatetoaddress(1, &mining_address).await.unwrap();
+
+        let mut bob = Bobtimus {
+            rng: &mut thread_rng(),
+            rate_service: fixed_rate::Service::new(),
+            secp: Secp256k1::new(),
+            elementsd: client.clone(),
+            lending_wallet: client.clone(),
+            liquidation_wallet: client.clone(),
+            btc_asset_id,
+            usdt_asset_id,
+            db: db.clone(),
+            loan_offers: HashMap::new(),
+            lender_states: HashMap::new(),
+            repayment_states: HashMap::new(),
+            exposure_limits: ExposureLimits::default(),
+            margin_call_distance: dec!(0.05),
+            grace_period: Duration::from_secs(24 * 60 * 60),
+            max_pending_negotiations: 100,
+            idempotency_key_expiry: Duration::from_secs(60 * 60),
+            min_input_confirmations: 0,
+            input_reservations: InputReservations::default(),
+            swap_watcher: SwapWatcher::new(swap_watcher::WatcherConfig {
+                broadcast_timeout: Duration::from_secs(60),
+                max_lapses: 3,
+                lapse_window: Duration::from_secs(60 * 60),
+            }),
+            events: EventBus::new(db, Vec::new()),
+            paused: PauseState::default(),
+            identity: MakerIdentity::load(&client).await.unwrap(),
+            swap_batcher: None,
+        };
+
+        let refund_amount = LiquidUsdt::from_str_in_dollar("400").unwrap();
+        let refund_input = bob.refund_input(refund_amount).await.unwrap();
+
+        let secrets = refund_input
+            .original_txout
+            .unblind(SECP256K1, refund_input.blinding_key)
+            .unwrap();
+        assert_eq!(secrets.asset, usdt_asset_id);
+        assert_eq!(secrets.value, refund_amount.as_satodollar());
+        assert!(!is_unspent(&client, usdt_asset_id, refund_input.txin).await);
+
+        bob.repayment_states.insert(
+            refund_input.txin.txid,
+            RepaymentState {
+                refund: Some(refund_input.txin),
+                expires_at: 1,
+            },
+        );
+        bob.prune_repayment_states(1).await.unwrap();
+
+        assert!(bob.repayment_states.is_empty());
+        assert!(is_unspent(&client, usdt_asset_id, refund_input.txin).await);
+    }
+
+    /// Whether the wallet lists the output as unspent, which it does not
+    /// for locked outputs.
+    async fn is_unspent(client: &Client, asset_id: AssetId, outpoint: OutPoint) -> bool {
+        client
+            .listunspent(
+                Some(0),
+                None,
+                None,
+                None,
+                Some(ListUnspentOptions {
+                    asset: Some(asset_id),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap()
+            .iter()
+            .any(|utxo| utxo.txid == outpoint.txid && utxo.vout == outpoint.vout)
+    }
+
     fn extract_input(tx: &Transaction, address: Address) -> Result<(OutPoint, TxOut)> {
         let vout = tx
             .output
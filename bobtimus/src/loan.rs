@@ -3,21 +3,29 @@ use anyhow::{Context, Result};
 use baru::input::Input;
 use elements::{
     bitcoin::{Amount, PublicKey},
-    Address,
+    secp256k1_zkp::rand::RngCore,
+    Address, Txid,
 };
 use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
+/// Serialized as [`bobtimus_client::LoanOffer`].
 #[derive(Debug, Clone, serde::Serialize)]
+#[serde(into = "bobtimus_client::LoanOffer")]
 pub struct LoanOffer {
-    // TODO: Potentially add an id if we want to track offers - for now we just check if an incoming request is acceptable
+    /// Identifies the offer in the borrower's loan request
+    pub id: OfferId,
+    /// Unix timestamp after which loan requests for this offer are rejected
+    pub expires_at: u32,
+
+    /// The rate at which the offer is binding
+    ///
+    /// Loan requests are validated against the bid price of this rate.
     pub rate: Rate,
 
-    #[serde(with = "::elements::bitcoin::util::amount::serde::as_sat")]
     pub fee_sats_per_vbyte: Amount,
 
-    #[serde(serialize_with = "LiquidUsdt::serialize_to_nominal")]
     pub min_principal: LiquidUsdt,
-    #[serde(serialize_with = "LiquidUsdt::serialize_to_nominal")]
     pub max_principal: LiquidUsdt,
 
     /// The maximum LTV that defines at what point the lender liquidates
@@ -49,21 +57,131 @@ pub struct LoanOffer {
     pub base_interest_rate: Decimal,
 
     /// Interest in relation to terms
+    ///
+    /// The terms are the points of a piecewise-linear curve. Any term
+    /// between the shortest and the longest term is accepted and the
+    /// interest modifier is interpolated between the two closest points.
     pub terms: Vec<Term>,
 
     /// Interest rates in relation to collteralization
+    ///
+    /// The collateralizations are the points of a piecewise-linear curve.
+    /// Any collateralization above the lowest one is accepted, the interest
+    /// modifier is interpolated between the two closest points and capped
+    /// at the modifier of the highest collateralization.
     pub collateralizations: Vec<Collateralization>,
 }
 
-#[derive(Debug, Clone, Copy, serde::Serialize)]
+impl LoanOffer {
+    pub fn is_expired(&self, now: u32) -> bool {
+        now > self.expires_at
+    }
+}
+
+impl From<LoanOffer> for bobtimus_client::LoanOffer {
+    fn from(offer: LoanOffer) -> Self {
+        Self {
+            id: offer.id.to_string(),
+            expires_at: offer.expires_at,
+            rate: offer.rate.into(),
+            fee_sats_per_vbyte: offer.fee_sats_per_vbyte,
+            min_principal: offer.min_principal.as_nominal(),
+            max_principal: offer.max_principal.as_nominal(),
+            max_ltv: decimal_to_f64(offer.max_ltv),
+            base_interest_rate: decimal_to_f64(offer.base_interest_rate),
+            terms: offer
+                .terms
+                .into_iter()
+                .map(|term| bobtimus_client::Term {
+                    days: term.days,
+                    interest_mod: decimal_to_f64(term.interest_mod),
+                })
+                .collect(),
+            collateralizations: offer
+                .collateralizations
+                .into_iter()
+                .map(|collateralization| bobtimus_client::Collateralization {
+                    collateralization: decimal_to_f64(collateralization.collateralization),
+                    interest_mod: decimal_to_f64(collateralization.interest_mod),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn decimal_to_f64(decimal: Decimal) -> f64 {
+    decimal
+        .to_f64()
+        .expect("decimal to be representable as f64")
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OfferId([u8; 16]);
+
+impl OfferId {
+    pub fn random<R>(rng: &mut R) -> Self
+    where
+        R: RngCore,
+    {
+        let mut bytes = [0u8; 16];
+        rng.fill_bytes(&mut bytes);
+
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for OfferId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for OfferId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for OfferId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0u8; 16];
+        hex::decode_to_slice(s, &mut bytes).context("invalid offer id")?;
+
+        Ok(Self(bytes))
+    }
+}
+
+impl serde::Serialize for OfferId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for OfferId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Term {
     pub days: u32,
     /// Interest to be added on top of the base interest rate for this term
     pub interest_mod: Decimal,
 }
 
-/// Allows to specify a better rate for users that
-#[derive(Debug, Clone, Copy, serde::Serialize)]
+/// Allows to specify a better rate for users that over-collateralize more
+#[derive(Debug, Clone, Copy)]
 pub struct Collateralization {
     pub collateralization: Decimal,
     /// Interest to be added on top of the base interest rate for this term.
@@ -71,8 +189,15 @@ pub struct Collateralization {
 }
 
 // TODO: Make sure that removing sat_per_vbyte is OK here
+/// Serialized as [`bobtimus_client::LoanRequest`].
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(
+    try_from = "bobtimus_client::LoanRequest",
+    into = "bobtimus_client::LoanRequest"
+)]
 pub struct LoanRequest {
+    /// The offer the loan is requested under
+    pub offer_id: OfferId,
     /// Loan term in days
     pub term: u32,
     pub principal_amount: LiquidUsdt,
@@ -83,16 +208,166 @@ pub struct LoanRequest {
     pub borrower_address: Address,
 }
 
+impl TryFrom<bobtimus_client::LoanRequest> for LoanRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(request: bobtimus_client::LoanRequest) -> Result<Self> {
+        Ok(Self {
+            offer_id: request.offer_id.parse()?,
+            term: request.term,
+            principal_amount: LiquidUsdt::from_satodollar(request.principal_amount.as_sat()),
+            // Parsed from its shortest representation, so that e.g. 1.1
+            // is not turned into 1.100000000000000088817841970012523
+            collateralization: Decimal::from_str(&request.collateralization.to_string())
+                .context("invalid collateralization")?,
+            collateral_amount: LiquidBtc::from(request.collateral_amount),
+            collateral_inputs: request.collateral_inputs,
+            borrower_pk: request.borrower_pk,
+            borrower_address: request.borrower_address,
+        })
+    }
+}
+
+impl From<LoanRequest> for bobtimus_client::LoanRequest {
+    fn from(request: LoanRequest) -> Self {
+        Self {
+            offer_id: request.offer_id.to_string(),
+            term: request.term,
+            principal_amount: request.principal_amount.into(),
+            collateralization: decimal_to_f64(request.collateralization),
+            collateral_amount: request.collateral_amount.into(),
+            collateral_inputs: request.collateral_inputs,
+            borrower_pk: request.borrower_pk,
+            borrower_address: request.borrower_address,
+        }
+    }
+}
+
 pub struct ValidatedLoan {
     pub repayment_amount: LiquidUsdt,
     pub liquidation_price: LiquidUsdt,
 }
 
+/// The terms of a loan which the lender keeps track of for as long as the loan is open
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoanTerms {
+    pub principal_amount: LiquidUsdt,
+    pub repayment_amount: LiquidUsdt,
+    pub collateral_amount: LiquidBtc,
+    pub liquidation_price: LiquidUsdt,
+    pub max_ltv: Decimal,
+    /// Unix timestamp at which the loan was taken out
+    pub start: u32,
+    /// Absolute timelock as Unix timestamp
+    pub timelock: u32,
+    /// The borrower's public key, unknown for loans taken out before it was tracked
+    pub borrower_pk: Option<PublicKey>,
+}
+
+/// The amount the borrower has to pay to repay a loan in full today
+///
+/// Interest is pro-rated by the days that have passed since the loan
+/// was taken out. Every started day counts, so that a quote stays valid
+/// for the rest of the day.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, schemars::JsonSchema)]
+pub struct RepaymentQuote {
+    #[schemars(with = "String")]
+    pub loan_txid: Txid,
+    /// The amount to be paid, i.e. the principal plus the pro-rated interest
+    pub repayment_amount: LiquidUsdt,
+    /// The interest which has not accrued yet, i.e. the difference to the
+    /// repayment amount at maturity
+    pub refund_amount: LiquidUsdt,
+}
+
+/// The borrower's request to repay a loan in full before the end of its term
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct RepaymentRequest {
+    #[schemars(with = "String")]
+    pub loan_txid: Txid,
+}
+
+/// Our part of an early repayment
+///
+/// The collateral contract only releases the collateral if the
+/// repayment amount fixed when the loan was taken out is paid to us. We
+/// refund the interest which has not accrued yet by contributing an
+/// input worth the refund, which the borrower spends in the repayment
+/// transaction together with L-USDt worth the quoted repayment amount.
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
+pub struct RepaymentResponse {
+    #[schemars(with = "String")]
+    pub loan_txid: Txid,
+    /// The amount the borrower pays, i.e. the principal plus the pro-rated interest
+    pub repayment_amount: LiquidUsdt,
+    /// Our input worth the refund, `None` if nothing is refunded
+    #[schemars(with = "Option<bobtimus_client::schema::Input>")]
+    pub refund_input: Option<Input>,
+    /// Unix timestamp until which the repayment can be finalized
+    pub expires_at: u32,
+}
+
+/// Limits on how much L-USDt we are willing to lend out
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct ExposureLimits {
+    /// Maximum principal of all open loans combined
+    pub max_total_principal: Option<LiquidUsdt>,
+    /// Maximum principal of all open loans of a single borrower
+    pub max_borrower_principal: Option<LiquidUsdt>,
+    /// Amount of L-USDt which is never lent out
+    pub usdt_reserve: LiquidUsdt,
+}
+
+/// How much L-USDt is lent out and how much is left to lend
+#[derive(Debug, Clone, Copy)]
+pub struct Exposure {
+    /// Principal of all open loans combined
+    pub total_principal: LiquidUsdt,
+    /// Principal of all open loans of the borrower
+    pub borrower_principal: LiquidUsdt,
+    /// Our L-USDt balance
+    pub usdt_balance: LiquidUsdt,
+}
+
+/// The health of an open loan at the current price
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoanStatus {
+    Open,
+    /// The LTV is within the configured margin call distance of the
+    /// maximum LTV. The borrower should repay (part of) the loan.
+    MarginCall,
+    /// The LTV has reached the maximum LTV, i.e. the price has fallen to
+    /// the liquidation price.
+    Undercollateralized,
+    /// The timelock has been reached and the collateral can be liquidated
+    Expired,
+}
+
+/// The terms and status of an open loan, as reported to the borrower
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, schemars::JsonSchema)]
+pub struct LoanDetails {
+    #[schemars(with = "String")]
+    pub loan_txid: Txid,
+    pub status: LoanStatus,
+    pub principal_amount: LiquidUsdt,
+    pub repayment_amount: LiquidUsdt,
+    pub collateral_amount: LiquidBtc,
+    pub liquidation_price: LiquidUsdt,
+    /// Absolute timelock as Unix timestamp
+    pub timelock: u32,
+    /// The bid price the current LTV is calculated with
+    pub current_price: LiquidUsdt,
+    #[schemars(with = "f64")]
+    pub current_ltv: Decimal,
+    #[schemars(with = "f64")]
+    pub max_ltv: Decimal,
+}
+
 #[derive(Debug, Clone)]
 struct LoanValidationParams {
     request_price: LiquidUsdt,
-    current_price: LiquidUsdt,
-    price_fluctuation_interval: (Decimal, Decimal),
+    offer_price: LiquidUsdt,
     request_principal: LiquidUsdt,
     min_principal: LiquidUsdt,
     max_principal: LiquidUsdt,
@@ -104,12 +379,17 @@ struct LoanValidationParams {
     collateralizations: Vec<Collateralization>,
 }
 
+/// Calculates and validates the loan requested under the given offer
+///
+/// The offer is binding, i.e. the loan is calculated with the bid price
+/// of the offer's rate regardless of how the price has moved since.
 pub fn loan_calculation_and_validation(
     loan_request: &LoanRequest,
     loan_offer: &LoanOffer,
-    price_fluctuation_interval: (Decimal, Decimal),
-    current_price: LiquidUsdt,
 ) -> Result<ValidatedLoan> {
+    // The bid price is used so the lender is covered under the assumption of selling the asset
+    let offer_price = loan_offer.rate.bid;
+
     let interest_rate = calculate_interest_rate(
         loan_request.term,
         loan_request.collateralization,
@@ -130,13 +410,12 @@ pub fn loan_calculation_and_validation(
     let request_ltv = calculate_ltv(
         repayment_amount,
         loan_request.collateral_amount,
-        current_price,
+        offer_price,
     )?;
 
     validate_loan_is_acceptable(LoanValidationParams {
         request_price,
-        current_price,
-        price_fluctuation_interval,
+        offer_price,
         request_principal: loan_request.principal_amount,
         min_principal: loan_offer.min_principal,
         max_principal: loan_offer.max_principal,
@@ -162,30 +441,193 @@ pub fn loan_calculation_and_validation(
     Ok(validated_loan)
 }
 
+pub fn calculate_repayment_quote(
+    loan_txid: Txid,
+    loan_terms: &LoanTerms,
+    now: u32,
+) -> Result<RepaymentQuote> {
+    let elapsed_fraction =
+        calculate_elapsed_term_fraction(loan_terms.start, loan_terms.timelock, now)?;
+
+    let interest = Decimal::from(loan_terms.repayment_amount.as_satodollar())
+        .checked_sub(Decimal::from(loan_terms.principal_amount.as_satodollar()))
+        .context("subtraction overflow")?;
+    let accrued_interest = interest
+        .checked_mul(elapsed_fraction)
+        .context("multiplication overflow")?
+        .ceil()
+        .to_u64()
+        .context("decimal cannot be represented as u64")?;
+
+    let repayment_amount = loan_terms
+        .principal_amount
+        .as_satodollar()
+        .checked_add(accrued_interest)
+        .context("addition overflow")?;
+    let refund_amount = loan_terms
+        .repayment_amount
+        .as_satodollar()
+        .checked_sub(repayment_amount)
+        .context("subtraction overflow")?;
+
+    Ok(RepaymentQuote {
+        loan_txid,
+        repayment_amount: LiquidUsdt::from_satodollar(repayment_amount),
+        refund_amount: LiquidUsdt::from_satodollar(refund_amount),
+    })
+}
+
+/// Calculates the fraction of the loan term that has passed, counting every started day
+fn calculate_elapsed_term_fraction(start: u32, timelock: u32, now: u32) -> Result<Decimal> {
+    const DAY_IN_SECS: u32 = 24 * 60 * 60;
+
+    if timelock <= start {
+        return Ok(Decimal::ONE);
+    }
+
+    let term_days = Decimal::from(timelock - start)
+        .checked_div(Decimal::from(DAY_IN_SECS))
+        .context("division error")?
+        .ceil();
+    let elapsed_days = Decimal::from(now.saturating_sub(start))
+        .checked_div(Decimal::from(DAY_IN_SECS))
+        .context("division error")?
+        .ceil()
+        .min(term_days);
+
+    let fraction = elapsed_days
+        .checked_div(term_days)
+        .context("division error")?;
+
+    Ok(fraction)
+}
+
+/// Validates that lending `principal_amount` keeps us within the exposure limits
+pub fn validate_exposure(
+    principal_amount: LiquidUsdt,
+    exposure: Exposure,
+    limits: &ExposureLimits,
+) -> Result<Result<(), LoanValidationError>> {
+    let principal_amount = principal_amount.as_satodollar();
+
+    let total_principal = exposure
+        .total_principal
+        .as_satodollar()
+        .checked_add(principal_amount)
+        .context("addition overflow")?;
+    let total_principal = LiquidUsdt::from_satodollar(total_principal);
+
+    if let Some(max_total_principal) = limits.max_total_principal {
+        if total_principal > max_total_principal {
+            return Ok(Err(LoanValidationError::TotalPrincipalAboveMax {
+                total_principal,
+                max_total_principal,
+            }));
+        }
+    }
+
+    let borrower_principal = exposure
+        .borrower_principal
+        .as_satodollar()
+        .checked_add(principal_amount)
+        .context("addition overflow")?;
+    let borrower_principal = LiquidUsdt::from_satodollar(borrower_principal);
+
+    if let Some(max_borrower_principal) = limits.max_borrower_principal {
+        if borrower_principal > max_borrower_principal {
+            return Ok(Err(LoanValidationError::BorrowerPrincipalAboveMax {
+                borrower_principal,
+                max_borrower_principal,
+            }));
+        }
+    }
+
+    let remaining_balance = exposure
+        .usdt_balance
+        .as_satodollar()
+        .saturating_sub(principal_amount);
+    let remaining_balance = LiquidUsdt::from_satodollar(remaining_balance);
+
+    if remaining_balance < limits.usdt_reserve {
+        return Ok(Err(LoanValidationError::ReserveBelowMin {
+            remaining_balance,
+            usdt_reserve: limits.usdt_reserve,
+        }));
+    }
+
+    Ok(Ok(()))
+}
+
+/// Calculates the LTV of an open loan at the current bid price and
+/// derives its status from it
+///
+/// A loan is in margin call once its LTV is at most
+/// `margin_call_distance` below the maximum LTV.
+pub fn calculate_loan_details(
+    loan_txid: Txid,
+    loan_terms: &LoanTerms,
+    current_price: LiquidUsdt,
+    margin_call_distance: Decimal,
+    now: u32,
+) -> Result<LoanDetails> {
+    let current_ltv = calculate_ltv(
+        loan_terms.repayment_amount,
+        loan_terms.collateral_amount,
+        current_price,
+    )?;
+
+    let max_ltv = loan_terms.max_ltv;
+    let status = if now >= loan_terms.timelock {
+        LoanStatus::Expired
+    } else if current_ltv >= max_ltv {
+        LoanStatus::Undercollateralized
+    } else if current_ltv >= max_ltv - margin_call_distance {
+        LoanStatus::MarginCall
+    } else {
+        LoanStatus::Open
+    };
+
+    Ok(LoanDetails {
+        loan_txid,
+        status,
+        principal_amount: loan_terms.principal_amount,
+        repayment_amount: loan_terms.repayment_amount,
+        collateral_amount: loan_terms.collateral_amount,
+        liquidation_price: loan_terms.liquidation_price,
+        timelock: loan_terms.timelock,
+        current_price,
+        current_ltv,
+        max_ltv,
+    })
+}
+
 fn calculate_interest_rate(
     borrower_term: u32,
     borrower_collateralization: Decimal,
-    term_thresholds: &[Term],
-    collateralization_thresholds: &[Collateralization],
+    term_curve: &[Term],
+    collateralization_curve: &[Collateralization],
     base_interest_rate: Decimal,
 ) -> Result<Decimal> {
-    let mut term_interest_mod = Decimal::ZERO;
-    for term in term_thresholds {
-        if borrower_term >= term.days {
-            term_interest_mod = term.interest_mod;
-            continue;
-        }
-        break;
-    }
+    let term_interest_mod = interpolate(
+        term_curve
+            .iter()
+            .map(|term| (Decimal::from(term.days), term.interest_mod))
+            .collect(),
+        Decimal::from(borrower_term),
+    )?;
 
-    let mut collateralization_interest_mod = Decimal::ZERO;
-    for collateralization in collateralization_thresholds {
-        if borrower_collateralization >= collateralization.collateralization {
-            collateralization_interest_mod = collateralization.interest_mod;
-            continue;
-        }
-        break;
-    }
+    let collateralization_interest_mod = interpolate(
+        collateralization_curve
+            .iter()
+            .map(|collateralization| {
+                (
+                    collateralization.collateralization,
+                    collateralization.interest_mod,
+                )
+            })
+            .collect(),
+        borrower_collateralization,
+    )?;
 
     let interest_rate = base_interest_rate
         .checked_add(term_interest_mod)
@@ -196,6 +638,55 @@ fn calculate_interest_rate(
     Ok(interest_rate)
 }
 
+/// Evaluates the piecewise-linear curve defined by `points` at `x`
+///
+/// The points do not have to be sorted. Values of `x` below the curve's
+/// range evaluate to zero, as the curve only starts at its first point,
+/// values above it are clamped to the last point. An empty curve always
+/// evaluates to zero.
+fn interpolate(mut points: Vec<(Decimal, Decimal)>, x: Decimal) -> Result<Decimal> {
+    points.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let first_x = match points.first() {
+        Some((first_x, _)) => *first_x,
+        None => return Ok(Decimal::ZERO),
+    };
+    let (last_x, last_y) = *points.last().expect("non-empty points");
+
+    if x < first_x {
+        return Ok(Decimal::ZERO);
+    }
+    if x >= last_x {
+        return Ok(last_y);
+    }
+
+    let (x0, y0, x1, y1) = points
+        .windows(2)
+        .find_map(|window| {
+            let (x0, y0) = window[0];
+            let (x1, y1) = window[1];
+
+            (x0 <= x && x <= x1).then(|| (x0, y0, x1, y1))
+        })
+        .context("x is within the range of the curve")?;
+
+    // Two points on the same x would make the curve a step, in which case we
+    // take the value of the later point
+    if x0 == x1 {
+        return Ok(y1);
+    }
+
+    let y = (y1 - y0)
+        .checked_mul(x - x0)
+        .context("multiplication overflow")?
+        .checked_div(x1 - x0)
+        .context("division error")?
+        .checked_add(y0)
+        .context("addition overflow")?;
+
+    Ok(y)
+}
+
 fn calculate_repayment_amount(
     principal_amount: LiquidUsdt,
     interest_percentage: Decimal,
@@ -260,6 +751,11 @@ fn calculate_liquidation_price(
     Ok(liquidation_price)
 }
 
+/// Calculates the lowest price at which the collateral covers the repayment amount
+///
+/// The price is rounded up to the satodollar, so the collateral covers the
+/// repayment amount at the returned price and at any price above it.
+/// Borrowers therefore have to round the collateral up to the satoshi.
 fn calculate_request_price(
     repayment_amount: LiquidUsdt,
     collateral_amount: LiquidBtc,
@@ -268,17 +764,16 @@ fn calculate_request_price(
     let repayment_amount = Decimal::from(repayment_amount.as_satodollar());
 
     let one_btc_as_sat = Decimal::from(Amount::ONE_BTC.as_sat());
-    let collateral_as_btc = Decimal::from(collateral_amount.0.as_sat())
-        .checked_div(one_btc_as_sat)
-        .context("division error")?;
+    let collateral_as_sat = Decimal::from(collateral_amount.0.as_sat());
 
     let price = repayment_amount
-        .checked_div(
-            collateral_as_btc
-                .checked_div(collateralization)
-                .context("division error")?,
-        )
-        .context("division error")?;
+        .checked_mul(collateralization)
+        .context("multiplication overflow")?
+        .checked_mul(one_btc_as_sat)
+        .context("multiplication overflow")?
+        .checked_div(collateral_as_sat)
+        .context("division error")?
+        .ceil();
     let price = LiquidUsdt::from_satodollar(
         price
             .to_u64()
@@ -314,14 +809,18 @@ fn calculate_ltv(
 
 #[derive(Debug, PartialEq, thiserror::Error)]
 pub enum LoanValidationError {
-    #[error(
-        "The given price {request_price} is not acceptable with current price {current_price}"
-    )]
+    #[error("The given price {request_price} is not acceptable with offered price {offer_price}")]
     PriceNotAcceptable {
         request_price: LiquidUsdt,
-        current_price: LiquidUsdt,
+        offer_price: LiquidUsdt,
     },
 
+    #[error("The offer {offer_id} is unknown")]
+    UnknownOffer { offer_id: OfferId },
+
+    #[error("The offer {offer_id} expired at {expired_at}")]
+    OfferExpired { offer_id: OfferId, expired_at: u32 },
+
     #[error("The given principal amount {request_principal} is below the configured minimum {min_principal}")]
     PrincipalBelowMin {
         request_principal: LiquidUsdt,
@@ -340,23 +839,50 @@ pub enum LoanValidationError {
         max_ltv: Decimal,
     },
 
-    #[error("The given term {term} is not allowed")]
-    TermNotAllowed { term: u32 },
+    #[error(
+        "The given term {term} is not within the offered range of {min_term} to {max_term} days"
+    )]
+    TermNotAllowed {
+        term: u32,
+        min_term: u32,
+        max_term: u32,
+    },
 
     #[error("The given collateralization {request_collateralization} is below the configured minimum {min_collateralization}")]
     CollateralizationBelowMin {
         request_collateralization: Decimal,
         min_collateralization: Decimal,
     },
+
+    #[error("The loan would raise the principal of all open loans to {total_principal}, above the configured maximum {max_total_principal}")]
+    TotalPrincipalAboveMax {
+        total_principal: LiquidUsdt,
+        max_total_principal: LiquidUsdt,
+    },
+
+    #[error("The loan would raise the principal of the borrower's open loans to {borrower_principal}, above the configured maximum {max_borrower_principal}")]
+    BorrowerPrincipalAboveMax {
+        borrower_principal: LiquidUsdt,
+        max_borrower_principal: LiquidUsdt,
+    },
+
+    #[error("The loan would lower the remaining balance to {remaining_balance}, below the configured reserve {usdt_reserve}")]
+    ReserveBelowMin {
+        remaining_balance: LiquidUsdt,
+        usdt_reserve: LiquidUsdt,
+    },
 }
 
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("The loan {0} is unknown")]
+pub struct UnknownLoan(pub Txid);
+
 fn validate_loan_is_acceptable(
     loan_validation_params: LoanValidationParams,
 ) -> Result<Result<(), LoanValidationError>> {
     let LoanValidationParams {
         request_price,
-        current_price,
-        price_fluctuation_interval,
+        offer_price,
         request_principal,
         min_principal,
         max_principal,
@@ -368,24 +894,13 @@ fn validate_loan_is_acceptable(
         collateralizations,
     } = loan_validation_params;
 
-    let request_price_dec = Decimal::from(request_price.as_satodollar());
-    let current_price_dec = Decimal::from(current_price.as_satodollar());
-
-    // TODO: Evaluate if we want to use an upper and a lower bound.
-    //  We could just restrict by upper bound, because that is what makes it more expensive for the lender
-    //  i.e. if price was 1000 and is 100 now we must ensure to accept only if the current price it not higher than 100 + x%
-    let (lower, upper) = price_fluctuation_interval;
-    let lower_bound = current_price_dec
-        .checked_mul(lower)
-        .context("multiplication error")?;
-    let upper_bound = current_price_dec
-        .checked_mul(upper)
-        .context("multiplication error")?;
-
-    if request_price_dec < lower_bound || request_price_dec > upper_bound {
+    // A request price above the offer price means that the requested
+    // collateralization is not backed by the collateral. A lower request
+    // price only means that the borrower put up more collateral than needed.
+    if request_price > offer_price {
         return Ok(Err(LoanValidationError::PriceNotAcceptable {
             request_price,
-            current_price,
+            offer_price,
         }));
     }
 
@@ -428,9 +943,26 @@ fn validate_loan_is_acceptable(
         }));
     }
 
-    if !terms.iter().any(|a| a.days == request_term) {
+    // Any term between the shortest and the longest offered term is acceptable, because the
+    // interest is interpolated along the term curve. An offer without terms allows no term at
+    // all, which we report as an empty range.
+    let days = terms.iter().map(|term| term.days);
+    let (min_term, max_term) = match (days.clone().min(), days.max()) {
+        (Some(min_term), Some(max_term)) => (min_term, max_term),
+        _ => {
+            return Ok(Err(LoanValidationError::TermNotAllowed {
+                term: request_term,
+                min_term: 0,
+                max_term: 0,
+            }))
+        }
+    };
+
+    if request_term < min_term || request_term > max_term {
         return Ok(Err(LoanValidationError::TermNotAllowed {
             term: request_term,
+            min_term,
+            max_term,
         }));
     }
 
@@ -440,6 +972,7 @@ fn validate_loan_is_acceptable(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use elements::secp256k1_zkp::rand::thread_rng;
     use proptest::proptest;
     use rust_decimal::prelude::FromPrimitive;
     use rust_decimal_macros::dec;
@@ -448,6 +981,7 @@ mod tests {
     #[test]
     fn test_loan_calculation_and_validation() {
         let loan_request = LoanRequest {
+            offer_id: OfferId([0u8; 16]),
             term: 30,
             principal_amount: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
             collateralization: dec!(1.4),
@@ -470,28 +1004,21 @@ mod tests {
                 interest_mod: Decimal::ZERO,
             }],
             collateralizations: vec![],
-
-            // irrelevant for this test
             rate: Rate {
                 ask: Default::default(),
-                bid: Default::default(),
+                bid: LiquidUsdt::from_str_in_dollar("40000").unwrap(),
             },
+
+            // irrelevant for this test
+            id: OfferId([0u8; 16]),
+            expires_at: 0,
             fee_sats_per_vbyte: Default::default(),
         };
 
-        let current_price = LiquidUsdt::from_str_in_dollar("40000").unwrap();
-        let price_fluctuation_interval = (dec!(0.99), dec!(1.01));
-
         let ValidatedLoan {
             repayment_amount,
             liquidation_price,
-        } = loan_calculation_and_validation(
-            &loan_request,
-            &loan_offer,
-            price_fluctuation_interval,
-            current_price,
-        )
-        .unwrap();
+        } = loan_calculation_and_validation(&loan_request, &loan_offer).unwrap();
 
         assert_eq!(
             repayment_amount,
@@ -510,6 +1037,7 @@ mod tests {
         // This has the effect that the lender over-collateralized with 50% (or a total of 150%),
         // i.e. 1.5 BTC.
         let loan_request = LoanRequest {
+            offer_id: OfferId([0u8; 16]),
             term: 30,
             principal_amount: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
             collateralization: dec!(1.5),
@@ -532,28 +1060,21 @@ mod tests {
                 interest_mod: Decimal::ZERO,
             }],
             collateralizations: vec![],
-
-            // irrelevant for this test
             rate: Rate {
                 ask: Default::default(),
-                bid: Default::default(),
+                bid: LiquidUsdt::from_str_in_dollar("10500").unwrap(),
             },
+
+            // irrelevant for this test
+            id: OfferId([0u8; 16]),
+            expires_at: 0,
             fee_sats_per_vbyte: Default::default(),
         };
 
-        let current_price = LiquidUsdt::from_str_in_dollar("10500").unwrap();
-        let price_fluctuation_interval = (dec!(0.99), dec!(1.01));
-
         let ValidatedLoan {
             repayment_amount,
             liquidation_price,
-        } = loan_calculation_and_validation(
-            &loan_request,
-            &loan_offer,
-            price_fluctuation_interval,
-            current_price,
-        )
-        .unwrap();
+        } = loan_calculation_and_validation(&loan_request, &loan_offer).unwrap();
 
         assert_eq!(
             repayment_amount,
@@ -571,63 +1092,193 @@ mod tests {
 
     #[test]
     fn test_calculate_interest_rate() {
-        let term_thresholds = vec![Term {
-            days: 30,
-            interest_mod: dec!(0.001),
-        }];
-        let collateralization_thresholds = vec![Collateralization {
-            collateralization: dec!(1.5),
-            interest_mod: dec!(-0.002),
-        }];
+        let term_curve = vec![
+            Term {
+                days: 30,
+                interest_mod: dec!(0.001),
+            },
+            Term {
+                days: 90,
+                interest_mod: dec!(0.007),
+            },
+        ];
+        let collateralization_curve = vec![
+            Collateralization {
+                collateralization: dec!(1.5),
+                interest_mod: dec!(-0.002),
+            },
+            Collateralization {
+                collateralization: dec!(2.5),
+                interest_mod: dec!(-0.012),
+            },
+        ];
         let base_interest_rate = dec!(0.05);
 
-        let borrower_term = 30;
-        let borrower_collateralization = dec!(1.5);
-        let interest_rate = calculate_interest_rate(
-            borrower_term,
-            borrower_collateralization,
-            &term_thresholds,
-            &collateralization_thresholds,
-            base_interest_rate,
-        )
-        .unwrap();
-        assert_eq!(interest_rate, dec!(0.049));
-
-        let borrower_term = 29;
-        let borrower_collateralization = dec!(1.4);
-        let interest_rate = calculate_interest_rate(
-            borrower_term,
-            borrower_collateralization,
-            &term_thresholds,
-            &collateralization_thresholds,
-            base_interest_rate,
-        )
-        .unwrap();
-        assert_eq!(interest_rate, dec!(0.05));
-
-        let borrower_term = 30;
-        let borrower_collateralization = dec!(1.4);
-        let interest_rate = calculate_interest_rate(
-            borrower_term,
-            borrower_collateralization,
-            &term_thresholds,
-            &collateralization_thresholds,
-            base_interest_rate,
-        )
-        .unwrap();
-        assert_eq!(interest_rate, dec!(0.051));
-
-        let borrower_term = 29;
-        let borrower_collateralization = dec!(1.5);
-        let interest_rate = calculate_interest_rate(
-            borrower_term,
-            borrower_collateralization,
-            &term_thresholds,
-            &collateralization_thresholds,
-            base_interest_rate,
-        )
-        .unwrap();
-        assert_eq!(interest_rate, dec!(0.048));
+        let interest_rate = |borrower_term, borrower_collateralization| {
+            calculate_interest_rate(
+                borrower_term,
+                borrower_collateralization,
+                &term_curve,
+                &collateralization_curve,
+                base_interest_rate,
+            )
+            .unwrap()
+        };
+
+        // on the points of the curves
+        assert_eq!(interest_rate(30, dec!(1.5)), dec!(0.049));
+        assert_eq!(interest_rate(90, dec!(2.5)), dec!(0.045));
+
+        // in between the points of the curves
+        assert_eq!(interest_rate(60, dec!(1.5)), dec!(0.052));
+        assert_eq!(interest_rate(30, dec!(2.0)), dec!(0.044));
+        assert_eq!(interest_rate(40, dec!(1.75)), dec!(0.0485));
+
+        // below the curves there is no modifier
+        assert_eq!(interest_rate(29, dec!(1.4)), dec!(0.05));
+        assert_eq!(interest_rate(29, dec!(1.5)), dec!(0.048));
+        assert_eq!(interest_rate(30, dec!(1.4)), dec!(0.051));
+
+        // above the curves the last point is used
+        assert_eq!(interest_rate(91, dec!(10.0)), dec!(0.045));
+    }
+
+    #[test]
+    fn test_interpolate_empty_curve_is_zero() {
+        let value = interpolate(vec![], dec!(42)).unwrap();
+
+        assert_eq!(value, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_interpolate_unsorted_curve() {
+        let points = vec![
+            (dec!(120), dec!(0.03)),
+            (dec!(30), dec!(0.0)),
+            (dec!(60), dec!(0.01)),
+        ];
+
+        assert_eq!(interpolate(points.clone(), dec!(45)).unwrap(), dec!(0.005));
+        assert_eq!(interpolate(points, dec!(90)).unwrap(), dec!(0.02));
+    }
+
+    proptest! {
+        #[test]
+        fn interpolated_term_curve_is_monotonically_increasing(
+            mut days in proptest::collection::vec(1u32..18250, 1..6),
+            mut interest_mods in proptest::collection::vec(-500i64..500, 6),
+            request_terms in (1u32..18250, 1u32..18250),
+        ) {
+            days.sort_unstable();
+            days.dedup();
+            interest_mods.sort_unstable();
+
+            let term_curve = days
+                .iter()
+                .zip(interest_mods)
+                .map(|(days, interest_mod)| Term {
+                    days: *days,
+                    interest_mod: Decimal::new(interest_mod, 4),
+                })
+                .collect::<Vec<_>>();
+
+            // below the curve there is no modifier at all
+            let request_terms = (request_terms.0.max(days[0]), request_terms.1.max(days[0]));
+            let (shorter_term, longer_term) = if request_terms.0 <= request_terms.1 {
+                request_terms
+            } else {
+                (request_terms.1, request_terms.0)
+            };
+
+            let shorter_interest =
+                calculate_interest_rate(shorter_term, Decimal::ONE, &term_curve, &[], Decimal::ZERO)
+                    .unwrap();
+            let longer_interest =
+                calculate_interest_rate(longer_term, Decimal::ONE, &term_curve, &[], Decimal::ZERO)
+                    .unwrap();
+
+            assert!(shorter_interest <= longer_interest);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn interpolated_collateralization_curve_is_monotonically_decreasing(
+            mut collateralizations in proptest::collection::vec(100i64..1000, 1..6),
+            mut interest_mods in proptest::collection::vec(-500i64..500, 6),
+            request_collateralizations in (100i64..1000, 100i64..1000),
+        ) {
+            collateralizations.sort_unstable();
+            collateralizations.dedup();
+            interest_mods.sort_unstable_by(|a, b| b.cmp(a));
+
+            let collateralization_curve = collateralizations
+                .iter()
+                .zip(interest_mods)
+                .map(|(collateralization, interest_mod)| Collateralization {
+                    collateralization: Decimal::new(*collateralization, 2),
+                    interest_mod: Decimal::new(interest_mod, 4),
+                })
+                .collect::<Vec<_>>();
+
+            // below the curve there is no modifier at all
+            let request_collateralizations = (
+                request_collateralizations.0.max(collateralizations[0]),
+                request_collateralizations.1.max(collateralizations[0]),
+            );
+            let (lower, higher) = if request_collateralizations.0 <= request_collateralizations.1 {
+                request_collateralizations
+            } else {
+                (request_collateralizations.1, request_collateralizations.0)
+            };
+
+            let lower_interest = calculate_interest_rate(
+                30,
+                Decimal::new(lower, 2),
+                &[],
+                &collateralization_curve,
+                Decimal::ZERO,
+            )
+            .unwrap();
+            let higher_interest = calculate_interest_rate(
+                30,
+                Decimal::new(higher, 2),
+                &[],
+                &collateralization_curve,
+                Decimal::ZERO,
+            )
+            .unwrap();
+
+            assert!(lower_interest >= higher_interest);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn interpolated_value_is_within_bounds_of_curve(
+            mut xs in proptest::collection::vec(0i64..100_000, 2..6),
+            ys in proptest::collection::vec(-10_000i64..10_000, 6),
+            x in 0i64..100_000,
+        ) {
+            xs.sort_unstable();
+            xs.dedup();
+
+            let points = xs
+                .iter()
+                .zip(ys)
+                .map(|(x, y)| (Decimal::from(*x), Decimal::new(y, 4)))
+                .collect::<Vec<_>>();
+            let min_y = points.iter().map(|(_, y)| *y).min().unwrap();
+            let max_y = points.iter().map(|(_, y)| *y).max().unwrap();
+
+            let y = interpolate(points, Decimal::from(x)).unwrap();
+
+            if x < xs[0] {
+                assert_eq!(y, Decimal::ZERO);
+            } else {
+                assert!(min_y <= y && y <= max_y);
+            }
+        }
     }
 
     #[test]
@@ -684,6 +1335,267 @@ mod tests {
         }
     }
 
+    fn thirty_day_loan_terms() -> LoanTerms {
+        LoanTerms {
+            principal_amount: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
+            repayment_amount: LiquidUsdt::from_str_in_dollar("10600").unwrap(),
+            collateral_amount: LiquidBtc::from(Amount::from_btc(0.5).unwrap()),
+            liquidation_price: LiquidUsdt::from_str_in_dollar("26500").unwrap(),
+            max_ltv: dec!(0.8),
+            start: 1_600_000_000,
+            timelock: 1_600_000_000 + 30 * DAY,
+            borrower_pk: None,
+        }
+    }
+
+    const DAY: u32 = 24 * 60 * 60;
+
+    fn loan_details_at(price: &str, now: u32) -> LoanDetails {
+        let loan_terms = thirty_day_loan_terms();
+
+        calculate_loan_details(
+            Txid::default(),
+            &loan_terms,
+            LiquidUsdt::from_str_in_dollar(price).unwrap(),
+            dec!(0.05),
+            now,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn given_price_well_above_liquidation_price_then_loan_is_open() {
+        let details = loan_details_at("40000", thirty_day_loan_terms().start);
+
+        assert_eq!(details.current_ltv, dec!(0.53));
+        assert_eq!(details.status, LoanStatus::Open);
+    }
+
+    #[test]
+    fn given_ltv_within_margin_call_distance_then_margin_call() {
+        // 10600 / (0.5 * 28000) = 0.757...
+        let details = loan_details_at("28000", thirty_day_loan_terms().start);
+
+        assert_eq!(details.status, LoanStatus::MarginCall);
+    }
+
+    #[test]
+    fn given_price_at_liquidation_price_then_undercollateralized() {
+        let details = loan_details_at("26500", thirty_day_loan_terms().start);
+
+        assert_eq!(details.current_ltv, dec!(0.8));
+        assert_eq!(details.status, LoanStatus::Undercollateralized);
+    }
+
+    #[test]
+    fn given_timelock_reached_then_expired_regardless_of_price() {
+        let details = loan_details_at("40000", thirty_day_loan_terms().timelock);
+
+        assert_eq!(details.status, LoanStatus::Expired);
+    }
+
+    #[test]
+    fn given_full_repayment_after_a_third_of_the_term_then_a_third_of_the_interest_is_due() {
+        let loan_terms = thirty_day_loan_terms();
+        let now = loan_terms.start + 10 * DAY;
+
+        let quote = calculate_repayment_quote(Txid::default(), &loan_terms, now).unwrap();
+
+        assert_eq!(
+            quote.repayment_amount,
+            LiquidUsdt::from_str_in_dollar("10200").unwrap()
+        );
+        assert_eq!(
+            quote.refund_amount,
+            LiquidUsdt::from_str_in_dollar("400").unwrap()
+        );
+    }
+
+    #[test]
+    fn given_repayment_within_a_day_then_the_started_day_is_charged() {
+        let loan_terms = thirty_day_loan_terms();
+        let now = loan_terms.start + 1;
+
+        let quote = calculate_repayment_quote(Txid::default(), &loan_terms, now).unwrap();
+
+        assert_eq!(
+            quote.repayment_amount,
+            LiquidUsdt::from_str_in_dollar("10020").unwrap()
+        );
+    }
+
+    #[test]
+    fn given_repayment_after_timelock_then_full_interest_is_due() {
+        let loan_terms = thirty_day_loan_terms();
+        let now = loan_terms.timelock + 5 * DAY;
+
+        let quote = calculate_repayment_quote(Txid::default(), &loan_terms, now).unwrap();
+
+        assert_eq!(quote.repayment_amount, loan_terms.repayment_amount);
+        assert_eq!(quote.refund_amount, LiquidUsdt::default());
+    }
+
+    fn loan_offer() -> LoanOffer {
+        LoanOffer {
+            min_principal: LiquidUsdt::from_str_in_dollar("1000").unwrap(),
+            max_principal: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
+            max_ltv: dec!(0.75),
+            base_interest_rate: dec!(0.05),
+
+            terms: vec![
+                Term {
+                    days: 30,
+                    interest_mod: Decimal::ZERO,
+                },
+                Term {
+                    days: 60,
+                    interest_mod: dec!(0.01),
+                },
+            ],
+            collateralizations: vec![],
+
+            // irrelevant for this test
+            id: OfferId([0u8; 16]),
+            expires_at: 0,
+            rate: Rate {
+                ask: Default::default(),
+                bid: Default::default(),
+            },
+            fee_sats_per_vbyte: Default::default(),
+        }
+    }
+
+    fn dollars(dollars: &str) -> LiquidUsdt {
+        LiquidUsdt::from_str_in_dollar(dollars).unwrap()
+    }
+
+    fn exposure() -> Exposure {
+        Exposure {
+            total_principal: dollars("50000"),
+            borrower_principal: dollars("5000"),
+            usdt_balance: dollars("100000"),
+        }
+    }
+
+    #[test]
+    fn given_no_exposure_limits_then_no_error() {
+        validate_exposure(dollars("10000"), exposure(), &ExposureLimits::default())
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn given_loan_above_total_principal_limit_then_error() {
+        let limits = ExposureLimits {
+            max_total_principal: Some(dollars("55000")),
+            ..Default::default()
+        };
+
+        let error = validate_exposure(dollars("10000"), exposure(), &limits)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            LoanValidationError::TotalPrincipalAboveMax {
+                total_principal: dollars("60000"),
+                max_total_principal: dollars("55000"),
+            }
+        );
+    }
+
+    #[test]
+    fn given_loan_above_borrower_principal_limit_then_error() {
+        let limits = ExposureLimits {
+            max_borrower_principal: Some(dollars("10000")),
+            ..Default::default()
+        };
+
+        let error = validate_exposure(dollars("5000.01"), exposure(), &limits)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            LoanValidationError::BorrowerPrincipalAboveMax {
+                borrower_principal: dollars("10000.01"),
+                max_borrower_principal: dollars("10000"),
+            }
+        );
+    }
+
+    #[test]
+    fn given_second_loan_above_borrower_principal_limit_then_error() {
+        let limits = ExposureLimits {
+            max_borrower_principal: Some(dollars("15000")),
+            ..Default::default()
+        };
+        let first_loan = exposure();
+
+        validate_exposure(dollars("10000"), first_loan, &limits)
+            .unwrap()
+            .unwrap();
+
+        let second_loan = Exposure {
+            total_principal: dollars("60000"),
+            borrower_principal: dollars("15000"),
+            ..first_loan
+        };
+        let error = validate_exposure(dollars("1000"), second_loan, &limits)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            LoanValidationError::BorrowerPrincipalAboveMax {
+                borrower_principal: dollars("16000"),
+                max_borrower_principal: dollars("15000"),
+            }
+        );
+    }
+
+    #[test]
+    fn given_loan_eating_into_reserve_then_error() {
+        let limits = ExposureLimits {
+            usdt_reserve: dollars("95000"),
+            ..Default::default()
+        };
+
+        let error = validate_exposure(dollars("10000"), exposure(), &limits)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            LoanValidationError::ReserveBelowMin {
+                remaining_balance: dollars("90000"),
+                usdt_reserve: dollars("95000"),
+            }
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn repayment_and_refund_add_up_to_repayment_amount_at_maturity(
+            elapsed_secs in 0u32..(60 * DAY),
+        ) {
+            let loan_terms = thirty_day_loan_terms();
+
+            let quote = calculate_repayment_quote(
+                Txid::default(),
+                &loan_terms,
+                loan_terms.start + elapsed_secs,
+            )
+            .unwrap();
+
+            assert!(quote.repayment_amount >= loan_terms.principal_amount);
+            assert_eq!(
+                quote.repayment_amount.as_satodollar() + quote.refund_amount.as_satodollar(),
+                loan_terms.repayment_amount.as_satodollar()
+            );
+        }
+    }
+
     #[test]
     fn test_calculate_price() {
         let repayment_amount = LiquidUsdt::from_str_in_dollar("10500").unwrap();
@@ -716,10 +1628,10 @@ mod tests {
     }
 
     #[test]
-    fn given_loan_request_and_price_drop_lower_then_fluctuation_then_error() {
-        let current_price = LiquidUsdt::from_str_in_dollar("39603.96039603").unwrap();
+    fn given_loan_request_above_offer_price_then_error() {
+        let offer_price = LiquidUsdt::from_str_in_dollar("39999.99999999").unwrap();
         let loan_validation_params =
-            LoanValidationParams::test_defaults().with_current_price(current_price);
+            LoanValidationParams::test_defaults().with_offer_price(offer_price);
 
         let error = validate_loan_is_acceptable(loan_validation_params.clone())
             .unwrap()
@@ -729,30 +1641,158 @@ mod tests {
             error,
             LoanValidationError::PriceNotAcceptable {
                 request_price: loan_validation_params.request_price,
-                current_price
+                offer_price
             }
         )
     }
 
     #[test]
-    fn given_loan_request_and_price_raise_higher_then_fluctuation_then_error() {
-        let current_price = LiquidUsdt::from_str_in_dollar("44444.44444445").unwrap();
+    fn given_loan_request_below_offer_price_then_no_error() {
+        let offer_price = LiquidUsdt::from_str_in_dollar("44444.44444445").unwrap();
         let loan_validation_params =
-            LoanValidationParams::test_defaults().with_current_price(current_price);
+            LoanValidationParams::test_defaults().with_offer_price(offer_price);
 
-        let error = validate_loan_is_acceptable(loan_validation_params.clone())
+        validate_loan_is_acceptable(loan_validation_params)
             .unwrap()
-            .unwrap_err();
+            .unwrap();
+    }
+
+    #[test]
+    fn given_collateral_exactly_covering_the_repayment_then_price_is_exact() {
+        let repayment_amount = LiquidUsdt::from_str_in_dollar("10500").unwrap();
+        let collateralization = dec!(1.5);
+
+        // 10_500 * 1.5 / 40_000 = 0.39375 BTC
+        let exact = LiquidBtc::from(Amount::from_sat(39_375_000));
+        let one_sat_less = LiquidBtc::from(Amount::from_sat(39_374_999));
+
+        let price = calculate_request_price(repayment_amount, exact, collateralization).unwrap();
+        let price_one_sat_less =
+            calculate_request_price(repayment_amount, one_sat_less, collateralization).unwrap();
+
+        assert_eq!(price, LiquidUsdt::from_str_in_dollar("40000").unwrap());
+        assert_eq!(
+            price_one_sat_less,
+            LiquidUsdt::from_satodollar(40_000_001_016)
+        );
+    }
+
+    #[test]
+    fn given_collateral_rounded_up_to_the_satoshi_then_no_error() {
+        let loan_request = rounded_loan_request(Amount::from_sat(42_567_568));
+
+        loan_calculation_and_validation(&loan_request, &loan_offer_at_37_000()).unwrap();
+    }
+
+    #[test]
+    fn given_collateral_rounded_down_to_the_satoshi_then_error() {
+        let loan_request = rounded_loan_request(Amount::from_sat(42_567_567));
+
+        let error = loan_calculation_and_validation(&loan_request, &loan_offer_at_37_000())
+            .unwrap_err()
+            .downcast::<LoanValidationError>()
+            .unwrap();
 
         assert_eq!(
             error,
             LoanValidationError::PriceNotAcceptable {
-                request_price: loan_validation_params.request_price,
-                current_price
+                request_price: LiquidUsdt::from_satodollar(37_000_000_494),
+                offer_price: LiquidUsdt::from_str_in_dollar("37000").unwrap(),
             }
         )
     }
 
+    /// 10_500 * 1.5 / 37_000 = 0.42567567567... BTC
+    fn rounded_loan_request(collateral_amount: Amount) -> LoanRequest {
+        LoanRequest {
+            offer_id: OfferId([0u8; 16]),
+            term: 30,
+            principal_amount: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
+            collateralization: dec!(1.5),
+            collateral_amount: collateral_amount.into(),
+
+            // irrelevant for these tests
+            collateral_inputs: vec![],
+            borrower_pk: PublicKey::from_str("0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166").unwrap(),
+            borrower_address: Address::from_str("el1qq0zel5lg55nvhv9kkrq8gme8hnvp0lemuzcmu086dn2m8laxjgkewkhqnh8vxdnlp4cejs3925j0gu9n9krdgmqm89vku0kc8").unwrap()
+        }
+    }
+
+    fn loan_offer_at_37_000() -> LoanOffer {
+        LoanOffer {
+            rate: Rate {
+                ask: Default::default(),
+                bid: LiquidUsdt::from_str_in_dollar("37000").unwrap(),
+            },
+            ..loan_offer()
+        }
+    }
+
+    #[test]
+    fn given_offer_past_expiry_then_it_is_expired() {
+        let loan_offer = LoanOffer {
+            expires_at: 1_600_000_000,
+            ..loan_offer()
+        };
+
+        assert!(!loan_offer.is_expired(1_600_000_000));
+        assert!(loan_offer.is_expired(1_600_000_001));
+    }
+
+    #[test]
+    fn offer_id_roundtrips_through_json() {
+        let offer_id = OfferId::random(&mut thread_rng());
+
+        let json = serde_json::to_string(&offer_id).unwrap();
+        let deserialized = serde_json::from_str::<OfferId>(&json).unwrap();
+
+        assert_eq!(deserialized, offer_id);
+    }
+
+    #[test]
+    fn loan_offer_is_understood_by_client() {
+        let loan_offer = loan_offer();
+
+        let json = serde_json::to_string(&loan_offer).unwrap();
+        let client_offer = serde_json::from_str::<bobtimus_client::LoanOffer>(&json).unwrap();
+
+        assert_eq!(client_offer.id, loan_offer.id.to_string());
+        assert_eq!(client_offer.min_principal, 1000.0);
+        assert_eq!(client_offer.max_principal, 10000.0);
+        assert_eq!(client_offer.max_ltv, 0.75);
+        assert_eq!(client_offer.terms.len(), 2);
+        assert_eq!(client_offer.terms[1].interest_mod, 0.01);
+    }
+
+    #[test]
+    fn loan_request_from_client_is_understood() {
+        let offer_id = OfferId::random(&mut thread_rng());
+        let client_request = bobtimus_client::LoanRequest {
+            offer_id: offer_id.to_string(),
+            term: 30,
+            principal_amount: Amount::from_sat(1_000_000_000_000),
+            collateralization: 1.5,
+            collateral_amount: Amount::from_sat(30_000_000),
+            collateral_inputs: vec![],
+            borrower_pk: PublicKey::from_str("0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166").unwrap(),
+            borrower_address: Address::from_str("el1qq0zel5lg55nvhv9kkrq8gme8hnvp0lemuzcmu086dn2m8laxjgkewkhqnh8vxdnlp4cejs3925j0gu9n9krdgmqm89vku0kc8").unwrap()
+        };
+
+        let json = serde_json::to_string(&client_request).unwrap();
+        let loan_request = serde_json::from_str::<LoanRequest>(&json).unwrap();
+
+        assert_eq!(loan_request.offer_id, offer_id);
+        assert_eq!(
+            loan_request.principal_amount,
+            LiquidUsdt::from_str_in_dollar("10000").unwrap()
+        );
+        assert_eq!(loan_request.collateralization, dec!(1.5));
+        assert_eq!(
+            loan_request.collateral_amount,
+            LiquidBtc::from(Amount::from_sat(30_000_000))
+        );
+    }
+
     #[test]
     fn given_loan_request_with_principal_lower_min_then_error() {
         let request_principal = LiquidUsdt::from_str_in_dollar("999.99999999").unwrap();
@@ -792,11 +1832,31 @@ mod tests {
                 interest_mod: Decimal::ZERO,
             },
         ];
-        let request_term = 29;
+        for request_term in [27, 121] {
+            let loan_validation_params = LoanValidationParams::test_defaults()
+                .with_terms(terms.clone())
+                .with_request_term(request_term);
+
+            let error = validate_loan_is_acceptable(loan_validation_params)
+                .unwrap()
+                .unwrap_err();
+
+            assert_eq!(
+                error,
+                LoanValidationError::TermNotAllowed {
+                    term: request_term,
+                    min_term: 28,
+                    max_term: 120
+                }
+            )
+        }
+    }
 
+    #[test]
+    fn given_offer_without_terms_then_term_not_allowed() {
         let loan_validation_params = LoanValidationParams::test_defaults()
-            .with_terms(terms)
-            .with_request_term(request_term);
+            .with_terms(vec![])
+            .with_request_term(30);
 
         let error = validate_loan_is_acceptable(loan_validation_params)
             .unwrap()
@@ -804,10 +1864,38 @@ mod tests {
 
         assert_eq!(
             error,
-            LoanValidationError::TermNotAllowed { term: request_term }
+            LoanValidationError::TermNotAllowed {
+                term: 30,
+                min_term: 0,
+                max_term: 0
+            }
         )
     }
 
+    #[test]
+    fn given_loan_request_with_term_between_offered_terms_then_no_error() {
+        let terms = vec![
+            Term {
+                days: 30,
+                interest_mod: Decimal::ZERO,
+            },
+            Term {
+                days: 120,
+                interest_mod: dec!(0.02),
+            },
+        ];
+
+        for request_term in [30, 31, 77, 120] {
+            let loan_validation_params = LoanValidationParams::test_defaults()
+                .with_terms(terms.clone())
+                .with_request_term(request_term);
+
+            validate_loan_is_acceptable(loan_validation_params)
+                .unwrap()
+                .unwrap();
+        }
+    }
+
     #[test]
     fn given_loan_request_with_collateralization_lower_min_then_error() {
         let collateralizations = vec![Collateralization {
@@ -855,8 +1943,7 @@ mod tests {
     impl LoanValidationParams {
         fn test_defaults() -> Self {
             let request_price = LiquidUsdt::from_str_in_dollar("40000").unwrap();
-            let current_price = LiquidUsdt::from_str_in_dollar("39603.96039604").unwrap();
-            let price_fluctuation_interval = (dec!(0.90), dec!(1.01));
+            let offer_price = LiquidUsdt::from_str_in_dollar("40000").unwrap();
             let request_principal = LiquidUsdt::from_str_in_dollar("1000").unwrap();
             let min_principal = LiquidUsdt::from_str_in_dollar("1000").unwrap();
             let max_principal = LiquidUsdt::from_str_in_dollar("10000").unwrap();
@@ -875,8 +1962,7 @@ mod tests {
 
             LoanValidationParams {
                 request_price,
-                current_price,
-                price_fluctuation_interval,
+                offer_price,
                 request_principal,
                 min_principal,
                 max_principal,
@@ -893,15 +1979,8 @@ mod tests {
             self.request_price = request_price;
             self
         }
-        pub fn with_current_price(mut self, current_price: LiquidUsdt) -> Self {
-            self.current_price = current_price;
-            self
-        }
-        pub fn with_price_fluctuation_interval(
-            mut self,
-            price_fluctuation_interval: (Decimal, Decimal),
-        ) -> Self {
-            self.price_fluctuation_interval = price_fluctuation_interval;
+        pub fn with_offer_price(mut self, offer_price: LiquidUsdt) -> Self {
+            self.offer_price = offer_price;
             self
         }
         pub fn with_request_principal(mut self, request_principal: LiquidUsdt) -> Self {
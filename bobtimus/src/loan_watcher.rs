@@ -0,0 +1,52 @@
+//! Follows the collateral of the loans we stored, to know which of
+//! them are still open.
+//!
+//! We only hear about early repayments and replacements of a loan
+//! through our API. A repayment at maturity or a liquidation spends the
+//! collateral without us, so we watch the collateral outputs instead.
+//! A loan counts as open as long as its collateral is unspent, also
+//! after its timelock, and counts again if the transaction spending it
+//! is dropped from the mempool.
+
+use crate::{
+    database::{queries, Sqlite},
+    elements_rpc::Client,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+pub async fn run(elementsd: Client, db: Sqlite, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = check(&elementsd, &db).await {
+            tracing::error!("failed to check on loan collateral: {:#}", e);
+        }
+    }
+}
+
+/// Record for every loan whether its collateral is spent, by a
+/// transaction in the mempool or in a block.
+pub async fn check(elementsd: &Client, db: &Sqlite) -> Result<()> {
+    let collaterals = db.do_in_transaction(queries::get_loan_collaterals).await?;
+
+    // The database is not locked across RPC calls
+    let mut spent = Vec::with_capacity(collaterals.len());
+    for (loan_txid, collateral) in collaterals {
+        let is_spent = elementsd.get_tx_out(collateral, true).await?.is_none();
+        spent.push((loan_txid, is_spent));
+    }
+
+    db.do_in_transaction(|conn| {
+        for (loan_txid, is_spent) in spent {
+            queries::set_collateral_spent(conn, loan_txid, is_spent)?;
+        }
+
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
@@ -1,13 +1,47 @@
 use anyhow::Result;
 use bobtimus::{
-    cli::Config, database::Sqlite, elements_rpc::Client, http, kraken, liquidate_loans, Bobtimus,
+    admin::PauseState,
+    cli::{Config, TransferAmount},
+    database::Sqlite,
+    elements_rpc::Client,
+    event::{EventBus, InventoryMonitor, WebhookDispatcher},
+    health::{ReadinessChecks, ReadinessReport},
+    http::{self, AdminAuth},
+    identity::MakerIdentity,
+    input_validation::InputReservations,
+    kraken, liquidate_loans, loan_watcher,
+    notification::{NotificationSubscription, Notifier},
+    rate_limit::RateLimiter,
+    swap_batch::{self, SwapBatcher},
+    swap_watcher::SwapWatcher,
+    wallets, Bobtimus,
 };
 use elements::{
     bitcoin::secp256k1::Secp256k1,
     secp256k1_zkp::rand::{rngs::StdRng, thread_rng, SeedableRng},
 };
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::{join, sync::Mutex};
+use warp::Filter;
+
+/// How often we check for loans whose borrowers need to be notified
+const NOTIFICATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often we check for events which are due to be delivered
+const EVENT_DISPATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often we check whether our balances fell below their inventory
+/// thresholds
+const INVENTORY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often we check whether the swaps we handed out were broadcast
+const SWAP_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often we check whether the collateral of our loans is spent
+const LOAN_WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often we check whether we are ready to build transactions
+const READINESS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,47 +49,207 @@ async fn main() -> Result<()> {
 
     match Config::parse()? {
         Config::Start {
-            elementsd_url,
+            elementsd,
+            wallets: wallet_names,
             http,
             usdt_asset_id,
             db_file,
             https,
+            exposure_limits,
+            margin_call_distance,
+            max_rate_age,
+            grace_period,
+            event_webhooks,
+            webhook_secret,
+            inventory_thresholds,
+            admin_http,
+            admin_token,
+            metrics_http,
+            metrics_token,
+            request_limits,
+            max_pending_negotiations,
+            idempotency_key_expiry,
+            min_input_confirmations,
+            swap_watcher,
+            swap_batching,
         } => {
             let db = Sqlite::new(db_file.as_path())?;
 
-            let elementsd = Client::new(elementsd_url.into())?;
+            // Events stored by other commands are delivered as well,
+            // as long as we can sign them
+            if let Some(webhook_secret) = webhook_secret {
+                let dispatcher = WebhookDispatcher::new(db.clone(), webhook_secret);
+                tokio::spawn(dispatcher.run(EVENT_DISPATCH_INTERVAL));
+            }
+
+            let events = EventBus::new(db.clone(), event_webhooks);
+            let notification_subscription = NotificationSubscription::new(events.clone());
+            let notifier = Notifier::new(db.clone(), events.clone());
+            tokio::spawn(notifier.run(NOTIFICATION_INTERVAL));
+
+            let node = Client::with_auth(elementsd.url.into(), elementsd.auth)?;
+            let elementsd = wallets::open(&node, wallet_names.swap.as_deref()).await?;
+            let lending_wallet = wallets::open(&node, wallet_names.lending.as_deref()).await?;
+            let liquidation_wallet =
+                wallets::open(&node, wallet_names.liquidation.as_deref()).await?;
             let btc_asset_id = elementsd.get_bitcoin_asset_id().await?;
+            let identity = MakerIdentity::load(&elementsd).await?;
+            tracing::info!("Maker identity: {}", identity.public_key());
+
+            let inventory_thresholds: HashMap<_, _> = vec![
+                inventory_thresholds
+                    .btc
+                    .map(|threshold| (btc_asset_id, threshold)),
+                inventory_thresholds
+                    .usdt
+                    .map(|threshold| (usdt_asset_id, threshold.into())),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
 
             let rate_service = kraken::RateService::new().await?;
             let subscription = rate_service.subscribe();
 
+            let inventory = InventoryMonitor::new(inventory_thresholds.clone());
+            tokio::spawn(inventory.run(
+                elementsd.clone(),
+                events.clone(),
+                INVENTORY_CHECK_INTERVAL,
+            ));
+            let swap_watcher = SwapWatcher::new(swap_watcher);
+            tokio::spawn(swap_watcher.clone().run(
+                elementsd.clone(),
+                events.clone(),
+                SWAP_WATCH_INTERVAL,
+            ));
+            tokio::spawn(loan_watcher::run(
+                elementsd.clone(),
+                db.clone(),
+                LOAN_WATCH_INTERVAL,
+            ));
+            // A report missing a few checks in a row means they got stuck
+            let readiness_report = ReadinessReport::new(3 * READINESS_CHECK_INTERVAL);
+            let readiness_checks = ReadinessChecks {
+                rate_service: rate_service.clone(),
+                elementsd: elementsd.clone(),
+                lending_wallet: lending_wallet.clone(),
+                liquidation_wallet: liquidation_wallet.clone(),
+                db: db.clone(),
+                btc_asset_id,
+                usdt_asset_id,
+                max_rate_age,
+                inventory_thresholds,
+            };
+            tokio::spawn(readiness_checks.run(readiness_report.clone(), READINESS_CHECK_INTERVAL));
+
             let bobtimus = Bobtimus {
                 rng: StdRng::from_rng(&mut thread_rng()).unwrap(),
                 rate_service,
                 secp: Secp256k1::new(),
                 elementsd,
+                lending_wallet,
+                liquidation_wallet,
                 btc_asset_id,
                 usdt_asset_id,
                 db,
+                loan_offers: HashMap::new(),
                 lender_states: HashMap::new(),
+                repayment_states: HashMap::new(),
+                exposure_limits,
+                margin_call_distance,
+                grace_period,
+                max_pending_negotiations,
+                idempotency_key_expiry,
+                min_input_confirmations,
+                input_reservations: InputReservations::default(),
+                swap_watcher,
+                events,
+                paused: PauseState::default(),
+                identity,
+                swap_batcher: swap_batching.map(SwapBatcher::new),
             };
             let bobtimus = Arc::new(Mutex::new(bobtimus));
+            if let Some(swap_batching) = swap_batching {
+                tokio::spawn(swap_batch::run(bobtimus.clone(), swap_batching.window));
+            }
+            let max_body_bytes = request_limits.max_body_bytes;
+            let rate_limiter = Arc::new(RateLimiter::new(request_limits));
+
+            // The admin API is served alongside the public API only if
+            // it is protected by a token
+            let public_admin_auth = match (&admin_http, &admin_token) {
+                (None, Some(token)) => AdminAuth::Token(token.clone()),
+                _ => AdminAuth::Disabled,
+            };
+
+            if let Some(listen_admin) = admin_http {
+                let auth = match admin_token {
+                    Some(token) => AdminAuth::Token(token),
+                    None => {
+                        tracing::warn!("admin API on {} is not protected by a token", listen_admin);
+                        AdminAuth::Open
+                    }
+                };
+
+                let filter = http::admin_routes(
+                    bobtimus.clone(),
+                    readiness_report.clone(),
+                    auth,
+                    max_body_bytes,
+                )
+                .with(http::record_responses());
+                tokio::spawn(warp::serve(filter).run(listen_admin));
+            }
+
+            // Like the admin API, the metrics are served alongside the
+            // public API only if they are protected by a token
+            let public_metrics_auth = match (&metrics_http, &metrics_token) {
+                (None, Some(token)) => AdminAuth::Token(token.clone()),
+                _ => AdminAuth::Disabled,
+            };
+
+            if let Some(listen_metrics) = metrics_http {
+                let auth = match metrics_token {
+                    Some(token) => AdminAuth::Token(token),
+                    None => AdminAuth::Open,
+                };
+
+                let filter =
+                    http::metrics_routes(bobtimus.clone(), auth).with(http::record_responses());
+                tokio::spawn(warp::serve(filter).run(listen_metrics));
+            }
 
             let https = https.map(|https| {
-                warp::serve(http::routes(bobtimus.clone(), subscription.clone()))
-                    .tls()
-                    .cert_path(https.tls_certificate)
-                    .key_path(https.tls_private_key)
-                    .run(https.listen_https)
+                warp::serve(http::routes(
+                    bobtimus.clone(),
+                    subscription.clone(),
+                    notification_subscription.clone(),
+                    readiness_report.clone(),
+                    public_admin_auth.clone(),
+                    public_metrics_auth.clone(),
+                    rate_limiter.clone(),
+                ))
+                .tls()
+                .cert_path(https.tls_certificate)
+                .key_path(https.tls_private_key)
+                .run(https.listen_https)
             });
 
             let http = http.map(|listen_http| {
-                let filter = http::routes(bobtimus.clone(), subscription);
+                let filter = http::routes(
+                    bobtimus.clone(),
+                    subscription,
+                    notification_subscription,
+                    readiness_report,
+                    public_admin_auth,
+                    public_metrics_auth,
+                    rate_limiter,
+                );
 
                 #[cfg(feature = "faucet")]
                 let filter = {
                     use elements::Address;
-                    use warp::Filter;
 
                     let cors = warp::cors().allow_any_origin();
 
@@ -88,13 +282,48 @@ async fn main() -> Result<()> {
             }
         }
         Config::LiquidateLoans {
-            elementsd_url,
+            elementsd,
             db_file,
+            event_webhooks,
+            webhook_secret,
         } => {
             let db = Sqlite::new(db_file.as_path())?;
-            let elementsd = Client::new(elementsd_url.into())?;
+            let elementsd = Client::with_auth(elementsd.url.into(), elementsd.auth)?;
+            let events = EventBus::new(db.clone(), event_webhooks);
+
+            liquidate_loans(&elementsd, db.clone(), &events).await?;
+
+            // Deliver the events right away instead of leaving them to
+            // the dispatcher of a running Bobtimus
+            if let Some(webhook_secret) = webhook_secret {
+                WebhookDispatcher::new(db, webhook_secret)
+                    .dispatch_due()
+                    .await?;
+            }
+        }
+        Config::Transfer {
+            elementsd,
+            usdt_asset_id,
+            from_wallet,
+            to_wallet,
+            amount,
+        } => {
+            let node = Client::with_auth(elementsd.url.into(), elementsd.auth)?;
+            let from = wallets::open(&node, from_wallet.as_deref()).await?;
+            let to = wallets::open(&node, to_wallet.as_deref()).await?;
+
+            let (asset_id, amount) = match amount {
+                TransferAmount::Btc(amount) => (node.get_bitcoin_asset_id().await?, amount),
+                TransferAmount::Usdt(amount) => (usdt_asset_id, amount.into()),
+            };
+            let txid = wallets::transfer(&from, &to, asset_id, amount).await?;
 
-            liquidate_loans(&elementsd, db).await?;
+            tracing::info!(
+                "Transferred {} sats of asset {} in transaction {}",
+                amount.as_sat(),
+                asset_id,
+                txid
+            );
         }
     }
 
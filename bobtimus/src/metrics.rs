@@ -0,0 +1,370 @@
+use crate::{
+    database::queries,
+    event::{Event, SwapSide},
+    unix_timestamp, Bobtimus, LatestRate,
+};
+use anyhow::{Context, Result};
+use elements::secp256k1_zkp::rand::{CryptoRng, RngCore};
+use futures::{Future, Stream, StreamExt};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::{
+    convert::TryFrom,
+    time::{Duration, SystemTime},
+};
+use warp::http::StatusCode;
+
+lazy_static! {
+    static ref SWAPS: IntCounterVec = register_int_counter_vec!(
+        "bobtimus_swaps_total",
+        "Swap transactions we signed and handed to takers",
+        &["side"]
+    )
+    .expect("valid metric");
+    static ref SWAP_VOLUME: IntCounterVec = register_int_counter_vec!(
+        "bobtimus_swap_volume_sats_total",
+        "Amount of each asset in the swap transactions we signed, in satoshi",
+        &["side", "asset"]
+    )
+    .expect("valid metric");
+    static ref UNBROADCAST_SWAPS: IntCounterVec = register_int_counter_vec!(
+        "bobtimus_unbroadcast_swaps_total",
+        "Swap transactions the taker did not broadcast, by whether they lapsed or conflicted",
+        &["reason"]
+    )
+    .expect("valid metric");
+    static ref SWAP_BROADCAST_DELAY: Histogram = register_histogram!(
+        "bobtimus_swap_broadcast_delay_seconds",
+        "Time between handing out a swap transaction and seeing it broadcast",
+        vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .expect("valid metric");
+    static ref LOANS: IntCounter = register_int_counter!(
+        "bobtimus_loans_opened_total",
+        "Loan transactions we broadcast"
+    )
+    .expect("valid metric");
+    static ref OPEN_LOANS: IntGauge = register_int_gauge!(
+        "bobtimus_open_loans",
+        "Loans whose collateral has not been spent"
+    )
+    .expect("valid metric");
+    static ref OUTSTANDING_PRINCIPAL: IntGauge = register_int_gauge!(
+        "bobtimus_outstanding_principal_sats",
+        "Principal of all loans whose collateral has not been spent, in L-USDt satoshi"
+    )
+    .expect("valid metric");
+    static ref LIQUIDATION_ATTEMPTS: IntCounter = register_int_counter!(
+        "bobtimus_liquidation_attempts_total",
+        "Liquidation transactions we tried to broadcast"
+    )
+    .expect("valid metric");
+    static ref LIQUIDATION_FAILURES: IntCounter = register_int_counter!(
+        "bobtimus_liquidation_failures_total",
+        "Liquidation transactions elementsd rejected"
+    )
+    .expect("valid metric");
+    static ref ELEMENTSD_RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "bobtimus_elementsd_rpc_duration_seconds",
+        "Latency of the RPC calls to elementsd",
+        &["method"]
+    )
+    .expect("valid metric");
+    static ref RATE_UPDATED_AT: IntGauge = register_int_gauge!(
+        "bobtimus_rate_updated_timestamp_seconds",
+        "Unix timestamp of the last rate received from the feed"
+    )
+    .expect("valid metric");
+    static ref RATE_AGE: IntGauge = register_int_gauge!(
+        "bobtimus_rate_age_seconds",
+        "Seconds since the last rate was received from the feed"
+    )
+    .expect("valid metric");
+    static ref RATE_FEED_RECONNECTS: IntCounter = register_int_counter!(
+        "bobtimus_rate_feed_reconnects_total",
+        "Times we reconnected to the rate feed after losing the connection"
+    )
+    .expect("valid metric");
+    static ref SSE_SUBSCRIBERS: IntGaugeVec = register_int_gauge_vec!(
+        "bobtimus_sse_subscribers",
+        "Clients currently subscribed to a server-sent event stream",
+        &["stream"]
+    )
+    .expect("valid metric");
+    static ref HTTP_RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "bobtimus_http_responses_total",
+        "HTTP responses by route and status code",
+        &["route", "status"]
+    )
+    .expect("valid metric");
+}
+
+/// Count the swaps, loans and liquidations described by an event.
+pub fn observe_event(event: &Event) {
+    match event {
+        Event::SwapSigned {
+            side,
+            btc_amount,
+            usdt_amount,
+            ..
+        } => {
+            let side = match side {
+                SwapSide::Buy => "buy",
+                SwapSide::Sell => "sell",
+            };
+
+            SWAPS.with_label_values(&[side]).inc();
+            SWAP_VOLUME
+                .with_label_values(&[side, "lbtc"])
+                .inc_by(btc_amount.0.as_sat());
+            SWAP_VOLUME
+                .with_label_values(&[side, "lusdt"])
+                .inc_by(usdt_amount.as_satodollar());
+        }
+        Event::SwapLapsed { .. } => {
+            UNBROADCAST_SWAPS.with_label_values(&["lapsed"]).inc();
+        }
+        Event::SwapConflicted { .. } => {
+            UNBROADCAST_SWAPS.with_label_values(&["conflicted"]).inc();
+        }
+        Event::LoanOpened { .. } => {
+            LOANS.inc();
+        }
+        Event::LiquidationBroadcast { .. } => {
+            LIQUIDATION_ATTEMPTS.inc();
+        }
+        Event::LiquidationFailed { .. } => {
+            LIQUIDATION_ATTEMPTS.inc();
+            LIQUIDATION_FAILURES.inc();
+        }
+        Event::BorrowerNotified(_) | Event::InventoryLow { .. } => {}
+    }
+}
+
+/// Record how long the taker took to broadcast a swap transaction.
+///
+/// Only as precise as the interval in which pending swaps are checked.
+pub fn swap_broadcast(delay: Duration) {
+    SWAP_BROADCAST_DELAY.observe(delay.as_secs_f64());
+}
+
+/// Measure how long elementsd takes to answer an RPC call.
+pub async fn time_rpc<F>(method: &str, request: F) -> F::Output
+where
+    F: Future,
+{
+    let _timer = ELEMENTSD_RPC_DURATION
+        .with_label_values(&[method])
+        .start_timer();
+
+    request.await
+}
+
+pub fn rate_updated(now: SystemTime) {
+    if let Ok(timestamp) = unix_timestamp(now) {
+        RATE_UPDATED_AT.set(timestamp.into());
+    }
+}
+
+pub fn rate_feed_reconnected() {
+    RATE_FEED_RECONNECTS.inc();
+}
+
+/// Count the subscriber of an event stream for as long as the stream
+/// is alive.
+pub fn track_subscriber<S>(name: &'static str, stream: S) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    let subscriber = Subscriber::new(name);
+
+    stream.map(move |item| {
+        let _ = &subscriber;
+        item
+    })
+}
+
+struct Subscriber(&'static str);
+
+impl Subscriber {
+    fn new(name: &'static str) -> Self {
+        SSE_SUBSCRIBERS.with_label_values(&[name]).inc();
+
+        Self(name)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        SSE_SUBSCRIBERS.with_label_values(&[self.0]).dec();
+    }
+}
+
+pub fn observe_http_response(path: &str, status: StatusCode) {
+    HTTP_RESPONSES
+        .with_label_values(&[route(path), status.as_str()])
+        .inc();
+}
+
+/// The routes we serve outside of the embedded app, with their path
+/// parameters replaced by a placeholder.
+pub(crate) const ROUTES: &[&str] = &[
+    "/health/live",
+    "/health/ready",
+    "/metrics",
+    "/api/openapi.json",
+    "/api/rate/lbtc-lusdt",
+    "/api/swap/lbtc-lusdt/buy",
+    "/api/swap/lbtc-lusdt/sell",
+    "/api/swap/lbtc-lusdt/buy/batch",
+    "/api/swap/lbtc-lusdt/sell/batch",
+    "/api/swap/lbtc-lusdt/batch/sign",
+    "/api/loan/lbtc-lusdt",
+    "/api/loan/lbtc-lusdt/{param}/repayment",
+    "/api/loan/lbtc-lusdt/repay",
+    "/api/loan/lbtc-lusdt/repay/finalize",
+    "/api/loan/lbtc-lusdt/finalize",
+    "/api/loan/{param}",
+    "/api/loan/{param}/status",
+    "/api/faucet/{param}",
+    "/admin/balances",
+    "/admin/health",
+    "/admin/negotiations",
+    "/admin/pause",
+    "/admin/limits",
+    "/admin/loans/{param}/liquidate",
+    "/admin/utxos/unlock",
+];
+
+/// The route a request was made to, without the parameters in its path.
+///
+/// Parameters such as transaction IDs and addresses are replaced with a
+/// placeholder so that they do not create a new time series each, and
+/// every path we do not serve is counted as `unmatched`. Everything
+/// outside of the API is served from the embedded app.
+fn route(path: &str) -> &'static str {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+
+    match segments.first() {
+        Some(&"api") | Some(&"admin") | Some(&"metrics") | Some(&"health") => {}
+        _ => return "/app",
+    }
+
+    let route = segments
+        .iter()
+        .map(|segment| {
+            if segment.len() >= 32 {
+                "/{param}".to_owned()
+            } else {
+                format!("/{}", segment)
+            }
+        })
+        .collect::<String>();
+
+    ROUTES
+        .iter()
+        .find(|known| **known == route)
+        .copied()
+        .unwrap_or("unmatched")
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// All metrics in the Prometheus text format.
+    pub async fn handle_metrics_request(&self) -> Result<String> {
+        let now = SystemTime::now();
+        let timestamp = unix_timestamp(now)?;
+
+        let (open_loans, outstanding_principal) = self
+            .db
+            .do_in_transaction(|conn| {
+                let open_loans = queries::count_open_loans(conn)?;
+                let outstanding_principal = queries::get_outstanding_principal(conn, None)?;
+
+                Ok((open_loans, outstanding_principal))
+            })
+            .await?;
+        OPEN_LOANS.set(open_loans);
+        OUTSTANDING_PRINCIPAL.set(
+            i64::try_from(outstanding_principal.as_satodollar())
+                .context("outstanding principal does not fit into an i64")?,
+        );
+
+        let rate_updated_at = RATE_UPDATED_AT.get();
+        if rate_updated_at > 0 {
+            RATE_AGE.set(i64::from(timestamp) - rate_updated_at);
+        }
+
+        encode()
+    }
+}
+
+fn encode() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .context("failed to encode metrics")?;
+
+    String::from_utf8(buffer).context("metrics are not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_are_removed_from_route() {
+        let txid = "a3f4c5b6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4";
+
+        assert_eq!(
+            route(&format!("/api/loan/{}/status", txid)),
+            "/api/loan/{param}/status"
+        );
+        assert_eq!(
+            route("/api/swap/lbtc-lusdt/sell"),
+            "/api/swap/lbtc-lusdt/sell"
+        );
+    }
+
+    #[test]
+    fn unknown_paths_share_a_route() {
+        assert_eq!(route("/api/swap/lbtc-lusdt/hold"), "unmatched");
+        assert_eq!(route("/api/does-not-exist"), "unmatched");
+        assert_eq!(route("/admin/x"), "unmatched");
+    }
+
+    #[test]
+    fn every_documented_route_is_known() {
+        let document = crate::openapi::document();
+
+        for path in document["paths"].as_object().unwrap().keys() {
+            let route = path.replace("{txid}", "{param}");
+
+            assert!(ROUTES.contains(&route.as_str()), "{} is not known", path);
+        }
+    }
+
+    #[test]
+    fn app_resources_share_a_route() {
+        assert_eq!(route("/app/main.js"), "/app");
+        assert_eq!(route("/"), "/app");
+    }
+
+    #[test]
+    fn subscriber_is_counted_while_stream_is_alive() {
+        let subscribers = || SSE_SUBSCRIBERS.with_label_values(&["test"]).get();
+
+        let stream = track_subscriber("test", futures::stream::empty::<()>());
+        assert_eq!(subscribers(), 1);
+
+        drop(stream);
+        assert_eq!(subscribers(), 0);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{
+    database::{queries, NotificationForm, Sqlite},
+    event::{Event, EventBus},
+    loan::LoanTerms,
+    unix_timestamp,
+};
+use anyhow::Result;
+use elements::{bitcoin::PublicKey, Txid};
+use futures::{stream, Stream};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+
+const DAY: u32 = 24 * 60 * 60;
+
+/// Reminder for the borrower to repay a loan before its collateral is
+/// liquidated
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Notification {
+    #[schemars(with = "String")]
+    pub loan_txid: Txid,
+    pub kind: NotificationKind,
+    /// Absolute timelock of the loan as Unix timestamp
+    pub timelock: u32,
+    /// Unix timestamp after which the collateral will be liquidated
+    pub liquidation_at: u32,
+    /// The borrower's public key, unknown for loans taken out before it was tracked
+    #[schemars(with = "Option<String>")]
+    pub borrower_pk: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    MaturityInSevenDays,
+    MaturityInOneDay,
+    /// The timelock has been reached, the collateral will be liquidated
+    /// once the grace period has passed
+    Matured,
+}
+
+impl NotificationKind {
+    const ALL: [NotificationKind; 3] = [
+        NotificationKind::MaturityInSevenDays,
+        NotificationKind::MaturityInOneDay,
+        NotificationKind::Matured,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::MaturityInSevenDays => "maturity_in_seven_days",
+            NotificationKind::MaturityInOneDay => "maturity_in_one_day",
+            NotificationKind::Matured => "matured",
+        }
+    }
+
+    fn due_at(&self, timelock: u32) -> u32 {
+        match self {
+            NotificationKind::MaturityInSevenDays => timelock.saturating_sub(7 * DAY),
+            NotificationKind::MaturityInOneDay => timelock.saturating_sub(DAY),
+            NotificationKind::Matured => timelock,
+        }
+    }
+}
+
+/// Notifies borrowers whose loans are about to mature.
+///
+/// Notifications are published as events, which pushes them to the
+/// borrowers subscribed to the status of their loan and delivers them
+/// to the event webhooks. The notifications which were sent are
+/// recorded, so that every notification is only sent once.
+pub struct Notifier {
+    db: Sqlite,
+    events: EventBus,
+}
+
+impl Notifier {
+    pub fn new(db: Sqlite, events: EventBus) -> Self {
+        Self { db, events }
+    }
+
+    /// Send the notifications which are due every `interval` until the
+    /// process is stopped.
+    pub async fn run(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let result = async {
+                let now = unix_timestamp(SystemTime::now())?;
+                self.schedule(now).await
+            }
+            .await;
+
+            if let Err(e) = result {
+                tracing::error!("failed to notify borrowers: {:#}", e);
+            }
+        }
+    }
+
+    /// Publish all notifications which have become due at `now` and
+    /// were not sent before.
+    ///
+    /// Loans whose collateral has been spent, e.g. because they were
+    /// repaid or liquidated, are not considered anymore.
+    pub async fn schedule(&self, now: u32) -> Result<()> {
+        let loans = self
+            .db
+            .do_in_transaction(|conn| {
+                queries::get_open_loans_maturing_by(conn, now.saturating_add(7 * DAY))
+            })
+            .await?;
+
+        let notifications = loans
+            .iter()
+            .flat_map(|(loan_txid, terms, liquidation_at)| {
+                due_notifications(*loan_txid, terms, *liquidation_at, now)
+            })
+            .collect::<Vec<_>>();
+
+        let unsent = self
+            .db
+            .do_in_transaction(|conn| {
+                let mut unsent = Vec::new();
+                for notification in notifications.iter() {
+                    if NotificationForm::new(notification, now)?.insert(conn)? {
+                        unsent.push(*notification);
+                    }
+                }
+
+                Ok(unsent)
+            })
+            .await?;
+
+        for notification in unsent {
+            self.events
+                .publish(Event::BorrowerNotified(notification))
+                .await;
+        }
+
+        Ok(())
+    }
+}
+
+/// The notifications which are due for a loan at `now`.
+///
+/// Notifications which were due before the loan was taken out are
+/// skipped, e.g. there is no reminder seven days before maturity for a
+/// loan with a term of three days.
+fn due_notifications(
+    loan_txid: Txid,
+    terms: &LoanTerms,
+    liquidation_at: u32,
+    now: u32,
+) -> Vec<Notification> {
+    NotificationKind::ALL
+        .iter()
+        .filter(|kind| {
+            let due_at = kind.due_at(terms.timelock);
+            terms.start <= due_at && due_at <= now
+        })
+        .map(|kind| Notification {
+            loan_txid,
+            kind: *kind,
+            timelock: terms.timelock,
+            liquidation_at,
+            borrower_pk: terms.borrower_pk,
+        })
+        .collect()
+}
+
+/// The notifications published on the event bus.
+#[derive(Clone)]
+pub struct NotificationSubscription {
+    events: EventBus,
+}
+
+impl NotificationSubscription {
+    pub fn new(events: EventBus) -> Self {
+        Self { events }
+    }
+
+    /// Notifications published from now on, for all loans.
+    pub fn into_stream(self) -> impl Stream<Item = Notification> {
+        stream::unfold(self.events.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(Event::BorrowerNotified(notification)) => {
+                        return Some((notification, receiver))
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("notification subscriber skipped {} events", skipped)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LiquidBtc, LiquidUsdt};
+    use elements::bitcoin::Amount;
+    use futures::StreamExt;
+    use rust_decimal_macros::dec;
+
+    fn loan_terms(term_in_days: u32) -> LoanTerms {
+        LoanTerms {
+            principal_amount: LiquidUsdt::from_str_in_dollar("10000").unwrap(),
+            repayment_amount: LiquidUsdt::from_str_in_dollar("10600").unwrap(),
+            collateral_amount: LiquidBtc::from(Amount::from_btc(0.5).unwrap()),
+            liquidation_price: LiquidUsdt::from_str_in_dollar("26500").unwrap(),
+            max_ltv: dec!(0.8),
+            start: 1_600_000_000,
+            timelock: 1_600_000_000 + term_in_days * DAY,
+            borrower_pk: None,
+        }
+    }
+
+    fn due_kinds(terms: &LoanTerms, now: u32) -> Vec<NotificationKind> {
+        due_notifications(Txid::default(), terms, terms.timelock + DAY, now)
+            .into_iter()
+            .map(|notification| notification.kind)
+            .collect()
+    }
+
+    #[test]
+    fn given_more_than_seven_days_to_maturity_then_nothing_is_due() {
+        let terms = loan_terms(30);
+
+        assert!(due_kinds(&terms, terms.timelock - 8 * DAY).is_empty());
+    }
+
+    #[test]
+    fn given_seven_days_to_maturity_then_first_reminder_is_due() {
+        let terms = loan_terms(30);
+
+        assert_eq!(
+            due_kinds(&terms, terms.timelock - 7 * DAY),
+            vec![NotificationKind::MaturityInSevenDays]
+        );
+    }
+
+    #[test]
+    fn given_timelock_reached_then_all_notifications_are_due() {
+        let terms = loan_terms(30);
+
+        assert_eq!(
+            due_kinds(&terms, terms.timelock),
+            NotificationKind::ALL.to_vec()
+        );
+    }
+
+    #[test]
+    fn given_term_shorter_than_seven_days_then_first_reminder_is_skipped() {
+        let terms = loan_terms(3);
+
+        assert_eq!(
+            due_kinds(&terms, terms.timelock),
+            vec![
+                NotificationKind::MaturityInOneDay,
+                NotificationKind::Matured
+            ]
+        );
+    }
+
+    #[test]
+    fn liquidation_is_announced_after_grace_period() {
+        let terms = loan_terms(30);
+
+        let notifications = due_notifications(
+            Txid::default(),
+            &terms,
+            terms.timelock + DAY,
+            terms.timelock,
+        );
+
+        assert!(notifications
+            .iter()
+            .all(|notification| notification.liquidation_at == terms.timelock + DAY));
+    }
+
+    #[tokio::test]
+    async fn subscription_only_streams_notifications() {
+        let events = EventBus::new(Sqlite::new_ephemeral_db().unwrap(), Vec::new());
+        let mut stream = Box::pin(NotificationSubscription::new(events.clone()).into_stream());
+
+        let terms = loan_terms(30);
+        let notification = due_notifications(
+            Txid::default(),
+            &terms,
+            terms.timelock + DAY,
+            terms.timelock,
+        )[0];
+        events
+            .publish(Event::LiquidationBroadcast {
+                txid: Txid::default(),
+            })
+            .await;
+        events.publish(Event::BorrowerNotified(notification)).await;
+
+        assert_eq!(stream.next().await, Some(notification));
+    }
+}
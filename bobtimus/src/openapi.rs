@@ -0,0 +1,593 @@
+//! The OpenAPI 3 description of the public HTTP API served by
+//! [`crate::http::routes`].
+//!
+//! The schemas of request and response bodies are generated from the
+//! types which are serialized on the wire, those of `bobtimus_client`
+//! and the ones only Bobtimus uses. The loan response of `baru` is
+//! described by a mirror, which the tests check against it.
+//! Every route has to be described here, which is enforced by the tests
+//! at the bottom of this module.
+
+use crate::{
+    health::{Liveness, ReadinessStatus},
+    loan::{LoanDetails, RepaymentQuote, RepaymentRequest, RepaymentResponse},
+    notification::Notification,
+};
+use bobtimus_client::{
+    schema::LoanResponse, CreateSwapPayload, FinalizeLoanPayload, LoanOffer, LoanRequest, Problem,
+    PsetLoanResponse, Rate, IDENTITY_HEADER, PSET_CONTENT_TYPE, SIGNATURE_HEADER,
+};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde_json::{json, Value};
+
+/// The OpenAPI document, served at `/api/openapi.json`.
+pub fn document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+
+    let rate = schema::<Rate>(&mut generator);
+    let create_swap_payload = schema::<CreateSwapPayload>(&mut generator);
+    let loan_offer = schema::<LoanOffer>(&mut generator);
+    let loan_request = schema::<LoanRequest>(&mut generator);
+    let loan_response = schema::<LoanResponse>(&mut generator);
+    let pset_loan_response = schema::<PsetLoanResponse>(&mut generator);
+    let finalize_loan_payload = schema::<FinalizeLoanPayload>(&mut generator);
+    let problem = schema::<Problem>(&mut generator);
+    let repayment_quote = schema::<RepaymentQuote>(&mut generator);
+    let repayment_request = schema::<RepaymentRequest>(&mut generator);
+    let repayment_response = schema::<RepaymentResponse>(&mut generator);
+    let loan_details = schema::<LoanDetails>(&mut generator);
+    let notification = schema::<Notification>(&mut generator);
+    let liveness = schema::<Liveness>(&mut generator);
+    let readiness = schema::<ReadinessStatus>(&mut generator);
+
+    let object = json!({ "type": "object" });
+    let txid = json!({ "type": "string", "description": "Hex-encoded transaction ID" });
+    let txid_parameter = json!({
+        "name": "txid",
+        "in": "path",
+        "required": true,
+        "description": "The ID of the loan transaction",
+        "schema": { "type": "string" }
+    });
+    let idempotency_key_parameter = json!({
+        "name": "Idempotency-Key",
+        "in": "header",
+        "required": false,
+        "description": "Identifies the request across retries, repeating it with the same key from the same address returns the original response",
+        "schema": { "type": "string", "minLength": 1, "maxLength": 255 }
+    });
+    let transaction = json!({ "type": "string", "description": "Hex-encoded transaction" });
+    let pset = json!({ "type": "string", "description": "Base64-encoded PSET" });
+    let loan_response = json!({ "oneOf": [loan_response, pset_loan_response] });
+    let errors = problem_response(&problem);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Bobtimus",
+            "description": "Swaps between L-BTC and L-USDt, and loans of L-USDt against L-BTC collateral.",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": {
+            "/api/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "responses": {
+                        "200": response("application/json", &object)
+                    }
+                }
+            },
+            "/api/rate/lbtc-lusdt": {
+                "get": {
+                    "summary": "Every update of the rate, as `rate` server-sent events",
+                    "responses": {
+                        "200": response("text/event-stream", &rate)
+                    }
+                }
+            },
+            "/api/swap/lbtc-lusdt/buy": {
+                "post": {
+                    "summary": "Create a swap transaction in which the taker buys L-BTC with L-USDt",
+                    "parameters": [idempotency_key_parameter],
+                    "requestBody": request(&create_swap_payload),
+                    "responses": with_errors(&errors, signed(transaction_response(&transaction, &pset)))
+                }
+            },
+            "/api/swap/lbtc-lusdt/sell": {
+                "post": {
+                    "summary": "Create a swap transaction in which the taker sells L-BTC for L-USDt",
+                    "parameters": [idempotency_key_parameter],
+                    "requestBody": request(&create_swap_payload),
+                    "responses": with_errors(&errors, signed(transaction_response(&transaction, &pset)))
+                }
+            },
+            "/api/swap/lbtc-lusdt/buy/batch": {
+                "post": {
+                    "summary": "Join the next batch of swaps in which takers buy L-BTC with L-USDt, replying with the batch transaction once it is built",
+                    "requestBody": request(&create_swap_payload),
+                    "responses": with_errors(&errors, signed(response("text/plain", &transaction)))
+                }
+            },
+            "/api/swap/lbtc-lusdt/sell/batch": {
+                "post": {
+                    "summary": "Join the next batch of swaps in which takers sell L-BTC for L-USDt, replying with the batch transaction once it is built",
+                    "requestBody": request(&create_swap_payload),
+                    "responses": with_errors(&errors, signed(response("text/plain", &transaction)))
+                }
+            },
+            "/api/swap/lbtc-lusdt/batch/sign": {
+                "post": {
+                    "summary": "Add the signatures of a taker to a batch transaction, replying once every taker signed and it is broadcast",
+                    "requestBody": signed_transaction_request(&finalize_loan_payload, &pset),
+                    "responses": with_errors(&errors, response("application/json", &txid))
+                }
+            },
+            "/api/loan/lbtc-lusdt": {
+                "get": {
+                    "summary": "The terms under which loans are currently offered",
+                    "responses": with_errors(&errors, signed(response("application/json", &loan_offer)))
+                },
+                "post": {
+                    "summary": "Request a loan under a previous offer",
+                    "parameters": [idempotency_key_parameter],
+                    "requestBody": request(&loan_request),
+                    "responses": with_errors(&errors, signed(response("application/json", &loan_response)))
+                }
+            },
+            "/api/loan/lbtc-lusdt/finalize": {
+                "post": {
+                    "summary": "Sign and broadcast a loan transaction signed by the borrower",
+                    "requestBody": signed_transaction_request(&finalize_loan_payload, &pset),
+                    "responses": with_errors(&errors, response("application/json", &txid))
+                }
+            },
+            "/api/loan/lbtc-lusdt/{txid}/repayment": {
+                "get": {
+                    "summary": "Quote for repaying an open loan early, in full",
+                    "parameters": [txid_parameter],
+                    "responses": with_errors(&errors, signed(response("application/json", &repayment_quote)))
+                }
+            },
+            "/api/loan/lbtc-lusdt/repay": {
+                "post": {
+                    "summary": "Request our refund input for repaying an open loan early",
+                    "requestBody": request(&repayment_request),
+                    "responses": with_errors(&errors, response("application/json", &repayment_response))
+                }
+            },
+            "/api/loan/lbtc-lusdt/repay/finalize": {
+                "post": {
+                    "summary": "Sign and broadcast a repayment transaction signed by the borrower",
+                    "requestBody": signed_transaction_request(&finalize_loan_payload, &pset),
+                    "responses": with_errors(&errors, response("application/json", &txid))
+                }
+            },
+            "/api/loan/{txid}": {
+                "get": {
+                    "summary": "The details and status of a loan",
+                    "parameters": [txid_parameter],
+                    "responses": with_errors(&errors, response("application/json", &loan_details))
+                }
+            },
+            "/api/loan/{txid}/status": {
+                "get": {
+                    "summary": "Every update of a loan as `loan` server-sent events, with `margin_call` and `notification` events",
+                    "description": "The data of `loan` and `margin_call` events are the details of the loan, the data of `notification` events a reminder of its maturity.",
+                    "parameters": [txid_parameter],
+                    "responses": with_errors(&errors, response("text/event-stream", &json!({ "oneOf": [loan_details, notification] })))
+                }
+            },
+            "/health/live": {
+                "get": {
+                    "summary": "Whether Bobtimus is running",
+                    "responses": {
+                        "200": response("application/json", &liveness)
+                    }
+                }
+            },
+            "/health/ready": {
+                "get": {
+                    "summary": "Whether Bobtimus is able to build transactions, as of the latest readiness checks",
+                    "responses": {
+                        "200": response("application/json", &readiness),
+                        "503": response("application/json", &readiness)
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": generator.take_definitions()
+        }
+    })
+}
+
+fn schema<T>(generator: &mut SchemaGenerator) -> Value
+where
+    T: schemars::JsonSchema,
+{
+    serde_json::to_value(generator.subschema_for::<T>()).expect("schemas serialize to JSON")
+}
+
+fn request(schema: &Value) -> Value {
+    json!({
+        "required": true,
+        "content": {
+            "application/json": { "schema": schema }
+        }
+    })
+}
+
+/// The request body of a signed transaction, which may also be sent as
+/// PSET.
+fn signed_transaction_request(schema: &Value, pset: &Value) -> Value {
+    json!({
+        "required": true,
+        "content": {
+            "application/json": { "schema": schema },
+            PSET_CONTENT_TYPE: { "schema": pset }
+        }
+    })
+}
+
+/// A transaction, as PSET if that is accepted by the client.
+fn transaction_response(transaction: &Value, pset: &Value) -> Value {
+    json!({
+        "description": "",
+        "content": {
+            "text/plain": { "schema": transaction },
+            PSET_CONTENT_TYPE: { "schema": pset }
+        }
+    })
+}
+
+/// A response signed with the identity key of Bobtimus.
+fn signed(mut response: Value) -> Value {
+    response["headers"] = json!({
+        IDENTITY_HEADER: {
+            "description": "Hex-encoded public key of the identity of Bobtimus",
+            "schema": { "type": "string" }
+        },
+        SIGNATURE_HEADER: {
+            "description": "Base64-encoded signed message over the swap or loan transaction ID, or the SHA256 digest of the body of a quote",
+            "schema": { "type": "string" }
+        }
+    });
+
+    response
+}
+
+fn response(content_type: &str, schema: &Value) -> Value {
+    json!({
+        "description": "",
+        "content": {
+            content_type: { "schema": schema }
+        }
+    })
+}
+
+/// The problem details returned for failed requests.
+fn problem_response(problem: &Value) -> Value {
+    json!({
+        "description": "The request failed",
+        "content": {
+            "application/problem+json": { "schema": problem }
+        }
+    })
+}
+
+fn with_errors(errors: &Value, ok: Value) -> Value {
+    json!({
+        "200": ok,
+        "default": errors
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http, metrics};
+    use bobtimus_client::ProblemType;
+    use elements::{
+        bitcoin::{Amount, PublicKey},
+        Address, Transaction, Txid,
+    };
+    use std::{collections::HashMap, str::FromStr};
+    use warp::{
+        filters::BoxedFilter, http::header::CONTENT_TYPE, hyper::body, reply::Response, Reply,
+    };
+
+    const TXID: &str = "a3f4c5b6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4";
+    const ADDRESS: &str = "el1qq0zel5lg55nvhv9kkrq8gme8hnvp0lemuzcmu086dn2m8laxjgkewkhqnh8vxdnlp4cejs3925j0gu9n9krdgmqm89vku0kc8";
+    const METHODS: [&str; 4] = ["get", "post", "put", "delete"];
+
+    #[tokio::test]
+    async fn every_documented_operation_is_routed() {
+        let routes = http::tests::offline_routes();
+
+        for (path, method, operation) in operations(&document()) {
+            let uri = example_uri(&path, &operation);
+
+            // Only the filter is run, the body is not consumed as event
+            // streams never end
+            let response = send(&routes, &method, &uri, None).await;
+
+            assert!(
+                response.is_some(),
+                "{} {} is documented but not routed",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn undocumented_methods_are_not_routed() {
+        let routes = http::tests::offline_routes();
+        let document = document();
+
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            let uri = example_uri(path, &Value::Null);
+
+            for method in METHODS.iter() {
+                if operations.get(*method).is_some() {
+                    continue;
+                }
+
+                let response = send(&routes, method, &uri, None).await;
+
+                assert!(
+                    response.is_none(),
+                    "{} {} is routed but not documented",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn documented_request_bodies_are_accepted() {
+        let routes = http::tests::offline_routes();
+        let examples = example_bodies();
+
+        for (path, method, operation) in operations(&document()) {
+            let schema = &operation["requestBody"]["content"]["application/json"]["schema"];
+            let name = match schema["$ref"].as_str() {
+                Some(reference) => reference.trim_start_matches("#/components/schemas/"),
+                None => continue,
+            };
+            let example = examples
+                .get(name)
+                .unwrap_or_else(|| panic!("no example of {}", name));
+
+            let uri = example_uri(&path, &operation);
+            let response = send(&routes, &method, &uri, Some(example))
+                .await
+                .unwrap_or_else(|| panic!("{} {} is not routed", method, path));
+            let body = body::to_bytes(response.into_body()).await.unwrap();
+
+            if let Ok(problem) = serde_json::from_slice::<Problem>(&body) {
+                assert_ne!(
+                    problem.problem_type(),
+                    Some(ProblemType::InvalidBody),
+                    "{} {} does not accept a {}: {:?}",
+                    method,
+                    path,
+                    name,
+                    problem.detail
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let document = document();
+        let documented = document["paths"].as_object().unwrap();
+
+        let public_routes = metrics::ROUTES.iter().filter(|route| {
+            !route.starts_with("/admin")
+                && **route != "/metrics"
+                && !route.starts_with("/api/faucet")
+        });
+        for route in public_routes {
+            let path = route.replace("{param}", "{txid}");
+
+            assert!(
+                documented.contains_key(&path),
+                "{} is routed but not documented",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn named_types_are_in_components() {
+        let document = document();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+
+        for name in &[
+            "CreateSwapPayload",
+            "LoanOffer",
+            "LoanRequest",
+            "LoanResponse",
+            "PsetLoanResponse",
+            "FinalizeLoanPayload",
+            "RepaymentQuote",
+            "RepaymentRequest",
+            "RepaymentResponse",
+            "LoanDetails",
+            "Notification",
+            "Problem",
+        ] {
+            assert!(
+                schemas.contains_key(*name),
+                "{} is not in the schemas",
+                name
+            );
+        }
+    }
+
+    /// The loan response is defined in `baru`, the schema only mirrors
+    /// it.
+    ///
+    /// A field which is known to the type fails to deserialize from a
+    /// boolean, while unknown fields are skipped and deserialization
+    /// fails on the first missing field instead.
+    #[test]
+    fn loan_response_mirror_has_the_fields_of_baru() {
+        let document = document();
+        let schemas = &document["components"]["schemas"];
+
+        assert_fields_known::<baru::loan::LoanResponse>(&schemas["LoanResponse"]);
+        assert_fields_known::<baru::input::Input>(&schemas["Input"]);
+        assert_fields_known::<elements::TxOut>(&schemas["TxOut"]);
+        assert_fields_known::<elements::OutPoint>(&schemas["OutPoint"]);
+    }
+
+    fn assert_fields_known<T>(schema: &Value)
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(!properties.is_empty());
+
+        for field in properties.keys() {
+            let error = serde_json::from_value::<T>(json!({ field: true }))
+                .err()
+                .unwrap_or_else(|| panic!("{} was deserialized from a boolean", field))
+                .to_string();
+
+            assert!(
+                !error.starts_with("missing field") && !error.starts_with("unknown field"),
+                "{} is not a field of {}",
+                field,
+                std::any::type_name::<T>()
+            );
+        }
+    }
+
+    /// The response to the request, `None` if it is not routed.
+    async fn send(
+        routes: &BoxedFilter<(impl Reply + 'static,)>,
+        method: &str,
+        uri: &str,
+        body: Option<&Value>,
+    ) -> Option<Response> {
+        let mut request = warp::test::request()
+            .method(&method.to_uppercase())
+            .path(uri);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = request.filter(routes).await.ok()?.into_response();
+
+        // Everything else falls through to the embedded app
+        let is_app = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|content_type| content_type.as_bytes().starts_with(b"text/html"))
+            .unwrap_or_default();
+
+        if is_app {
+            None
+        } else {
+            Some(response)
+        }
+    }
+
+    /// An example of every request body, by the name of its schema.
+    fn example_bodies() -> HashMap<&'static str, Value> {
+        let txid = Txid::from_str(TXID).unwrap();
+        let address = Address::from_str(ADDRESS).unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+
+        let mut examples = HashMap::new();
+        examples.insert(
+            "CreateSwapPayload",
+            to_value(CreateSwapPayload {
+                alice_inputs: vec![],
+                address: address.clone(),
+                amount: 100_000,
+            }),
+        );
+        examples.insert(
+            "LoanRequest",
+            to_value(LoanRequest {
+                offer_id: "00".repeat(16),
+                term: 30,
+                principal_amount: Amount::from_sat(1_000_000_000_000),
+                collateralization: 1.5,
+                collateral_amount: Amount::from_sat(30_000_000),
+                collateral_inputs: vec![],
+                borrower_pk: PublicKey::from_str(
+                    "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+                )
+                .unwrap(),
+                borrower_address: address,
+            }),
+        );
+        examples.insert(
+            "FinalizeLoanPayload",
+            to_value(FinalizeLoanPayload {
+                tx_hex: transaction,
+            }),
+        );
+        examples.insert(
+            "RepaymentRequest",
+            to_value(RepaymentRequest { loan_txid: txid }),
+        );
+
+        examples
+    }
+
+    fn to_value(body: impl serde::Serialize) -> Value {
+        serde_json::to_value(body).unwrap()
+    }
+
+    fn operations(document: &Value) -> Vec<(String, String, Value)> {
+        document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, operations)| {
+                operations
+                    .as_object()
+                    .unwrap()
+                    .iter()
+                    .map(move |(method, operation)| {
+                        (path.clone(), method.clone(), operation.clone())
+                    })
+            })
+            .collect()
+    }
+
+    /// A URI for the path with example values for its parameters and
+    /// its required query parameters.
+    fn example_uri(path: &str, operation: &Value) -> String {
+        let path = path.replace("{txid}", TXID);
+
+        let query = operation["parameters"]
+            .as_array()
+            .map(|parameters| {
+                parameters
+                    .iter()
+                    .filter(|parameter| parameter["in"] == "query" && parameter["required"] == true)
+                    .map(|parameter| format!("{}=1", parameter["name"].as_str().unwrap()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if query.is_empty() {
+            path
+        } else {
+            format!("{}?{}", path, query.join("&"))
+        }
+    }
+}
@@ -1,10 +1,22 @@
-use crate::loan::LoanValidationError;
+use crate::{
+    admin::ServicePaused,
+    idempotency::{IdempotencyKeyReused, InvalidIdempotencyKey},
+    input_validation::InputValidationError,
+    loan::{LoanValidationError, UnknownLoan},
+    rate_limit::{RateLimited, TooManyPendingNegotiations},
+    swap_batch::SwapBatchError,
+    swap_watcher::TakerThrottled,
+    LiquidUsdt,
+};
 use baru::swap::{ChangeAmountTooSmall, InputAmountTooSmall, InvalidAssetTypes};
+use bobtimus_client::{ProblemType, PsetError};
 use http_api_problem::HttpApiProblem;
+use serde_json::{json, Value};
 use std::error::Error;
 use warp::{
     body::BodyDeserializeError,
     http::{self, StatusCode},
+    reject::PayloadTooLarge,
     Rejection, Reply,
 };
 
@@ -16,26 +28,106 @@ pub fn from_anyhow(e: anyhow::Error) -> HttpApiProblem {
     };
 
     let known_error = match &e {
-        e if e.is::<InvalidAssetTypes>() => HttpApiProblem::new("Invalid asset types in inputs.")
-            .set_status(StatusCode::BAD_REQUEST),
+        e if e.is::<InvalidAssetTypes>() => {
+            problem(ProblemType::InvalidAssetTypes, StatusCode::BAD_REQUEST)
+        }
         e if e.is::<InputAmountTooSmall>() => {
-            HttpApiProblem::new("Input amount too small.").set_status(StatusCode::BAD_REQUEST)
+            problem(ProblemType::InputAmountTooSmall, StatusCode::BAD_REQUEST)
         }
         e if e.is::<ChangeAmountTooSmall>() => {
-            HttpApiProblem::new("Change amount too small to cover fee.")
-                .set_status(StatusCode::BAD_REQUEST)
+            problem(ProblemType::ChangeAmountTooSmall, StatusCode::BAD_REQUEST)
+        }
+        e if e.is::<ServicePaused>() => {
+            problem(ProblemType::ServicePaused, StatusCode::SERVICE_UNAVAILABLE)
+                .set_detail(e.to_string())
+        }
+        e if e.is::<InputValidationError>() => input_validation_problem(
+            e.downcast_ref::<InputValidationError>()
+                .expect("type checked above"),
+        ),
+        e if e.is::<PsetError>() => problem(ProblemType::InvalidPset, StatusCode::BAD_REQUEST)
+            .set_detail(format!("{:#}", e)),
+        e if e.is::<InvalidIdempotencyKey>() => {
+            problem(ProblemType::InvalidIdempotencyKey, StatusCode::BAD_REQUEST)
+                .set_detail(e.to_string())
+        }
+        e if e.is::<IdempotencyKeyReused>() => {
+            let IdempotencyKeyReused { key } = e
+                .downcast_ref::<IdempotencyKeyReused>()
+                .expect("type checked above");
+
+            with_values(
+                problem(
+                    ProblemType::IdempotencyKeyReused,
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                )
+                .set_detail(e.to_string()),
+                &[("idempotency_key", json!(key))],
+            )
+        }
+        e if e.is::<RateLimited>() => {
+            let RateLimited { retry_after } =
+                e.downcast_ref::<RateLimited>().expect("type checked above");
+
+            with_values(
+                problem(ProblemType::RateLimited, StatusCode::TOO_MANY_REQUESTS)
+                    .set_detail(e.to_string()),
+                // rounded up, so that retrying after this many seconds succeeds
+                &[("retry_after", json!((retry_after.as_millis() + 999) / 1000))],
+            )
+        }
+        e if e.is::<TakerThrottled>() => {
+            let TakerThrottled { retry_after } = e
+                .downcast_ref::<TakerThrottled>()
+                .expect("type checked above");
+
+            with_values(
+                problem(ProblemType::TakerThrottled, StatusCode::TOO_MANY_REQUESTS)
+                    .set_detail(e.to_string()),
+                &[("retry_after", json!((retry_after.as_millis() + 999) / 1000))],
+            )
+        }
+        e if e.is::<SwapBatchError>() => swap_batch_problem(
+            e.downcast_ref::<SwapBatchError>()
+                .expect("type checked above"),
+        ),
+        e if e.is::<TooManyPendingNegotiations>() => {
+            let TooManyPendingNegotiations { max } = e
+                .downcast_ref::<TooManyPendingNegotiations>()
+                .expect("type checked above");
+
+            with_values(
+                problem(
+                    ProblemType::TooManyPendingNegotiations,
+                    StatusCode::TOO_MANY_REQUESTS,
+                )
+                .set_detail(e.to_string()),
+                &[("max_pending_negotiations", json!(max))],
+            )
+        }
+        e if e.is::<UnknownLoan>() => {
+            let UnknownLoan(loan_txid) =
+                e.downcast_ref::<UnknownLoan>().expect("type checked above");
+
+            with_values(
+                problem(ProblemType::UnknownLoan, StatusCode::NOT_FOUND).set_detail(e.to_string()),
+                &[("loan_txid", json!(loan_txid))],
+            )
         }
-        e if e.is::<LoanValidationError>() => HttpApiProblem::new("Loan Validation Error")
-            .set_status(StatusCode::BAD_REQUEST)
-            .set_detail(e.to_string()),
+        e if e.is::<LoanValidationError>() => loan_validation_problem(
+            e.downcast_ref::<LoanValidationError>()
+                .expect("type checked above"),
+        ),
         e => {
             tracing::error!("unhandled error: {:#}", e);
 
-            // early return in this branch to avoid double logging the error
-            return HttpApiProblem::with_title_and_type_from_status(
+            // early return in this branch to avoid double logging the
+            // error, the error itself is not exposed as it may contain
+            // internals such as the responses of elementsd
+            return problem(
+                ProblemType::InternalError,
                 StatusCode::INTERNAL_SERVER_ERROR,
-            )
-            .set_detail(e.to_string());
+            );
         }
     };
 
@@ -44,13 +136,241 @@ pub fn from_anyhow(e: anyhow::Error) -> HttpApiProblem {
     known_error
 }
 
+/// A problem with a stable type URI, which takes its title from the
+/// type.
+fn problem(problem_type: ProblemType, status: StatusCode) -> HttpApiProblem {
+    HttpApiProblem::new(problem_type.title())
+        .set_type_url(problem_type.uri())
+        .set_status(status)
+}
+
+fn loan_validation_problem(error: &LoanValidationError) -> HttpApiProblem {
+    use LoanValidationError::*;
+
+    let (problem_type, values) = match error {
+        PriceNotAcceptable {
+            request_price,
+            offer_price,
+        } => (
+            ProblemType::PriceNotAcceptable,
+            vec![
+                ("request_price", nominal(request_price)),
+                ("offer_price", nominal(offer_price)),
+            ],
+        ),
+        UnknownOffer { offer_id } => (
+            ProblemType::UnknownOffer,
+            vec![("offer_id", json!(offer_id))],
+        ),
+        OfferExpired {
+            offer_id,
+            expired_at,
+        } => (
+            ProblemType::OfferExpired,
+            vec![
+                ("offer_id", json!(offer_id)),
+                ("expired_at", json!(expired_at)),
+            ],
+        ),
+        PrincipalBelowMin {
+            request_principal,
+            min_principal,
+        } => (
+            ProblemType::PrincipalBelowMin,
+            vec![
+                ("request_principal", nominal(request_principal)),
+                ("min_principal", nominal(min_principal)),
+            ],
+        ),
+        PrincipalAboveMax {
+            request_principal,
+            max_principal,
+        } => (
+            ProblemType::PrincipalAboveMax,
+            vec![
+                ("request_principal", nominal(request_principal)),
+                ("max_principal", nominal(max_principal)),
+            ],
+        ),
+        LtvAboveMax {
+            request_ltv,
+            max_ltv,
+        } => (
+            ProblemType::LtvAboveMax,
+            vec![
+                ("request_ltv", json!(request_ltv)),
+                ("max_ltv", json!(max_ltv)),
+            ],
+        ),
+        TermNotAllowed {
+            term,
+            min_term,
+            max_term,
+        } => (
+            ProblemType::TermNotAllowed,
+            vec![
+                ("term", json!(term)),
+                ("min_term", json!(min_term)),
+                ("max_term", json!(max_term)),
+            ],
+        ),
+        CollateralizationBelowMin {
+            request_collateralization,
+            min_collateralization,
+        } => (
+            ProblemType::CollateralizationBelowMin,
+            vec![
+                (
+                    "request_collateralization",
+                    json!(request_collateralization),
+                ),
+                ("min_collateralization", json!(min_collateralization)),
+            ],
+        ),
+        TotalPrincipalAboveMax {
+            total_principal,
+            max_total_principal,
+        } => (
+            ProblemType::TotalPrincipalAboveMax,
+            vec![
+                ("total_principal", nominal(total_principal)),
+                ("max_total_principal", nominal(max_total_principal)),
+            ],
+        ),
+        BorrowerPrincipalAboveMax {
+            borrower_principal,
+            max_borrower_principal,
+        } => (
+            ProblemType::BorrowerPrincipalAboveMax,
+            vec![
+                ("borrower_principal", nominal(borrower_principal)),
+                ("max_borrower_principal", nominal(max_borrower_principal)),
+            ],
+        ),
+        ReserveBelowMin {
+            remaining_balance,
+            usdt_reserve,
+        } => (
+            ProblemType::ReserveBelowMin,
+            vec![
+                ("remaining_balance", nominal(remaining_balance)),
+                ("usdt_reserve", nominal(usdt_reserve)),
+            ],
+        ),
+    };
+
+    with_values(
+        problem(problem_type, StatusCode::BAD_REQUEST).set_detail(error.to_string()),
+        &values,
+    )
+}
+
+fn input_validation_problem(error: &InputValidationError) -> HttpApiProblem {
+    use InputValidationError::*;
+
+    let (problem_type, values) = match *error {
+        Duplicate(outpoint) => (
+            ProblemType::DuplicateInput,
+            vec![("outpoint", json!(outpoint.to_string()))],
+        ),
+        Spent(outpoint) => (
+            ProblemType::InputSpent,
+            vec![("outpoint", json!(outpoint.to_string()))],
+        ),
+        Unconfirmed {
+            outpoint,
+            confirmations,
+            min_confirmations,
+        } => (
+            ProblemType::InputUnconfirmed,
+            vec![
+                ("outpoint", json!(outpoint.to_string())),
+                ("confirmations", json!(confirmations)),
+                ("min_confirmations", json!(min_confirmations)),
+            ],
+        ),
+        AlreadyReserved(outpoint) => (
+            ProblemType::InputAlreadyReserved,
+            vec![("outpoint", json!(outpoint.to_string()))],
+        ),
+        SecretsMissing(outpoint) => (
+            ProblemType::InputSecretsMissing,
+            vec![("outpoint", json!(outpoint.to_string()))],
+        ),
+        SecretsMismatch(outpoint) => (
+            ProblemType::InputSecretsMismatch,
+            vec![("outpoint", json!(outpoint.to_string()))],
+        ),
+    };
+
+    with_values(
+        problem(problem_type, StatusCode::BAD_REQUEST).set_detail(error.to_string()),
+        &values,
+    )
+}
+
+fn swap_batch_problem(error: &SwapBatchError) -> HttpApiProblem {
+    use SwapBatchError::*;
+
+    let (problem_type, status, txid) = match error {
+        Disabled => (
+            ProblemType::SwapBatchingDisabled,
+            StatusCode::NOT_FOUND,
+            None,
+        ),
+        Unknown(txid) => (
+            ProblemType::UnknownSwapBatch,
+            StatusCode::NOT_FOUND,
+            Some(txid),
+        ),
+        NotBuilt => (ProblemType::SwapBatchAbandoned, StatusCode::CONFLICT, None),
+        Abandoned(txid) => (
+            ProblemType::SwapBatchAbandoned,
+            StatusCode::CONFLICT,
+            Some(txid),
+        ),
+        Unsigned(txid) | InvalidSignature(txid, _) => (
+            ProblemType::InvalidBody,
+            StatusCode::BAD_REQUEST,
+            Some(txid),
+        ),
+    };
+    let mut values = txid
+        .map(|txid| vec![("txid", json!(txid))])
+        .unwrap_or_default();
+    if let InvalidSignature(_, input) = error {
+        values.push(("input", json!(input.to_string())));
+    }
+
+    with_values(
+        problem(problem_type, status).set_detail(error.to_string()),
+        &values,
+    )
+}
+
+fn with_values(mut problem: HttpApiProblem, values: &[(&str, Value)]) -> HttpApiProblem {
+    for (key, value) in values {
+        problem
+            .set_value(*key, value)
+            .expect("extension fields do not clash with the standard fields");
+    }
+
+    problem
+}
+
+/// L-USDt amounts are nominal, in line with the loan offer.
+fn nominal(amount: &LiquidUsdt) -> Value {
+    LiquidUsdt::serialize_to_nominal(amount, serde_json::value::Serializer)
+        .expect("amounts serialize to JSON")
+}
+
 pub async fn unpack_problem(rejection: Rejection) -> Result<impl Reply, Rejection> {
     if let Some(problem) = rejection.find::<HttpApiProblem>() {
         return Ok(problem_to_reply(problem));
     }
 
     if let Some(invalid_body) = rejection.find::<BodyDeserializeError>() {
-        let mut problem = HttpApiProblem::new("Invalid body.").set_status(StatusCode::BAD_REQUEST);
+        let mut problem = problem(ProblemType::InvalidBody, StatusCode::BAD_REQUEST);
 
         if let Some(source) = invalid_body.source() {
             problem = problem.set_detail(format!("{}", source));
@@ -59,6 +379,12 @@ pub async fn unpack_problem(rejection: Rejection) -> Result<impl Reply, Rejectio
         return Ok(problem_to_reply(&problem));
     }
 
+    if rejection.find::<PayloadTooLarge>().is_some() {
+        let problem = problem(ProblemType::PayloadTooLarge, StatusCode::PAYLOAD_TOO_LARGE);
+
+        return Ok(problem_to_reply(&problem));
+    }
+
     Err(rejection)
 }
 
@@ -74,3 +400,65 @@ fn problem_to_reply(problem: &HttpApiProblem) -> impl Reply {
         http_api_problem::PROBLEM_JSON_MEDIA_TYPE,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bobtimus_client::Problem;
+
+    #[test]
+    fn loan_validation_error_has_type_and_extension_fields() {
+        let error = LoanValidationError::PrincipalBelowMin {
+            request_principal: LiquidUsdt::from_str_in_dollar("50").unwrap(),
+            min_principal: LiquidUsdt::from_str_in_dollar("100").unwrap(),
+        };
+
+        let problem = from_anyhow(error.into());
+
+        let json = serde_json::to_string(&problem).unwrap();
+        let problem = serde_json::from_str::<Problem>(&json).unwrap();
+        assert_eq!(problem.problem_type(), Some(ProblemType::PrincipalBelowMin));
+        assert_eq!(problem.status, Some(400));
+        assert_eq!(problem.extension::<f64>("min_principal"), Some(100.0));
+        assert_eq!(problem.extension::<f64>("request_principal"), Some(50.0));
+    }
+
+    #[test]
+    fn stale_price_is_recoverable() {
+        let error = LoanValidationError::PriceNotAcceptable {
+            request_price: LiquidUsdt::from_str_in_dollar("40000").unwrap(),
+            offer_price: LiquidUsdt::from_str_in_dollar("39000").unwrap(),
+        };
+
+        let problem = from_anyhow(error.into());
+
+        let json = serde_json::to_string(&problem).unwrap();
+        let problem = serde_json::from_str::<Problem>(&json).unwrap();
+        assert!(problem.is_recoverable());
+        assert_eq!(problem.extension::<f64>("offer_price"), Some(39000.0));
+    }
+
+    #[test]
+    fn rate_limited_requests_are_told_when_to_retry() {
+        let error = RateLimited {
+            retry_after: std::time::Duration::from_millis(1500),
+        };
+
+        let problem = from_anyhow(error.into());
+
+        assert_eq!(problem.status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(problem.type_url, Some(ProblemType::RateLimited.uri()));
+        let json = serde_json::to_string(&problem).unwrap();
+        let problem = serde_json::from_str::<Problem>(&json).unwrap();
+        assert_eq!(problem.extension::<u64>("retry_after"), Some(2));
+    }
+
+    #[test]
+    fn unknown_errors_are_not_exposed() {
+        let problem = from_anyhow(anyhow::anyhow!("RPC error: wallet is locked"));
+
+        assert_eq!(problem.status, Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(problem.detail, None);
+        assert_eq!(problem.type_url, Some(ProblemType::InternalError.uri()));
+    }
+}
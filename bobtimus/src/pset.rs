@@ -0,0 +1,53 @@
+//! Transactions of swaps and loans as PSETs, for takers and borrowers
+//! whose wallets do not speak our own wire format.
+
+use crate::{AliceInput, Bobtimus, LatestRate};
+use anyhow::{Context, Result};
+use bobtimus_client::pset::{self, PsetInput};
+use elements::{
+    pset::PartiallySignedTransaction,
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    OutPoint, Transaction,
+};
+use futures::future;
+use std::collections::HashMap;
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Describe a transaction we built as PSET.
+    ///
+    /// Only the inputs of the counterparty carry blinding factors, so
+    /// that we do not disclose the amounts of our own inputs.
+    pub async fn describe_as_pset(
+        &self,
+        transaction: &Transaction,
+        taker_inputs: &HashMap<OutPoint, AliceInput>,
+    ) -> Result<PartiallySignedTransaction> {
+        let inputs = transaction.input.iter().map(|input| async move {
+            let outpoint = input.previous_output;
+            let previous = self
+                .elementsd
+                .get_raw_transaction(outpoint.txid)
+                .await
+                .with_context(|| format!("failed to fetch transaction {}", outpoint.txid))?;
+            let utxo = previous
+                .output
+                .get(outpoint.vout as usize)
+                .cloned()
+                .with_context(|| format!("output {} does not exist", outpoint))?;
+
+            let secrets = match taker_inputs.get(&outpoint) {
+                Some(input) => Some(self.unblind_taker_input(input, &utxo)?),
+                None => None,
+            };
+
+            Result::<_, anyhow::Error>::Ok(PsetInput { utxo, secrets })
+        });
+        let inputs = future::try_join_all(inputs).await?;
+
+        Ok(pset::from_transaction(transaction, inputs))
+    }
+}
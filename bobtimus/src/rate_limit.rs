@@ -0,0 +1,351 @@
+use crate::{Bobtimus, LatestRate};
+use elements::secp256k1_zkp::rand::{CryptoRng, RngCore};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Beyond this many clients, the clients which have not made a request
+/// for long enough to be back at their full burst are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// How many requests may be made in a burst and how fast the burst
+/// recovers.
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    /// Requests per minute, 0 disables the limit
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Quota {
+    fn is_unlimited(&self) -> bool {
+        self.per_minute == 0
+    }
+
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.max(1))
+    }
+
+    fn tokens_per_sec(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// Limits on the requests which make us build transactions.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    /// Quota of every client
+    pub per_ip: Quota,
+    /// Quota of all clients combined
+    pub global: Quota,
+    /// Identify clients by the last address in `X-Forwarded-For`, i.e.
+    /// the one added by our proxy, only safe behind a proxy which sets it
+    pub trust_forwarded_for: bool,
+    /// Maximum size of request bodies in bytes
+    pub max_body_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("Too many requests, retry in {}s", retry_after.as_secs())]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("Too many pending negotiations, at most {max} are allowed")]
+pub struct TooManyPendingNegotiations {
+    pub max: usize,
+}
+
+/// Token buckets for every client and for all clients combined.
+///
+/// Shared by all servers, so that serving the API on multiple addresses
+/// does not multiply the limits.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RequestLimits,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: TokenBucket,
+    per_ip: HashMap<IpAddr, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RequestLimits) -> Self {
+        let now = Instant::now();
+
+        Self {
+            limits,
+            buckets: Mutex::new(Buckets {
+                global: TokenBucket::full(limits.global, now),
+                per_ip: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn max_body_bytes(&self) -> u64 {
+        self.limits.max_body_bytes
+    }
+
+    /// The address of the client which made the request.
+    ///
+    /// Clients can send `X-Forwarded-For` themselves, so only the
+    /// address our proxy appended to it is trusted.
+    pub fn client_ip(
+        &self,
+        remote: Option<SocketAddr>,
+        forwarded_for: Option<&str>,
+    ) -> Option<IpAddr> {
+        let forwarded_for = forwarded_for
+            .filter(|_| self.limits.trust_forwarded_for)
+            .and_then(|forwarded_for| forwarded_for.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        forwarded_for.or_else(|| remote.map(|remote| remote.ip()))
+    }
+
+    /// Take a token from the bucket of the client and the global bucket,
+    /// only if both have one left.
+    ///
+    /// Requests of unknown clients only count towards the global limit.
+    pub fn check(&self, ip: Option<IpAddr>, now: Instant) -> Result<(), RateLimited> {
+        let RequestLimits {
+            per_ip: per_ip_quota,
+            global: global_quota,
+            ..
+        } = self.limits;
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("no panic while holding the lock");
+        let Buckets { global, per_ip } = &mut *buckets;
+
+        if per_ip.len() > MAX_TRACKED_CLIENTS {
+            per_ip.retain(|_, bucket| !bucket.is_full(per_ip_quota, now));
+        }
+
+        let client = match ip {
+            Some(ip) if !per_ip_quota.is_unlimited() => Some(
+                per_ip
+                    .entry(ip)
+                    .or_insert_with(|| TokenBucket::full(per_ip_quota, now)),
+            ),
+            _ => None,
+        };
+
+        let retry_after = [
+            client.as_deref().map(|bucket| (*bucket, per_ip_quota)),
+            Some((*global, global_quota)).filter(|_| !global_quota.is_unlimited()),
+        ]
+        .iter()
+        .flatten()
+        .filter_map(|(bucket, quota)| bucket.wait_time(*quota, now))
+        .max();
+        if let Some(retry_after) = retry_after {
+            return Err(RateLimited { retry_after });
+        }
+
+        if let Some(client) = client {
+            client.take(per_ip_quota, now);
+        }
+        if !global_quota.is_unlimited() {
+            global.take(global_quota, now);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(quota: Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.capacity(),
+            updated_at: now,
+        }
+    }
+
+    fn tokens_at(&self, quota: Quota, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+
+        (self.tokens + elapsed.as_secs_f64() * quota.tokens_per_sec()).min(quota.capacity())
+    }
+
+    fn is_full(&self, quota: Quota, now: Instant) -> bool {
+        self.tokens_at(quota, now) >= quota.capacity()
+    }
+
+    /// How long until a token is available, `None` if one is available
+    /// now.
+    fn wait_time(&self, quota: Quota, now: Instant) -> Option<Duration> {
+        let missing = 1.0 - self.tokens_at(quota, now);
+
+        if missing <= 0.0 {
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(missing / quota.tokens_per_sec()))
+    }
+
+    fn take(&mut self, quota: Quota, now: Instant) {
+        self.tokens = self.tokens_at(quota, now) - 1.0;
+        self.updated_at = now;
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Every negotiation keeps the state of the lender in memory until it
+    /// is finalized, which borrowers may never do.
+    pub(crate) fn ensure_negotiation_capacity(&self) -> Result<(), TooManyPendingNegotiations> {
+        let pending = self.lender_states.len() + self.repayment_states.len();
+
+        if pending >= self.max_pending_negotiations {
+            return Err(TooManyPendingNegotiations {
+                max: self.max_pending_negotiations,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PER_IP: Quota = Quota {
+        per_minute: 60,
+        burst: 2,
+    };
+    const GLOBAL: Quota = Quota {
+        per_minute: 600,
+        burst: 3,
+    };
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RequestLimits {
+            per_ip: PER_IP,
+            global: GLOBAL,
+            trust_forwarded_for: false,
+            max_body_bytes: 1024,
+        })
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, last]))
+    }
+
+    #[test]
+    fn client_is_limited_after_burst_until_refilled() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.check(ip(1), now).is_ok());
+        assert!(limiter.check(ip(1), now).is_ok());
+        let RateLimited { retry_after } = limiter.check(ip(1), now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        assert!(limiter.check(ip(1), now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn global_limit_applies_to_all_clients_combined() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.check(ip(1), now).is_ok());
+        assert!(limiter.check(ip(2), now).is_ok());
+        assert!(limiter.check(ip(3), now).is_ok());
+
+        assert!(limiter.check(ip(4), now).is_err());
+    }
+
+    #[test]
+    fn rejected_request_does_not_use_up_the_quota_of_the_client() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.check(ip(1), now).is_ok());
+        assert!(limiter.check(ip(2), now).is_ok());
+        assert!(limiter.check(ip(3), now).is_ok());
+        assert!(limiter.check(ip(1), now).is_err());
+
+        // the global bucket refills a token every 100ms
+        assert!(limiter
+            .check(ip(1), now + Duration::from_millis(100))
+            .is_ok());
+    }
+
+    #[test]
+    fn zero_per_minute_disables_the_limit() {
+        let limiter = RateLimiter::new(RequestLimits {
+            per_ip: Quota {
+                per_minute: 0,
+                burst: 0,
+            },
+            global: Quota {
+                per_minute: 0,
+                burst: 0,
+            },
+            trust_forwarded_for: false,
+            max_body_bytes: 1024,
+        });
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert!(limiter.check(ip(1), now).is_ok());
+        }
+    }
+
+    #[test]
+    fn forwarded_for_is_only_used_if_trusted() {
+        let remote = Some(SocketAddr::from(([127, 0, 0, 1], 8080)));
+        let forwarded_for = Some("10.0.0.1, 203.0.113.7");
+
+        assert_eq!(
+            limiter().client_ip(remote, forwarded_for),
+            Some(IpAddr::from([127, 0, 0, 1]))
+        );
+
+        let trusting = RateLimiter::new(RequestLimits {
+            trust_forwarded_for: true,
+            ..limiter().limits
+        });
+        assert_eq!(
+            trusting.client_ip(remote, forwarded_for),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+    }
+
+    #[test]
+    fn spoofed_forwarded_for_entries_are_ignored() {
+        let remote = Some(SocketAddr::from(([127, 0, 0, 1], 8080)));
+        let trusting = RateLimiter::new(RequestLimits {
+            trust_forwarded_for: true,
+            ..limiter().limits
+        });
+
+        // The client claims to be 198.51.100.1, the proxy appends the
+        // address it was actually connected from
+        assert_eq!(
+            trusting.client_ip(remote, Some("198.51.100.1,203.0.113.7")),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Authentication of our RPC calls to elementsd.
+//!
+//! Credentials are sent as basic auth instead of being embedded in the
+//! URL, where they would show up in process listings and logs. The
+//! cookie file of elementsd is re-read whenever elementsd rejects the
+//! credentials, as it writes a new cookie every time it starts.
+
+use jsonrpc_client::{Response, SendRequest};
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+/// How we authenticate with elementsd
+#[derive(Clone)]
+pub enum RpcAuth {
+    /// No credentials, or credentials embedded in the URL
+    None,
+    UserPass {
+        user: String,
+        password: String,
+    },
+    /// The cookie file elementsd writes on startup
+    Cookie(PathBuf),
+}
+
+impl fmt::Debug for RpcAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcAuth::None => write!(f, "None"),
+            RpcAuth::UserPass { user, .. } => write!(f, "UserPass({}, <redacted>)", user),
+            RpcAuth::Cookie(path) => write!(f, "Cookie({})", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error("Request to elementsd failed")]
+    Http(#[from] reqwest::Error),
+    #[error("Failed to read cookie file {path}")]
+    Cookie {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Sends the RPC calls to elementsd with our credentials.
+#[derive(Clone, Debug)]
+pub struct Transport {
+    inner: reqwest::Client,
+    auth: RpcAuth,
+    /// The credentials last read from the cookie file
+    cookie: Arc<RwLock<Option<Credentials>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Credentials {
+    user: String,
+    password: String,
+}
+
+impl Transport {
+    pub fn new(auth: RpcAuth) -> Self {
+        Self {
+            inner: reqwest::Client::new(),
+            auth,
+            cookie: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    async fn send(
+        &self,
+        endpoint: &Url,
+        body: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let request = self
+            .inner
+            .post(endpoint.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_owned());
+
+        with_credentials(request, credentials).send().await
+    }
+
+    /// The credentials from the cookie file, read again if `reload` is
+    /// set or they were never read.
+    fn cookie_credentials(&self, path: &Path, reload: bool) -> Result<Credentials, TransportError> {
+        if !reload {
+            let cached = self.cookie.read().expect("no panic while holding the lock");
+            if let Some(credentials) = cached.as_ref() {
+                return Ok(credentials.clone());
+            }
+        }
+
+        let credentials = read_cookie(path).map_err(|source| TransportError::Cookie {
+            path: path.to_owned(),
+            source,
+        })?;
+        *self
+            .cookie
+            .write()
+            .expect("no panic while holding the lock") = Some(credentials.clone());
+
+        Ok(credentials)
+    }
+}
+
+#[async_trait::async_trait]
+impl SendRequest for Transport {
+    type Error = TransportError;
+
+    async fn send_request<P>(&self, endpoint: Url, body: String) -> Result<Response<P>, Self::Error>
+    where
+        P: DeserializeOwned,
+    {
+        let response = match &self.auth {
+            RpcAuth::None => self.send(&endpoint, &body, None).await?,
+            RpcAuth::UserPass { user, password } => {
+                let credentials = Credentials {
+                    user: user.clone(),
+                    password: password.clone(),
+                };
+
+                self.send(&endpoint, &body, Some(&credentials)).await?
+            }
+            RpcAuth::Cookie(path) => {
+                let credentials = self.cookie_credentials(path, false)?;
+                let response = self.send(&endpoint, &body, Some(&credentials)).await?;
+
+                if response.status() == StatusCode::UNAUTHORIZED {
+                    tracing::info!("elementsd rejected the cookie, reading it again");
+
+                    let credentials = self.cookie_credentials(path, true)?;
+                    self.send(&endpoint, &body, Some(&credentials)).await?
+                } else {
+                    response
+                }
+            }
+        };
+
+        Ok(response.json().await?)
+    }
+}
+
+fn with_credentials(request: RequestBuilder, credentials: Option<&Credentials>) -> RequestBuilder {
+    match credentials {
+        Some(Credentials { user, password }) => request.basic_auth(user, Some(password)),
+        None => request,
+    }
+}
+
+fn read_cookie(path: &Path) -> io::Result<Credentials> {
+    let cookie = fs::read_to_string(path)?;
+
+    parse_cookie(&cookie)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "expected <user>:<password>"))
+}
+
+/// The cookie file holds a single line of `<user>:<password>`.
+fn parse_cookie(cookie: &str) -> Option<Credentials> {
+    let (user, password) = cookie.trim().split_once(':')?;
+
+    Some(Credentials {
+        user: user.to_owned(),
+        password: password.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_is_split_into_user_and_password() {
+        assert_eq!(
+            parse_cookie("__cookie__:6b2a1c:f0\n"),
+            Some(Credentials {
+                user: "__cookie__".to_owned(),
+                password: "6b2a1c:f0".to_owned(),
+            })
+        );
+        assert_eq!(parse_cookie("no separator"), None);
+    }
+}
@@ -1,7 +1,73 @@
+table! {
+    event_outbox (id) {
+        id -> Integer,
+        endpoint -> Text,
+        payload -> Text,
+        created_at -> BigInt,
+        attempts -> Integer,
+        next_attempt_at -> BigInt,
+        delivered_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    health_check (id) {
+        id -> Integer,
+        checked_at -> BigInt,
+    }
+}
+
+table! {
+    idempotency_keys (client, key) {
+        client -> Text,
+        key -> Text,
+        endpoint -> Text,
+        fingerprint -> Text,
+        response -> Text,
+        created_at -> BigInt,
+    }
+}
+
 table! {
     liquidations (id) {
         id -> Text,
         tx_hex -> Text,
         locktime -> BigInt,
+        grace_period -> BigInt,
+    }
+}
+
+table! {
+    loans (id) {
+        id -> Text,
+        lender_state -> Text,
+        principal_amount -> BigInt,
+        repayment_amount -> BigInt,
+        collateral_amount -> BigInt,
+        liquidation_price -> BigInt,
+        max_ltv -> Text,
+        timelock -> BigInt,
+        start -> BigInt,
+        borrower_pk -> Nullable<Text>,
+        collateral_spent -> Bool,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Integer,
+        loan_txid -> Text,
+        kind -> Text,
+        payload -> Text,
+        created_at -> BigInt,
     }
 }
+
+allow_tables_to_appear_in_same_query!(
+    event_outbox,
+    health_check,
+    idempotency_keys,
+    liquidations,
+    loans,
+    notifications,
+);
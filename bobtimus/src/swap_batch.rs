@@ -0,0 +1,843 @@
+//! Optional batching of swaps, in which the requests of takers on the
+//! same side are collected for a short window and settled in a single
+//! transaction.
+//!
+//! We select and sign our inputs once per batch instead of once per
+//! swap, which saves fees and keeps our UTXOs from fragmenting into a
+//! change output per swap. Every taker then signs their own inputs of
+//! the batch transaction and hands it back to us, and we broadcast it
+//! once all takers signed. All inputs are signed over the whole
+//! transaction, so the takers may sign in any order. The signatures of
+//! a taker are checked before they are added, so that a single taker
+//! cannot make the batch fail to broadcast.
+//!
+//! A batch is abandoned if one of its takers does not sign in time, in
+//! which case the other takers have to request a new swap. Every taker
+//! holds the batch signed by us, so we spend our inputs back to
+//! ourselves instead of leaving it to the takers whether the batch is
+//! broadcast.
+
+use crate::{
+    admin::ServicePaused,
+    event::{Event, SwapSide},
+    input_validation::InputReservations,
+    swap_transaction::{self, Party},
+    swap_watcher::{self, SwapWatcher},
+    Bobtimus, LatestRate, LiquidBtc, LiquidUsdt,
+};
+use anyhow::{bail, Context, Result};
+use bobtimus_client::CreateSwapPayload;
+use elements::{
+    bitcoin::{Amount, PublicKey},
+    secp256k1_zkp::{
+        rand::{CryptoRng, RngCore},
+        Message, Secp256k1, Signature, Verification,
+    },
+    sighash::SigHashCache,
+    Address, AddressParams, OutPoint, SigHashType, Transaction, TxOut, Txid,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// How long requests are collected before a batch is built
+    pub window: Duration,
+    /// How long takers have to sign the batch transaction
+    pub signing_timeout: Duration,
+    /// How many takers are settled in one transaction at most
+    pub max_takers: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum SwapBatchError {
+    #[error("Swaps are not batched")]
+    Disabled,
+    #[error("Swap batch {0} is not waiting for signatures")]
+    Unknown(Txid),
+    #[error("Swap batch could not be built")]
+    NotBuilt,
+    #[error("Swap batch {0} was abandoned, as not every taker signed it")]
+    Abandoned(Txid),
+    #[error("Transaction does not carry the signatures of a taker of swap batch {0}")]
+    Unsigned(Txid),
+    #[error("Input {1} of swap batch {0} is not signed by its owner")]
+    InvalidSignature(Txid, OutPoint),
+}
+
+/// Receives the batch transaction once it is built.
+pub type BatchTransaction = oneshot::Receiver<Result<Transaction, SwapBatchError>>;
+
+/// Receives the txid of the batch transaction once it is broadcast.
+pub type BatchBroadcast = oneshot::Receiver<Result<Txid, SwapBatchError>>;
+
+/// Handle to the swaps being batched, shared between Bobtimus and the
+/// task building the batches.
+#[derive(Clone)]
+pub struct SwapBatcher {
+    config: BatchConfig,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    queued: Vec<QueuedSwap>,
+    /// Batches built and waiting for the signatures of their takers
+    signing: HashMap<Txid, SigningBatch>,
+}
+
+struct QueuedSwap {
+    side: SwapSide,
+    taker: BatchTaker,
+    party: Party,
+    btc_amount: LiquidBtc,
+    usdt_amount: LiquidUsdt,
+    reply: oneshot::Sender<Result<Transaction, SwapBatchError>>,
+}
+
+struct BatchTaker {
+    taker: Option<IpAddr>,
+    inputs: Vec<OutPoint>,
+    /// The outputs spent by the inputs, to check the signatures of the
+    /// taker against
+    spent: HashMap<OutPoint, TxOut>,
+    /// Waiting for the batch to be broadcast, once the taker signed
+    signed: Option<oneshot::Sender<Result<Txid, SwapBatchError>>>,
+}
+
+struct SigningBatch {
+    transaction: Transaction,
+    takers: Vec<BatchTaker>,
+    maker_inputs: Vec<OutPoint>,
+    built_at: SystemTime,
+}
+
+impl SigningBatch {
+    fn is_signed(&self) -> bool {
+        self.takers.iter().all(|taker| taker.signed.is_some())
+    }
+
+    fn taker_inputs(&self) -> impl Iterator<Item = OutPoint> + '_ {
+        self.takers
+            .iter()
+            .flat_map(|taker| taker.inputs.iter().copied())
+    }
+
+    /// Add the witnesses of the taker who signed `transaction` to the
+    /// batch transaction, once their signatures are checked.
+    fn add_signatures<C>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        transaction: &Transaction,
+        reply: oneshot::Sender<Result<Txid, SwapBatchError>>,
+    ) -> Result<(), SwapBatchError>
+    where
+        C: Verification,
+    {
+        let txid = self.transaction.txid();
+        if transaction.txid() != txid {
+            return Err(SwapBatchError::Unknown(transaction.txid()));
+        }
+
+        let signed_inputs = transaction
+            .input
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| !input.witness.script_witness.is_empty())
+            .map(|(index, input)| (input.previous_output, index))
+            .collect::<HashMap<_, _>>();
+        let taker = self
+            .takers
+            .iter_mut()
+            .find(|taker| {
+                taker.signed.is_none()
+                    && taker
+                        .inputs
+                        .iter()
+                        .all(|input| signed_inputs.contains_key(input))
+            })
+            .ok_or(SwapBatchError::Unsigned(txid))?;
+
+        for input in taker.inputs.iter() {
+            let spent = taker
+                .spent
+                .get(input)
+                .ok_or(SwapBatchError::InvalidSignature(txid, *input))?;
+            verify_input_signature(secp, transaction, signed_inputs[input], spent)
+                .map_err(|_| SwapBatchError::InvalidSignature(txid, *input))?;
+        }
+
+        for input in self.transaction.input.iter_mut() {
+            if taker.inputs.contains(&input.previous_output) {
+                input.witness = transaction.input[signed_inputs[&input.previous_output]]
+                    .witness
+                    .clone();
+            }
+        }
+        taker.signed = Some(reply);
+
+        Ok(())
+    }
+
+    /// Release the inputs of the takers, counting the batch against the
+    /// takers who did not sign it.
+    fn abandon(
+        self,
+        swap_watcher: &SwapWatcher,
+        input_reservations: &mut InputReservations,
+        now: SystemTime,
+    ) {
+        let txid = self.transaction.txid();
+
+        for taker in self.takers {
+            input_reservations.release(taker.inputs);
+            match taker.signed {
+                Some(signed) => {
+                    let _ = signed.send(Err(SwapBatchError::Abandoned(txid)));
+                }
+                None => swap_watcher.record_lapse(taker.taker, now),
+            }
+        }
+    }
+}
+
+impl State {
+    /// Stop waiting for the signatures of the batches which were built
+    /// `signing_timeout` ago.
+    fn take_expired(&mut self, now: SystemTime, signing_timeout: Duration) -> Vec<SigningBatch> {
+        let expired = self
+            .signing
+            .iter()
+            .filter(|(_, batch)| batch.built_at + signing_timeout <= now)
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|txid| self.signing.remove(&txid))
+            .collect()
+    }
+}
+
+impl SwapBatcher {
+    pub fn new(config: BatchConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    pub fn config(&self) -> BatchConfig {
+        self.config
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("no panic while holding the lock")
+    }
+}
+
+/// Build the queued swaps into batches at the end of every window, until
+/// the process is stopped.
+pub async fn run<R, RS>(bobtimus: Arc<tokio::sync::Mutex<Bobtimus<R, RS>>>, window: Duration)
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    let mut interval = tokio::time::interval(window);
+
+    loop {
+        interval.tick().await;
+
+        bobtimus
+            .lock()
+            .await
+            .settle_swap_batches(SystemTime::now())
+            .await;
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Queue a swap request of a taker in good standing for the next
+    /// batch.
+    ///
+    /// The inputs of the taker are checked right away, so that a batch
+    /// is not held up by a single invalid request.
+    pub async fn queue_batched_swap(
+        &mut self,
+        side: SwapSide,
+        taker: Option<IpAddr>,
+        payload: CreateSwapPayload,
+    ) -> Result<BatchTransaction> {
+        let batcher = self.swap_batcher.clone().ok_or(SwapBatchError::Disabled)?;
+        if self.paused.swaps {
+            return Err(ServicePaused::Swaps.into());
+        }
+
+        let now = SystemTime::now();
+        self.swap_watcher.ensure_in_good_standing(taker, now)?;
+
+        let (btc_amount, usdt_amount) = self.swap_amounts(side, payload.amount)?;
+
+        let inputs = payload
+            .alice_inputs
+            .iter()
+            .map(|input| input.outpoint)
+            .collect::<Vec<_>>();
+        // Queued inputs are reserved already, so they cannot be queued
+        // twice
+        self.validate_taker_inputs(&inputs, now).await?;
+
+        let btc = (self.btc_asset_id, btc_amount.into());
+        let usdt = (self.usdt_asset_id, usdt_amount.into());
+        let (pays, receives) = match side {
+            SwapSide::Buy => (usdt, btc),
+            SwapSide::Sell => (btc, usdt),
+        };
+        let party = Party {
+            inputs: self.unblind_taker_inputs(&payload.alice_inputs).await?,
+            address: payload.address,
+            pays,
+            receives,
+        };
+
+        self.reserve_taker_inputs(&inputs, now);
+
+        let spent = party
+            .inputs
+            .iter()
+            .map(|(outpoint, txout, _)| (*outpoint, txout.clone()))
+            .collect();
+        let (reply, transaction) = oneshot::channel();
+        batcher.lock().queued.push(QueuedSwap {
+            side,
+            taker: BatchTaker {
+                taker,
+                inputs,
+                spent,
+                signed: None,
+            },
+            party,
+            btc_amount,
+            usdt_amount,
+            reply,
+        });
+
+        Ok(transaction)
+    }
+
+    /// Add the signatures of a taker to the batch transaction, and
+    /// broadcast it if they were the last taker to sign.
+    pub async fn sign_swap_batch(&mut self, transaction: Transaction) -> Result<BatchBroadcast> {
+        let batcher = self.swap_batcher.clone().ok_or(SwapBatchError::Disabled)?;
+        let txid = transaction.txid();
+        let (reply, broadcast) = oneshot::channel();
+
+        let signed = {
+            let mut state = batcher.lock();
+            let batch = state
+                .signing
+                .get_mut(&txid)
+                .ok_or(SwapBatchError::Unknown(txid))?;
+
+            batch.add_signatures(&self.secp, &transaction, reply)?;
+
+            if batch.is_signed() {
+                state.signing.remove(&txid)
+            } else {
+                None
+            }
+        };
+
+        if let Some(batch) = signed {
+            self.broadcast_swap_batch(batch).await;
+        }
+
+        Ok(broadcast)
+    }
+
+    /// Abandon the batches which were not signed in time and build the
+    /// queued swaps into new batches.
+    pub async fn settle_swap_batches(&mut self, now: SystemTime) {
+        let batcher = match self.swap_batcher.clone() {
+            Some(batcher) => batcher,
+            None => return,
+        };
+        let config = batcher.config();
+
+        let (expired, queued) = {
+            let mut state = batcher.lock();
+            let expired = state.take_expired(now, config.signing_timeout);
+
+            (expired, std::mem::take(&mut state.queued))
+        };
+
+        for batch in expired {
+            tracing::info!(
+                "Swap batch {} was not signed in time",
+                batch.transaction.txid()
+            );
+            self.abandon_swap_batch(batch, now).await;
+        }
+
+        let (buys, sells) = queued
+            .into_iter()
+            .partition::<Vec<_>, _>(|swap| swap.side == SwapSide::Buy);
+        for side in vec![buys, sells] {
+            let mut side = side.into_iter().peekable();
+            while side.peek().is_some() {
+                let swaps = side.by_ref().take(config.max_takers).collect();
+                self.build_swap_batch(swaps, now).await;
+            }
+        }
+    }
+
+    async fn build_swap_batch(&mut self, swaps: Vec<QueuedSwap>, now: SystemTime) {
+        let mut takers = Vec::new();
+        let mut waiting = Vec::new();
+        let mut events = Vec::new();
+        for swap in swaps {
+            takers.push(swap.party);
+            events.push((swap.side, swap.btc_amount, swap.usdt_amount));
+            waiting.push((swap.taker, swap.reply));
+        }
+
+        let transaction = match self.batch_transaction(&events, takers).await {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                tracing::error!("Failed to build swap batch: {:#}", e);
+                for (taker, reply) in waiting {
+                    self.input_reservations.release(taker.inputs);
+                    let _ = reply.send(Err(SwapBatchError::NotBuilt));
+                }
+                return;
+            }
+        };
+        let txid = transaction.txid();
+
+        let mut takers = Vec::new();
+        for (taker, reply) in waiting {
+            // A taker who is gone can no longer sign, the batch will be
+            // abandoned once the signing timeout passes
+            let _ = reply.send(Ok(transaction.clone()));
+            takers.push(taker);
+        }
+
+        let mut batch = SigningBatch {
+            transaction,
+            takers,
+            maker_inputs: Vec::new(),
+            built_at: now,
+        };
+        let taker_inputs = batch.taker_inputs().collect::<Vec<_>>();
+        batch.maker_inputs = batch
+            .transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .filter(|outpoint| !taker_inputs.contains(outpoint))
+            .collect();
+        if let Err(e) = self.elementsd.lock_utxos(batch.maker_inputs.clone()).await {
+            tracing::warn!("Failed to lock inputs of swap batch {}: {:#}", txid, e);
+        }
+
+        for (side, btc_amount, usdt_amount) in events {
+            self.events
+                .publish(Event::SwapSigned {
+                    txid,
+                    side,
+                    btc_amount,
+                    usdt_amount,
+                })
+                .await;
+        }
+
+        if let Some(batcher) = &self.swap_batcher {
+            batcher.lock().signing.insert(txid, batch);
+        }
+    }
+
+    /// Build and sign our part of a transaction settling the swaps of
+    /// one side.
+    async fn batch_transaction(
+        &mut self,
+        swaps: &[(SwapSide, LiquidBtc, LiquidUsdt)],
+        takers: Vec<Party>,
+    ) -> Result<Transaction> {
+        let side = swaps
+            .first()
+            .map(|(side, ..)| *side)
+            .context("empty batch")?;
+        let btc_amount = total(swaps.iter().map(|(_, btc_amount, _)| (*btc_amount).into()))?;
+        let usdt_amount = total(
+            swaps
+                .iter()
+                .map(|(_, _, usdt_amount)| (*usdt_amount).into()),
+        )?;
+
+        let ((input_asset_id, input_amount), (output_asset_id, output_amount)) = match side {
+            SwapSide::Buy => (
+                (self.btc_asset_id, btc_amount),
+                (self.usdt_asset_id, usdt_amount),
+            ),
+            SwapSide::Sell => (
+                (self.usdt_asset_id, usdt_amount),
+                (self.btc_asset_id, btc_amount),
+            ),
+        };
+
+        let bob_inputs = Self::find_inputs(&self.elementsd, input_asset_id, input_amount)
+            .await
+            .context("could not find transaction inputs for swap batch")?;
+        let bob_address = self
+            .elementsd
+            .get_new_segwit_confidential_address()
+            .await
+            .context("failed to get redeem address")?;
+        let bob = Party::unblind(
+            &self.secp,
+            bob_inputs,
+            bob_address,
+            (input_asset_id, input_amount),
+            (output_asset_id, output_amount),
+        )?;
+
+        let transaction = swap_transaction::create_transaction(
+            &mut self.rng,
+            &self.secp,
+            takers,
+            bob,
+            self.btc_asset_id,
+            Amount::from_sat(1), // TODO: Make this dynamic once there is something going on on Liquid
+            {
+                let elementsd = self.elementsd.clone();
+                move |transaction| async move {
+                    let tx = elementsd.sign_raw_transaction(&transaction).await?;
+
+                    Result::<_, anyhow::Error>::Ok(tx)
+                }
+            },
+        )
+        .await?;
+
+        Ok(transaction)
+    }
+
+    async fn broadcast_swap_batch(&mut self, batch: SigningBatch) {
+        let txid = batch.transaction.txid();
+
+        match self
+            .elementsd
+            .send_raw_transaction(&batch.transaction)
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Broadcast swap batch {}", txid);
+                for taker in batch.takers {
+                    if let Some(signed) = taker.signed {
+                        let _ = signed.send(Ok(txid));
+                    }
+                }
+            }
+            Err(e) => {
+                // We cannot tell which of the takers signed incorrectly
+                tracing::warn!("Failed to broadcast swap batch {}: {:#}", txid, e);
+                self.abandon_swap_batch(batch, SystemTime::now()).await;
+            }
+        }
+    }
+
+    /// Spend our inputs of the batch back to ourselves and make the
+    /// inputs of the takers available again, counting the batch against
+    /// the takers who did not sign it.
+    async fn abandon_swap_batch(&mut self, batch: SigningBatch, now: SystemTime) {
+        let txid = batch.transaction.txid();
+
+        match swap_watcher::double_spend(&self.elementsd, &batch.maker_inputs).await {
+            Ok(double_spend_txid) => tracing::info!(
+                "Spent inputs of abandoned swap batch {} in {}",
+                txid,
+                double_spend_txid
+            ),
+            // They stay locked, so they are not used in another swap
+            Err(e) => tracing::warn!("Failed to spend inputs of swap batch {}: {:#}", txid, e),
+        }
+
+        batch.abandon(&self.swap_watcher, &mut self.input_reservations, now);
+
+        self.events.publish(Event::SwapLapsed { txid }).await;
+    }
+
+    /// The amounts of L-BTC and L-USDt of a swap at the latest rate, for
+    /// an amount given in the asset the taker pays with.
+    fn swap_amounts(&mut self, side: SwapSide, amount: u64) -> Result<(LiquidBtc, LiquidUsdt)> {
+        let latest_rate = self.rate_service.latest_rate();
+
+        let amounts = match side {
+            SwapSide::Buy => {
+                let usdt_amount = LiquidUsdt::from_satodollar(amount);
+                (latest_rate.sell_base(usdt_amount)?, usdt_amount)
+            }
+            SwapSide::Sell => {
+                let btc_amount = Amount::from_sat(amount);
+                (btc_amount.into(), latest_rate.buy_quote(btc_amount.into())?)
+            }
+        };
+
+        Ok(amounts)
+    }
+}
+
+/// Check that the input at `index` is signed over the whole transaction
+/// by the owner of the P2WPKH output it spends.
+fn verify_input_signature<C>(
+    secp: &Secp256k1<C>,
+    transaction: &Transaction,
+    index: usize,
+    spent: &TxOut,
+) -> Result<()>
+where
+    C: Verification,
+{
+    let (signature, public_key) = match transaction.input[index].witness.script_witness.as_slice() {
+        [signature, public_key] => (signature, public_key),
+        _ => bail!("witness is not the one of a P2WPKH input"),
+    };
+    let public_key = PublicKey::from_slice(public_key).context("invalid public key")?;
+    if Address::p2wpkh(&public_key, None, &AddressParams::ELEMENTS).script_pubkey()
+        != spent.script_pubkey
+    {
+        bail!("public key does not own the spent output")
+    }
+
+    let (sighash_type, signature) = signature.split_last().context("empty signature")?;
+    if *sighash_type != SigHashType::All as u8 {
+        bail!("signature does not commit to the whole transaction")
+    }
+    let signature = Signature::from_der(signature).context("invalid signature")?;
+
+    // The script code of a P2WPKH input is the P2PKH script of its key
+    let script_code = Address::p2pkh(&public_key, None, &AddressParams::ELEMENTS).script_pubkey();
+    let sighash = SigHashCache::new(transaction).segwitv0_sighash(
+        index,
+        &script_code,
+        spent.value,
+        SigHashType::All,
+    );
+    secp.verify(&Message::from(sighash), &signature, &public_key.key)
+        .context("invalid signature")?;
+
+    Ok(())
+}
+
+fn total(amounts: impl Iterator<Item = Amount>) -> Result<Amount> {
+    amounts
+        .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+        .context("total amount of swap batch overflows")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap_watcher::WatcherConfig;
+    use baru::swap::sign_with_key;
+    use elements::{
+        bitcoin::{Network, PrivateKey},
+        confidential::Value,
+        secp256k1_zkp::{rand::thread_rng, SecretKey, SECP256K1},
+        TxIn,
+    };
+
+    const SIGNING_TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn make_keypair() -> (SecretKey, PublicKey) {
+        let sk = SecretKey::new(&mut thread_rng());
+        let pk = PublicKey::from_private_key(
+            SECP256K1,
+            &PrivateKey {
+                compressed: true,
+                network: Network::Regtest,
+                key: sk,
+            },
+        );
+
+        (sk, pk)
+    }
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::default(),
+            vout,
+        }
+    }
+
+    fn spent_txout(pk: &PublicKey) -> TxOut {
+        TxOut {
+            script_pubkey: Address::p2wpkh(pk, None, &AddressParams::ELEMENTS).script_pubkey(),
+            value: Value::Explicit(1_000),
+            ..Default::default()
+        }
+    }
+
+    /// A batch spending one input of every taker and one of ours.
+    fn batch(takers: &[(OutPoint, PublicKey)], built_at: SystemTime) -> SigningBatch {
+        let maker_input = outpoint(99);
+        let input = takers
+            .iter()
+            .map(|(outpoint, _)| *outpoint)
+            .chain(std::iter::once(maker_input))
+            .map(|previous_output| TxIn {
+                previous_output,
+                is_pegin: false,
+                has_issuance: false,
+                script_sig: Default::default(),
+                sequence: 0xFFFF_FFFF,
+                asset_issuance: Default::default(),
+                witness: Default::default(),
+            })
+            .collect();
+
+        SigningBatch {
+            transaction: Transaction {
+                version: 2,
+                lock_time: 0,
+                input,
+                output: vec![],
+            },
+            takers: takers
+                .iter()
+                .map(|(outpoint, pk)| BatchTaker {
+                    taker: None,
+                    inputs: vec![*outpoint],
+                    spent: vec![(*outpoint, spent_txout(pk))].into_iter().collect(),
+                    signed: None,
+                })
+                .collect(),
+            maker_inputs: vec![maker_input],
+            built_at,
+        }
+    }
+
+    fn sign(transaction: &Transaction, index: usize, sk: &SecretKey) -> Transaction {
+        let mut signed = transaction.clone();
+        let mut cache = SigHashCache::new(transaction);
+        signed.input[index].witness.script_witness =
+            sign_with_key(SECP256K1, &mut cache, index, sk, Value::Explicit(1_000));
+
+        signed
+    }
+
+    fn reply() -> oneshot::Sender<Result<Txid, SwapBatchError>> {
+        oneshot::channel().0
+    }
+
+    #[test]
+    fn checked_signatures_of_every_taker_are_merged() {
+        let (sk_a, pk_a) = make_keypair();
+        let (sk_b, pk_b) = make_keypair();
+        let (sk_other, _) = make_keypair();
+        let mut batch = batch(
+            &[(outpoint(0), pk_a), (outpoint(1), pk_b)],
+            SystemTime::now(),
+        );
+        let unsigned = batch.transaction.clone();
+        let txid = unsigned.txid();
+
+        assert_eq!(
+            batch.add_signatures(SECP256K1, &unsigned, reply()),
+            Err(SwapBatchError::Unsigned(txid))
+        );
+
+        let signed_by_a = sign(&unsigned, 0, &sk_a);
+        batch
+            .add_signatures(SECP256K1, &signed_by_a, reply())
+            .unwrap();
+        assert_eq!(
+            batch.transaction.input[0].witness,
+            signed_by_a.input[0].witness
+        );
+        assert!(!batch.is_signed());
+
+        assert_eq!(
+            batch.add_signatures(SECP256K1, &sign(&unsigned, 1, &sk_other), reply()),
+            Err(SwapBatchError::InvalidSignature(txid, outpoint(1)))
+        );
+        assert!(batch.transaction.input[1].witness.script_witness.is_empty());
+
+        batch
+            .add_signatures(SECP256K1, &sign(&unsigned, 1, &sk_b), reply())
+            .unwrap();
+        assert!(batch.is_signed());
+        assert!(batch.transaction.input[2].witness.script_witness.is_empty());
+    }
+
+    #[test]
+    fn batches_expire_once_the_signing_timeout_passed() {
+        let now = SystemTime::now();
+        let (_, pk) = make_keypair();
+        let old = batch(&[(outpoint(0), pk)], now - SIGNING_TIMEOUT);
+        let recent = batch(&[(outpoint(1), pk)], now - SIGNING_TIMEOUT / 2);
+        let old_txid = old.transaction.txid();
+        let recent_txid = recent.transaction.txid();
+        let mut state = State::default();
+        state.signing.insert(old_txid, old);
+        state.signing.insert(recent_txid, recent);
+
+        let expired = state.take_expired(now, SIGNING_TIMEOUT);
+
+        assert_eq!(
+            expired
+                .iter()
+                .map(|batch| batch.transaction.txid())
+                .collect::<Vec<_>>(),
+            vec![old_txid]
+        );
+        assert!(state.signing.contains_key(&recent_txid));
+    }
+
+    #[test]
+    fn abandoned_batch_counts_against_the_takers_who_did_not_sign() {
+        let now = SystemTime::now();
+        let (sk, pk) = make_keypair();
+        let signer = Some(IpAddr::from([10, 0, 0, 1]));
+        let idler = Some(IpAddr::from([10, 0, 0, 2]));
+        let mut batch = batch(&[(outpoint(0), pk), (outpoint(1), pk)], now);
+        batch.takers[0].taker = signer;
+        batch.takers[1].taker = idler;
+        let txid = batch.transaction.txid();
+
+        let (reply, mut broadcast) = oneshot::channel();
+        let signed = sign(&batch.transaction, 0, &sk);
+        batch.add_signatures(SECP256K1, &signed, reply).unwrap();
+
+        let watcher = SwapWatcher::new(WatcherConfig {
+            broadcast_timeout: Duration::from_secs(60),
+            max_lapses: 1,
+            lapse_window: Duration::from_secs(60 * 60),
+        });
+        batch.abandon(&watcher, &mut InputReservations::default(), now);
+
+        assert_eq!(
+            broadcast.try_recv().unwrap(),
+            Err(SwapBatchError::Abandoned(txid))
+        );
+        assert!(watcher.ensure_in_good_standing(signer, now).is_ok());
+        assert!(watcher.ensure_in_good_standing(idler, now).is_err());
+    }
+
+    #[test]
+    fn amounts_of_a_batch_are_summed_up() {
+        let amounts = vec![Amount::from_sat(1_000), Amount::from_sat(2_500)];
+
+        assert_eq!(total(amounts.into_iter()).unwrap(), Amount::from_sat(3_500));
+        assert!(total(vec![Amount::MAX, Amount::from_sat(1)].into_iter()).is_err());
+    }
+}
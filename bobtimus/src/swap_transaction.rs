@@ -0,0 +1,419 @@
+//! Swap transactions between us and one or more takers.
+//!
+//! `baru::swap` unblinds the inputs of every party with their blinding
+//! key, so a taker would have to disclose the key which unblinds every
+//! output they ever received on the same address. Building the
+//! transaction only takes the asset, value and blinding factors of the
+//! spent outputs, which is all a taker discloses here.
+//!
+//! Every party receives one output of the asset they swap into and one
+//! change output per asset they have left over. The fee is paid by the
+//! parties paying with L-BTC, in equal shares.
+
+use anyhow::{bail, Context, Result};
+use baru::input::Input;
+use elements::{
+    bitcoin::Amount,
+    confidential::Asset,
+    secp256k1_zkp::{
+        rand::{CryptoRng, RngCore},
+        Secp256k1, Signing, Verification,
+    },
+    Address, AssetId, OutPoint, Transaction, TxIn, TxOut, TxOutSecrets,
+};
+use estimate_transaction_size::estimate_virtual_size;
+use std::{collections::HashMap, future::Future, iter};
+
+/// A party to a swap transaction.
+#[derive(Debug, Clone)]
+pub struct Party {
+    /// The inputs of the party, with the outputs they spend and their
+    /// secrets
+    pub inputs: Vec<(OutPoint, TxOut, TxOutSecrets)>,
+    /// Where the party receives the asset they swap into and their
+    /// change
+    pub address: Address,
+    /// The asset and amount the party pays to the other parties
+    pub pays: (AssetId, Amount),
+    /// The asset and amount the party receives from the other parties
+    pub receives: (AssetId, Amount),
+}
+
+impl Party {
+    /// A party whose inputs are unblinded with their blinding key, as is
+    /// the case for our own inputs.
+    pub fn unblind<C>(
+        secp: &Secp256k1<C>,
+        inputs: Vec<Input>,
+        address: Address,
+        pays: (AssetId, Amount),
+        receives: (AssetId, Amount),
+    ) -> Result<Self>
+    where
+        C: Verification,
+    {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| {
+                let secrets = input
+                    .original_txout
+                    .unblind(secp, input.blinding_key)
+                    .with_context(|| format!("failed to unblind input {}", input.txin))?;
+
+                Ok((input.txin, input.original_txout, secrets))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            inputs,
+            address,
+            pays,
+            receives,
+        })
+    }
+
+    /// What is left of the inputs of every asset once the party paid.
+    fn change(&self) -> Result<HashMap<AssetId, u64>> {
+        let mut change = HashMap::<AssetId, u64>::new();
+        for (_, _, secrets) in self.inputs.iter() {
+            let value = change.entry(secrets.asset).or_default();
+            *value = value
+                .checked_add(secrets.value)
+                .context("amount of inputs overflows")?;
+        }
+
+        let (asset_id, amount) = self.pays;
+        let value = change.entry(asset_id).or_default();
+        *value = value.checked_sub(amount.as_sat()).with_context(|| {
+            format!(
+                "inputs do not cover {} sats of asset {}",
+                amount.as_sat(),
+                asset_id
+            )
+        })?;
+
+        Ok(change)
+    }
+}
+
+/// Build a swap transaction between us, as `maker`, and the `takers`,
+/// signing our inputs with `sign_maker_inputs`.
+///
+/// The takers sign their inputs after us, every input is signed over
+/// the whole transaction.
+pub async fn create_transaction<R, C, S, F>(
+    rng: &mut R,
+    secp: &Secp256k1<C>,
+    takers: Vec<Party>,
+    maker: Party,
+    btc_asset_id: AssetId,
+    fee_sats_per_vbyte: Amount,
+    sign_maker_inputs: S,
+) -> Result<Transaction>
+where
+    R: RngCore + CryptoRng,
+    C: Signing,
+    S: FnOnce(Transaction) -> F,
+    F: Future<Output = Result<Transaction>>,
+{
+    let parties = takers
+        .into_iter()
+        .chain(iter::once(maker))
+        .collect::<Vec<_>>();
+    ensure_balanced(&parties)?;
+
+    let mut changes = parties
+        .iter()
+        .map(Party::change)
+        .collect::<Result<Vec<_>>>()?;
+
+    let number_of_inputs = parties
+        .iter()
+        .map(|party| party.inputs.len())
+        .sum::<usize>();
+    let number_of_outputs = parties.len()
+        + changes
+            .iter()
+            .map(|change| change.values().filter(|value| **value > 0).count())
+            .sum::<usize>();
+    let fee = estimate_virtual_size(number_of_inputs as u64, number_of_outputs as u64)
+        * fee_sats_per_vbyte.as_sat();
+    let fee = pay_fee(&parties, &mut changes, btc_asset_id, fee)?;
+
+    let outputs = parties
+        .iter()
+        .zip(changes)
+        .flat_map(|(party, change)| {
+            let (asset_id, amount) = party.receives;
+            let address = party.address.clone();
+
+            iter::once((address.clone(), asset_id, amount.as_sat())).chain(
+                change
+                    .into_iter()
+                    .filter(|(_, value)| *value > 0)
+                    .map(move |(asset_id, value)| (address.clone(), asset_id, value)),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let spent = parties
+        .iter()
+        .flat_map(|party| party.inputs.iter())
+        .collect::<Vec<_>>();
+    let input_secrets = spent
+        .iter()
+        .map(|(_, txout, secrets)| (txout.asset, secrets))
+        .collect::<Vec<_>>();
+    let output = blind_outputs(rng, secp, &outputs, &input_secrets)?
+        .into_iter()
+        .chain(iter::once(TxOut::new_fee(fee, btc_asset_id)))
+        .collect();
+
+    let input = spent
+        .iter()
+        .map(|(outpoint, _, _)| TxIn {
+            previous_output: *outpoint,
+            is_pegin: false,
+            has_issuance: false,
+            script_sig: Default::default(),
+            sequence: 0xFFFF_FFFF,
+            asset_issuance: Default::default(),
+            witness: Default::default(),
+        })
+        .collect();
+
+    let transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input,
+        output,
+    };
+
+    sign_maker_inputs(transaction).await
+}
+
+/// Every asset has to be paid as much as it is received.
+fn ensure_balanced(parties: &[Party]) -> Result<()> {
+    let paid = totals(parties.iter().map(|party| party.pays))?;
+    let received = totals(parties.iter().map(|party| party.receives))?;
+
+    if paid != received {
+        bail!("assets paid do not match the assets received")
+    }
+
+    Ok(())
+}
+
+fn totals(amounts: impl Iterator<Item = (AssetId, Amount)>) -> Result<HashMap<AssetId, u64>> {
+    let mut totals = HashMap::<AssetId, u64>::new();
+    for (asset_id, amount) in amounts {
+        let total = totals.entry(asset_id).or_default();
+        *total = total
+            .checked_add(amount.as_sat())
+            .context("amount of swap overflows")?;
+    }
+
+    Ok(totals)
+}
+
+/// Take equal shares of the fee out of the L-BTC change of the parties
+/// paying with L-BTC, returning the fee paid in total.
+///
+/// The fee is rounded up so that it can be split evenly.
+fn pay_fee(
+    parties: &[Party],
+    changes: &mut [HashMap<AssetId, u64>],
+    btc_asset_id: AssetId,
+    fee: u64,
+) -> Result<u64> {
+    let payers = parties
+        .iter()
+        .enumerate()
+        .filter(|(_, party)| party.pays.0 == btc_asset_id)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if payers.is_empty() {
+        bail!("nobody pays with L-BTC to pay the fee")
+    }
+
+    let number_of_payers = payers.len() as u64;
+    let share = (fee + number_of_payers - 1) / number_of_payers;
+    for index in payers {
+        let change = changes[index].entry(btc_asset_id).or_default();
+        *change = change
+            .checked_sub(share)
+            .context("inputs do not cover the share of the fee")?;
+    }
+
+    Ok(share * number_of_payers)
+}
+
+/// Blind the outputs, the last one balancing the blinding factors of all
+/// inputs and outputs.
+fn blind_outputs<R, C>(
+    rng: &mut R,
+    secp: &Secp256k1<C>,
+    outputs: &[(Address, AssetId, u64)],
+    inputs: &[(Asset, &TxOutSecrets)],
+) -> Result<Vec<TxOut>>
+where
+    R: RngCore + CryptoRng,
+    C: Signing,
+{
+    let ((last_address, last_asset_id, last_value), others) =
+        outputs.split_last().context("transaction has no outputs")?;
+    let not_last_inputs = inputs
+        .iter()
+        .map(|(asset, secrets)| (*asset, Some(*secrets)))
+        .collect::<Vec<_>>();
+
+    let mut txouts = Vec::new();
+    let mut output_secrets = Vec::new();
+    for (address, asset_id, value) in others {
+        let (txout, abf, vbf) = TxOut::new_not_last_confidential(
+            rng,
+            secp,
+            *value,
+            address.clone(),
+            *asset_id,
+            not_last_inputs.as_slice(),
+        )
+        .context("failed to blind output")?;
+
+        txouts.push(txout);
+        output_secrets.push(TxOutSecrets::new(*asset_id, abf, *value, vbf));
+    }
+
+    let (last_txout, _, _) = TxOut::new_last_confidential(
+        rng,
+        secp,
+        *last_value,
+        last_address.clone(),
+        *last_asset_id,
+        inputs,
+        output_secrets.iter().collect::<Vec<_>>().as_ref(),
+    )
+    .context("failed to blind last output")?;
+    txouts.push(last_txout);
+
+    Ok(txouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::confidential::{AssetBlindingFactor, ValueBlindingFactor};
+    use std::str::FromStr;
+
+    fn btc() -> AssetId {
+        AssetId::from_str("6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d")
+            .unwrap()
+    }
+
+    fn usdt() -> AssetId {
+        AssetId::from_str("ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2")
+            .unwrap()
+    }
+
+    fn address() -> Address {
+        Address::from_str("el1qq0zel5lg55nvhv9kkrq8gme8hnvp0lemuzcmu086dn2m8laxjgkewkhqnh8vxdnlp4cejs3925j0gu9n9krdgmqm89vku0kc8").unwrap()
+    }
+
+    fn party(inputs: &[(AssetId, u64)], pays: (AssetId, u64), receives: (AssetId, u64)) -> Party {
+        let inputs = inputs
+            .iter()
+            .enumerate()
+            .map(|(vout, (asset_id, value))| {
+                let outpoint = OutPoint {
+                    txid: Default::default(),
+                    vout: vout as u32,
+                };
+                let secrets = TxOutSecrets::new(
+                    *asset_id,
+                    AssetBlindingFactor::zero(),
+                    *value,
+                    ValueBlindingFactor::zero(),
+                );
+
+                (outpoint, TxOut::default(), secrets)
+            })
+            .collect();
+
+        Party {
+            inputs,
+            address: address(),
+            pays: (pays.0, Amount::from_sat(pays.1)),
+            receives: (receives.0, Amount::from_sat(receives.1)),
+        }
+    }
+
+    #[test]
+    fn change_is_what_is_left_of_the_inputs() {
+        let party = party(
+            &[(btc(), 600), (btc(), 500), (usdt(), 10)],
+            (btc(), 1_000),
+            (usdt(), 50),
+        );
+
+        let change = party.change().unwrap();
+
+        assert_eq!(change[&btc()], 100);
+        assert_eq!(change[&usdt()], 10);
+    }
+
+    #[test]
+    fn inputs_have_to_cover_the_payment() {
+        let party = party(&[(btc(), 999)], (btc(), 1_000), (usdt(), 50));
+
+        assert!(party.change().is_err());
+    }
+
+    #[test]
+    fn swap_has_to_be_balanced() {
+        let taker = party(&[(btc(), 1_000)], (btc(), 1_000), (usdt(), 50));
+        let maker = party(&[(usdt(), 100)], (usdt(), 50), (btc(), 1_000));
+        let greedy_maker = party(&[(usdt(), 100)], (usdt(), 50), (btc(), 1_001));
+
+        assert!(ensure_balanced(&[taker.clone(), maker]).is_ok());
+        assert!(ensure_balanced(&[taker, greedy_maker]).is_err());
+    }
+
+    #[test]
+    fn fee_is_shared_by_the_parties_paying_with_btc() {
+        let takers = vec![
+            party(&[(btc(), 1_100)], (btc(), 1_000), (usdt(), 50)),
+            party(&[(btc(), 2_100)], (btc(), 2_000), (usdt(), 100)),
+        ];
+        let maker = party(&[(usdt(), 150)], (usdt(), 150), (btc(), 3_000));
+        let parties = takers
+            .into_iter()
+            .chain(iter::once(maker))
+            .collect::<Vec<_>>();
+        let mut changes = parties
+            .iter()
+            .map(Party::change)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        let fee = pay_fee(&parties, &mut changes, btc(), 101).unwrap();
+
+        assert_eq!(fee, 102);
+        assert_eq!(changes[0][&btc()], 49);
+        assert_eq!(changes[1][&btc()], 49);
+        assert_eq!(changes[2].get(&btc()).copied().unwrap_or_default(), 0);
+    }
+
+    #[test]
+    fn fee_has_to_be_covered() {
+        let taker = party(&[(btc(), 1_050)], (btc(), 1_000), (usdt(), 50));
+        let maker = party(&[(usdt(), 50)], (usdt(), 50), (btc(), 1_000));
+        let parties = vec![taker, maker];
+        let mut changes = parties
+            .iter()
+            .map(Party::change)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert!(pay_fee(&parties, &mut changes, btc(), 51).is_err());
+    }
+}
@@ -0,0 +1,514 @@
+//! Follows the swap transactions we handed out until they are
+//! broadcast.
+//!
+//! A taker may sit on a swap transaction and only broadcast it if the
+//! price moved in their favour, or spend their inputs elsewhere. Our
+//! inputs are locked while a swap is pending. Once it lapsed we spend
+//! them back to ourselves, which invalidates the swap transaction
+//! instead of leaving it to the taker. If the taker spent their inputs
+//! elsewhere the swap transaction is invalid already and our inputs are
+//! unlocked. Takers who let too many swaps lapse are throttled.
+
+use crate::{
+    elements_rpc::Client,
+    event::{Event, EventBus, SwapSide},
+    metrics, Bobtimus, LatestRate,
+};
+use anyhow::{Context, Result};
+use bobtimus_client::CreateSwapPayload;
+use elements::{
+    bitcoin::Amount,
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    AssetId, OutPoint, Transaction, TxOut, Txid,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatcherConfig {
+    /// How long a taker has to broadcast a swap transaction
+    pub broadcast_timeout: Duration,
+    /// How many swaps a taker may let lapse within `lapse_window`
+    /// before being throttled
+    pub max_lapses: usize,
+    pub lapse_window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("Too many swaps were not broadcast, retry in {}s", retry_after.as_secs())]
+pub struct TakerThrottled {
+    pub retry_after: Duration,
+}
+
+/// Handle to the swaps being watched, shared between Bobtimus and the
+/// task checking on them.
+#[derive(Debug, Clone)]
+pub struct SwapWatcher {
+    config: WatcherConfig,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: HashMap<Txid, PendingSwap>,
+    /// When the swaps of each taker lapsed, within the lapse window
+    lapses: HashMap<IpAddr, Vec<SystemTime>>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingSwap {
+    taker: Option<IpAddr>,
+    taker_inputs: Vec<OutPoint>,
+    maker_inputs: Vec<OutPoint>,
+    handed_out_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Pending,
+    Broadcast,
+    /// The inputs of the taker were spent by another transaction
+    Conflicted,
+    /// The taker did not broadcast in time
+    Lapsed,
+}
+
+impl SwapWatcher {
+    pub fn new(config: WatcherConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Watch a swap transaction we just handed out, returning our
+    /// inputs, which are all inputs which are not the taker's.
+    pub fn watch(
+        &self,
+        transaction: &Transaction,
+        taker_inputs: &[OutPoint],
+        taker: Option<IpAddr>,
+        now: SystemTime,
+    ) -> Vec<OutPoint> {
+        let maker_inputs = transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .filter(|outpoint| !taker_inputs.contains(outpoint))
+            .collect::<Vec<_>>();
+
+        self.lock().pending.insert(
+            transaction.txid(),
+            PendingSwap {
+                taker,
+                taker_inputs: taker_inputs.to_vec(),
+                maker_inputs: maker_inputs.clone(),
+                handed_out_at: now,
+            },
+        );
+
+        maker_inputs
+    }
+
+    /// Reject takers who let too many swaps lapse recently, until the
+    /// oldest of these lapses is outside the lapse window.
+    ///
+    /// Takers we cannot identify are never throttled.
+    pub fn ensure_in_good_standing(
+        &self,
+        taker: Option<IpAddr>,
+        now: SystemTime,
+    ) -> Result<(), TakerThrottled> {
+        let taker = match taker {
+            Some(taker) => taker,
+            None => return Ok(()),
+        };
+
+        let mut state = self.lock();
+        let lapses = match state.lapses.get_mut(&taker) {
+            Some(lapses) => lapses,
+            None => return Ok(()),
+        };
+        let lapse_window = self.config.lapse_window;
+        lapses.retain(|lapsed_at| *lapsed_at + lapse_window > now);
+
+        if lapses.len() < self.config.max_lapses {
+            return Ok(());
+        }
+
+        let oldest = lapses.iter().min().copied().unwrap_or(now);
+        let retry_after = (oldest + lapse_window)
+            .duration_since(now)
+            .unwrap_or_default();
+
+        Err(TakerThrottled { retry_after })
+    }
+
+    /// Count a swap which lapsed without being watched against the
+    /// taker, e.g. a swap batch they did not sign.
+    pub fn record_lapse(&self, taker: Option<IpAddr>, now: SystemTime) {
+        if let Some(taker) = taker {
+            self.lock().lapses.entry(taker).or_default().push(now);
+        }
+    }
+
+    /// Check on the pending swaps every `interval` until the process is
+    /// stopped.
+    pub async fn run(self, elementsd: Client, events: EventBus, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.check(&elementsd, &events, SystemTime::now()).await {
+                tracing::error!("failed to check on pending swaps: {:#}", e);
+            }
+        }
+    }
+
+    async fn check(&self, elementsd: &Client, events: &EventBus, now: SystemTime) -> Result<()> {
+        // The lock is not held across RPC calls, so that handing out
+        // swaps is not blocked by slow responses of elementsd
+        let pending = self
+            .lock()
+            .pending
+            .iter()
+            .map(|(txid, swap)| (*txid, swap.clone()))
+            .collect::<Vec<_>>();
+
+        for (txid, swap) in pending {
+            let outcome = self.outcome(elementsd, txid, &swap, now).await?;
+
+            match outcome {
+                Outcome::Pending => continue,
+                Outcome::Broadcast => {
+                    let delay = now.duration_since(swap.handed_out_at).unwrap_or_default();
+                    metrics::swap_broadcast(delay);
+                }
+                Outcome::Conflicted => {
+                    tracing::warn!("Inputs of swap {} were spent by another transaction", txid);
+                    elementsd.unlock_utxos(Some(swap.maker_inputs)).await?;
+                    events.publish(Event::SwapConflicted { txid }).await;
+                }
+                Outcome::Lapsed => {
+                    // The swap stays pending until our inputs are
+                    // spent, also if the taker broadcasts it meanwhile
+                    match double_spend(elementsd, &swap.maker_inputs).await {
+                        Ok(double_spend_txid) => tracing::info!(
+                            "Swap {} was not broadcast in time, spent our inputs in {}",
+                            txid,
+                            double_spend_txid
+                        ),
+                        Err(e) => {
+                            tracing::warn!("Failed to spend inputs of swap {}: {:#}", txid, e);
+                            continue;
+                        }
+                    }
+                    events.publish(Event::SwapLapsed { txid }).await;
+                }
+            }
+
+            self.settle(txid, swap.taker, outcome, now);
+        }
+
+        Ok(())
+    }
+
+    async fn outcome(
+        &self,
+        elementsd: &Client,
+        txid: Txid,
+        swap: &PendingSwap,
+        now: SystemTime,
+    ) -> Result<Outcome> {
+        if is_broadcast(elementsd, txid).await {
+            return Ok(Outcome::Broadcast);
+        }
+
+        for outpoint in swap.taker_inputs.iter() {
+            if elementsd.get_tx_out(*outpoint, true).await?.is_none() {
+                // The swap may have been broadcast since we checked
+                let outcome = if is_broadcast(elementsd, txid).await {
+                    Outcome::Broadcast
+                } else {
+                    Outcome::Conflicted
+                };
+
+                return Ok(outcome);
+            }
+        }
+
+        if swap.handed_out_at + self.config.broadcast_timeout <= now {
+            return Ok(Outcome::Lapsed);
+        }
+
+        Ok(Outcome::Pending)
+    }
+
+    /// Stop watching the swap and count it against the taker if it was
+    /// not broadcast.
+    fn settle(&self, txid: Txid, taker: Option<IpAddr>, outcome: Outcome, now: SystemTime) {
+        let lapse_window = self.config.lapse_window;
+        let mut state = self.lock();
+        state.pending.remove(&txid);
+        state.lapses.retain(|_, lapses| {
+            lapses.retain(|lapsed_at| *lapsed_at + lapse_window > now);
+            !lapses.is_empty()
+        });
+
+        if let (Outcome::Conflicted | Outcome::Lapsed, Some(taker)) = (outcome, taker) {
+            state.lapses.entry(taker).or_default().push(now);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("no panic while holding the lock")
+    }
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Handle a swap request of a taker in good standing and watch the
+    /// transaction until it is broadcast.
+    ///
+    /// Our inputs are locked, so that they are not handed out in another
+    /// swap until this one lapsed.
+    pub async fn handle_watched_swap(
+        &mut self,
+        side: SwapSide,
+        taker: Option<IpAddr>,
+        payload: CreateSwapPayload,
+    ) -> Result<Transaction> {
+        let now = SystemTime::now();
+        self.swap_watcher.ensure_in_good_standing(taker, now)?;
+
+        let taker_inputs = payload
+            .alice_inputs
+            .iter()
+            .map(|input| input.outpoint)
+            .collect::<Vec<_>>();
+        let transaction = match side {
+            SwapSide::Buy => self.handle_create_buy_swap(payload).await?,
+            SwapSide::Sell => self.handle_create_sell_swap(payload).await?,
+        };
+
+        let maker_inputs = self
+            .swap_watcher
+            .watch(&transaction, &taker_inputs, taker, now);
+        // The transaction is signed already, the taker gets it either way
+        if let Err(e) = self.elementsd.lock_utxos(maker_inputs).await {
+            tracing::warn!(
+                "Failed to lock inputs of swap {}: {:#}",
+                transaction.txid(),
+                e
+            );
+        }
+
+        Ok(transaction)
+    }
+}
+
+/// Whether the transaction is in the mempool or in the chain.
+///
+/// A swap transaction spends inputs of our wallet, so the wallet knows
+/// it once it is broadcast.
+async fn is_broadcast(elementsd: &Client, txid: Txid) -> bool {
+    match elementsd.get_wallet_transaction(txid).await {
+        Ok(transaction) => transaction.confirmations >= 0,
+        Err(_) => false,
+    }
+}
+
+/// Spend our inputs of a swap back to our wallet, so that the swap
+/// transaction can no longer be broadcast.
+///
+/// The inputs stay locked, which does not matter once they are spent.
+pub(crate) async fn double_spend(elementsd: &Client, maker_inputs: &[OutPoint]) -> Result<Txid> {
+    let mut txouts = Vec::new();
+    for outpoint in maker_inputs {
+        let source = elementsd
+            .get_wallet_transaction(outpoint.txid)
+            .await
+            .context("cannot get source transaction")?
+            .transaction;
+        let source = elementsd
+            .unblind_raw_transaction(&source)
+            .await
+            .context("cannot unblind source transaction")?;
+        let txout = source
+            .output
+            .get(outpoint.vout as usize)
+            .with_context(|| format!("no output {}", outpoint))?;
+
+        txouts.push(txout.clone());
+    }
+
+    let mut outputs = Vec::new();
+    for (asset_id, amount) in amounts_by_asset(&txouts)? {
+        let address = elementsd.get_new_segwit_confidential_address().await?;
+        outputs.push((address, asset_id, amount));
+    }
+
+    let transaction = elementsd
+        .create_raw_transaction(maker_inputs, &outputs)
+        .await?;
+    // The fee is paid by another input of ours
+    let transaction = elementsd.fund_raw_transaction(&transaction).await?;
+    let transaction = elementsd.blind_raw_transaction(&transaction).await?;
+    let transaction = elementsd.sign_raw_transaction(&transaction).await?;
+
+    elementsd.send_raw_transaction(&transaction).await
+}
+
+/// The total amount of every asset of unblinded outputs.
+fn amounts_by_asset(txouts: &[TxOut]) -> Result<HashMap<AssetId, Amount>> {
+    let mut amounts = HashMap::<AssetId, Amount>::new();
+    for txout in txouts {
+        let asset_id = txout.asset.explicit().context("asset is not unblinded")?;
+        let value = txout.value.explicit().context("value is not unblinded")?;
+
+        let amount = amounts.entry(asset_id).or_default();
+        *amount = amount
+            .checked_add(Amount::from_sat(value))
+            .context("amount overflow")?;
+    }
+
+    Ok(amounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::confidential::{Asset, Nonce, Value};
+    use std::str::FromStr;
+
+    const CONFIG: WatcherConfig = WatcherConfig {
+        broadcast_timeout: Duration::from_secs(60),
+        max_lapses: 2,
+        lapse_window: Duration::from_secs(60 * 60),
+    };
+
+    fn txid(last: u8) -> Txid {
+        Txid::from_str(&format!("{:064x}", last)).unwrap()
+    }
+
+    fn taker() -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, 1]))
+    }
+
+    #[test]
+    fn taker_is_throttled_after_too_many_lapses_until_window_passed() {
+        let watcher = SwapWatcher::new(CONFIG);
+        let now = SystemTime::now();
+
+        watcher.settle(txid(1), taker(), Outcome::Lapsed, now);
+        assert!(watcher.ensure_in_good_standing(taker(), now).is_ok());
+
+        watcher.settle(txid(2), taker(), Outcome::Conflicted, now);
+        assert_eq!(
+            watcher.ensure_in_good_standing(taker(), now),
+            Err(TakerThrottled {
+                retry_after: CONFIG.lapse_window
+            })
+        );
+
+        assert!(watcher
+            .ensure_in_good_standing(taker(), now + CONFIG.lapse_window)
+            .is_ok());
+    }
+
+    #[test]
+    fn broadcast_swaps_do_not_count_against_the_taker() {
+        let watcher = SwapWatcher::new(CONFIG);
+        let now = SystemTime::now();
+
+        for i in 0..5 {
+            watcher.settle(txid(i), taker(), Outcome::Broadcast, now);
+        }
+
+        assert!(watcher.ensure_in_good_standing(taker(), now).is_ok());
+    }
+
+    #[test]
+    fn only_inputs_of_the_maker_are_released() {
+        let watcher = SwapWatcher::new(CONFIG);
+        let taker_input = OutPoint {
+            txid: txid(1),
+            vout: 0,
+        };
+        let maker_input = OutPoint {
+            txid: txid(2),
+            vout: 1,
+        };
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![taker_input, maker_input]
+                .into_iter()
+                .map(|previous_output| elements::TxIn {
+                    previous_output,
+                    is_pegin: false,
+                    has_issuance: false,
+                    script_sig: Default::default(),
+                    sequence: 0,
+                    asset_issuance: Default::default(),
+                    witness: Default::default(),
+                })
+                .collect(),
+            output: vec![],
+        };
+
+        watcher.watch(&transaction, &[taker_input], taker(), SystemTime::now());
+
+        let state = watcher.lock();
+        let swap = &state.pending[&transaction.txid()];
+        assert_eq!(swap.maker_inputs, vec![maker_input]);
+    }
+
+    #[test]
+    fn double_spend_returns_the_amount_of_every_asset() {
+        let btc =
+            AssetId::from_str("6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d")
+                .unwrap();
+        let usdt =
+            AssetId::from_str("ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2")
+                .unwrap();
+        let txout = |asset_id, value| TxOut {
+            asset: Asset::Explicit(asset_id),
+            value: Value::Explicit(value),
+            nonce: Nonce::Null,
+            script_pubkey: Default::default(),
+            witness: Default::default(),
+        };
+
+        let amounts =
+            amounts_by_asset(&[txout(btc, 1_000), txout(usdt, 5), txout(btc, 500)]).unwrap();
+
+        assert_eq!(
+            amounts,
+            vec![(btc, Amount::from_sat(1_500)), (usdt, Amount::from_sat(5))]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn double_spend_requires_unblinded_inputs() {
+        let txout = TxOut {
+            asset: Asset::Null,
+            value: Value::Null,
+            nonce: Nonce::Null,
+            script_pubkey: Default::default(),
+            witness: Default::default(),
+        };
+
+        assert!(amounts_by_asset(&[txout]).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! The elementsd wallets our funds are kept in.
+//!
+//! Swaps, lending and liquidations can each use a wallet of their own,
+//! so that a burst of swaps cannot spend the L-USDt set aside for
+//! loans. The swap wallet also holds our identity and is used for calls
+//! which do not depend on a wallet. Wallets which are not configured
+//! are the default wallet of elementsd.
+
+use crate::{elements_rpc::Client, Bobtimus, LatestRate};
+use anyhow::{Context, Result};
+use elements::{
+    bitcoin::Amount,
+    secp256k1_zkp::rand::{CryptoRng, RngCore},
+    AssetId, Txid,
+};
+
+/// The names of the wallets to use, the default wallet where not given.
+#[derive(Debug, Clone, Default)]
+pub struct WalletNames {
+    pub swap: Option<String>,
+    pub lending: Option<String>,
+    /// Receives repayments and the collateral of liquidated loans
+    pub liquidation: Option<String>,
+}
+
+/// A client for the wallet with the given name, or the default wallet.
+///
+/// Fails if elementsd has not loaded the wallet.
+pub async fn open(elementsd: &Client, name: Option<&str>) -> Result<Client> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(elementsd.clone()),
+    };
+
+    let wallet = elementsd.with_wallet(name)?;
+    wallet
+        .get_wallet_info()
+        .await
+        .with_context(|| format!("wallet {} is not loaded in elementsd", name))?;
+
+    Ok(wallet)
+}
+
+/// Move funds from one of our wallets to another, e.g. to top up the
+/// lending wallet with the L-USDt earned in swaps.
+pub async fn transfer(
+    from: &Client,
+    to: &Client,
+    asset_id: AssetId,
+    amount: Amount,
+) -> Result<Txid> {
+    let address = to
+        .get_new_segwit_confidential_address()
+        .await
+        .context("failed to get address of receiving wallet")?;
+
+    let txid = from
+        .send_asset_to_address(&address, amount, Some(asset_id))
+        .await
+        .context("failed to send from sending wallet")?;
+
+    Ok(txid)
+}
+
+impl<R, RS> Bobtimus<R, RS>
+where
+    R: RngCore + CryptoRng,
+    RS: LatestRate,
+{
+    /// Our wallets with their names, a wallet used for several purposes
+    /// is only given once.
+    pub(crate) async fn distinct_wallets(&self) -> Result<Vec<(String, &Client)>> {
+        let mut wallets = Vec::<(String, &Client)>::new();
+        for wallet in vec![
+            &self.elementsd,
+            &self.lending_wallet,
+            &self.liquidation_wallet,
+        ] {
+            let name = wallet.get_wallet_info().await?.walletname;
+
+            if !wallets.iter().any(|(known, _)| *known == name) {
+                wallets.push((name, wallet));
+            }
+        }
+
+        Ok(wallets)
+    }
+}
@@ -8,19 +8,28 @@ import { Status } from "./background/api";
 import AddressQr from "./components/AddressQr";
 import WalletBalances from "./components/Balances";
 import ConfirmLoanWizard from "./components/ConfirmLoanWizard";
+import ConfirmRepayment from "./components/ConfirmRepayment";
 import ConfirmSwap from "./components/ConfirmSwap";
 import CreateWallet from "./components/CreateWallet";
 import OpenLoans from "./components/OpenLoans";
 import UnlockWallet from "./components/UnlockWallet";
 import WithdrawAll from "./components/WithdrawAll";
 import theme from "./theme";
-import { useBalances, useLoanToSign, useOpenLoans, useSwapToSign, useWalletStatus } from "./walletHooks";
+import {
+    useBalances,
+    useLoanToSign,
+    useOpenLoans,
+    useRepaymentToSign,
+    useSwapToSign,
+    useWalletStatus,
+} from "./walletHooks";
 
 const App = () => {
     const { data: walletStatus, reload: reloadWalletStatus, error } = useWalletStatus();
     const { data: balanceUpdates, reload: reloadWalletBalances } = useBalances();
     const { data: swapToSign, reload: reloadSwapToSign } = useSwapToSign();
     const { data: loanToSign, reload: reloadLoanToSign } = useLoanToSign();
+    const { data: repaymentToSign, reload: reloadRepaymentToSign } = useRepaymentToSign();
     const { data: openLoans, reload: reloadOpenLoans } = useOpenLoans();
 
     const refreshAll = () => {
@@ -28,14 +37,20 @@ const App = () => {
         reloadWalletStatus();
         reloadSwapToSign();
         reloadLoanToSign();
+        reloadRepaymentToSign();
         reloadOpenLoans();
     };
 
-    // we want to either sign a swap or the loan but not both:
+    // we want to sign either a swap, a loan or a repayment but only one at a time:
     let signLoan = false;
     if (!swapToSign && loanToSign) {
         signLoan = true;
     }
+    let signRepayment = false;
+    if (!swapToSign && !loanToSign && repaymentToSign) {
+        signRepayment = true;
+    }
+    const isSigning = signLoan || signRepayment || !!swapToSign;
 
     return (
         <ChakraProvider theme={theme}>
@@ -57,9 +72,9 @@ const App = () => {
                         </Flex>
 
                         {balanceUpdates && <WalletBalances balanceUpdates={balanceUpdates} />}
-                        {!signLoan && !swapToSign && <AddressQr />}
-                        {!signLoan && !swapToSign && <WithdrawAll />}
-                        {!signLoan && !swapToSign && <OpenLoans openLoans={openLoans} onRepayed={refreshAll} />}
+                        {!isSigning && <AddressQr />}
+                        {!isSigning && <WithdrawAll />}
+                        {!isSigning && <OpenLoans openLoans={openLoans} onRepayed={refreshAll} />}
 
                         {swapToSign && <ConfirmSwap
                             onCancel={refreshAll}
@@ -72,6 +87,12 @@ const App = () => {
                                 onSuccess={refreshAll}
                                 loanToSign={loanToSign!}
                             />}
+                        {signRepayment
+                            && <ConfirmRepayment
+                                onCancel={refreshAll}
+                                onSuccess={refreshAll}
+                                repaymentToSign={repaymentToSign!}
+                            />}
                     </>}
                 {walletStatus?.status === Status.NotLoaded
                     && <>
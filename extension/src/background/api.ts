@@ -9,15 +9,17 @@ export type BackgroundWindow = BackgroundWindowWasm & BackgroundWindowTypescript
 interface BackgroundWindowWasm {
     extractTrade(hex: string): Promise<Trade>;
     extractLoan(loanRequest: LoanRequestPayload): Promise<LoanDetails>;
-    signAndSendSwap(hex: string): Promise<Txid>;
+    signAndSendSwap(hex: string, signature: MakerSignature, origin: string): Promise<Txid>;
+    signSwapBatch(hex: string, signature: MakerSignature, origin: string): Promise<string>;
     unlockWallet(password: string): Promise<void>;
     withdrawAll(address: string): Promise<Txid>;
     getWalletStatus(): Promise<WalletStatus>;
     getBalances(): Promise<BalanceEntry[]>;
     createNewWallet(seedWords: string, password: string): Promise<void>;
     repayLoan(txid: string): Promise<Txid>;
+    signEarlyRepayment(repayment: RepaymentResponse): Promise<string>;
     getAddress(): Promise<string>;
-    signLoan(): Promise<string>; // TODO: It is weird that we are calling this without any parameters.
+    signLoan(signature: MakerSignature, origin: string): Promise<string>;
 
     // TODO: Implement these in Typescript instead.
     getOpenLoans(): Promise<LoanDetails[]>;
@@ -30,11 +32,14 @@ interface BackgroundWindowWasm {
 export interface BackgroundWindowTypescript {
     swapToSign: SwapToSign | null;
     loanToSign: LoanToSign | null;
+    repaymentToSign: RepaymentToSign | null;
     approveSwap(): Promise<void>;
     rejectSwap(): Promise<void>;
     approveLoan(): Promise<string>;
     rejectLoan(): Promise<void>;
     publishLoan(tx: string): Promise<void>;
+    approveRepayment(): Promise<void>;
+    rejectRepayment(): Promise<void>;
 }
 
 // Represents the API of our wallet that is accessible from non-privileged contexts like the content script and as a result of that, the web page.
@@ -49,8 +54,10 @@ interface EventListenersWasm {
 }
 
 export interface EventListenersTypescript {
-    requestSignSwap(hex: string): Promise<Txid>;
-    requestSignLoan(loanRequest: LoanRequestPayload): Promise<string>;
+    requestSignSwap(hex: string, signature: MakerSignature): Promise<Txid>;
+    requestSignSwapBatch(hex: string, signature: MakerSignature): Promise<string>;
+    requestSignLoan(loanRequest: LoanRequestPayload, signature: MakerSignature): Promise<string>;
+    requestSignRepayment(repayment: RepaymentResponse): Promise<string>;
 }
 
 // Access the background page directly.
@@ -89,10 +96,22 @@ export interface RpcMessage<T extends keyof Wallet> {
 export interface SwapToSign {
     txHex: string;
     decoded: Trade;
+    signature: MakerSignature;
+    // The origin of the page which asked for the signature, under which the identity of Bobtimus is pinned
+    origin: string;
+    // Whether the swap is part of a batch, which Bobtimus broadcasts once every taker signed it
+    batched: boolean;
 }
 
 export interface LoanToSign {
     details: LoanDetails;
+    signature: MakerSignature;
+    origin: string;
+}
+
+export interface RepaymentToSign {
+    details: LoanDetails;
+    repayment: RepaymentResponse;
 }
 
 export type RpcResponse<T extends keyof Wallet> = {
@@ -124,16 +143,30 @@ export interface BalanceEntry {
 }
 
 export interface CreateSwapPayload {
-    alice_inputs: { outpoint: OutPoint; blinding_key: string }[];
+    alice_inputs: { outpoint: OutPoint; blinding_key?: string; secrets?: InputSecrets }[];
     address: string;
     amount: number;
 }
 
+// The identity of Bobtimus and its signature over a swap or loan transaction
+export interface MakerSignature {
+    maker: string;
+    signature: string;
+}
+
 export interface OutPoint {
     txid: string;
     vout: number;
 }
 
+// Amounts and blinding factors of the output spent by an input
+export interface InputSecrets {
+    asset: string;
+    asset_blinding_factor: string;
+    value: number;
+    value_blinding_factor: string;
+}
+
 export interface LoanRequestPayload {
     collateral_amount: number;
     // TODO: Replace `any` with concrete type or get rid of `original_txout` field
@@ -144,6 +177,15 @@ export interface LoanRequestPayload {
     borrower_address: string;
 }
 
+// The lender's part of repaying a loan early, in full
+export interface RepaymentResponse {
+    loan_txid: Txid;
+    repayment_amount: number;
+    // TODO: Replace `any` with concrete type or get rid of `original_txout` field
+    refund_input: { txin: OutPoint; original_txout: any; blinding_key: string } | null;
+    expires_at: number;
+}
+
 export type Txid = string;
 
 export interface TradeSide {
@@ -1,5 +1,5 @@
 import debug from "debug";
-import { browser } from "webextension-polyfill-ts";
+import { browser, Runtime } from "webextension-polyfill-ts";
 import { ParametersObject } from "../type-utils";
 import { BackgroundWindow, BackgroundWindowTypescript, EventListenersTypescript, RpcMessage, RpcResponse } from "./api";
 
@@ -23,11 +23,11 @@ function loadSettings() {
 }
 
 function initializeEventListeners() {
-    addRpcMessageListener("requestSignSwap", ({ hex }) => {
+    addRpcMessageListener("requestSignSwap", ({ hex, signature }, origin) => {
         return new Promise(resolve => {
             window.extractTrade(hex)
                 .then(decoded => {
-                    window.swapToSign = { txHex: hex, decoded };
+                    window.swapToSign = { txHex: hex, decoded, signature, origin, batched: false };
                     resolveSwapSignRequest = resolve;
 
                     return updateBadge();
@@ -39,12 +39,28 @@ function initializeEventListeners() {
         });
     });
 
+    addRpcMessageListener("requestSignSwapBatch", ({ hex, signature }, origin) => {
+        return new Promise(resolve => {
+            window.extractTrade(hex)
+                .then(decoded => {
+                    window.swapToSign = { txHex: hex, decoded, signature, origin, batched: true };
+                    resolveSwapBatchSignRequest = resolve;
+
+                    return updateBadge();
+                })
+                .catch(e => {
+                    resolve({ Err: e });
+                    return cleanupPendingSwap();
+                });
+        });
+    });
+
     // @ts-ignore: Why does this not work?
-    addRpcMessageListener("requestSignLoan", ({ loanRequest }) => {
+    addRpcMessageListener("requestSignLoan", ({ loanRequest, signature }, origin) => {
         return new Promise(resolve => {
             void window.extractLoan(loanRequest)
                 .then(details => {
-                    window.loanToSign = { details };
+                    window.loanToSign = { details, signature, origin };
                     resolveLoanSignRequest = resolve;
 
                     return updateBadge();
@@ -56,21 +72,45 @@ function initializeEventListeners() {
         });
     });
 
+    addRpcMessageListener("requestSignRepayment", ({ repayment }) => {
+        return new Promise(resolve => {
+            window.getOpenLoans()
+                .then(openLoans => {
+                    const details = openLoans.find(loan => loan.txid === repayment.loan_txid);
+                    if (!details) {
+                        throw new Error(`No open loan with txid ${repayment.loan_txid}`);
+                    }
+
+                    window.repaymentToSign = { details, repayment };
+                    resolveRepaymentSignRequest = resolve;
+
+                    return updateBadge();
+                })
+                .catch(e => {
+                    resolve({ Err: e });
+                    return cleanupPendingRepayment();
+                });
+        });
+    });
+
     log("Typescript event listeners initialized");
 }
 
 function addRpcMessageListener<T extends keyof EventListenersTypescript>(
     method: T,
-    callback: (args: ParametersObject<EventListenersTypescript[T]>) => Promise<RpcResponse<T>>,
+    callback: (args: ParametersObject<EventListenersTypescript[T]>, origin: string) => Promise<RpcResponse<T>>,
 ) {
-    browser.runtime.onMessage.addListener((msg: RpcMessage<T>) => {
+    browser.runtime.onMessage.addListener((msg: RpcMessage<T>, sender: Runtime.MessageSender) => {
         if (msg.type !== "rpc-message" || msg.method !== method) {
             return;
         }
 
         log(`Received: %o`, msg);
 
-        return callback(msg.args);
+        // Requests from pages without an origin cannot be attributed to a maker
+        const origin = sender.url ? new URL(sender.url).origin : "";
+
+        return callback(msg.args, origin);
     });
 }
 
@@ -78,13 +118,36 @@ function initializeWindowExtensions() {
     const windowExt: BackgroundWindowTypescript = {
         swapToSign: null,
         loanToSign: null,
+        repaymentToSign: null,
         approveSwap: async () => {
-            if (!resolveSwapSignRequest || !window.swapToSign) {
+            if (!window.swapToSign) {
+                throw new Error("No pending promise function for swap sign request");
+            }
+
+            const { txHex, signature, origin, batched } = window.swapToSign;
+            if (batched) {
+                if (!resolveSwapBatchSignRequest) {
+                    throw new Error("No pending promise function for swap batch sign request");
+                }
+
+                try {
+                    // Bobtimus broadcasts the batch once every taker signed it
+                    const tx = await window.signSwapBatch(txHex, signature, origin);
+                    resolveSwapBatchSignRequest({ Ok: tx });
+                } catch (e) {
+                    resolveSwapBatchSignRequest({ Err: e });
+                } finally {
+                    await cleanupPendingSwap();
+                }
+                return;
+            }
+
+            if (!resolveSwapSignRequest) {
                 throw new Error("No pending promise function for swap sign request");
             }
 
             try {
-                const txid = await window.signAndSendSwap(window.swapToSign.txHex);
+                const txid = await window.signAndSendSwap(txHex, signature, origin);
                 resolveSwapSignRequest({ Ok: txid });
             } catch (e) {
                 resolveSwapSignRequest({ Err: e });
@@ -93,11 +156,12 @@ function initializeWindowExtensions() {
             }
         },
         rejectSwap: () => {
-            if (!resolveSwapSignRequest) {
+            const resolve = window.swapToSign?.batched ? resolveSwapBatchSignRequest : resolveSwapSignRequest;
+            if (!resolve) {
                 throw new Error("No pending promise function for swap sign request");
             }
 
-            resolveSwapSignRequest({ Err: "User declined signing request" });
+            resolve({ Err: "User declined signing request" });
             return cleanupPendingSwap();
         },
         approveLoan: () => {
@@ -105,7 +169,7 @@ function initializeWindowExtensions() {
                 throw new Error("No pending promise function for loan sign request");
             }
 
-            return window.signLoan();
+            return window.signLoan(window.loanToSign.signature, window.loanToSign.origin);
         },
         rejectLoan: () => {
             if (!resolveLoanSignRequest) {
@@ -124,6 +188,29 @@ function initializeWindowExtensions() {
             resolveLoanSignRequest({ Ok: tx });
             await cleanupPendingLoan();
         },
+        approveRepayment: async () => {
+            if (!resolveRepaymentSignRequest || !window.repaymentToSign) {
+                throw new Error("No pending promise function for repayment sign request");
+            }
+
+            try {
+                // The lender adds its signature and broadcasts the transaction
+                const tx = await window.signEarlyRepayment(window.repaymentToSign.repayment);
+                resolveRepaymentSignRequest({ Ok: tx });
+            } catch (e) {
+                resolveRepaymentSignRequest({ Err: e });
+            } finally {
+                await cleanupPendingRepayment();
+            }
+        },
+        rejectRepayment: () => {
+            if (!resolveRepaymentSignRequest) {
+                throw new Error("No pending promise function for repayment sign request");
+            }
+
+            resolveRepaymentSignRequest({ Err: "User declined signing request" });
+            return cleanupPendingRepayment();
+        },
     };
 
     Object.assign(window, windowExt);
@@ -140,7 +227,9 @@ function initializeWasmModule() {
 
 // Private fields of the background script
 var resolveSwapSignRequest: ((response: RpcResponse<"requestSignSwap">) => void) | null;
+var resolveSwapBatchSignRequest: ((response: RpcResponse<"requestSignSwapBatch">) => void) | null;
 var resolveLoanSignRequest: ((response: RpcResponse<"requestSignLoan">) => void) | null;
+var resolveRepaymentSignRequest: ((response: RpcResponse<"requestSignRepayment">) => void) | null;
 
 // First we check environment variable. If set, we honor it and overwrite settings in local storage.
 // For the environment variable we add the prefix `REACT_APP_`.
@@ -159,6 +248,7 @@ function updateBadge() {
     let count = 0;
     if (window.loanToSign) count++;
     if (window.swapToSign) count++;
+    if (window.repaymentToSign) count++;
 
     return browser.browserAction.setBadgeText(
         { text: (count === 0 ? null : count.toString()) },
@@ -167,6 +257,7 @@ function updateBadge() {
 
 function cleanupPendingSwap() {
     resolveSwapSignRequest = null;
+    resolveSwapBatchSignRequest = null;
     window.swapToSign = null;
     return updateBadge();
 }
@@ -176,3 +267,9 @@ function cleanupPendingLoan() {
     window.loanToSign = null;
     return updateBadge();
 }
+
+function cleanupPendingRepayment() {
+    resolveRepaymentSignRequest = null;
+    window.repaymentToSign = null;
+    return updateBadge();
+}
@@ -0,0 +1,72 @@
+import { Box, Button, Heading, HStack, Text } from "@chakra-ui/react";
+import React from "react";
+import { useAsync } from "react-async";
+import { backgroundPage, RepaymentToSign, USDT_TICKER } from "../background/api";
+
+const USDT_SATS = 100000000;
+
+interface ConfirmRepaymentProps {
+    onCancel: () => void;
+    onSuccess: () => void;
+    repaymentToSign: RepaymentToSign;
+}
+
+export default function ConfirmRepayment(
+    { onCancel, onSuccess, repaymentToSign }: ConfirmRepaymentProps,
+) {
+    let { isPending, run } = useAsync({
+        deferFn: async () => {
+            const page = await backgroundPage();
+            await page.approveRepayment();
+
+            onSuccess();
+        },
+    });
+
+    const { details, repayment } = repaymentToSign;
+    const repaymentAmount = repayment.repayment_amount / USDT_SATS;
+
+    return (<Box>
+        <form
+            onSubmit={e => {
+                e.preventDefault();
+                run();
+            }}
+        >
+            <Heading>Confirm Repayment</Heading>
+            <HStack justify="space-between">
+                <Text>You repay now:</Text>
+                <Text>{repaymentAmount} {USDT_TICKER}</Text>
+            </HStack>
+            <HStack justify="space-between">
+                <Text>Instead of at maturity:</Text>
+                <Text>{details.principalRepayment} {details.principal.ticker}</Text>
+            </HStack>
+            <HStack justify="space-between">
+                <Text>You receive:</Text>
+                <Text>{details.collateral.amount} {details.collateral.ticker}</Text>
+            </HStack>
+
+            <Button
+                variant="secondary"
+                mr={3}
+                onClick={async () => {
+                    const page = await backgroundPage();
+                    await page.rejectRepayment();
+
+                    onCancel();
+                }}
+            >
+                Cancel
+            </Button>
+            <Button
+                type="submit"
+                variant="primary"
+                isLoading={isPending}
+                data-cy="data-cy-sign-repayment-button"
+            >
+                Sign
+            </Button>
+        </form>
+    </Box>);
+}
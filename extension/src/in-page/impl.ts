@@ -1,7 +1,14 @@
 import debug from "debug";
 import { AsyncReturnType } from "type-fest";
 import { v4 } from "uuid";
-import { CreateSwapPayload, LoanRequestPayload, Wallet, WalletStatus } from "../background/api";
+import {
+    CreateSwapPayload,
+    LoanRequestPayload,
+    MakerSignature,
+    RepaymentResponse,
+    Wallet,
+    WalletStatus,
+} from "../background/api";
 import { RpcRequest, RpcResponse } from "../contentScript";
 import { ParametersObject } from "../type-utils";
 
@@ -23,11 +30,17 @@ export class InvokeEventListenerViaContentScript implements Wallet {
     makeLoanRequestPayload(collateral: string, fee_rate: string): Promise<LoanRequestPayload> {
         return invokeContentScript("makeLoanRequestPayload", { collateral, fee_rate });
     }
-    requestSignSwap(hex: string): Promise<string> {
-        return invokeContentScript("requestSignSwap", { hex });
+    requestSignSwap(hex: string, signature: MakerSignature): Promise<string> {
+        return invokeContentScript("requestSignSwap", { hex, signature });
     }
-    requestSignLoan(loanRequest: LoanRequestPayload): Promise<string> {
-        return invokeContentScript("requestSignLoan", { loanRequest });
+    requestSignSwapBatch(hex: string, signature: MakerSignature): Promise<string> {
+        return invokeContentScript("requestSignSwapBatch", { hex, signature });
+    }
+    requestSignLoan(loanRequest: LoanRequestPayload, signature: MakerSignature): Promise<string> {
+        return invokeContentScript("requestSignLoan", { loanRequest, signature });
+    }
+    requestSignRepayment(repayment: RepaymentResponse): Promise<string> {
+        return invokeContentScript("requestSignRepayment", { repayment });
     }
 }
 
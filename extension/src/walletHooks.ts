@@ -1,5 +1,13 @@
 import { useAsync } from "react-async";
-import { backgroundPage, BalanceEntry, LoanDetails, LoanToSign, Trade, WalletStatus } from "./background/api";
+import {
+    backgroundPage,
+    BalanceEntry,
+    LoanDetails,
+    LoanToSign,
+    RepaymentToSign,
+    Trade,
+    WalletStatus,
+} from "./background/api";
 
 export function useWalletStatus() {
     return useAsync({ promiseFn: getWalletStatus });
@@ -17,6 +25,10 @@ export function useLoanToSign() {
     return useAsync({ promiseFn: getLoanToSign });
 }
 
+export function useRepaymentToSign() {
+    return useAsync({ promiseFn: getRepaymentToSign });
+}
+
 export function useOpenLoans() {
     return useAsync({ promiseFn: getOpenLoans });
 }
@@ -57,6 +69,12 @@ async function getLoanToSign(): Promise<LoanToSign | null> {
     return page.loanToSign;
 }
 
+async function getRepaymentToSign(): Promise<RepaymentToSign | null> {
+    const page = await backgroundPage();
+
+    return page.repaymentToSign;
+}
+
 async function getOpenLoans(): Promise<LoanDetails[]> {
     const page = await backgroundPage();
 
@@ -70,6 +70,42 @@ pub async fn fetch_transaction(txid: Txid) -> Result<Transaction> {
     Ok(deserialize(&hex::decode(body.as_bytes())?)?)
 }
 
+/// Fetch the status of a transaction, `None` if esplora does not know it.
+///
+/// The status changes once the transaction is mined, as such this
+/// function never uses a cache.
+pub async fn fetch_transaction_status(txid: Txid) -> Result<Option<UtxoStatus>> {
+    let esplora_url = {
+        let guard = ESPLORA_API_URL.lock().expect_throw("can get lock");
+        guard.clone()
+    };
+
+    let path = format!("tx/{}/status", txid);
+    let esplora_url = esplora_url.join(path.as_str())?;
+    let response = reqwest::get(esplora_url.clone())
+        .await
+        .context("failed to fetch transaction status")?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    if !response.status().is_success() {
+        let error_body = response.text().await?;
+        return Err(anyhow!(
+            "failed to fetch transaction status, esplora returned '{}'",
+            error_body
+        ));
+    }
+
+    let status = response
+        .json::<UtxoStatus>()
+        .await
+        .context("failed to deserialize response")?;
+
+    Ok(Some(status))
+}
+
 pub async fn broadcast(tx: Transaction) -> Result<Txid> {
     let esplora_url = {
         let guard = ESPLORA_API_URL.lock().expect_throw("can get lock");
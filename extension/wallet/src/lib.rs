@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use baru::loan::LoanResponse;
 use bip32::{Language, Mnemonic};
+use bobtimus_client::MakerSignature;
 use conquer_once::Lazy;
 use elements::{bitcoin::util::amount::Amount, encode::serialize_hex, Address, AddressParams};
 use futures::{lock::Mutex, TryFutureExt};
@@ -166,18 +167,45 @@ pub fn initialize() {
     );
     impl_window!(
         window,
-        async fn signAndSendSwap(hex: String) -> Result<elements::TxId> {
+        async fn signAndSendSwap(
+            hex: String,
+            signature: MakerSignature,
+            origin: String,
+        ) -> Result<elements::TxId> {
             let transaction = deserialize_hex::<elements::Transaction>(&hex)?;
             let txid = wallet::sign_and_send_swap_transaction(
                 "demo".to_owned(),
                 &LOADED_WALLET,
                 transaction,
+                signature,
+                &origin,
             )
             .await?;
 
             Ok(txid)
         }
     );
+    impl_window!(
+        window,
+        async fn signSwapBatch(
+            hex: String,
+            signature: MakerSignature,
+            origin: String,
+        ) -> Result<String> {
+            let transaction = deserialize_hex::<elements::Transaction>(&hex)?;
+            let transaction = wallet::sign_swap_batch(
+                "demo".to_owned(),
+                &LOADED_WALLET,
+                transaction,
+                signature,
+                &origin,
+            )
+            .await?;
+            let hex = serialize_hex(&transaction);
+
+            Ok(hex)
+        }
+    );
     impl_window!(
         window,
         async fn unlockWallet(password: String) -> Result<()> {
@@ -232,6 +260,16 @@ pub fn initialize() {
             Ok(repay_txid)
         }
     );
+    impl_window!(
+        window,
+        async fn signEarlyRepayment(response: RepaymentResponse) -> Result<String> {
+            let transaction =
+                wallet::sign_early_repayment("demo".to_owned(), &LOADED_WALLET, response).await?;
+            let hex = serialize_hex(&transaction);
+
+            Ok(hex)
+        }
+    );
     impl_window!(
         window,
         async fn getAddress() -> Result<elements::Address> {
@@ -242,8 +280,9 @@ pub fn initialize() {
     );
     impl_window!(
         window,
-        async fn signLoan() -> Result<String> {
-            let transaction = wallet::sign_loan("demo".to_owned(), &LOADED_WALLET).await?;
+        async fn signLoan(signature: MakerSignature, origin: String) -> Result<String> {
+            let transaction =
+                wallet::sign_loan("demo".to_owned(), &LOADED_WALLET, signature, &origin).await?;
             let hex = serialize_hex(&transaction);
 
             Ok(hex)
@@ -252,6 +291,7 @@ pub fn initialize() {
     impl_window!(
         window,
         async fn getOpenLoans() -> Result<Vec<LoanDetails>> {
+            wallet::settle_pending_repayments().await?;
             let loans = Storage::local_storage()?.get_open_loans().await?;
 
             Ok(loans)
@@ -41,7 +41,7 @@ macro_rules! replace_expr {
 
 #[macro_export]
 macro_rules! impl_window {
-    ($window: ident, async fn $fn_name:ident( $( $arg_name: ident: $arg_type: ty ),* ) -> $return_type: ty $body: block) => {{
+    ($window: ident, async fn $fn_name:ident( $( $arg_name: ident: $arg_type: ty ),* $(,)* ) -> $return_type: ty $body: block) => {{
         let handler = Closure::wrap(Box::new(|$($arg_name: JsValue),*| {
             let future = async move {
                 $( let $arg_name = $arg_name.into_serde::<$arg_type>().map_err(|_| anyhow::anyhow!("wrong type"))?; )*
@@ -17,7 +17,7 @@ use elements::{
     },
     confidential,
     secp256k1_zkp::{rand, PublicKey},
-    Address, AssetId, OutPoint, TxOut, Txid,
+    Address, AssetId, TxOut, Txid,
 };
 use futures::{
     lock::{MappedMutexGuard, Mutex, MutexGuard},
@@ -39,6 +39,7 @@ use std::{
 use wasm_bindgen::UnwrapThrowExt;
 
 use bip32::{ExtendedPrivateKey, Prefix};
+pub use bobtimus_client::{AliceInput, CreateSwapPayload};
 pub use create_new::{bip39_seed_words, create_from_bip39};
 pub use extract_loan::{extract_loan, Error as ExtractLoanError};
 pub use extract_trade::{extract_trade, Trade};
@@ -49,8 +50,10 @@ pub use load_existing::load_existing;
 pub use loan_backup::{create_loan_backup, load_loan_backup, BackupDetails};
 pub use make_create_swap_payload::{make_buy_create_swap_payload, make_sell_create_swap_payload};
 pub use make_loan_request::make_loan_request;
+pub use pending_repayment::settle_pending_repayments;
 pub use repay_loan::repay_loan;
-pub(crate) use sign_and_send_swap_transaction::sign_and_send_swap_transaction;
+pub use repay_loan_early::{sign_early_repayment, RepaymentResponse};
+pub(crate) use sign_and_send_swap_transaction::{sign_and_send_swap_transaction, sign_swap_batch};
 pub(crate) use sign_loan::sign_loan;
 use std::str::FromStr;
 pub use withdraw_everything_to::withdraw_everything_to;
@@ -65,7 +68,10 @@ mod load_existing;
 mod loan_backup;
 mod make_create_swap_payload;
 mod make_loan_request;
+mod maker_identity;
+mod pending_repayment;
 mod repay_loan;
+mod repay_loan_early;
 mod sign_and_send_swap_transaction;
 mod sign_loan;
 mod withdraw_everything_to;
@@ -301,21 +307,6 @@ impl fmt::Display for ListOfWallets {
     }
 }
 
-/// Represents the payload for creating a swap.
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-pub struct CreateSwapPayload {
-    pub alice_inputs: Vec<SwapUtxo>,
-    pub address: Address,
-    #[serde(with = "elements::bitcoin::util::amount::serde::as_sat")]
-    pub amount: elements::bitcoin::Amount,
-}
-
-#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
-pub struct SwapUtxo {
-    pub outpoint: OutPoint,
-    pub blinding_key: SecretKey,
-}
-
 /// A single balance entry as returned by [`get_balances`].
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct BalanceEntry {
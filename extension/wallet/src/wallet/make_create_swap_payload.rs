@@ -1,5 +1,5 @@
 use crate::{
-    wallet::{current, get_txouts, CreateSwapPayload, SwapUtxo, Wallet},
+    wallet::{current, get_txouts, AliceInput, CreateSwapPayload, Wallet},
     BTC_ASSET_ID, USDT_ASSET_ID,
 };
 use anyhow::{Context, Result};
@@ -7,6 +7,7 @@ use coin_selection::{self, coin_select};
 use elements::{bitcoin::Amount, secp256k1_zkp::SECP256K1, AssetId, OutPoint};
 use estimate_transaction_size::avg_vbytes;
 use futures::lock::Mutex;
+use std::collections::HashMap;
 use wasm_bindgen::UnwrapThrowExt;
 
 pub async fn make_buy_create_swap_payload(
@@ -72,12 +73,14 @@ async fn make_create_swap_payload(
             let candidate_asset = unblinded_txout.asset;
 
             if candidate_asset == sell_asset {
-                Some(coin_selection::Utxo {
+                let utxo = coin_selection::Utxo {
                     outpoint,
                     value: unblinded_txout.value,
                     script_pubkey: txout.script_pubkey,
                     asset: candidate_asset,
-                })
+                };
+
+                Some((utxo, unblinded_txout))
             } else {
                 log::debug!(
                     "utxo {} with asset id {} is not the sell asset, ignoring",
@@ -90,6 +93,13 @@ async fn make_create_swap_payload(
     })
     .await
     .context("Failed to get UTXOs")?;
+    let (utxos, secrets) = utxos
+        .into_iter()
+        .map(|(utxo, secrets)| {
+            let outpoint = utxo.outpoint;
+            (utxo, (outpoint, secrets))
+        })
+        .unzip::<_, _, Vec<_>, HashMap<_, _>>();
 
     let (bobs_fee_rate, fee_offset) = if fee_asset == sell_asset {
         // Bob currently hardcodes a fee-rate of 1 sat / vbyte, hence
@@ -112,17 +122,25 @@ async fn make_create_swap_payload(
     )
     .context("Failed to select UTXOs")?;
 
+    // Only the secrets of the selected outputs are disclosed, the
+    // blinding key would unblind everything we ever received
+    let alice_inputs = output
+        .coins
+        .into_iter()
+        .map(|utxo| {
+            let secrets = secrets
+                .get(&utxo.outpoint)
+                .copied()
+                .context("selected UTXO was not a candidate")?;
+
+            Ok(AliceInput::with_secrets(utxo.outpoint, secrets))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
     Ok(CreateSwapPayload {
         address: wallet.get_address(),
-        alice_inputs: output
-            .coins
-            .into_iter()
-            .map(|utxo| SwapUtxo {
-                outpoint: utxo.outpoint,
-                blinding_key,
-            })
-            .collect(),
-        amount: output.target_amount,
+        alice_inputs,
+        amount: output.target_amount.as_sat(),
     })
 }
 
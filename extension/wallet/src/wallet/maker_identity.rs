@@ -0,0 +1,76 @@
+use crate::storage::Storage;
+use anyhow::{bail, Context, Result};
+use bobtimus_client::{MakerSignature, Receipt, Statement};
+use elements::bitcoin::PublicKey;
+use std::collections::HashMap;
+
+const MAKER_IDENTITIES_KEY: &str = "maker_identities";
+const RECEIPTS_KEY: &str = "receipts";
+
+/// Verify that Bobtimus signed the statement with the identity it used
+/// before on the page the request came from.
+///
+/// The first identity seen on a page is pinned for it, every later
+/// request from that page has to be signed with the same key.
+pub(crate) fn verify_maker_signature(
+    origin: &str,
+    signature: MakerSignature,
+    statement: Statement,
+) -> Result<Receipt> {
+    if origin.is_empty() {
+        bail!("Request does not come from a page whose maker could be pinned");
+    }
+
+    let receipt = signature.receipt(statement);
+    receipt
+        .verify()
+        .with_context(|| format!("Invalid signature over \"{}\"", statement))?;
+
+    let storage = Storage::local_storage()?;
+    let mut identities = match storage.get_item::<String>(MAKER_IDENTITIES_KEY)? {
+        Some(identities) => serde_json::from_str::<HashMap<String, PublicKey>>(&identities)
+            .context("Failed to deserialize maker identities")?,
+        None => HashMap::new(),
+    };
+
+    match identities.get(origin) {
+        Some(pinned) if *pinned == receipt.maker => {}
+        Some(pinned) => bail!(
+            "Maker at {} signed with {} instead of its known identity {}",
+            origin,
+            receipt.maker,
+            pinned
+        ),
+        None => {
+            log::info!("Pinning identity {} for maker at {}", receipt.maker, origin);
+            identities.insert(origin.to_owned(), receipt.maker);
+            storage.set_item(
+                MAKER_IDENTITIES_KEY,
+                serde_json::to_string(&identities)
+                    .context("Failed to serialize maker identities")?,
+            )?;
+        }
+    }
+
+    Ok(receipt)
+}
+
+/// Keep the receipt as record of what was agreed with the maker.
+pub(crate) fn store_receipt(receipt: &Receipt) -> Result<()> {
+    let storage = Storage::local_storage()?;
+    let mut receipts = match storage.get_item::<String>(RECEIPTS_KEY)? {
+        Some(receipts) => serde_json::from_str::<Vec<Receipt>>(&receipts)
+            .context("Failed to deserialize receipts")?,
+        None => Vec::new(),
+    };
+
+    if !receipts.contains(receipt) {
+        receipts.push(receipt.clone());
+        storage.set_item(
+            RECEIPTS_KEY,
+            serde_json::to_string(&receipts).context("Failed to serialize receipts")?,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use crate::{esplora::fetch_transaction_status, storage::Storage};
+use anyhow::{Context, Result};
+use elements::Txid;
+use serde::{Deserialize, Serialize};
+
+const PENDING_REPAYMENTS_KEY: &str = "pending_repayments";
+
+/// An early repayment of an open loan, which we signed but the lender
+/// has not broadcast yet.
+///
+/// The loan stays in the list of open loans until the repayment
+/// transaction is confirmed, so that it can still be repaid at maturity
+/// if the lender never broadcasts the transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PendingRepayment {
+    pub loan_txid: Txid,
+    pub txid: Txid,
+}
+
+/// Remember an early repayment until it is confirmed.
+pub(crate) fn add_pending_repayment(storage: &Storage, repayment: PendingRepayment) -> Result<()> {
+    let mut repayments = pending_repayments(storage)?;
+    repayments.push(repayment);
+
+    storage.set_item(
+        PENDING_REPAYMENTS_KEY,
+        serde_json::to_string(&repayments).context("Failed to serialize pending repayments")?,
+    )?;
+
+    Ok(())
+}
+
+/// Remove the open loans whose early repayment is confirmed.
+///
+/// Repayments of loans which are no longer open, e.g. because the
+/// borrower repaid the loan at maturity or another repayment was
+/// confirmed, are dropped.
+pub async fn settle_pending_repayments() -> Result<()> {
+    let storage = Storage::local_storage()?;
+
+    let mut pending = Vec::new();
+    for repayment in pending_repayments(&storage)? {
+        let open_loans = storage.get_open_loans().await?;
+        if open_loans
+            .iter()
+            .all(|details| details.txid != repayment.loan_txid)
+        {
+            log::debug!(
+                "Dropping repayment {} of loan {} which is no longer open",
+                repayment.txid,
+                repayment.loan_txid
+            );
+            continue;
+        }
+
+        let is_confirmed = fetch_transaction_status(repayment.txid)
+            .await?
+            .map(|status| status.confirmed)
+            .unwrap_or(false);
+        if !is_confirmed {
+            pending.push(repayment);
+            continue;
+        }
+
+        let open_loans = open_loans
+            .into_iter()
+            .filter(|details| details.txid != repayment.loan_txid)
+            .collect::<Vec<_>>();
+        storage.set_item(
+            "open_loans",
+            serde_json::to_string(&open_loans).context("Failed to serialize open loans")?,
+        )?;
+        storage.remove_item(&format!("loan_state:{}", repayment.loan_txid))?;
+
+        log::debug!(
+            "Loan {} was repaid by {}",
+            repayment.loan_txid,
+            repayment.txid
+        );
+    }
+
+    storage.set_item(
+        PENDING_REPAYMENTS_KEY,
+        serde_json::to_string(&pending).context("Failed to serialize pending repayments")?,
+    )?;
+
+    Ok(())
+}
+
+fn pending_repayments(storage: &Storage) -> Result<Vec<PendingRepayment>> {
+    let repayments = match storage.get_item::<String>(PENDING_REPAYMENTS_KEY)? {
+        Some(repayments) => {
+            serde_json::from_str(&repayments).context("Failed to deserialize pending repayments")?
+        }
+        None => Vec::new(),
+    };
+
+    Ok(repayments)
+}
@@ -0,0 +1,219 @@
+use crate::{
+    storage::Storage,
+    wallet::{
+        current, get_txouts,
+        pending_repayment::{add_pending_repayment, PendingRepayment},
+        sign_loan::sign_wallet_inputs,
+        Wallet,
+    },
+    DEFAULT_SAT_PER_VBYTE, USDT_ASSET_ID,
+};
+use anyhow::{bail, Context, Result};
+use baru::{input::Input, loan::Borrower1};
+use coin_selection::{self, coin_select};
+use elements::{
+    bitcoin::util::amount::Amount, secp256k1_zkp::SECP256K1, OutPoint, Transaction, Txid,
+};
+use futures::lock::Mutex;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::UnwrapThrowExt;
+
+/// The lender's part of an early repayment of an open loan in full.
+///
+/// The collateral contract only releases the collateral against the
+/// repayment amount fixed when the loan was taken out. The lender
+/// refunds the interest which has not accrued yet through an input we
+/// spend in the repayment transaction, so that we only pay the quoted
+/// repayment amount ourselves.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepaymentResponse {
+    pub loan_txid: Txid,
+    /// The principal plus the pro-rated interest
+    #[serde(with = "elements::bitcoin::util::amount::serde::as_sat")]
+    pub repayment_amount: Amount,
+    /// The lender's input worth the refund, `None` if nothing is refunded
+    pub refund_input: Option<Input>,
+}
+
+/// Select L-USDt inputs worth at least `amount` from the wallet.
+async fn select_usdt_inputs(
+    name: &str,
+    current_wallet: &Mutex<Option<Wallet>>,
+    amount: Amount,
+) -> Result<Vec<Input>> {
+    if amount == Amount::ZERO {
+        return Ok(Vec::new());
+    }
+
+    let usdt_asset_id = {
+        let guard = USDT_ASSET_ID.lock().expect_throw("can get lock");
+        *guard
+    };
+
+    let wallet = current(name, current_wallet).await?;
+    let blinding_key = wallet.blinding_key();
+
+    let utxos = get_txouts(&wallet, |utxo, txout| {
+        Ok({
+            let unblinded_txout = txout.unblind(SECP256K1, blinding_key)?;
+            let outpoint = OutPoint {
+                txid: utxo.txid,
+                vout: utxo.vout,
+            };
+            let candidate_asset = unblinded_txout.asset;
+
+            if candidate_asset == usdt_asset_id {
+                Some((
+                    coin_selection::Utxo {
+                        outpoint,
+                        value: unblinded_txout.value,
+                        script_pubkey: txout.script_pubkey.clone(),
+                        asset: candidate_asset,
+                    },
+                    txout,
+                ))
+            } else {
+                log::debug!(
+                    "utxo {} with asset id {} is not L-USDt, ignoring",
+                    outpoint,
+                    candidate_asset
+                );
+                None
+            }
+        })
+    })
+    .await
+    .context("Failed to get UTXOs")?;
+
+    // We are selecting coins with an asset which cannot be used to pay
+    // for fees. The fee is paid with the collateral
+    let zero_fee_rate = 0f32;
+    let zero_fee_offset = Amount::ZERO;
+
+    let output = coin_select(
+        utxos.iter().map(|(utxo, _)| utxo).cloned().collect(),
+        amount,
+        zero_fee_rate,
+        zero_fee_offset,
+    )
+    .context("Failed to select UTXOs")?;
+
+    let inputs = output
+        .coins
+        .iter()
+        .map(|coin| {
+            let original_txout = utxos
+                .iter()
+                .find_map(|(utxo, txout)| (utxo.outpoint == coin.outpoint).then(|| txout))
+                .expect("same source of utxos")
+                .clone();
+
+            Input {
+                txin: coin.outpoint,
+                original_txout,
+                blinding_key,
+            }
+        })
+        .collect();
+
+    Ok(inputs)
+}
+
+/// Build and sign the transaction repaying an open loan in full before
+/// the end of its term.
+///
+/// The lender's refund input is spent together with our L-USDt, which
+/// only has to cover the quoted repayment amount. We sign our inputs
+/// and leave the refund input to the lender, who broadcasts the
+/// transaction. The loan is only removed from the list of open loans
+/// once the transaction is confirmed.
+pub async fn sign_early_repayment(
+    name: String,
+    current_wallet: &Mutex<Option<Wallet>>,
+    repayment: RepaymentResponse,
+) -> Result<Transaction> {
+    let RepaymentResponse {
+        loan_txid,
+        repayment_amount,
+        refund_input,
+    } = repayment;
+
+    let storage = Storage::local_storage()?;
+
+    let borrower = storage
+        .get_item::<String>(&format!("loan_state:{}", loan_txid))?
+        .with_context(|| format!("No open loan with txid {}", loan_txid))?;
+    let borrower = serde_json::from_str::<Borrower1>(&borrower)
+        .context("Failed to deserialize `Borrower1`")?;
+
+    let usdt_asset_id = {
+        let guard = USDT_ASSET_ID.lock().expect_throw("can get lock");
+        *guard
+    };
+
+    let coin_selector = {
+        let name = name.clone();
+        |amount: Amount, asset| async move {
+            if asset != usdt_asset_id {
+                bail!("Loan is not repaid in L-USDt")
+            }
+
+            let refund_amount = match &refund_input {
+                Some(input) => {
+                    let refund = input
+                        .original_txout
+                        .unblind(SECP256K1, input.blinding_key)
+                        .context("Failed to unblind the lender's refund input")?;
+                    if refund.asset != usdt_asset_id {
+                        bail!("Lender's refund input is not L-USDt")
+                    }
+
+                    Amount::from_sat(refund.value)
+                }
+                None => Amount::ZERO,
+            };
+
+            // The lender's refund has to cover everything but the quoted
+            // repayment amount
+            let own_amount = amount
+                .checked_sub(refund_amount)
+                .context("Lender's refund exceeds the repayment amount")?;
+            if own_amount != repayment_amount {
+                bail!(
+                    "Lender's refund leaves {} to be paid instead of the quoted {}",
+                    own_amount,
+                    repayment_amount
+                )
+            }
+
+            let mut inputs = select_usdt_inputs(&name, current_wallet, own_amount).await?;
+            inputs.extend(refund_input);
+
+            Ok(inputs)
+        }
+    };
+
+    let transaction = borrower
+        .loan_repayment_transaction(
+            &mut thread_rng(),
+            SECP256K1,
+            coin_selector,
+            |transaction| sign_wallet_inputs(&name, current_wallet, transaction),
+            Amount::from_sat(DEFAULT_SAT_PER_VBYTE),
+        )
+        .await
+        .context("Failed to build early repayment transaction")?;
+
+    // The loan is only forgotten once the repayment transaction is
+    // confirmed, until then we can still repay it at maturity
+    add_pending_repayment(
+        &storage,
+        PendingRepayment {
+            loan_txid,
+            txid: transaction.txid(),
+        },
+    )?;
+
+    Ok(transaction)
+}
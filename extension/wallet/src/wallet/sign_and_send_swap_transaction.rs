@@ -1,9 +1,14 @@
 use crate::{
     esplora::broadcast,
-    wallet::{current, get_txouts, Wallet},
+    wallet::{
+        current, get_txouts,
+        maker_identity::{store_receipt, verify_maker_signature},
+        Wallet,
+    },
 };
 use anyhow::Result;
 use baru::swap::{alice_finalize_transaction, sign_with_key};
+use bobtimus_client::{MakerSignature, Statement};
 use elements::{secp256k1_zkp::SECP256K1, sighash::SigHashCache, Transaction, Txid};
 use futures::lock::Mutex;
 
@@ -11,7 +16,59 @@ pub(crate) async fn sign_and_send_swap_transaction(
     name: String,
     current_wallet: &Mutex<Option<Wallet>>,
     transaction: Transaction,
+    signature: MakerSignature,
+    origin: &str,
 ) -> Result<Txid, Error> {
+    let receipt = verify_maker_signature(
+        origin,
+        signature,
+        Statement::Swap {
+            txid: transaction.txid(),
+        },
+    )
+    .map_err(Error::VerifyMaker)?;
+
+    let transaction = sign_swap_transaction(name, current_wallet, transaction).await?;
+
+    let txid = broadcast(transaction).await.map_err(Error::Send)?;
+
+    // The swap happened either way
+    if let Err(e) = store_receipt(&receipt) {
+        log::warn!("Failed to store receipt of swap {}: {:#}", txid, e);
+    }
+
+    Ok(txid)
+}
+
+/// Sign our inputs of a swap batch, for the maker to broadcast it once
+/// every taker of the batch signed.
+///
+/// No receipt is stored, as the batch is abandoned if another taker
+/// does not sign it.
+pub(crate) async fn sign_swap_batch(
+    name: String,
+    current_wallet: &Mutex<Option<Wallet>>,
+    transaction: Transaction,
+    signature: MakerSignature,
+    origin: &str,
+) -> Result<Transaction, Error> {
+    verify_maker_signature(
+        origin,
+        signature,
+        Statement::Swap {
+            txid: transaction.txid(),
+        },
+    )
+    .map_err(Error::VerifyMaker)?;
+
+    sign_swap_transaction(name, current_wallet, transaction).await
+}
+
+async fn sign_swap_transaction(
+    name: String,
+    current_wallet: &Mutex<Option<Wallet>>,
+    transaction: Transaction,
+) -> Result<Transaction, Error> {
     let wallet = current(&name, current_wallet)
         .await
         .map_err(Error::LoadWallet)?;
@@ -20,7 +77,7 @@ pub(crate) async fn sign_and_send_swap_transaction(
         .await
         .map_err(Error::GetTxOuts)?;
 
-    let transaction = alice_finalize_transaction(transaction, |mut transaction| async {
+    alice_finalize_transaction(transaction, |mut transaction| async {
         let mut cache = SigHashCache::new(&transaction);
 
         let witnesses = transaction
@@ -57,15 +114,13 @@ pub(crate) async fn sign_and_send_swap_transaction(
         Ok(transaction)
     })
     .await
-    .map_err(Error::Sign)?;
-
-    let txid = broadcast(transaction).await.map_err(Error::Send)?;
-
-    Ok(txid)
+    .map_err(Error::Sign)
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Swap is not signed by the maker: {0:#}")]
+    VerifyMaker(anyhow::Error),
     #[error("Wallet is not loaded: {0}")]
     LoadWallet(anyhow::Error),
     #[error("Failed to get transaction outputs: {0}")]
@@ -1,16 +1,23 @@
 use crate::{
     storage::Storage,
-    wallet::{current, get_txouts, LoanDetails},
+    wallet::{
+        current, get_txouts,
+        maker_identity::{store_receipt, verify_maker_signature},
+        LoanDetails,
+    },
     Wallet,
 };
 use anyhow::{Context, Result};
 use baru::{loan::Borrower1, swap::sign_with_key};
+use bobtimus_client::{MakerSignature, Statement};
 use elements::{secp256k1_zkp::SECP256K1, sighash::SigHashCache, Transaction};
 use futures::lock::Mutex;
 
 pub(crate) async fn sign_loan(
     name: String,
     current_wallet: &Mutex<Option<Wallet>>,
+    signature: MakerSignature,
+    origin: &str,
 ) -> Result<Transaction> {
     let storage = Storage::local_storage()?;
     // load temporary loan_borrower state. When the frontend _asks_ the extension to
@@ -22,6 +29,18 @@ pub(crate) async fn sign_loan(
 
     let loan_transaction = sign_transaction(&name, current_wallet, &borrower).await?;
 
+    // Our signatures do not change the txid, which is what the maker
+    // signed
+    let receipt = verify_maker_signature(
+        origin,
+        signature,
+        Statement::Loan {
+            txid: loan_transaction.txid(),
+        },
+    )
+    .context("Loan is not signed by the maker")?;
+    store_receipt(&receipt)?;
+
     // We don't broadcast this transaction ourselves, but we expect
     // the lender to do so very soon. We therefore save the borrower
     // state so that we can later on build, sign and broadcast the
@@ -40,59 +59,66 @@ fn load_borrower_state(storage: &Storage) -> Result<(Borrower1, LoanDetails)> {
     Ok((borrower, loan_details))
 }
 
-async fn sign_transaction(
+pub(crate) async fn sign_transaction(
     name: &str,
     current_wallet: &Mutex<Option<Wallet>>,
     borrower: &Borrower1,
 ) -> Result<Transaction> {
     let loan_transaction = borrower
-        .sign(|mut transaction| async {
-            let wallet = current(name, current_wallet).await?;
-            let txouts = get_txouts(&wallet, |utxo, txout| Ok(Some((utxo, txout)))).await?;
-
-            let mut cache = SigHashCache::new(&transaction);
-            let witnesses = transaction
-                .clone()
-                .input
+        .sign(|transaction| sign_wallet_inputs(name, current_wallet, transaction))
+        .await
+        .context("Failed to sign transaction")?;
+
+    Ok(loan_transaction)
+}
+
+/// Sign all inputs of the transaction which belong to the wallet.
+pub(crate) async fn sign_wallet_inputs(
+    name: &str,
+    current_wallet: &Mutex<Option<Wallet>>,
+    mut transaction: Transaction,
+) -> Result<Transaction> {
+    let wallet = current(name, current_wallet).await?;
+    let txouts = get_txouts(&wallet, |utxo, txout| Ok(Some((utxo, txout)))).await?;
+
+    let mut cache = SigHashCache::new(&transaction);
+    let witnesses = transaction
+        .clone()
+        .input
+        .iter()
+        .enumerate()
+        .filter_map(|(index, input)| {
+            txouts
                 .iter()
-                .enumerate()
-                .filter_map(|(index, input)| {
-                    txouts
-                        .iter()
-                        .find(|(utxo, _)| {
-                            utxo.txid == input.previous_output.txid
-                                && utxo.vout == input.previous_output.vout
-                        })
-                        .map(|(_, txout)| (index, txout))
-                })
-                .map(|(index, output)| {
-                    // TODO: It is convenient to use this import, but
-                    // it is weird to use an API from the swap library
-                    // here. Maybe we should move it to a common
-                    // place, so it can be used for different
-                    // protocols
-                    let script_witness = sign_with_key(
-                        SECP256K1,
-                        &mut cache,
-                        index,
-                        &wallet.secret_key,
-                        output.value,
-                    );
-
-                    (index, script_witness)
+                .find(|(utxo, _)| {
+                    utxo.txid == input.previous_output.txid
+                        && utxo.vout == input.previous_output.vout
                 })
-                .collect::<Vec<_>>();
-
-            for (index, witness) in witnesses {
-                transaction.input[index].witness.script_witness = witness
-            }
+                .map(|(_, txout)| (index, txout))
+        })
+        .map(|(index, output)| {
+            // TODO: It is convenient to use this import, but
+            // it is weird to use an API from the swap library
+            // here. Maybe we should move it to a common
+            // place, so it can be used for different
+            // protocols
+            let script_witness = sign_with_key(
+                SECP256K1,
+                &mut cache,
+                index,
+                &wallet.secret_key,
+                output.value,
+            );
 
-            Ok(transaction)
+            (index, script_witness)
         })
-        .await
-        .context("Failed to sign transaction")?;
+        .collect::<Vec<_>>();
 
-    Ok(loan_transaction)
+    for (index, witness) in witnesses {
+        transaction.input[index].witness.script_witness = witness
+    }
+
+    Ok(transaction)
 }
 
 pub fn update_open_loans(
@@ -8,6 +8,7 @@ import "./App.css";
 import { fundAddress, LoanOffer } from "./Bobtimus";
 import Borrow from "./Borrow";
 import COMIT from "./components/comit_logo_spellout_opacity_50.svg";
+import Loans from "./Loans";
 import Trade from "./Trade";
 
 Debug.enable("*");
@@ -318,6 +319,7 @@ function App() {
                     <HStack spacing={4} as="nav">
                         <NavLink text="Trade" path={"/trade"} />
                         <NavLink text="Borrow" path={"/borrow"} />
+                        <NavLink text="Loans" path={"/loans"} />
                     </HStack>
                     <Divider />
                     <Switch>
@@ -339,6 +341,9 @@ function App() {
                                 wavesProvider={wavesProvider}
                             />
                         </Route>
+                        <Route path="/loans">
+                            <Loans wavesProvider={wavesProvider} />
+                        </Route>
                     </Switch>
                 </VStack>
             </Center>
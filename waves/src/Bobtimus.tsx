@@ -1,25 +1,217 @@
 import Debug from "debug";
 import React, { ReactElement } from "react";
 import { SSEProvider } from "react-hooks-sse";
-import { CreateSwapPayload, LoanRequestPayload, OutPoint } from "./waves-provider/wavesProvider";
+import {
+    CreateSwapPayload,
+    LoanRequestPayload,
+    MakerSignature,
+    OutPoint,
+    RepaymentResponse,
+    Txid,
+} from "./waves-provider/wavesProvider";
+
+// Every problem type URI of Bobtimus starts with this prefix, followed by the problem type
+export const PROBLEM_TYPE_BASE = "https://coblox.tech/bobtimus/problems/";
+
+export enum ProblemType {
+    PriceNotAcceptable = "price-not-acceptable",
+    UnknownOffer = "unknown-offer",
+    OfferExpired = "offer-expired",
+    PrincipalBelowMin = "principal-below-min",
+    PrincipalAboveMax = "principal-above-max",
+    LtvAboveMax = "ltv-above-max",
+    TermNotAllowed = "term-not-allowed",
+    CollateralizationBelowMin = "collateralization-below-min",
+    TotalPrincipalAboveMax = "total-principal-above-max",
+    BorrowerPrincipalAboveMax = "borrower-principal-above-max",
+    ReserveBelowMin = "reserve-below-min",
+    UnknownLoan = "unknown-loan",
+    InvalidAssetTypes = "invalid-asset-types",
+    InputAmountTooSmall = "input-amount-too-small",
+    ChangeAmountTooSmall = "change-amount-too-small",
+    DuplicateInput = "duplicate-input",
+    InputSpent = "input-spent",
+    InputUnconfirmed = "input-unconfirmed",
+    InputAlreadyReserved = "input-already-reserved",
+    InputSecretsMissing = "input-secrets-missing",
+    InputSecretsMismatch = "input-secrets-mismatch",
+    InvalidBody = "invalid-body",
+    InvalidPset = "invalid-pset",
+    InvalidIdempotencyKey = "invalid-idempotency-key",
+    IdempotencyKeyReused = "idempotency-key-reused",
+    PayloadTooLarge = "payload-too-large",
+    RateLimited = "rate-limited",
+    TooManyPendingNegotiations = "too-many-pending-negotiations",
+    TakerThrottled = "taker-throttled",
+    SwapBatchingDisabled = "swap-batching-disabled",
+    UnknownSwapBatch = "unknown-swap-batch",
+    SwapBatchAbandoned = "swap-batch-abandoned",
+    ServicePaused = "service-paused",
+    InternalError = "internal-error",
+}
+
+// Problem details as returned by Bobtimus for failed requests, see RFC 7807.
+//
+// Amounts in L-USDt in the extension fields are nominal, i.e. in dollars.
+export interface Problem {
+    type?: string;
+    title: string;
+    status?: number;
+    detail?: string;
+    [extension: string]: any;
+}
+
+export function problemType(problem: Problem): string | undefined {
+    if (!problem.type || !problem.type.startsWith(PROBLEM_TYPE_BASE)) {
+        return undefined;
+    }
+
+    return problem.type.substring(PROBLEM_TYPE_BASE.length);
+}
+
+// Whether the request may succeed if it is repeated with a fresh offer
+export function isRecoverable(problem: Problem): boolean {
+    switch (problemType(problem)) {
+        case ProblemType.PriceNotAcceptable:
+        case ProblemType.UnknownOffer:
+        case ProblemType.OfferExpired:
+            return true;
+        default:
+            return false;
+    }
+}
+
+// A message telling the user what to change for the request to succeed, falling back to the detail of the problem
+export function describeProblem(problem: Problem): string | undefined {
+    switch (problemType(problem)) {
+        case ProblemType.PriceNotAcceptable:
+            return `The price moved to ${problem.offer_price} L-USDt, please try again.`;
+        case ProblemType.UnknownOffer:
+        case ProblemType.OfferExpired:
+            return "The loan offer expired, please try again.";
+        case ProblemType.PrincipalBelowMin:
+            return `Please borrow at least ${problem.min_principal} L-USDt.`;
+        case ProblemType.PrincipalAboveMax:
+            return `Please borrow at most ${problem.max_principal} L-USDt.`;
+        case ProblemType.LtvAboveMax:
+        case ProblemType.CollateralizationBelowMin:
+            return "Please increase the collateralization of the loan.";
+        case ProblemType.TermNotAllowed:
+            return `Please choose a term between ${problem.min_term} and ${problem.max_term} days.`;
+        case ProblemType.TotalPrincipalAboveMax:
+        case ProblemType.BorrowerPrincipalAboveMax:
+        case ProblemType.ReserveBelowMin:
+            return "Bobtimus cannot lend this much right now, please borrow less.";
+        case ProblemType.InputSpent:
+        case ProblemType.InputAlreadyReserved:
+            return "Some of your coins are already being spent, please wait for your pending transactions to confirm.";
+        case ProblemType.InputUnconfirmed:
+            return "Some of your coins are not confirmed yet, please try again once they are.";
+        case ProblemType.InputSecretsMissing:
+        case ProblemType.InputSecretsMismatch:
+            return "Your wallet described its coins incorrectly, please update the extension.";
+        case ProblemType.RateLimited:
+            return `Too many requests, please try again in ${problem.retry_after} seconds.`;
+        case ProblemType.TakerThrottled:
+            return `Too many of your swaps were not broadcast, please try again in ${problem.retry_after} seconds.`;
+        case ProblemType.TooManyPendingNegotiations:
+            return "Bobtimus is busy, please try again later.";
+        case ProblemType.SwapBatchingDisabled:
+            return "Bobtimus does not batch swaps right now, please swap without batching.";
+        case ProblemType.SwapBatchAbandoned:
+            return "Not every trader signed the batched swap in time, please try again.";
+        case ProblemType.ServicePaused:
+            return "Bobtimus is paused for maintenance, please try again later.";
+        default:
+            return problem.detail;
+    }
+}
 
 export class LoanError extends Error {
     title: string;
     description?: string;
+    problem?: Problem;
 
-    constructor(title: string, description?: string) {
+    constructor(title: string, description?: string, problem?: Problem) {
         super(title);
         this.title = title;
         if (description) {
             this.description = description;
         }
+        if (problem) {
+            this.problem = problem;
+        }
 
         Object.setPrototypeOf(this, LoanError.prototype);
     }
+
+    static fromProblem(problem: Problem): LoanError {
+        return new LoanError(problem.title, describeProblem(problem), problem);
+    }
+
+    get recoverable(): boolean {
+        return this.problem ? isRecoverable(this.problem) : false;
+    }
 }
 
 const debug = Debug("bobtimus");
 const BTC_SATS = 100000000;
+// How often a swap or loan request is repeated if Bobtimus cannot be reached
+const MAX_ATTEMPTS = 3;
+
+// Identifies a swap or loan request across retries, so that Bobtimus returns the original response
+function newIdempotencyKey(): string {
+    const bytes = new Uint8Array(16);
+    window.crypto.getRandomValues(bytes);
+
+    return Array.from(bytes, (byte) => byte.toString(16).padStart(2, "0")).join("");
+}
+
+// Repeat the request with the same idempotency key if it fails before a response arrives
+async function postIdempotent(url: string, body: any): Promise<Response> {
+    const idempotencyKey = newIdempotencyKey();
+
+    for (let attempt = 1; ; attempt++) {
+        try {
+            return await fetch(url, {
+                method: "POST",
+                headers: {
+                    "Content-Type": "application/json",
+                    Accept: "application/json",
+                    "Idempotency-Key": idempotencyKey,
+                },
+                body: JSON.stringify(body),
+            });
+        } catch (e) {
+            if (attempt >= MAX_ATTEMPTS) {
+                throw e;
+            }
+            debug(`request to ${url} failed, retrying: ${e}`);
+        }
+    }
+}
+
+// The wallet only signs transactions which come with a signature of Bobtimus
+function readMakerSignature(res: Response): MakerSignature {
+    const maker = res.headers.get("Bobtimus-Identity");
+    const signature = res.headers.get("Bobtimus-Signature");
+
+    if (!maker || !signature) {
+        throw new Error("Response is not signed by Bobtimus");
+    }
+
+    return { maker, signature };
+}
+
+export interface SignedSwap {
+    txHex: string;
+    signature: MakerSignature;
+}
+
+export interface SignedLoanResponse {
+    loanResponse: any;
+    signature: MakerSignature;
+}
 
 export async function fundAddress(address: string): Promise<any> {
     await fetch("/api/faucet/" + address, {
@@ -27,14 +219,42 @@ export async function fundAddress(address: string): Promise<any> {
     });
 }
 
-export async function postSellPayload(payload: CreateSwapPayload) {
+export async function postSellPayload(payload: CreateSwapPayload): Promise<SignedSwap> {
     return await postPayload(payload, "sell");
 }
 
-export async function postBuyPayload(payload: CreateSwapPayload) {
+export async function postBuyPayload(payload: CreateSwapPayload): Promise<SignedSwap> {
     return await postPayload(payload, "buy");
 }
 
+// Joins the next batch of swaps, which only resolves once the batch transaction is built
+export async function postBatchedSellPayload(payload: CreateSwapPayload): Promise<SignedSwap> {
+    return await postPayload(payload, "sell/batch");
+}
+
+export async function postBatchedBuyPayload(payload: CreateSwapPayload): Promise<SignedSwap> {
+    return await postPayload(payload, "buy/batch");
+}
+
+export async function postSwapBatchSignature(txHex: string): Promise<Txid> {
+    let res = await fetch(`/api/swap/lbtc-lusdt/batch/sign`, {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+            Accept: "application/json",
+        },
+        body: JSON.stringify({ tx_hex: txHex }),
+    });
+
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to sign swap batch: " + JSON.stringify(problem));
+        throw new Error(describeProblem(problem) || problem.title);
+    }
+
+    return await res.json();
+}
+
 export interface Rate {
     ask: number; // sat
     bid: number; // sat
@@ -62,6 +282,10 @@ export interface Collateralization {
 }
 
 export interface LoanOffer {
+    id: string;
+    // unix timestamp in seconds after which the offer can no longer be taken
+    expires_at: number;
+    // the rate the loan request is validated against
     rate: Rate;
     fee_sats_per_vbyte: number;
     min_principal: number; // sat
@@ -75,6 +299,7 @@ export interface LoanOffer {
 }
 
 export interface LoanRequest {
+    offer_id: string;
     principal_amount: number;
     collateral_amount: number;
     collateral_inputs: { txin: OutPoint; original_txout: any; blinding_key: string }[];
@@ -96,24 +321,27 @@ export async function getLoanOffer(): Promise<LoanOffer> {
     });
 
     if (res.status !== 200) {
-        debug("failed to fetch loan offer");
-        throw new LoanError("failed to fetch loan offer");
+        const problem = await readProblem(res);
+        debug("failed to fetch loan offer: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
     }
 
     return await res.json();
 }
 
 export async function postLoanRequest(
+    offerId: string,
     walletParams: LoanRequestPayload,
     termInDays: number,
     collateralization: number,
     principal: number,
-) {
+): Promise<SignedLoanResponse> {
     // TODO: Make sure to convert all the other amounts to sats as well
     // convert principal to sats
     let principal_sats = principal * BTC_SATS;
 
     let loanRequest: LoanRequest = {
+        offer_id: offerId,
         collateralization: collateralization,
         principal_amount: principal_sats,
         borrower_address: walletParams.borrower_address,
@@ -123,67 +351,126 @@ export async function postLoanRequest(
         term: termInDays,
     };
 
-    let res = await fetch(`/api/loan/lbtc-lusdt`, {
+    let res = await postIdempotent(`/api/loan/lbtc-lusdt`, loanRequest);
+
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to create new loan: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
+    }
+
+    const signature = readMakerSignature(res);
+    const loanResponse = await res.json();
+
+    return { loanResponse, signature };
+}
+
+export async function postLoanFinalization(txHex: string) {
+    let res = await fetch(`/api/loan/lbtc-lusdt/finalize`, {
         method: "POST",
         headers: {
             "Content-Type": "application/json",
             Accept: "application/json",
         },
-        body: JSON.stringify(loanRequest),
+        body: JSON.stringify({ tx_hex: txHex }),
     });
 
-    if (res.status === 400) {
-        const resp = await res.json();
-        debug("received loan validation error: " + resp.title + resp.detail);
-        throw new LoanError(resp.title, resp.detail);
-    } else if (res.status !== 200) {
-        debug("failed to create new loan");
-        const resp = await res.json();
-        throw new LoanError("Failed to create new loan", resp.detail);
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to create new loan: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
     }
 
     return await res.json();
 }
 
-export async function postLoanFinalization(txHex: string) {
-    let res = await fetch(`/api/loan/lbtc-lusdt/finalize`, {
+// The amounts of repaying an open loan early, in full, in satodollars
+export interface RepaymentQuote {
+    loan_txid: Txid;
+    repayment_amount: number;
+    refund_amount: number;
+}
+
+export async function getRepaymentQuote(loanTxid: Txid): Promise<RepaymentQuote> {
+    let res = await fetch(`/api/loan/lbtc-lusdt/${loanTxid}/repayment`, {
+        method: "GET",
+        headers: {
+            "Content-Type": "application/json",
+            Accept: "application/json",
+        },
+    });
+
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to fetch repayment quote: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
+    }
+
+    return await res.json();
+}
+
+export async function postRepaymentRequest(loanTxid: Txid): Promise<RepaymentResponse> {
+    let res = await fetch(`/api/loan/lbtc-lusdt/repay`, {
         method: "POST",
         headers: {
             "Content-Type": "application/json",
             Accept: "application/json",
         },
-        body: JSON.stringify({ tx_hex: txHex }),
+        body: JSON.stringify({ loan_txid: loanTxid }),
     });
 
-    if (res.status === 400) {
-        const resp = await res.json();
-        debug("received loan validation error: " + resp.title + resp.detail);
-        throw new LoanError(resp.title, resp.detail);
-    } else if (res.status !== 200) {
-        debug("failed to create new loan");
-        const resp = await res.json();
-        throw new LoanError("Failed to create new loan", resp.detail);
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to request repayment: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
     }
 
     return await res.json();
 }
 
-async function postPayload(payload: CreateSwapPayload, path: string) {
-    let res = await fetch(`/api/swap/lbtc-lusdt/${path}`, {
+export async function postRepaymentFinalization(txHex: string): Promise<Txid> {
+    let res = await fetch(`/api/loan/lbtc-lusdt/repay/finalize`, {
         method: "POST",
         headers: {
             "Content-Type": "application/json",
             Accept: "application/json",
         },
-        body: JSON.stringify(payload),
+        body: JSON.stringify({ tx_hex: txHex }),
     });
 
     if (res.status !== 200) {
-        debug("failed to create new swap");
-        throw new Error("failed to create new swap");
+        const problem = await readProblem(res);
+        debug("failed to repay loan: " + JSON.stringify(problem));
+        throw LoanError.fromProblem(problem);
     }
 
-    return await res.text();
+    return await res.json();
+}
+
+async function postPayload(payload: CreateSwapPayload, path: string): Promise<SignedSwap> {
+    let res = await postIdempotent(`/api/swap/lbtc-lusdt/${path}`, payload);
+
+    if (res.status !== 200) {
+        const problem = await readProblem(res);
+        debug("failed to create new swap: " + JSON.stringify(problem));
+        throw new Error(describeProblem(problem) || problem.title);
+    }
+
+    const signature = readMakerSignature(res);
+    const txHex = await res.text();
+
+    return { txHex, signature };
+}
+
+// Responses which are not problems, e.g. from a proxy in between, are turned into one
+async function readProblem(res: Response): Promise<Problem> {
+    const body = await res.text();
+
+    try {
+        return JSON.parse(body);
+    } catch (e) {
+        return { title: res.statusText, status: res.status, detail: body };
+    }
 }
 
 interface RateProviderProps {
@@ -1,18 +1,28 @@
 import { Box, Button, Center, Tooltip, useToast, VStack } from "@chakra-ui/react";
 import Debug from "debug";
-import React, { Dispatch } from "react";
+import React, { Dispatch, useEffect } from "react";
 import { AsyncState, useAsync } from "react-async";
 import { useHistory } from "react-router-dom";
 import { Action, BorrowState, Rate } from "./App";
 import { getLoanOffer, LoanError, postLoanFinalization, postLoanRequest } from "./Bobtimus";
 import NumberInput from "./components/NumberInput";
 import RateInfo from "./components/RateInfo";
+import { rememberLoan } from "./Loans";
 import { Wallet } from "./waves-provider";
 import { Status, WalletStatus } from "./waves-provider/wavesProvider";
 
 const debug = Debug("Borrow");
 const error = Debug("Borrow:error");
 
+// How often a loan request is repeated with a fresh offer if Bobtimus rejected it because of a stale offer
+const MAX_LOAN_REQUEST_RETRIES = 2;
+const BTC_SATS = 100000000;
+
+// Bobtimus only accepts collateral that covers the repayment amount at the offer's price, hence we round up to the satoshi
+function calculateCollateralAmount(repaymentAmount: number, collateralization: number, bid: number): number {
+    return Math.ceil((repaymentAmount * collateralization / bid) * BTC_SATS) / BTC_SATS;
+}
+
 interface BorrowProps {
     dispatch: Dispatch<Action>;
     rate: Rate;
@@ -34,9 +44,18 @@ function Borrow({ dispatch, state, rate, wavesProvider, walletStatusAsyncState }
             });
         },
     });
-    let { isLoading: loanOfferLoading, data: loanOffer } = loanOfferHook;
-    // TODO: Some mechanism to refresh the loan offer values
-    //  Note: The rate is currently not taken from the offer, but from the state's rate
+    let { isLoading: loanOfferLoading, data: loanOffer, reload: reloadLoanOffer } = loanOfferHook;
+
+    // Bobtimus only accepts loan requests for offers which have not expired yet
+    useEffect(() => {
+        if (!loanOffer) {
+            return;
+        }
+        const millisUntilExpiry = loanOffer.expires_at * 1000 - Date.now();
+        const timeout = setTimeout(reloadLoanOffer, Math.max(millisUntilExpiry, 0));
+
+        return () => clearTimeout(timeout);
+    }, [loanOffer, reloadLoanOffer]);
 
     let { data: walletStatus, reload: reloadWalletStatus, error: walletStatusError } = walletStatusAsyncState;
 
@@ -57,8 +76,10 @@ function Borrow({ dispatch, state, rate, wavesProvider, walletStatusAsyncState }
     let interestAmount = principalAmount * interestRate;
     let repaymentAmount = principalAmount + interestAmount;
 
-    // The bid price is used so the lender is covered under the assumption of selling the asset
-    let collateralAmount = (repaymentAmount * state.collateralization) / rate.bid;
+    // The bid price is used so the lender is covered under the assumption of selling the asset.
+    // The loan request is validated against the offer's rate, so we use it once we have an offer
+    const bid = loanOffer ? loanOffer.rate.bid : rate.bid;
+    let collateralAmount = calculateCollateralAmount(repaymentAmount, state.collateralization, bid);
 
     let { run: takeLoan, isLoading: isTakingLoan } = useAsync({
         deferFn: async () => {
@@ -68,23 +89,50 @@ function Borrow({ dispatch, state, rate, wavesProvider, walletStatusAsyncState }
             }
 
             try {
-                const feeRate = state.loanOffer!.fee_sats_per_vbyte;
-
-                let loanRequestWalletParams = await wavesProvider.makeLoanRequestPayload(
-                    collateralAmount.toString(),
-                    feeRate.toString(),
-                );
-
-                let loanResponse = await postLoanRequest(
-                    loanRequestWalletParams,
-                    state.loanTermInDays,
-                    state.collateralization,
-                    principalAmount,
-                );
+                let offer = state.loanOffer!;
+                let signedLoanResponse;
+                for (let attempt = 0;; attempt++) {
+                    const feeRate = offer.fee_sats_per_vbyte;
+                    const collateralAmount = calculateCollateralAmount(
+                        repaymentAmount,
+                        state.collateralization,
+                        offer.rate.bid,
+                    );
+
+                    let loanRequestWalletParams = await wavesProvider.makeLoanRequestPayload(
+                        collateralAmount.toString(),
+                        feeRate.toString(),
+                    );
+
+                    try {
+                        signedLoanResponse = await postLoanRequest(
+                            offer.id,
+                            loanRequestWalletParams,
+                            state.loanTermInDays,
+                            state.collateralization,
+                            principalAmount,
+                        );
+                        break;
+                    } catch (e) {
+                        // The price may have moved or the offer expired in the meantime, a fresh offer may be accepted
+                        if (!(e instanceof LoanError && e.recoverable) || attempt >= MAX_LOAN_REQUEST_RETRIES) {
+                            throw e;
+                        }
+
+                        debug(`retrying loan request with fresh offer after: ${e.title}`);
+                        offer = await getLoanOffer();
+                        dispatch({
+                            type: "UpdateLoanOffer",
+                            value: offer,
+                        });
+                    }
+                }
+                const { loanResponse, signature } = signedLoanResponse;
                 debug(JSON.stringify(loanResponse));
 
-                let loanTransaction = await wavesProvider.requestSignLoan(loanResponse);
+                let loanTransaction = await wavesProvider.requestSignLoan(loanResponse, signature);
                 let txid = await postLoanFinalization(loanTransaction);
+                rememberLoan(txid);
 
                 // TODO: Add different page for loaned?
                 history.push(`/trade/swapped/${txid}`);
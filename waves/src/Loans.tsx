@@ -0,0 +1,147 @@
+import { Box, Button, Center, HStack, Text, useToast, VStack } from "@chakra-ui/react";
+import Debug from "debug";
+import React, { useState } from "react";
+import { useAsync } from "react-async";
+import { getRepaymentQuote, LoanError, postRepaymentFinalization, postRepaymentRequest } from "./Bobtimus";
+import { Wallet } from "./waves-provider";
+import { Txid } from "./waves-provider/wavesProvider";
+
+const debug = Debug("Loans");
+const error = Debug("Loans:error");
+
+// The loans taken out from this page, Bobtimus only knows them by their txid
+const LOANS_KEY = "loans";
+const USDT_SATS = 100000000;
+
+function takenLoans(): Txid[] {
+    const loans = localStorage.getItem(LOANS_KEY);
+
+    return loans ? JSON.parse(loans) : [];
+}
+
+export function rememberLoan(txid: Txid) {
+    localStorage.setItem(LOANS_KEY, JSON.stringify([...takenLoans(), txid]));
+}
+
+function forgetLoan(txid: Txid) {
+    localStorage.setItem(LOANS_KEY, JSON.stringify(takenLoans().filter(loan => loan !== txid)));
+}
+
+interface LoansProps {
+    wavesProvider: Wallet | undefined;
+}
+
+function Loans({ wavesProvider }: LoansProps) {
+    let [loans, setLoans] = useState(takenLoans());
+
+    return (
+        <VStack spacing={4} align="stretch">
+            {loans.length === 0 && <Center>You have no open loans.</Center>}
+            {loans.map(txid => (
+                <Loan
+                    key={txid}
+                    loanTxid={txid}
+                    wavesProvider={wavesProvider}
+                    onRepaid={() => {
+                        forgetLoan(txid);
+                        setLoans(takenLoans());
+                    }}
+                />
+            ))}
+        </VStack>
+    );
+}
+
+interface LoanProps {
+    loanTxid: Txid;
+    wavesProvider: Wallet | undefined;
+    onRepaid: () => void;
+}
+
+function Loan({ loanTxid, wavesProvider, onRepaid }: LoanProps) {
+    const toast = useToast();
+
+    let { data: quote, error: quoteError, reload: reloadQuote } = useAsync({
+        promiseFn: fetchRepaymentQuote,
+        loanTxid,
+    });
+
+    let { run: repay, isLoading: isRepaying } = useAsync({
+        deferFn: async () => {
+            if (!wavesProvider) {
+                error("Cannot repay. Waves provider not found.");
+                return;
+            }
+
+            try {
+                let repayment = await postRepaymentRequest(loanTxid);
+                let repaymentTransaction = await wavesProvider.requestSignRepayment(repayment);
+                let txid = await postRepaymentFinalization(repaymentTransaction);
+                debug(`repaid loan ${loanTxid} with ${txid}`);
+
+                onRepaid();
+            } catch (e) {
+                if (e instanceof LoanError) {
+                    const description = e.description ? e.description : "";
+
+                    toast({
+                        title: e.title,
+                        description,
+                        status: "error",
+                        duration: 10000,
+                        isClosable: true,
+                    });
+                } else {
+                    const description = typeof e === "string" ? e : JSON.stringify(e);
+
+                    toast({
+                        title: "Error",
+                        description,
+                        status: "error",
+                        duration: 5000,
+                        isClosable: true,
+                    });
+                }
+
+                // The quote moves on with the accrued interest
+                reloadQuote();
+            }
+        },
+    });
+
+    return (
+        <Box bg="gray.100" w={400} p={4} borderRadius={"md"}>
+            <VStack spacing={2} align="stretch">
+                <Text isTruncated>Loan {loanTxid}</Text>
+                {quote
+                    && <>
+                        <HStack justify="space-between">
+                            <Text>Repay now:</Text>
+                            <Text>{quote.repayment_amount / USDT_SATS} L-USDt</Text>
+                        </HStack>
+                        <HStack justify="space-between">
+                            <Text>Interest saved:</Text>
+                            <Text>{quote.refund_amount / USDT_SATS} L-USDt</Text>
+                        </HStack>
+                    </>}
+                {quoteError && <Text>{quoteError.message}</Text>}
+                <Button
+                    variant="primary"
+                    isLoading={isRepaying}
+                    isDisabled={!quote}
+                    onClick={repay}
+                    data-cy="data-cy-repay-loan-button"
+                >
+                    Repay early
+                </Button>
+            </VStack>
+        </Box>
+    );
+}
+
+// The useAsync hook requires a stable reference to the promise function
+async function fetchRepaymentQuote({ loanTxid }: any) {
+    return getRepaymentQuote(loanTxid);
+}
+
+export default Loans;
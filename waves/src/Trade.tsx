@@ -1,11 +1,29 @@
 import { ExternalLinkIcon } from "@chakra-ui/icons";
-import { Box, Button, Center, Flex, HStack, Link, StackDivider, Text, useToast, VStack } from "@chakra-ui/react";
+import {
+    Box,
+    Button,
+    Center,
+    Checkbox,
+    Flex,
+    HStack,
+    Link,
+    StackDivider,
+    Text,
+    useToast,
+    VStack,
+} from "@chakra-ui/react";
 import Debug from "debug";
-import React, { Dispatch } from "react";
+import React, { Dispatch, useState } from "react";
 import { AsyncState, useAsync } from "react-async";
 import { Route, Switch, useHistory, useParams } from "react-router-dom";
 import { Action, Asset, Rate, TradeState } from "./App";
-import { postBuyPayload, postSellPayload } from "./Bobtimus";
+import {
+    postBatchedBuyPayload,
+    postBatchedSellPayload,
+    postBuyPayload,
+    postSellPayload,
+    postSwapBatchSignature,
+} from "./Bobtimus";
 import calculateBetaAmount, { getDirection } from "./calculateBetaAmount";
 import AssetSelector from "./components/AssetSelector";
 import ExchangeIcon from "./components/ExchangeIcon";
@@ -37,23 +55,33 @@ function Trade({ state, dispatch, rate, walletStatusAsyncState, wavesProvider }:
 
     let { data: walletStatus, reload: reloadWalletStatus, error: walletStatusError } = walletStatusAsyncState;
 
+    // Batched swaps share the transaction fee with other takers, but wait for the next batch to be built
+    let [isBatched, setBatched] = useState(false);
+
     let { run: makeNewSwap, isLoading: isCreatingNewSwap } = useAsync({
         deferFn: async () => {
             if (!wavesProvider) {
                 error("Cannot swap. Waves provider not found.");
                 return;
             }
-            let tx;
+            let swap;
             try {
                 if (state.alpha.type === Asset.LBTC) {
                     const payload = await wavesProvider.makeSellCreateSwapPayload(state.alpha.amount.toString());
-                    tx = await postSellPayload(payload);
+                    swap = isBatched ? await postBatchedSellPayload(payload) : await postSellPayload(payload);
                 } else {
                     const payload = await wavesProvider.makeBuyCreateSwapPayload(state.alpha.amount.toString());
-                    tx = await postBuyPayload(payload);
+                    swap = isBatched ? await postBatchedBuyPayload(payload) : await postBuyPayload(payload);
                 }
 
-                let txid = await wavesProvider.requestSignSwap(tx);
+                let txid;
+                if (isBatched) {
+                    // Bobtimus broadcasts the batch once every taker signed it
+                    let signedBatch = await wavesProvider.requestSignSwapBatch(swap.txHex, swap.signature);
+                    txid = await postSwapBatchSignature(signedBatch);
+                } else {
+                    txid = await wavesProvider.requestSignSwap(swap.txHex, swap.signature);
+                }
 
                 history.push(`/trade/swapped/${txid}`);
             } catch (e) {
@@ -165,6 +193,13 @@ function Trade({ state, dispatch, rate, walletStatusAsyncState, wavesProvider }:
                         />
                     </Flex>
                     <RateInfo rate={rate} direction={getDirection(state.alpha.type)} />
+                    <Checkbox
+                        isChecked={isBatched}
+                        onChange={e => setBatched(e.target.checked)}
+                        data-cy="data-cy-batch-swap-checkbox"
+                    >
+                        Batch with other swaps to save fees
+                    </Checkbox>
                     <Box>
                         {swapButton}
                     </Box>
@@ -1,4 +1,13 @@
-import { Address, CreateSwapPayload, LoanRequestPayload, LoanTx, Txid, WalletStatus } from "./wavesProvider";
+import {
+    Address,
+    CreateSwapPayload,
+    LoanRequestPayload,
+    LoanTx,
+    MakerSignature,
+    RepaymentResponse,
+    Txid,
+    WalletStatus,
+} from "./wavesProvider";
 
 declare global {
     interface Window {
@@ -16,6 +25,8 @@ export interface Wallet {
         collateral: string,
         fee_rate: string,
     ): Promise<LoanRequestPayload>;
-    requestSignSwap(tx_hex: string): Promise<Txid>;
-    requestSignLoan(loan_response: any): Promise<LoanTx>;
+    requestSignSwap(tx_hex: string, signature: MakerSignature): Promise<Txid>;
+    requestSignSwapBatch(tx_hex: string, signature: MakerSignature): Promise<string>;
+    requestSignLoan(loan_response: any, signature: MakerSignature): Promise<LoanTx>;
+    requestSignRepayment(repayment: RepaymentResponse): Promise<string>;
 }
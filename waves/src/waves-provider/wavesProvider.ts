@@ -24,7 +24,7 @@ export enum Status {
 }
 
 export interface CreateSwapPayload {
-    alice_inputs: { outpoint: OutPoint; blinding_key: string }[];
+    alice_inputs: { outpoint: OutPoint; blinding_key?: string; secrets?: InputSecrets }[];
     address: string;
     amount: number;
 }
@@ -38,11 +38,34 @@ export interface LoanRequestPayload {
     borrower_address: string;
 }
 
+// Bobtimus' part of repaying a loan early, in full
+export interface RepaymentResponse {
+    loan_txid: Txid;
+    repayment_amount: number;
+    // TODO: Replace `any` with concrete type or get rid of `original_txout` field
+    refund_input: { txin: OutPoint; original_txout: any; blinding_key: string } | null;
+    expires_at: number;
+}
+
+// The identity of Bobtimus and its signature over a swap or loan transaction
+export interface MakerSignature {
+    maker: string;
+    signature: string;
+}
+
 export interface OutPoint {
     txid: string;
     vout: number;
 }
 
+// Amounts and blinding factors of the output spent by an input
+export interface InputSecrets {
+    asset: string;
+    asset_blinding_factor: string;
+    value: number;
+    value_blinding_factor: string;
+}
+
 export interface Trade {
     sell: TradeSide;
     buy: TradeSide;